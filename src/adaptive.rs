@@ -0,0 +1,644 @@
+//! Adaptive Huffman coding (Faller, Gallager and Knuth's algorithm, or Vitter's), where the tree changes after every
+//! symbol
+//!
+//! Nothing's known up front: the tree starts out as a single "not yet transmitted" (NYT) node, and the first time a
+//! symbol turns up it's sent as the NYT node's code followed by the symbol itself, in 9 bits, after which the NYT
+//! node splits into a new NYT node and a leaf for that symbol. Every symbol then bumps its leaf's weight (and every
+//! weight above it), swapping nodes around on the way up to keep the tree a Huffman tree for the counts so far. The
+//! decoder does exactly the same after every symbol it decodes, so the two trees never drift apart, and there's no
+//! header and no second pass: every byte gets coded the moment it comes in.
+//!
+//! Nodes are kept in an array in order of their number, the root first. The tree stays a Huffman tree as long as
+//! the weights never go up along the array (the sibling property), and the nodes sharing a weight (a block) sit next
+//! to each other, so before a node's weight goes up, it gets swapped with the first node of its block (unless that's
+//! its parent), which keeps it that way.
+//!
+//! Vitter's algorithm (`Algorithm::Vitter`) keeps a stricter order: within a weight, every leaf comes before every
+//! internal node. Rather than swapping, a node whose weight goes up slides past the block after its own (the internal
+//! nodes of its weight, for a leaf, or the leaves of the next weight up, for an internal node). That keeps the tree
+//! as shallow as it can be among the Huffman trees for the counts so far, which bounds it to at most one bit a
+//! symbol more than a two-pass Huffman code would take, where FGK can take up to twice as many; in practice it
+//! usually comes out a little shorter than FGK. Both ends have to use the same algorithm, and nothing in the stream
+//! says which one it was.
+//!
+//! The end of the stream is marked by symbol 256 (sent like any new symbol), after which the last byte's padded out
+//! with 0s. There's no checksum; wrap the stream in something that has one if it's going anywhere unreliable.
+
+use crate::bits::{bit_at, BitWriter};
+use crate::error::{Error, Result};
+
+/// The symbol marking the end of the stream; bytes are symbols 0 through 255, so this comes right after them
+const END_OF_STREAM: usize = 256;
+/// How many symbols there are: every byte value, plus the end-of-stream marker
+const SYMBOL_COUNT: usize = 257;
+/// How many bits a symbol takes when it's sent for the first time
+const SYMBOL_BITS: u8 = 9;
+/// Marks a missing link (the root's parent, or a symbol that hasn't been seen)
+const NONE: usize = usize::MAX;
+
+/// How the adaptive tree gets updated after each symbol
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Faller, Gallager and Knuth's: swap each node on the way up with the first node of its weight
+    #[default]
+    Fgk,
+    /// Vitter's Algorithm V: slide each node on the way up past the next block, keeping the tree as shallow as it
+    /// can be
+    Vitter,
+}
+
+/// A node in the adaptive tree
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    /// How many times the symbols under this node have been seen
+    weight: u64,
+    /// Where this node's parent is (`NONE` for the root)
+    parent: usize,
+    /// Where this node's children are (0 then 1), if it has any
+    children: Option<[usize; 2]>,
+    /// The symbol at this leaf (`NONE` for the NYT node and for internal nodes)
+    symbol: usize,
+}
+
+/// The adaptive tree, kept in lockstep by the encoder and the decoder
+struct Tree {
+    /// Every node, in order of number, highest (the root) first
+    nodes: Vec<Node>,
+    /// Where each symbol's leaf is (`NONE` if it hasn't been seen yet)
+    leaves: [usize; SYMBOL_COUNT],
+    /// Where the NYT node is
+    nyt: usize,
+    /// How the tree gets updated
+    algorithm: Algorithm,
+}
+
+/// What a leaf of the tree stands for
+enum Leaf {
+    /// A symbol that's already been seen
+    Symbol(usize),
+    /// The NYT node, i.e. a symbol that's being seen for the first time, whose bits come next
+    NotYetTransmitted,
+}
+
+impl Tree {
+    /// Creates the starting tree, which is just the NYT node
+    fn new(algorithm: Algorithm) -> Self {
+        Tree {
+            nodes: vec![Node {
+                weight: 0,
+                parent: NONE,
+                children: None,
+                symbol: NONE,
+            }],
+            leaves: [NONE; SYMBOL_COUNT],
+            nyt: 0,
+            algorithm,
+        }
+    }
+
+    /// Pushes the code for a symbol: its leaf's code, or the NYT node's code and the symbol itself if it's new
+    fn push_code(&self, symbol: usize, bits: &mut BitWriter, out: &mut Vec<u8>) {
+        let leaf = self.leaves[symbol];
+        self.push_path(if leaf == NONE { self.nyt } else { leaf }, bits, out);
+        if leaf == NONE {
+            bits.push_bits(symbol as u64, SYMBOL_BITS, out);
+        }
+    }
+
+    /// Pushes the bits leading from the root down to a node
+    fn push_path(&self, mut node: usize, bits: &mut BitWriter, out: &mut Vec<u8>) {
+        let mut path = Vec::new();
+        while self.nodes[node].parent != NONE {
+            let parent = self.nodes[node].parent;
+            path.push(
+                self.nodes[parent]
+                    .children
+                    .is_some_and(|[_, one]| one == node),
+            );
+            node = parent;
+        }
+        for &bit in path.iter().rev() {
+            bits.push_bits(bit as u64, 1, out);
+        }
+    }
+
+    /// What a node stands for, if it's a leaf
+    fn leaf(&self, node: usize) -> Option<Leaf> {
+        match (self.nodes[node].children, self.nodes[node].symbol) {
+            (Some(_), _) => None,
+            (None, NONE) => Some(Leaf::NotYetTransmitted),
+            (None, symbol) => Some(Leaf::Symbol(symbol)),
+        }
+    }
+
+    /// Counts one more of a symbol, reshaping the tree to match
+    fn update(&mut self, symbol: usize) {
+        match self.algorithm {
+            Algorithm::Fgk => self.update_fgk(symbol),
+            Algorithm::Vitter => self.update_vitter(symbol),
+        }
+    }
+
+    /// `update()`, Faller, Gallager and Knuth's way
+    fn update_fgk(&mut self, symbol: usize) {
+        let mut node = self.leaves[symbol];
+        if node == NONE {
+            node = self.split_nyt(symbol);
+        }
+        while node != NONE {
+            let leader = self.block_leader(node);
+            if leader != node && leader != self.nodes[node].parent {
+                self.swap(node, leader);
+                node = leader;
+            }
+            self.nodes[node].weight += 1;
+            node = self.nodes[node].parent;
+        }
+    }
+
+    /// `update()`, Vitter's way
+    fn update_vitter(&mut self, symbol: usize) {
+        // a leaf that's the NYT node's sibling gets incremented last, after the nodes above it, since it could slide
+        // past its own parent otherwise
+        let mut leaf_to_increment = NONE;
+        let mut node = self.leaves[symbol];
+        if node == NONE {
+            leaf_to_increment = self.split_nyt(symbol);
+            node = self.nodes[leaf_to_increment].parent;
+        } else {
+            let leader = self.block_leader(node);
+            if leader != node {
+                self.swap(node, leader);
+                node = leader;
+            }
+            if self.nodes[node].parent == self.nodes[self.nyt].parent {
+                leaf_to_increment = node;
+                node = self.nodes[node].parent;
+            }
+        }
+        while node != NONE {
+            node = self.slide_and_increment(node);
+        }
+        if leaf_to_increment != NONE {
+            self.slide_and_increment(leaf_to_increment);
+        }
+    }
+
+    /// Slides a node past the block after its own (if there's one it belongs ahead of) and bumps its weight, giving
+    /// back the node to go on to: the leaf's new parent, or the internal node's old one
+    fn slide_and_increment(&mut self, node: usize) -> usize {
+        let weight = self.nodes[node].weight;
+        let is_leaf = self.nodes[node].children.is_none();
+        let old_parent = self.nodes[node].parent;
+        let mut ahead = node;
+        // past the rest of its own block...
+        while ahead > 0 && self.same_block(ahead - 1, weight, is_leaf) {
+            ahead -= 1;
+        }
+        // ...and then the block it belongs ahead of
+        let (next_weight, next_is_leaf) = if is_leaf {
+            (weight, false)
+        } else {
+            (weight + 1, true)
+        };
+        let block_start = ahead;
+        while ahead > 0 && self.same_block(ahead - 1, next_weight, next_is_leaf) {
+            ahead -= 1;
+        }
+        let node = if ahead < block_start {
+            self.slide(node, ahead);
+            ahead
+        } else {
+            node
+        };
+        self.nodes[node].weight += 1;
+        if is_leaf {
+            self.nodes[node].parent
+        } else {
+            old_parent
+        }
+    }
+
+    /// Whether a node's in the block of a weight and kind (leaves or internal nodes)
+    fn same_block(&self, node: usize, weight: u64, is_leaf: bool) -> bool {
+        self.nodes[node].weight == weight && self.nodes[node].children.is_none() == is_leaf
+    }
+
+    /// Moves a node (and everything under it) up to an earlier place, moving everything in between (and everything
+    /// under them) back one place to make room
+    fn slide(&mut self, from: usize, to: usize) {
+        // the places keep their parents; it's what's in them that's moved
+        let parents: Vec<usize> = self.nodes[to..=from]
+            .iter()
+            .map(|node| node.parent)
+            .collect();
+        self.nodes[to..=from].rotate_right(1);
+        for (node, parent) in (to..=from).zip(parents) {
+            self.nodes[node].parent = parent;
+            self.relink(node);
+        }
+    }
+
+    /// Splits the NYT node into a new NYT node and a leaf for a new symbol, giving back where the leaf is
+    fn split_nyt(&mut self, symbol: usize) -> usize {
+        let parent = self.nyt;
+        let (leaf, nyt) = (self.nodes.len(), self.nodes.len() + 1);
+        for symbol in [symbol, NONE] {
+            self.nodes.push(Node {
+                weight: 0,
+                parent,
+                children: None,
+                symbol,
+            });
+        }
+        self.nodes[parent].children = Some([nyt, leaf]);
+        self.leaves[symbol] = leaf;
+        self.nyt = nyt;
+        leaf
+    }
+
+    /// The highest-numbered node in a node's block: for FGK, every node of the same weight, and for Vitter, every
+    /// node of the same weight that's also a leaf (or also internal)
+    fn block_leader(&self, node: usize) -> usize {
+        let weight = self.nodes[node].weight;
+        let is_leaf = self.nodes[node].children.is_none();
+        let mut leader = node;
+        while leader > 0
+            && match self.algorithm {
+                Algorithm::Fgk => self.nodes[leader - 1].weight == weight,
+                Algorithm::Vitter => self.same_block(leader - 1, weight, is_leaf),
+            }
+        {
+            leader -= 1;
+        }
+        leader
+    }
+
+    /// Swaps two nodes (and everything under them) between their places in the tree
+    fn swap(&mut self, a: usize, b: usize) {
+        self.nodes.swap(a, b);
+        // the places keep their parents; it's what's in them that's moved
+        let parent = self.nodes[a].parent;
+        self.nodes[a].parent = self.nodes[b].parent;
+        self.nodes[b].parent = parent;
+        for node in [a, b] {
+            self.relink(node);
+        }
+    }
+
+    /// Points everything that refers to a node that's just moved at its new place
+    fn relink(&mut self, node: usize) {
+        match self.nodes[node].children {
+            Some(children) => {
+                for child in children {
+                    self.nodes[child].parent = node;
+                }
+            }
+            None => match self.nodes[node].symbol {
+                NONE => self.nyt = node,
+                symbol => self.leaves[symbol] = node,
+            },
+        }
+    }
+}
+
+/// The adaptive encoder: bytes go in, compressed bytes come out, with nothing sent ahead of them
+pub struct Encoder {
+    /// The adaptive tree
+    tree: Tree,
+    /// Holds bits that haven't made up a whole byte yet
+    bits: BitWriter,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Encoder::new()
+    }
+}
+
+impl Encoder {
+    /// Creates a new encoder at the start of a stream, using FGK
+    pub fn new() -> Self {
+        Encoder::with_algorithm(Algorithm::default())
+    }
+
+    /// Creates a new encoder at the start of a stream, updating its tree with a given algorithm
+    ///
+    /// ## Arguments
+    ///
+    /// * `algorithm`: how the tree gets updated (which the decoder has to be told, too)
+    pub fn with_algorithm(algorithm: Algorithm) -> Self {
+        Encoder {
+            tree: Tree::new(algorithm),
+            bits: BitWriter::new(),
+        }
+    }
+
+    /// Encodes some more input, appending whatever output that produces
+    ///
+    /// Output goes out a 64-bit word at a time, so the last few bytes' worth of bits can be held back until there's a
+    /// whole word of them (or until `finish()`).
+    ///
+    /// ## Arguments
+    ///
+    /// * `input`: the bytes to encode
+    /// * `out`: where the compressed bytes go
+    pub fn encode(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        for &byte in input {
+            self.push_symbol(byte as usize, out);
+        }
+    }
+
+    /// Ends the stream, appending the end-of-stream marker and the padding for the last byte
+    ///
+    /// ## Arguments
+    ///
+    /// * `out`: where the compressed bytes go
+    pub fn finish(&mut self, out: &mut Vec<u8>) {
+        self.push_symbol(END_OF_STREAM, out);
+        self.bits.align(out);
+    }
+
+    /// Encodes one symbol and updates the tree with it
+    fn push_symbol(&mut self, symbol: usize, out: &mut Vec<u8>) {
+        self.tree.push_code(symbol, &mut self.bits, out);
+        self.tree.update(symbol);
+    }
+}
+
+/// The adaptive decoder: compressed bytes go in, the original bytes come out
+pub struct Decoder {
+    /// The adaptive tree, kept in lockstep with the encoder's
+    tree: Tree,
+    /// How far down the tree the bits so far have led (which carries over from one call to the next)
+    node: usize,
+    /// The bits of a new symbol read so far, and how many there are, once the NYT node's been reached
+    new_symbol: Option<(usize, u8)>,
+    /// Whether we've hit the end-of-stream marker
+    done: bool,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder::new()
+    }
+}
+
+impl Decoder {
+    /// Creates a new decoder at the start of a stream, using FGK
+    pub fn new() -> Self {
+        Decoder::with_algorithm(Algorithm::default())
+    }
+
+    /// Creates a new decoder at the start of a stream, updating its tree with a given algorithm
+    ///
+    /// ## Arguments
+    ///
+    /// * `algorithm`: how the tree gets updated (which has to be what the encoder used)
+    pub fn with_algorithm(algorithm: Algorithm) -> Self {
+        Decoder {
+            tree: Tree::new(algorithm),
+            node: 0,
+            new_symbol: None,
+            done: false,
+        }
+    }
+
+    /// Whether the end of the stream has been reached
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Decodes some more compressed input, appending the decoded bytes and returning how many input bytes were used
+    ///
+    /// All of the input gets used unless the end of the stream turns up partway through it, in which case whatever
+    /// follows the end-of-stream marker's last byte is left alone.
+    ///
+    /// ## Arguments
+    ///
+    /// * `input`: the compressed bytes
+    /// * `out`: where the decoded bytes go
+    pub fn decode(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<usize> {
+        let total_bits = input.len() * 8;
+        let mut pos = 0;
+        while !self.done {
+            let symbol = match (self.new_symbol, self.tree.leaf(self.node)) {
+                (Some((symbol, SYMBOL_BITS)), _) => {
+                    self.new_symbol = None;
+                    if symbol >= SYMBOL_COUNT || self.tree.leaves[symbol] != NONE {
+                        return Err(Error::Corrupt(
+                            "new symbol was out of range or already seen",
+                        ));
+                    }
+                    symbol
+                }
+                (None, Some(Leaf::Symbol(symbol))) => symbol,
+                (None, Some(Leaf::NotYetTransmitted)) => {
+                    self.new_symbol = Some((0, 0));
+                    continue;
+                }
+                // the rest all need another bit, which might not be here yet
+                _ if pos == total_bits => break,
+                (Some((bits, len)), _) => {
+                    self.new_symbol = Some((bits << 1 | bit_at(input, pos) as usize, len + 1));
+                    pos += 1;
+                    continue;
+                }
+                (None, None) => {
+                    let children = self.tree.nodes[self.node].children.unwrap();
+                    self.node = children[bit_at(input, pos) as usize];
+                    pos += 1;
+                    continue;
+                }
+            };
+            if symbol == END_OF_STREAM {
+                // whatever's left of the byte is padding
+                pos = pos.div_ceil(8) * 8;
+                self.done = true;
+            } else {
+                out.push(symbol as u8);
+            }
+            self.tree.update(symbol);
+            self.node = 0;
+        }
+        Ok(pos / 8)
+    }
+}
+
+/// Compresses a whole byte slice with adaptive Huffman coding, using FGK
+///
+/// ## Arguments
+///
+/// * `data`: the bytes to compress
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    compress_with(data, Algorithm::default())
+}
+
+/// Compresses a whole byte slice with adaptive Huffman coding, using a given algorithm
+///
+/// ## Arguments
+///
+/// * `data`: the bytes to compress
+/// * `algorithm`: how the tree gets updated
+pub fn compress_with(data: &[u8], algorithm: Algorithm) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2);
+    let mut encoder = Encoder::with_algorithm(algorithm);
+    encoder.encode(data, &mut out);
+    encoder.finish(&mut out);
+    out
+}
+
+/// Decompresses a whole adaptive Huffman stream made with FGK, failing if it stops before its end-of-stream marker
+///
+/// ## Arguments
+///
+/// * `data`: the compressed stream
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    decompress_with(data, Algorithm::default())
+}
+
+/// Decompresses a whole adaptive Huffman stream made with a given algorithm, failing if it stops before its
+/// end-of-stream marker
+///
+/// ## Arguments
+///
+/// * `data`: the compressed stream
+/// * `algorithm`: how the encoder updated its tree
+pub fn decompress_with(data: &[u8], algorithm: Algorithm) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() * 2);
+    let mut decoder = Decoder::with_algorithm(algorithm);
+    decoder.decode(data, &mut out)?;
+    if !decoder.is_done() {
+        return Err(Error::Corrupt(
+            "adaptive stream ends before its end-of-stream marker",
+        ));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compress, compress_with, decompress, decompress_with, Algorithm, Decoder, Tree};
+    use crate::error::Error;
+
+    /// Whether the tree's weights never go up along the array, and every internal node weighs what its children do
+    fn has_sibling_property(tree: &Tree) -> bool {
+        tree.nodes
+            .windows(2)
+            .all(|pair| pair[0].weight >= pair[1].weight)
+            && tree.nodes.iter().all(|node| match node.children {
+                Some([a, b]) => node.weight == tree.nodes[a].weight + tree.nodes[b].weight,
+                None => true,
+            })
+    }
+
+    #[test]
+    fn sibling_property_test() {
+        for algorithm in [Algorithm::Fgk, Algorithm::Vitter] {
+            let mut tree = Tree::new(algorithm);
+            for &byte in b"aardvark abracadabra, dagoth ur was a hotep".iter() {
+                tree.update(byte as usize);
+                assert!(has_sibling_property(&tree));
+                // Vitter's also keeps the leaves of each weight ahead of (i.e. after, in the array) its internal nodes
+                if algorithm == Algorithm::Vitter {
+                    assert!(tree
+                        .nodes
+                        .windows(2)
+                        .all(|pair| pair[0].weight > pair[1].weight
+                            || pair[0].children.is_some()
+                            || pair[1].children.is_none()));
+                }
+            }
+            assert_eq!(tree.nodes[0].weight, 43);
+        }
+    }
+
+    /// How deep the tree's leaves are, added up, and how deep the deepest one is
+    fn depths(tree: &Tree) -> (usize, usize) {
+        let depth = |mut node: usize| {
+            let mut depth = 0;
+            while tree.nodes[node].parent != super::NONE {
+                node = tree.nodes[node].parent;
+                depth += 1;
+            }
+            depth
+        };
+        let leaves: Vec<usize> = (0..tree.nodes.len())
+            .filter(|&node| tree.nodes[node].children.is_none())
+            .map(depth)
+            .collect();
+        (
+            leaves.iter().sum(),
+            leaves.iter().copied().max().unwrap_or(0),
+        )
+    }
+
+    #[test]
+    fn vitter_test() {
+        let text = b"whether 'tis nobler in the mind to suffer the slings and arrows of outrageous fortune, \
+            or to take arms against a sea of troubles, and by opposing end them"
+            .repeat(20);
+        let skewed: Vec<u8> = (0..20_000u32)
+            .map(|i| (i.trailing_zeros() * 7) as u8)
+            .collect();
+        for data in [&b""[..], b"a", &text, &skewed] {
+            let compressed = compress_with(data, Algorithm::Vitter);
+            assert_eq!(
+                decompress_with(&compressed, Algorithm::Vitter).unwrap(),
+                data
+            );
+        }
+        // of all the Huffman trees for the counts so far, Vitter's is always the shallowest, so it's never deeper than
+        // FGK's (and on text, it comes out a little shorter for it)
+        let (mut fgk, mut vitter) = (Tree::new(Algorithm::Fgk), Tree::new(Algorithm::Vitter));
+        for &byte in &text {
+            fgk.update(byte as usize);
+            vitter.update(byte as usize);
+            let (fgk_depths, vitter_depths) = (depths(&fgk), depths(&vitter));
+            assert!(vitter_depths.0 <= fgk_depths.0 && vitter_depths.1 <= fgk_depths.1);
+        }
+        assert!(compress_with(&text, Algorithm::Vitter).len() <= compress(&text).len());
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let skewed: Vec<u8> = (0..20_000u32)
+            .map(|i| (i.trailing_zeros() * 7) as u8)
+            .collect();
+        let everything: Vec<u8> = (0..=255).cycle().take(3000).collect();
+        for data in [
+            &b""[..],
+            b"a",
+            b"dagoth ur was a hotep",
+            &skewed,
+            &everything,
+        ] {
+            let compressed = compress(data);
+            assert_eq!(decompress(&compressed).unwrap(), data);
+            // a byte at a time comes out the same
+            let mut decoder = Decoder::new();
+            let mut out = Vec::new();
+            for byte in compressed.chunks(1) {
+                assert_eq!(decoder.decode(byte, &mut out).unwrap(), 1);
+            }
+            assert!(decoder.is_done());
+            assert_eq!(out, data);
+        }
+        assert!(compress(&skewed).len() < skewed.len() / 3);
+    }
+
+    #[test]
+    fn trailing_data_test() {
+        let mut compressed = compress(b"dagoth ur was a hotep");
+        let len = compressed.len();
+        compressed.extend_from_slice(b"whatever's next");
+        let mut decoder = Decoder::new();
+        let mut out = Vec::new();
+        assert_eq!(decoder.decode(&compressed, &mut out).unwrap(), len);
+        assert_eq!(out, b"dagoth ur was a hotep");
+        assert!(matches!(
+            decompress(&compressed[..len - 1]),
+            Err(Error::Corrupt(_))
+        ));
+    }
+}
@@ -0,0 +1,192 @@
+//! Alphabetic (order-preserving) codes, whose order matches the order of the symbols they stand for
+//!
+//! With codes like these, comparing two encodings bit by bit gives the same answer as comparing what they encode, so
+//! sorted data stays sorted once it's compressed, and a compressed index can be searched without decoding any of it.
+//! Keeping the codes in order costs a little, since the tree can't just merge whichever nodes are smallest, but
+//! never more than about 2 bits per symbol over a Huffman code (and often close to nothing).
+//!
+//! The lengths come from the Garsia–Wachs algorithm, which finds the same optimal alphabetic trees as Hu and
+//! Tucker's, but more simply: it merges pairs much like Huffman's does, only ever neighbours, and then slides each
+//! merged node left to where it fits among the others. The tree that makes isn't in order, but its leaves' depths
+//! are those of an optimal tree that is, and those are all the codes need.
+
+use std::collections::HashMap;
+
+/// Works out optimal alphabetic code lengths
+///
+/// Each symbol's length comes back in the same place as its weight. A single symbol gets a length of 0, like
+/// `HuffTree` would give it. This takes quadratic time, which is nothing for an alphabet of bytes.
+///
+/// ## Arguments
+///
+/// * `weights`: how often each symbol shows up, in symbol order
+pub fn code_lengths(weights: &[u64]) -> Vec<u64> {
+    let n = weights.len();
+    if n == 0 {
+        return Vec::new();
+    }
+    // every node ever made: the leaves first, then the merged ones, each with its weight and its parent (if it has
+    // one yet)
+    let mut node_weights: Vec<u64> = weights.to_vec();
+    let mut parents: Vec<Option<usize>> = vec![None; n];
+    // the nodes still waiting to be merged, in order
+    let mut queue: Vec<usize> = (0..n).collect();
+    while queue.len() > 1 {
+        // merge the first pair whose left node isn't any heavier than the node just after the pair (where the end
+        // counts as heavier than anything)
+        let weight = |i: usize| queue.get(i).map_or(u64::MAX, |&node| node_weights[node]);
+        let k = (0..queue.len() - 1)
+            .find(|&k| weight(k) <= weight(k + 2))
+            .unwrap();
+        let sum = weight(k) + weight(k + 1);
+        let merged = node_weights.len();
+        node_weights.push(sum);
+        parents.push(None);
+        parents[queue[k]] = Some(merged);
+        parents[queue[k + 1]] = Some(merged);
+        queue.drain(k..k + 2);
+        // and slide it left until it's just after something at least as heavy
+        let at = queue[..k]
+            .iter()
+            .rposition(|&node| node_weights[node] >= sum)
+            .map_or(0, |j| j + 1);
+        queue.insert(at, merged);
+    }
+    // every node comes after its children, so going backwards means each parent's depth is known before its children
+    let mut depths = vec![0; node_weights.len()];
+    for node in (0..node_weights.len()).rev() {
+        if let Some(parent) = parents[node] {
+            depths[node] = depths[parent] + 1;
+        }
+    }
+    depths.truncate(n);
+    depths
+}
+
+/// Fills in alphabetic codes for a set of code lengths
+///
+/// Each code is the one after the last, counting up in binary, with zeros added or taken off the end to make it the
+/// right length.
+///
+/// ## Arguments
+///
+/// * `lengths`: each symbol's code length, in symbol order, which have to be the depths of the leaves of a complete
+///   tree, left to right (like the ones from `code_lengths()` are)
+pub fn alphabetic_codes(lengths: &[(char, u64)]) -> HashMap<char, String> {
+    let mut huffman_map = HashMap::with_capacity(lengths.len());
+    // the next code, as '0'/'1's
+    let mut code = String::new();
+    for &(ch, len) in lengths {
+        // anything taken off is a 0, since the tree's complete
+        code.truncate(len as usize);
+        while (code.len() as u64) < len {
+            code.push('0');
+        }
+        huffman_map.insert(ch, code.clone());
+        // add one, carrying through any trailing 1s
+        let ones = code.len() - code.trim_end_matches('1').len();
+        code.truncate(code.len() - ones);
+        if code.pop().is_some() {
+            code.push('1');
+            code.extend(std::iter::repeat_n('0', ones));
+        }
+    }
+    huffman_map
+}
+
+/// Makes optimal alphabetic codes straight from char frequencies, in char order
+///
+/// ## Arguments
+///
+/// * `char_map`: how often each char shows up
+pub fn generate_huffman_map(char_map: &HashMap<char, i32>) -> HashMap<char, String> {
+    let mut symbols: Vec<(char, i32)> = char_map.iter().map(|(&ch, &freq)| (ch, freq)).collect();
+    symbols.sort_unstable_by_key(|&(ch, _)| ch);
+    let weights: Vec<u64> = symbols
+        .iter()
+        .map(|&(_, freq)| freq.max(0) as u64)
+        .collect();
+    let lengths: Vec<(char, u64)> = symbols
+        .iter()
+        .zip(code_lengths(&weights))
+        .map(|(&(ch, _), len)| (ch, len))
+        .collect();
+    alphabetic_codes(&lengths)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::btree::HuffTree;
+    use itertools::Itertools;
+
+    /// The cheapest alphabetic tree's cost, the slow way: every split of every range
+    fn optimal_cost(weights: &[u64]) -> u64 {
+        let n = weights.len();
+        let mut cost = vec![vec![0; n]; n];
+        for len in 2..=n {
+            for i in 0..=n - len {
+                let j = i + len - 1;
+                let sum: u64 = weights[i..=j].iter().sum();
+                cost[i][j] = (i..j).map(|k| cost[i][k] + cost[k + 1][j]).min().unwrap() + sum;
+            }
+        }
+        cost.first().map_or(0, |row| row[n - 1])
+    }
+
+    #[test]
+    fn code_lengths_test() {
+        assert!(code_lengths(&[]).is_empty());
+        assert_eq!(code_lengths(&[5]), vec![0]);
+        assert_eq!(code_lengths(&[1, 1]), vec![1, 1]);
+        // Huffman would give the heavy middle symbol a 1-bit code, but then the others couldn't stay in order
+        assert_eq!(code_lengths(&[1, 10, 1]), vec![2, 2, 1]);
+        let mut seed = 12345u64;
+        for n in 1..40 {
+            let weights: Vec<u64> = (0..n)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    (seed >> 33) % 100
+                })
+                .collect();
+            let lengths = code_lengths(&weights);
+            let cost: u64 = weights.iter().zip(&lengths).map(|(w, l)| w * l).sum();
+            assert_eq!(cost, optimal_cost(&weights), "{:?}", weights);
+            // the lengths make a complete code
+            if n > 1 {
+                let kraft: f64 = lengths.iter().map(|&l| 0.5f64.powi(l as i32)).sum();
+                assert!((kraft - 1.0).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn order_test() {
+        let input =
+            "whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune";
+        let char_map = HuffTree::find_input_freqs(input);
+        let huffman_map = generate_huffman_map(&char_map);
+        // the codes are in the same order as their chars,
+        let codes: Vec<&String> = huffman_map
+            .iter()
+            .sorted_by_key(|&(ch, _)| ch)
+            .map(|(_, code)| code)
+            .collect();
+        assert!(codes.windows(2).all(|pair| pair[0] < pair[1]));
+        // so encoded words compare just like the words do
+        let mut words: Vec<&str> = input.split(' ').collect();
+        words.sort_unstable_by_key(|word| HuffTree::encode(word, &huffman_map).unwrap());
+        assert!(words.windows(2).all(|pair| pair[0] <= pair[1]));
+        // and they cost a bit more than Huffman's, but not much
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree(&char_map);
+        let alphabetic_len = HuffTree::encoded_len(&char_map, &huffman_map);
+        let huffman_len = HuffTree::encoded_len(&char_map, &hufftree.generate_huffman_map());
+        assert!(huffman_len <= alphabetic_len);
+        assert!(alphabetic_len <= huffman_len + 2 * input.chars().count() as u64);
+        assert_eq!(
+            generate_huffman_map(&HuffTree::find_input_freqs("a"))[&'a'],
+            ""
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Async versions of the streaming writer and reader, for the `futures` IO traits (async-std, smol, etc.)
+//!
+//! These are the same as the ones in `async_tokio`, just against `futures::io::{AsyncRead, AsyncWrite}` instead of
+//! tokio's versions of those traits.
+
+use crate::async_state::{ReadState, WriteState};
+use futures_io::{AsyncBufRead, AsyncRead, AsyncWrite};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+/// Compresses everything written to it in a single pass, writing the compressed stream to the wrapped async writer
+///
+/// The stream is finished off (end-of-stream marker and last partial byte) by `close()`.
+pub struct AsyncHuffmanWriter<W: AsyncWrite + Unpin> {
+    /// Where the compressed bytes go
+    inner: W,
+    /// The encoder and the compressed bytes waiting to be written out
+    state: WriteState,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncHuffmanWriter<W> {
+    /// Creates a new async streaming compressor
+    ///
+    /// ## Arguments
+    ///
+    /// * `inner`: the writer the compressed stream goes to
+    pub fn new(inner: W) -> Self {
+        AsyncHuffmanWriter {
+            inner,
+            state: WriteState::new(),
+        }
+    }
+
+    /// Gets a shared ref to the wrapped writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Hands back the wrapped writer (which won't have the end of the stream unless this has been closed)
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncHuffmanWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let inner = &mut this.inner;
+        this.state.poll_write(cx, data, |cx, buf| {
+            Pin::new(&mut *inner).poll_write(cx, buf)
+        })
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let inner = &mut this.inner;
+        ready!(this
+            .state
+            .poll_flush(cx, |cx, buf| Pin::new(&mut *inner).poll_write(cx, buf)))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let inner = &mut this.inner;
+        ready!(this
+            .state
+            .poll_finish(cx, |cx, buf| Pin::new(&mut *inner).poll_write(cx, buf)))?;
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+/// Decompresses a stream made by `AsyncHuffmanWriter` (or `HuffmanWriter`) as it's read
+///
+/// Like `HuffmanReader`, this decodes straight out of the wrapped reader's buffer and leaves anything after the end
+/// of the stream alone; plain `AsyncRead`ers can be wrapped in a `futures::io::BufReader` first.
+pub struct AsyncHuffmanReader<R: AsyncBufRead + Unpin> {
+    /// Where the compressed bytes come from
+    inner: R,
+    /// The decoder and the decoded bytes that haven't been read out yet
+    state: ReadState,
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncHuffmanReader<R> {
+    /// Creates a new async streaming decompressor
+    ///
+    /// ## Arguments
+    ///
+    /// * `inner`: the reader the compressed stream comes from
+    pub fn new(inner: R) -> Self {
+        AsyncHuffmanReader {
+            inner,
+            state: ReadState::new(),
+        }
+    }
+
+    /// Gets a shared ref to the wrapped reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Hands back the wrapped reader, positioned just past the end of the stream if it's been read to the end
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncRead for AsyncHuffmanReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        while this.state.wants_input() {
+            let in_buf = ready!(Pin::new(&mut this.inner).poll_fill_buf(cx))?;
+            let used = this.state.decode(in_buf)?;
+            Pin::new(&mut this.inner).consume(used);
+        }
+        let out = this.state.take(buf.len());
+        buf[..out.len()].copy_from_slice(out);
+        Poll::Ready(Ok(out.len()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AsyncHuffmanReader, AsyncHuffmanWriter};
+    use crate::stream::{HuffmanReader, HuffmanWriter};
+    use futures_io::{AsyncRead, AsyncWrite};
+    use std::future::{poll_fn, Future};
+    use std::io::{Read, Write};
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+
+    /// Polls a future to completion; everything here is in memory, so it never actually has to wait
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = Box::pin(fut);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+                return out;
+            }
+        }
+    }
+
+    fn async_compress(input: &[u8]) -> Vec<u8> {
+        block_on(async {
+            let mut writer = AsyncHuffmanWriter::new(Vec::new());
+            for mut chunk in input.chunks(1000) {
+                while !chunk.is_empty() {
+                    let n = poll_fn(|cx| Pin::new(&mut writer).poll_write(cx, chunk))
+                        .await
+                        .unwrap();
+                    chunk = &chunk[n..];
+                }
+            }
+            poll_fn(|cx| Pin::new(&mut writer).poll_close(cx))
+                .await
+                .unwrap();
+            writer.into_inner()
+        })
+    }
+
+    fn async_decompress(compressed: &[u8]) -> Vec<u8> {
+        block_on(async {
+            let mut reader = AsyncHuffmanReader::new(compressed);
+            let mut decompressed = Vec::new();
+            let mut buf = [0; 100];
+            loop {
+                let n = poll_fn(|cx| Pin::new(&mut reader).poll_read(cx, &mut buf))
+                    .await
+                    .unwrap();
+                if n == 0 {
+                    return decompressed;
+                }
+                decompressed.extend_from_slice(&buf[..n]);
+            }
+        })
+    }
+
+    fn matches_sync(input: &[u8]) -> bool {
+        let mut writer = HuffmanWriter::new(Vec::new());
+        writer.write_all(input).unwrap();
+        let sync_compressed = writer.finish().unwrap();
+        let mut sync_decompressed = Vec::new();
+        HuffmanReader::new(sync_compressed.as_slice())
+            .read_to_end(&mut sync_decompressed)
+            .unwrap();
+        let compressed = async_compress(input);
+        compressed == sync_compressed && async_decompress(&compressed) == sync_decompressed
+    }
+
+    #[test]
+    fn async_round_trip_test() {
+        assert!(matches_sync(b""));
+        assert!(matches_sync(b"dagoth ur was a hotep"));
+        assert!(matches_sync(
+            "whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune"
+                .repeat(500)
+                .as_bytes()
+        ));
+    }
+}
@@ -0,0 +1,161 @@
+//! The state machines behind the async streaming writer and reader, shared between `async_futures` and `async_tokio`
+//!
+//! The two runtimes' IO traits only differ in their names and how reads hand back bytes, so everything that actually
+//! drives the `Encoder`/`Decoder` lives here, and the trait impls just poll the wrapped writer or reader for it.
+
+use crate::stream::{Decoder, Encoder};
+use std::io;
+use std::task::{ready, Context, Poll};
+
+/// The most input that gets encoded per `poll_write()`, which bounds how much compressed output can pile up
+const MAX_WRITE: usize = 64 * 1024;
+
+/// Everything an async writer keeps besides the writer it wraps
+pub(crate) struct WriteState {
+    /// The encoder doing the actual work
+    encoder: Encoder,
+    /// Compressed bytes waiting to be written out
+    buf: Vec<u8>,
+    /// How much of `buf` has been written out
+    pos: usize,
+    /// Whether the end of the stream has been encoded
+    finished: bool,
+}
+
+impl WriteState {
+    pub(crate) fn new() -> Self {
+        WriteState {
+            encoder: Encoder::new(),
+            buf: Vec::new(),
+            pos: 0,
+            finished: false,
+        }
+    }
+
+    /// Encodes as much of `data` as it takes on, once the last write's output is out of the way
+    ///
+    /// ## Arguments
+    ///
+    /// * `cx`: the task's context
+    /// * `data`: the bytes to compress
+    /// * `write`: polls the wrapped writer to write some bytes
+    pub(crate) fn poll_write(
+        &mut self,
+        cx: &mut Context<'_>,
+        data: &[u8],
+        write: impl FnMut(&mut Context<'_>, &[u8]) -> Poll<io::Result<usize>>,
+    ) -> Poll<io::Result<usize>> {
+        if self.finished {
+            return Poll::Ready(Err(io::Error::other("write after the end of the stream")));
+        }
+        ready!(self.poll_drain(cx, write))?;
+        let data = &data[..data.len().min(MAX_WRITE)];
+        self.encoder.encode(data, &mut self.buf);
+        Poll::Ready(Ok(data.len()))
+    }
+
+    /// Makes everything so far decodable and writes it all out (the wrapped writer still needs flushing after)
+    ///
+    /// ## Arguments
+    ///
+    /// * `cx`: the task's context
+    /// * `write`: polls the wrapped writer to write some bytes
+    pub(crate) fn poll_flush(
+        &mut self,
+        cx: &mut Context<'_>,
+        write: impl FnMut(&mut Context<'_>, &[u8]) -> Poll<io::Result<usize>>,
+    ) -> Poll<io::Result<()>> {
+        // this is a no-op if a previous poll already did it
+        self.encoder.flush(&mut self.buf);
+        self.poll_drain(cx, write)
+    }
+
+    /// Encodes the end of the stream and writes it all out (the wrapped writer still needs closing after)
+    ///
+    /// ## Arguments
+    ///
+    /// * `cx`: the task's context
+    /// * `write`: polls the wrapped writer to write some bytes
+    pub(crate) fn poll_finish(
+        &mut self,
+        cx: &mut Context<'_>,
+        write: impl FnMut(&mut Context<'_>, &[u8]) -> Poll<io::Result<usize>>,
+    ) -> Poll<io::Result<()>> {
+        if !self.finished {
+            self.encoder.finish(&mut self.buf);
+            self.finished = true;
+        }
+        self.poll_drain(cx, write)
+    }
+
+    /// Writes out whatever compressed bytes are waiting
+    fn poll_drain(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut write: impl FnMut(&mut Context<'_>, &[u8]) -> Poll<io::Result<usize>>,
+    ) -> Poll<io::Result<()>> {
+        while self.pos < self.buf.len() {
+            let n = ready!(write(cx, &self.buf[self.pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.pos += n;
+        }
+        self.buf.clear();
+        self.pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Everything an async reader keeps besides the reader it wraps
+pub(crate) struct ReadState {
+    /// The decoder doing the actual work
+    decoder: Decoder,
+    /// Decoded bytes that haven't been read out yet
+    out_buf: Vec<u8>,
+    /// How much of `out_buf` has been read out
+    out_pos: usize,
+}
+
+impl ReadState {
+    pub(crate) fn new() -> Self {
+        ReadState {
+            decoder: Decoder::new(),
+            out_buf: Vec::new(),
+            out_pos: 0,
+        }
+    }
+
+    /// Whether everything decoded so far has been read out, but the stream hasn't ended yet
+    pub(crate) fn wants_input(&self) -> bool {
+        self.out_pos == self.out_buf.len() && !self.decoder.is_done()
+    }
+
+    /// Decodes what's in the wrapped reader's buffer, giving back how much of it got used
+    ///
+    /// ## Arguments
+    ///
+    /// * `in_buf`: the wrapped reader's buffer, which is only empty if the reader's out of bytes
+    pub(crate) fn decode(&mut self, in_buf: &[u8]) -> io::Result<usize> {
+        if in_buf.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "compressed stream ended without an end-of-stream marker",
+            ));
+        }
+        self.out_buf.clear();
+        self.out_pos = 0;
+        Ok(self.decoder.decode(in_buf, &mut self.out_buf)?)
+    }
+
+    /// Takes up to `max` decoded bytes to be read out
+    ///
+    /// ## Arguments
+    ///
+    /// * `max`: the most bytes the caller has room for
+    pub(crate) fn take(&mut self, max: usize) -> &[u8] {
+        let n = max.min(self.out_buf.len() - self.out_pos);
+        self.out_pos += n;
+        &self.out_buf[self.out_pos - n..self.out_pos]
+    }
+}
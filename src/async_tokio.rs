@@ -0,0 +1,194 @@
+//! Async versions of the streaming writer and reader, for tokio
+//!
+//! These drive the same IO-free `Encoder`/`Decoder` as `HuffmanWriter`/`HuffmanReader`, so the streams they make
+//! and read are exactly the same; only the waiting around is different.
+
+use crate::async_state::{ReadState, WriteState};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
+
+/// Compresses everything written to it in a single pass, writing the compressed stream to the wrapped async writer
+///
+/// The stream is finished off (end-of-stream marker and last partial byte) by `shutdown()`.
+pub struct AsyncHuffmanWriter<W: AsyncWrite + Unpin> {
+    /// Where the compressed bytes go
+    inner: W,
+    /// The encoder and the compressed bytes waiting to be written out
+    state: WriteState,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncHuffmanWriter<W> {
+    /// Creates a new async streaming compressor
+    ///
+    /// ## Arguments
+    ///
+    /// * `inner`: the writer the compressed stream goes to
+    pub fn new(inner: W) -> Self {
+        AsyncHuffmanWriter {
+            inner,
+            state: WriteState::new(),
+        }
+    }
+
+    /// Gets a shared ref to the wrapped writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Hands back the wrapped writer (which won't have the end of the stream unless this has been shut down)
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncHuffmanWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let inner = &mut this.inner;
+        this.state.poll_write(cx, data, |cx, buf| {
+            Pin::new(&mut *inner).poll_write(cx, buf)
+        })
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let inner = &mut this.inner;
+        ready!(this
+            .state
+            .poll_flush(cx, |cx, buf| Pin::new(&mut *inner).poll_write(cx, buf)))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let inner = &mut this.inner;
+        ready!(this
+            .state
+            .poll_finish(cx, |cx, buf| Pin::new(&mut *inner).poll_write(cx, buf)))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Decompresses a stream made by `AsyncHuffmanWriter` (or `HuffmanWriter`) as it's read
+///
+/// Like `HuffmanReader`, this decodes straight out of the wrapped reader's buffer and leaves anything after the end
+/// of the stream alone; plain `AsyncRead`ers can be wrapped in a `tokio::io::BufReader` first.
+pub struct AsyncHuffmanReader<R: AsyncBufRead + Unpin> {
+    /// Where the compressed bytes come from
+    inner: R,
+    /// The decoder and the decoded bytes that haven't been read out yet
+    state: ReadState,
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncHuffmanReader<R> {
+    /// Creates a new async streaming decompressor
+    ///
+    /// ## Arguments
+    ///
+    /// * `inner`: the reader the compressed stream comes from
+    pub fn new(inner: R) -> Self {
+        AsyncHuffmanReader {
+            inner,
+            state: ReadState::new(),
+        }
+    }
+
+    /// Gets a shared ref to the wrapped reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Hands back the wrapped reader, positioned just past the end of the stream if it's been read to the end
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncRead for AsyncHuffmanReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.state.wants_input() {
+            let in_buf = ready!(Pin::new(&mut this.inner).poll_fill_buf(cx))?;
+            let used = this.state.decode(in_buf)?;
+            Pin::new(&mut this.inner).consume(used);
+        }
+        buf.put_slice(this.state.take(buf.remaining()));
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AsyncHuffmanReader, AsyncHuffmanWriter};
+    use crate::stream::{HuffmanReader, HuffmanWriter};
+    use std::future::Future;
+    use std::io::{Read, Write};
+    use std::task::{Context, Poll, Waker};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Polls a future to completion; everything here is in memory, so it never actually has to wait
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = Box::pin(fut);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+                return out;
+            }
+        }
+    }
+
+    fn async_compress(input: &[u8]) -> Vec<u8> {
+        block_on(async {
+            let mut writer = AsyncHuffmanWriter::new(Vec::new());
+            for chunk in input.chunks(1000) {
+                writer.write_all(chunk).await.unwrap();
+            }
+            writer.shutdown().await.unwrap();
+            writer.into_inner()
+        })
+    }
+
+    fn async_decompress(compressed: &[u8]) -> Vec<u8> {
+        block_on(async {
+            let mut decompressed = Vec::new();
+            AsyncHuffmanReader::new(compressed)
+                .read_to_end(&mut decompressed)
+                .await
+                .unwrap();
+            decompressed
+        })
+    }
+
+    fn matches_sync(input: &[u8]) -> bool {
+        let mut writer = HuffmanWriter::new(Vec::new());
+        writer.write_all(input).unwrap();
+        let sync_compressed = writer.finish().unwrap();
+        let mut sync_decompressed = Vec::new();
+        HuffmanReader::new(sync_compressed.as_slice())
+            .read_to_end(&mut sync_decompressed)
+            .unwrap();
+        let compressed = async_compress(input);
+        compressed == sync_compressed && async_decompress(&compressed) == sync_decompressed
+    }
+
+    #[test]
+    fn async_round_trip_test() {
+        assert!(matches_sync(b""));
+        assert!(matches_sync(b"dagoth ur was a hotep"));
+        assert!(matches_sync(
+            "whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune"
+                .repeat(500)
+                .as_bytes()
+        ));
+    }
+}
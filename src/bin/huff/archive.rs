@@ -0,0 +1,582 @@
+//! Archives of several files: `compress -r`/`--to-archive`, `list` and `extract`
+
+use crate::args::Args;
+use crate::compress::encode_options;
+use crate::files::{check_overwrite, load_dictionary, open_output, LazyFile};
+use crate::glob::glob_match;
+use crate::json::Json;
+use crate::verify::verify_written;
+use crate::{Failure, ARCHIVE_SUFFIX, STDIO};
+use huffman::container::{EntryName, FileMetadata};
+use huffman::{container, DecodeOptions, Error};
+use std::fmt::Write as _;
+use std::fs;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// `huff compress --to-archive`, which bundles the files given into one archive
+///
+/// Each file gets named in the archive by its path as given (with `/` between the parts), so paths outside the
+/// current directory get refused, the same as `extract` would refuse them.
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+/// * `files`: the files
+pub(crate) fn compress_to_archive(args: &Args, files: &[String]) -> Result<(), Failure> {
+    let dst = args.value("output").ok_or_else(|| {
+        Failure::Usage("--to-archive needs an --output to name the archive".to_string())
+    })?;
+    if files.is_empty() || files.iter().any(|src| src == STDIO) {
+        return Err(Failure::Usage(
+            "--to-archive needs files (not stdin) to archive".to_string(),
+        ));
+    }
+    let mut named = Vec::with_capacity(files.len());
+    for src in files {
+        // without an index there are no names to check
+        if args.has("no-index") {
+            named.push((src.clone(), PathBuf::from(src)));
+            continue;
+        }
+        // `./a.log` is just `a.log`
+        let relative: Vec<&str> = src.split('/').filter(|part| *part != ".").collect();
+        let name = entry_path(&relative.join("/"))?
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if named.iter().any(|(other, _)| *other == name) {
+            return Err(Failure::Usage(format!("{} is in the archive twice", name)));
+        }
+        named.push((name, PathBuf::from(src)));
+    }
+    check_overwrite(dst, true, args)?;
+    write_archive(args, dst, named)
+}
+
+/// `huff compress -r`, which archives a whole directory
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn compress_dir(args: &Args) -> Result<(), Failure> {
+    let src = match args.input()? {
+        STDIO => return Err(Failure::Usage("--recursive needs a directory".to_string())),
+        src => src,
+    };
+    if !Path::new(src).is_dir() {
+        return Err(Failure::Run(format!("{} isn't a directory", src)));
+    }
+    let dst = match args.value("output") {
+        Some(output) => output.to_string(),
+        None => format!("{}{}", src.trim_end_matches('/'), ARCHIVE_SUFFIX),
+    };
+    check_overwrite(&dst, true, args)?;
+    let mut files = walk(Path::new(src), &args.values("exclude"))
+        .map_err(|err| Failure::on(src, err.into()))?;
+    // an archive being written inside the directory it's archiving shouldn't end up in itself
+    files.retain(|(_, path)| path != Path::new(&dst));
+    write_archive(args, &dst, files)
+}
+
+/// Compresses files into an archive, keeping their metadata too if `--preserve` says to (or just one after another,
+/// with no index, if `--no-index` does)
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+/// * `dst`: where the archive goes (`-` for stdout)
+/// * `files`: each file's name in the archive, and where it is
+fn write_archive(args: &Args, dst: &str, files: Vec<(String, PathBuf)>) -> Result<(), Failure> {
+    let options = encode_options(args)?;
+    if args.has("preserve") && args.has("no-index") {
+        return Err(Failure::Usage(
+            "--preserve keeps the metadata in the index, so it can't go with --no-index"
+                .to_string(),
+        ));
+    }
+    let count = files.len();
+    let verify = args.has("verify-on-write");
+    if verify && dst == STDIO {
+        return Err(Failure::Usage(
+            "--verify-on-write reads the archive back, so it can't go to stdout".to_string(),
+        ));
+    }
+    let paths: Vec<PathBuf> = match verify {
+        true => files.iter().map(|(_, path)| path.clone()).collect(),
+        false => Vec::new(),
+    };
+    let output = open_output(dst)?;
+    let result = if args.has("no-index") {
+        let inputs = files.into_iter().map(|(_, path)| LazyFile::new(path));
+        container::compress_concatenated(inputs, output, &options)
+    } else if args.has("preserve") {
+        let mut inputs = Vec::with_capacity(files.len());
+        for (name, path) in files {
+            let metadata = file_metadata(&path)
+                .map_err(|err| Failure::on(&path.to_string_lossy(), err.into()))?;
+            inputs.push((name, metadata, LazyFile::new(path)));
+        }
+        container::compress_archive_with_metadata(inputs, output, &options)
+    } else {
+        let inputs = files
+            .into_iter()
+            .map(|(name, path)| (name, LazyFile::new(path)));
+        container::compress_archive(inputs, output, &options)
+    };
+    if let Err(err) = result.and_then(|mut output| Ok(output.flush()?)) {
+        // a half-written archive is no use to anybody
+        if dst != STDIO {
+            let _ = fs::remove_file(dst);
+        }
+        return Err(Failure::on(dst, err));
+    }
+    if verify {
+        // the index decodes to nothing, so the whole archive decompresses to every file, one after another
+        verify_written(dst, &options, paths.into_iter().map(LazyFile::new))?;
+    }
+    if args.has("json") && dst != STDIO {
+        let compressed = fs::metadata(dst).ok().map(|metadata| metadata.len());
+        let json = Json::Object(vec![
+            ("archive", dst.into()),
+            ("files", count.into()),
+            ("compressed", compressed.into()),
+        ]);
+        println!("{}", json);
+    }
+    Ok(())
+}
+
+/// Finds every file under a directory, with its name in the archive (its `/`-separated path relative to the
+/// directory), sorted by name
+///
+/// Symlinks get skipped, and so does anything `--exclude` matches, directories included.
+///
+/// ## Arguments
+///
+/// * `root`: the directory
+/// * `excludes`: the `--exclude` patterns
+pub(crate) fn walk(root: &Path, excludes: &[&str]) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![(String::new(), root.to_path_buf())];
+    while let Some((prefix, dir)) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let file_name = file_name.to_str().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} isn't valid UTF-8", entry.path().display()),
+                )
+            })?;
+            let name = format!("{}{}", prefix, file_name);
+            let excluded = excludes.iter().any(|pattern| match pattern.contains('/') {
+                true => glob_match(pattern, &name),
+                false => glob_match(pattern, file_name),
+            });
+            let kind = entry.file_type()?;
+            if excluded || kind.is_symlink() {
+                continue;
+            } else if kind.is_dir() {
+                dirs.push((format!("{}/", name), entry.path()));
+            } else if kind.is_file() {
+                files.push((name, entry.path()));
+            }
+        }
+    }
+    files.sort_unstable();
+    Ok(files)
+}
+
+/// Gets the parts of a file's metadata an archive can keep
+///
+/// ## Arguments
+///
+/// * `path`: where the file is
+fn file_metadata(path: &Path) -> io::Result<FileMetadata> {
+    let metadata = fs::metadata(path)?;
+    #[cfg(unix)]
+    let mode = std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o7777;
+    #[cfg(not(unix))]
+    let mode = if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    };
+    let mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    Ok(FileMetadata { mode, mtime })
+}
+
+/// Puts back the permissions and modification time an archive kept for a file
+///
+/// ## Arguments
+///
+/// * `path`: where the file got extracted to
+/// * `metadata`: what the archive kept
+fn apply_metadata(path: &str, metadata: FileMetadata) -> io::Result<()> {
+    // the time first, since the permissions might not let the file be opened for writing afterwards
+    File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(UNIX_EPOCH + Duration::from_secs(metadata.mtime))?;
+    let mut permissions = fs::metadata(path)?.permissions();
+    #[cfg(unix)]
+    std::os::unix::fs::PermissionsExt::set_mode(&mut permissions, metadata.mode);
+    #[cfg(not(unix))]
+    permissions.set_readonly(metadata.mode & 0o200 == 0);
+    fs::set_permissions(path, permissions)
+}
+
+/// `huff list`
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn list(args: &Args) -> Result<(), Failure> {
+    let src = match args.input()? {
+        STDIO => {
+            return Err(Failure::Usage(
+                "list needs a file, since the index is at the end".to_string(),
+            ))
+        }
+        src => src,
+    };
+    let mut input = File::open(src).map_err(|err| Failure::on(src, err.into()))?;
+    let entries: Vec<_> = match container::read_archive(&mut input) {
+        Ok(entries) => entries
+            .into_iter()
+            .map(|(info, entry)| (info, Some(entry)))
+            .collect(),
+        // a file from `compress_many()` has an index, just without any names in it
+        Err(Error::Unsupported(_)) => container::read_index(&mut input)
+            .map_err(|err| Failure::on(src, err))?
+            .into_iter()
+            .map(|info| (info, None))
+            .collect(),
+        Err(err) => return Err(Failure::on(src, err)),
+    };
+    if args.has("json") {
+        println!("{}", entries_json(&entries));
+    } else {
+        print!("{}", describe_entries(&entries));
+    }
+    Ok(())
+}
+
+/// `huff extract`
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn extract(args: &Args) -> Result<(), Failure> {
+    let (src, names) = match args.positional.split_first() {
+        Some((src, _)) if src == STDIO => {
+            return Err(Failure::Usage(
+                "extract needs a file, since the index is at the end".to_string(),
+            ))
+        }
+        Some((src, names)) => (src.as_str(), names),
+        None => return Err(Failure::Usage("no archive given".to_string())),
+    };
+    let mut input = BufReader::new(File::open(src).map_err(|err| Failure::on(src, err.into()))?);
+    let entries = container::read_archive(&mut input).map_err(|err| Failure::on(src, err))?;
+    // everything, if nothing in particular was asked for
+    let wanted: Vec<&str> = match names {
+        [] => entries
+            .iter()
+            .map(|(_, entry)| entry.name.as_str())
+            .collect(),
+        names => names.iter().map(String::as_str).collect(),
+    };
+    // checked up front, so a typo in the last name doesn't leave all the others extracted
+    if let Some(missing) = wanted
+        .iter()
+        .find(|&&name| entries.iter().all(|(_, entry)| entry.name != name))
+    {
+        return Err(Failure::on(src, Error::NoSuchEntry(missing.to_string())));
+    }
+    if args.value("output").is_some() && wanted.len() != 1 {
+        return Err(Failure::Usage(
+            "--output only works when extracting one file".to_string(),
+        ));
+    }
+    let options = DecodeOptions {
+        dictionary: load_dictionary(args)?,
+        ..DecodeOptions::default()
+    };
+    let mut extracted = Vec::with_capacity(wanted.len());
+    for name in wanted {
+        let dst = match args.value("output") {
+            Some(output) => output.to_string(),
+            None => entry_path(name)?.to_string_lossy().into_owned(),
+        };
+        check_overwrite(&dst, false, args)?;
+        if let Some(parent) = Path::new(&dst).parent().filter(|_| dst != STDIO) {
+            fs::create_dir_all(parent).map_err(|err| Failure::on(&dst, err.into()))?;
+        }
+        let output = open_output(&dst)?;
+        let result = container::extract_named(&mut input, name, output, &options)
+            .and_then(|mut output| Ok(output.flush()?));
+        if let Err(err) = result {
+            // a half-extracted file is no use to anybody
+            if dst != STDIO {
+                let _ = fs::remove_file(&dst);
+            }
+            return Err(Failure::on(src, err));
+        }
+        let metadata = entries
+            .iter()
+            .find(|(_, entry)| entry.name == name)
+            .and_then(|(_, entry)| entry.metadata);
+        if let Some(metadata) = metadata.filter(|_| dst != STDIO) {
+            apply_metadata(&dst, metadata).map_err(|err| Failure::on(&dst, err.into()))?;
+        }
+        if dst != STDIO {
+            let len = fs::metadata(&dst).ok().map(|metadata| metadata.len());
+            extracted.push(Json::Object(vec![
+                ("name", name.into()),
+                ("output", dst.into()),
+                ("bytes", len.into()),
+            ]));
+        }
+    }
+    if args.has("json") && args.value("output") != Some(STDIO) {
+        println!("{}", Json::List(extracted));
+    }
+    Ok(())
+}
+
+/// Turns the name of a file in an archive into where it gets extracted to, relative to the current directory
+///
+/// Names that would end up anywhere else (absolute paths, or paths with `..` in them) get refused.
+///
+/// ## Arguments
+///
+/// * `name`: the name in the archive
+fn entry_path(name: &str) -> Result<PathBuf, Failure> {
+    let path: PathBuf = name.split('/').filter(|part| !part.is_empty()).collect();
+    let inside = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if !inside || path.as_os_str().is_empty() || name.starts_with('/') {
+        return Err(Failure::Run(format!(
+            "refusing to extract {:?}, which isn't a relative path inside the current directory",
+            name
+        )));
+    }
+    Ok(path)
+}
+
+/// Lays out a table of what's in an archive, a line per input, like `tar -tv` does
+///
+/// ## Arguments
+///
+/// * `entries`: where each input is, and its name and checksum if the archive has them
+fn describe_entries(entries: &[(huffman::BlockInfo, Option<EntryName>)]) -> String {
+    let mut out = format!(
+        "{:>12} {:>12} {:>7}  {:<8}  name\n",
+        "original", "stored", "ratio", "crc32"
+    );
+    for (k, (info, entry)) in entries.iter().enumerate() {
+        let ratio = match info.original_len {
+            0 => "-".to_string(),
+            len => format!("{:.1}%", info.len as f64 / len as f64 * 100.0),
+        };
+        let (crc, name) = match entry {
+            Some(entry) => (format!("{:08x}", entry.crc), entry.name.clone()),
+            None => ("-".to_string(), format!("#{}", k)),
+        };
+        writeln!(
+            out,
+            "{:>12} {:>12} {:>7}  {:<8}  {}",
+            info.original_len, info.len, ratio, crc, name
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Puts what's in an archive into JSON, like `describe_entries()` lays it out as a table
+///
+/// ## Arguments
+///
+/// * `entries`: where each input is, and its name and checksum (and maybe metadata) if the archive has them
+pub(crate) fn entries_json(entries: &[(huffman::BlockInfo, Option<EntryName>)]) -> Json {
+    let entries = entries
+        .iter()
+        .map(|(info, entry)| {
+            let metadata = entry.as_ref().and_then(|entry| entry.metadata);
+            Json::Object(vec![
+                (
+                    "name",
+                    entry.as_ref().map(|entry| entry.name.as_str()).into(),
+                ),
+                ("original", info.original_len.into()),
+                ("stored", info.len.into()),
+                ("offset", info.offset.into()),
+                (
+                    "crc32",
+                    entry
+                        .as_ref()
+                        .map(|entry| format!("{:08x}", entry.crc))
+                        .into(),
+                ),
+                ("mode", metadata.map(|metadata| metadata.mode as u64).into()),
+                ("mtime", metadata.map(|metadata| metadata.mtime).into()),
+            ])
+        })
+        .collect();
+    Json::List(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{describe_entries, entry_path};
+    use crate::test::{args, scratch_dir};
+    use crate::{run, Failure};
+    use huffman::{container, EncodeOptions};
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn list_test() {
+        let inputs = vec![
+            ("dir/ur.txt".to_string(), &b"dagoth ur was a hotep"[..]),
+            ("empty".to_string(), &b""[..]),
+        ];
+        let archive =
+            container::compress_archive(inputs, Vec::new(), &EncodeOptions::default()).unwrap();
+        let entries: Vec<_> = container::read_archive(std::io::Cursor::new(&archive))
+            .unwrap()
+            .into_iter()
+            .map(|(info, entry)| (info, Some(entry)))
+            .collect();
+        let listed = describe_entries(&entries);
+        let lines: Vec<&str> = listed.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("          21 "));
+        assert!(lines[1].ends_with("  dir/ur.txt"));
+        assert!(lines[2].contains(" - "));
+        assert!(lines[2].ends_with("  empty"));
+        let unnamed = vec![(entries[0].0, None)];
+        assert!(describe_entries(&unnamed).ends_with("  #0\n"));
+    }
+
+    #[test]
+    fn extract_test() {
+        let dir = scratch_dir("extract");
+        let inputs = vec![
+            ("dir/ur.txt".to_string(), &b"dagoth ur was a hotep"[..]),
+            ("empty".to_string(), &b""[..]),
+        ];
+        let archive = dir.join("a.huffa");
+        let compressed =
+            container::compress_archive(inputs, Vec::new(), &EncodeOptions::default()).unwrap();
+        fs::write(&archive, compressed).unwrap();
+        let out = dir.join("out.txt");
+        let (archive, out) = (archive.to_str().unwrap(), out.to_str().unwrap());
+        run(&args(&format!("extract {} dir/ur.txt -o {}", archive, out))).unwrap();
+        assert_eq!(fs::read_to_string(out).unwrap(), "dagoth ur was a hotep");
+        assert!(matches!(
+            run(&args(&format!("extract {} dir/ur.txt nope", archive))),
+            Err(Failure::Run(_))
+        ));
+        assert!(matches!(
+            run(&args(&format!("extract {} -o {}", archive, out))),
+            Err(Failure::Usage(_))
+        ));
+        assert_eq!(
+            entry_path("dir//ur.txt").unwrap(),
+            PathBuf::from("dir/ur.txt")
+        );
+        assert!(entry_path("../ur.txt").is_err());
+        assert!(entry_path("/etc/passwd").is_err());
+        assert!(entry_path("").is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn recursive_test() {
+        let dir = scratch_dir("recursive");
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("a/deep")).unwrap();
+        fs::create_dir_all(src.join("target")).unwrap();
+        fs::write(src.join("a/deep/ur.txt"), "dagoth ur was a hotep").unwrap();
+        fs::write(src.join("a/debug.log"), "noise").unwrap();
+        fs::write(src.join("target/junk"), "more noise").unwrap();
+        fs::write(src.join("top.txt"), "").unwrap();
+        let archive = dir.join("out.huffa");
+        let (src, archive) = (src.to_str().unwrap(), archive.to_str().unwrap());
+        run(&args(&format!(
+            "compress -r {} -o {} --exclude *.log --exclude target --preserve",
+            src, archive
+        )))
+        .unwrap();
+        let entries = container::read_archive(fs::File::open(archive).unwrap()).unwrap();
+        let names: Vec<&str> = entries
+            .iter()
+            .map(|(_, entry)| entry.name.as_str())
+            .collect();
+        assert_eq!(names, ["a/deep/ur.txt", "top.txt"]);
+        let metadata = entries[0].1.metadata.unwrap();
+        assert!(metadata.mtime > 0);
+        // extracting puts the modification time back
+        let out = dir.join("ur.txt");
+        let out = out.to_str().unwrap();
+        run(&args(&format!(
+            "extract {} a/deep/ur.txt -o {}",
+            archive, out
+        )))
+        .unwrap();
+        assert_eq!(fs::read_to_string(out).unwrap(), "dagoth ur was a hotep");
+        let modified = fs::metadata(out).unwrap().modified().unwrap();
+        assert_eq!(
+            modified
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            metadata.mtime
+        );
+        // without --preserve, there's nothing kept
+        run(&args(&format!("compress -r -f {} -o {}", src, archive))).unwrap();
+        let entries = container::read_archive(fs::File::open(archive).unwrap()).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert!(entries[0].1.metadata.is_none());
+        assert!(matches!(
+            run(&args(&format!("compress --exclude *.log {}", src))),
+            Err(Failure::Usage(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn to_archive_test() {
+        let dir = scratch_dir("to-archive");
+        fs::write(dir.join("a.log"), "dagoth ur").unwrap();
+        fs::write(dir.join("b.log"), "was a hotep").unwrap();
+        let archive = dir.join("logs.huffa");
+        let archive = archive.to_str().unwrap();
+        // names come from the paths as given, so absolute ones can't go in
+        assert!(run(&args(&format!(
+            "compress --to-archive -o {} {}/a.log",
+            archive,
+            dir.to_str().unwrap()
+        )))
+        .is_err());
+        assert!(matches!(
+            run(&args("compress --to-archive a.log")),
+            Err(Failure::Usage(_))
+        ));
+        assert!(matches!(
+            run(&args("compress --to-archive -o x.huffa a.log ./a.log")),
+            Err(Failure::Usage(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
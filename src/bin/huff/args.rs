@@ -0,0 +1,171 @@
+//! Splitting up a command line into flags and files, checking the flags against the ones a command takes
+
+use crate::{Failure, STDIO};
+
+/// A flag the command line can have: its long name, its short name (if any), and whether it takes a value
+pub(crate) type Flag = (&'static str, Option<char>, bool);
+
+/// A command line, split up into flags and everything else
+#[derive(Debug, Default)]
+pub(crate) struct Args {
+    /// The flags that were given, by long name, with their values (if they take one)
+    pub(crate) flags: Vec<(&'static str, Option<String>)>,
+    /// Everything that isn't a flag, in order
+    pub(crate) positional: Vec<String>,
+}
+
+impl Args {
+    /// Splits up a command line, checking every flag against the ones that are allowed
+    ///
+    /// Long flags can be `--name value` or `--name=value`, short ones `-n value`, and `--` on its own means everything
+    /// after it is positional.
+    ///
+    /// ## Arguments
+    ///
+    /// * `args`: the command line (after the subcommand)
+    /// * `allowed`: the flags that are allowed
+    pub(crate) fn parse(args: &[String], allowed: &[Flag]) -> Result<Self, Failure> {
+        let mut parsed = Args::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline) = if arg == "--" {
+                parsed.positional.extend(args.by_ref().cloned());
+                break;
+            } else if let Some(long) = arg.strip_prefix("--") {
+                let (name, value) = match long.split_once('=') {
+                    Some((name, value)) => (name, Some(value.to_string())),
+                    None => (long, None),
+                };
+                let flag = allowed.iter().find(|flag| flag.0 == name);
+                (flag.ok_or_else(|| unknown(arg))?, value)
+            } else if arg.len() > 1 && arg.starts_with('-') {
+                let mut short = arg[1..].chars();
+                let flag = match (short.next(), short.next()) {
+                    (Some(ch), None) => allowed.iter().find(|flag| flag.1 == Some(ch)),
+                    _ => None,
+                };
+                (flag.ok_or_else(|| unknown(arg))?, None)
+            } else {
+                parsed.positional.push(arg.clone());
+                continue;
+            };
+            let value = match (flag.2, inline) {
+                (true, Some(value)) => Some(value),
+                (true, None) => Some(
+                    args.next()
+                        .cloned()
+                        .ok_or_else(|| Failure::Usage(format!("--{} needs a value", flag.0)))?,
+                ),
+                (false, None) => None,
+                (false, Some(_)) => {
+                    return Err(Failure::Usage(format!("--{} doesn't take a value", flag.0)))
+                }
+            };
+            parsed.flags.push((flag.0, value));
+        }
+        Ok(parsed)
+    }
+
+    /// Whether a flag was given
+    ///
+    /// ## Arguments
+    ///
+    /// * `name`: the flag's long name
+    pub(crate) fn has(&self, name: &str) -> bool {
+        self.flags.iter().any(|(flag, _)| *flag == name)
+    }
+
+    /// Gets a flag's value, if it was given (the last one wins if it was given more than once)
+    ///
+    /// ## Arguments
+    ///
+    /// * `name`: the flag's long name
+    pub(crate) fn value(&self, name: &str) -> Option<&str> {
+        self.flags
+            .iter()
+            .rev()
+            .find(|(flag, _)| *flag == name)
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    /// Gets every value a flag was given, in order
+    ///
+    /// ## Arguments
+    ///
+    /// * `name`: the flag's long name
+    pub(crate) fn values(&self, name: &str) -> Vec<&str> {
+        self.flags
+            .iter()
+            .filter(|(flag, _)| *flag == name)
+            .filter_map(|(_, value)| value.as_deref())
+            .collect()
+    }
+
+    /// Gets the one file a command reads from, which is stdin (`-`) if there's no file or it's `-`
+    pub(crate) fn input(&self) -> Result<&str, Failure> {
+        match &self.positional[..] {
+            [file] => Ok(file),
+            [] => Ok(STDIO),
+            _ => Err(Failure::Usage("too many files given".to_string())),
+        }
+    }
+}
+
+/// Makes the error for a flag that isn't allowed
+///
+/// ## Arguments
+///
+/// * `arg`: the flag
+pub(crate) fn unknown(arg: &str) -> Failure {
+    Failure::Usage(format!("unknown option {}", arg))
+}
+
+#[cfg(test)]
+mod test {
+    use super::Args;
+    use crate::test::args;
+    use crate::{Failure, COMPRESS_FLAGS, FILE_FLAGS};
+
+    #[test]
+    fn parse_test() {
+        let parsed = Args::parse(&args("-f in.txt --output=out.huf"), FILE_FLAGS).unwrap();
+        assert!(parsed.has("force"));
+        assert_eq!(parsed.value("output"), Some("out.huf"));
+        assert_eq!(parsed.positional, ["in.txt"]);
+        let parsed = Args::parse(&args("-o out.huf -- -f"), FILE_FLAGS).unwrap();
+        assert!(!parsed.has("force"));
+        assert_eq!(parsed.positional, ["-f"]);
+        assert!(matches!(
+            Args::parse(&args("--frobnicate"), FILE_FLAGS),
+            Err(Failure::Usage(_))
+        ));
+        assert!(matches!(
+            Args::parse(&args("in.txt -o"), FILE_FLAGS),
+            Err(Failure::Usage(_))
+        ));
+        // no file at all, or -, is stdin
+        assert_eq!(
+            Args::parse(&args("-f"), FILE_FLAGS)
+                .unwrap()
+                .input()
+                .unwrap(),
+            "-"
+        );
+        assert_eq!(
+            Args::parse(&args("-f -"), FILE_FLAGS)
+                .unwrap()
+                .input()
+                .unwrap(),
+            "-"
+        );
+        assert!(matches!(
+            Args::parse(&args("a b"), FILE_FLAGS).unwrap().input(),
+            Err(Failure::Usage(_))
+        ));
+        let parsed = Args::parse(
+            &args("-r --exclude *.log --exclude=target dir"),
+            COMPRESS_FLAGS,
+        );
+        assert_eq!(parsed.unwrap().values("exclude"), ["*.log", "target"]);
+    }
+}
@@ -0,0 +1,317 @@
+//! `huff bits`, which shows a payload bit by bit
+
+use crate::args::Args;
+use crate::files::load_dictionary;
+use crate::{Failure, STDIO};
+use huffman::btree::HuffTree;
+use huffman::container::{Header, Payload};
+use huffman::{canonical, Dictionary, Error};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+/// How many bytes of a payload `bits` shows on each line when it doesn't know the codes
+const BYTES_PER_ROW: usize = 8;
+
+/// `huff bits`
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn bits(args: &Args) -> Result<(), Failure> {
+    let src = args.input()?;
+    let dictionary = load_dictionary(args)?;
+    let output = BufWriter::new(io::stdout().lock());
+    let result = match src {
+        STDIO => write_bits(io::stdin().lock(), dictionary.as_deref(), output),
+        src => File::open(src)
+            .map_err(Error::from)
+            .and_then(|input| write_bits(BufReader::new(input), dictionary.as_deref(), output)),
+    };
+    match result {
+        Err(Error::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result.map_err(|err| Failure::verifying(src, err)),
+    }
+}
+
+/// Writes out every container's payload bit by bit, labeling each code with the byte it stands for wherever the codes
+/// are known
+///
+/// ## Arguments
+///
+/// * `input`: the compressed data
+/// * `dictionary`: the dictionary it was compressed with, if any
+/// * `output`: where it all goes
+fn write_bits<R: Read, W: Write>(
+    mut input: R,
+    dictionary: Option<&Dictionary>,
+    mut output: W,
+) -> huffman::Result<()> {
+    let mut k = 0;
+    loop {
+        // there's no telling a clean end from a truncated header without looking
+        let mut first = [0];
+        if input.read(&mut first)? == 0 {
+            break;
+        }
+        let header = Header::read_from(&mut first.chain(&mut input))?;
+        let (original_len, codes, payload_len) = match &header.payload {
+            Payload::Static {
+                original_len,
+                freqs,
+                payload_len,
+            } => {
+                writeln!(output, "container {}: static, {} symbols", k, freqs.len())?;
+                let mut tree = HuffTree::new();
+                tree.populate_tree(freqs.as_map());
+                (
+                    *original_len,
+                    Some(tree.generate_huffman_map()),
+                    *payload_len,
+                )
+            }
+            Payload::Canonical {
+                original_len,
+                lengths,
+                payload_len,
+            } => {
+                writeln!(
+                    output,
+                    "container {}: static, canonical codes for {} symbols",
+                    k,
+                    lengths.len()
+                )?;
+                let lengths: Vec<(char, u64)> = lengths
+                    .iter()
+                    .map(|&(sym, len)| (char::from(sym), len as u64))
+                    .collect();
+                (
+                    *original_len,
+                    Some(canonical::canonical_codes(&lengths)),
+                    *payload_len,
+                )
+            }
+            Payload::Dictionary {
+                original_len,
+                id,
+                payload_len,
+            } => {
+                let codes = match dictionary {
+                    Some(dict) if dict.id() == *id => {
+                        writeln!(
+                            output,
+                            "container {}: static, with dictionary {:08x}",
+                            k, id
+                        )?;
+                        let mut tree = HuffTree::new();
+                        tree.populate_tree(dict.freqs().as_map());
+                        Some(tree.generate_huffman_map())
+                    }
+                    _ => {
+                        writeln!(
+                            output,
+                            "container {}: static, with dictionary {:08x} (which --dict didn't give, so the codes \
+                             are unknown)",
+                            k, id
+                        )?;
+                        None
+                    }
+                };
+                (*original_len, codes, *payload_len)
+            }
+            Payload::Index { entries, .. } => {
+                writeln!(
+                    output,
+                    "container {}: index of {} inputs, with no payload",
+                    k,
+                    entries.len()
+                )?;
+                k += 1;
+                continue;
+            }
+            Payload::Streaming => {
+                writeln!(
+                    output,
+                    "container {}: streaming (adaptive), whose codes change as it goes, so there's no labeling them \
+                     (or telling where it ends without decoding it)",
+                    k
+                )?;
+                break;
+            }
+        };
+        let mut payload = Vec::new();
+        (&mut input).take(payload_len).read_to_end(&mut payload)?;
+        if (payload.len() as u64) < payload_len {
+            return Err(Error::Corrupt("payload is truncated"));
+        }
+        match codes {
+            Some(codes) => write_codes(&payload, original_len, &codes, &mut output)?,
+            None => {
+                for (row, bytes) in payload.chunks(BYTES_PER_ROW).enumerate() {
+                    let bits: Vec<String> =
+                        bytes.iter().map(|byte| format!("{:08b}", byte)).collect();
+                    writeln!(
+                        output,
+                        "    {:>10}  {}",
+                        row * BYTES_PER_ROW * 8,
+                        bits.join(" ")
+                    )?;
+                }
+            }
+        }
+        if header.checksum {
+            let mut crc = [0; 4];
+            input
+                .read_exact(&mut crc)
+                .map_err(|_| Error::Corrupt("checksum is missing"))?;
+            writeln!(output, "    checksum    {:08x}", u32::from_le_bytes(crc))?;
+        }
+        k += 1;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+/// Writes out a static payload one code per line, with the bit it starts at and the byte it stands for, then the
+/// padding after the last one
+///
+/// ## Arguments
+///
+/// * `payload`: the packed codes
+/// * `original_len`: how many codes there are
+/// * `codes`: the code for each byte
+/// * `output`: where it all goes
+fn write_codes<W: Write>(
+    payload: &[u8],
+    original_len: u64,
+    codes: &HashMap<char, String>,
+    output: &mut W,
+) -> huffman::Result<()> {
+    let label = |ch: char| match ch as u32 {
+        0..=0x7f => format!("{:?}", ch),
+        byte => format!("0x{:02x}", byte),
+    };
+    let symbols: HashMap<&str, char> = codes
+        .iter()
+        .map(|(&ch, code)| (code.as_str(), ch))
+        .collect();
+    let longest = codes.values().map(String::len).max().unwrap_or(0);
+    let width = longest.max(4);
+    // one symbol gets an empty code, so there's nothing to go through
+    if let Some(&ch) = symbols.get("") {
+        writeln!(
+            output,
+            "    {:>10}  {:<width$}  {} x {}",
+            0,
+            "",
+            label(ch),
+            original_len,
+            width = width
+        )?;
+    }
+    let (mut decoded, mut start) = (0, 0);
+    let mut code = String::new();
+    let bits = payload.iter().flat_map(|byte| {
+        (0..8)
+            .rev()
+            .map(move |bit| if byte >> bit & 1 == 1 { '1' } else { '0' })
+    });
+    for (pos, bit) in bits.enumerate() {
+        if code.is_empty() {
+            start = pos;
+        }
+        code.push(bit);
+        if decoded == original_len || symbols.contains_key("") {
+            continue;
+        }
+        if let Some(&ch) = symbols.get(code.as_str()) {
+            writeln!(
+                output,
+                "    {:>10}  {:<width$}  {}",
+                start,
+                code,
+                label(ch),
+                width = width
+            )?;
+            decoded += 1;
+            code.clear();
+        } else if code.len() >= longest {
+            return Err(Error::Corrupt("bits don't match any code"));
+        }
+    }
+    if decoded < original_len && !symbols.contains_key("") {
+        return Err(Error::Corrupt("payload is truncated"));
+    }
+    if !code.is_empty() {
+        writeln!(
+            output,
+            "    {:>10}  {:<width$}  (padding)",
+            start,
+            code,
+            width = width
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::write_bits;
+    use huffman::container::compress_bytes;
+    use huffman::{Dictionary, EncodeOptions};
+    use std::convert::TryInto;
+
+    #[test]
+    fn bits_test() {
+        let shown = |file: &[u8], dict: Option<&Dictionary>| {
+            let mut out = Vec::new();
+            write_bits(file, dict, &mut out).map(|_| String::from_utf8(out).unwrap())
+        };
+        let file = compress_bytes(b"aabc", &EncodeOptions::default()).unwrap();
+        let crc = u32::from_le_bytes(file[file.len() - 4..].try_into().unwrap());
+        assert_eq!(
+            shown(&file, None).unwrap(),
+            format!(
+                "container 0: static, 3 symbols
+             0  0     'a'
+             1  0     'a'
+             2  10    'b'
+             4  11    'c'
+             6  00    (padding)
+    checksum    {:08x}
+",
+                crc
+            )
+        );
+        let canonical = EncodeOptions {
+            canonical: true,
+            block_size: Some(3),
+            ..EncodeOptions::default()
+        };
+        let file = compress_bytes(b"aaa\xff", &canonical).unwrap();
+        let shown_canonical = shown(&file, None).unwrap();
+        assert!(shown_canonical.contains(
+            "container 0: static, canonical codes for 1 symbols\n             0        'a' x 3\n"
+        ));
+        assert!(shown_canonical.contains(
+            "container 1: static, canonical codes for 1 symbols\n             0        0xff x 1\n"
+        ));
+        // without the dictionary, there's only the bytes themselves to show
+        let dict = Dictionary::train(vec![&b"aaaab"[..]]).unwrap();
+        let with_dict = EncodeOptions {
+            dictionary: Some(std::sync::Arc::new(dict.clone())),
+            checksum: false,
+            ..EncodeOptions::default()
+        };
+        let file = compress_bytes(b"ab", &with_dict).unwrap();
+        assert!(shown(&file, Some(&dict)).unwrap().contains("  'b'\n"));
+        let unlabeled = shown(&file, None).unwrap();
+        assert!(unlabeled.contains("which --dict didn't give"));
+        assert!(unlabeled.contains("\n             0  "));
+        assert!(unlabeled.ends_with(&format!("{:08b}\n", file[file.len() - 1])));
+        assert!(matches!(
+            shown(&file[..file.len() - 1], Some(&dict)),
+            Err(huffman::Error::Corrupt(_))
+        ));
+    }
+}
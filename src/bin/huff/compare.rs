@@ -0,0 +1,148 @@
+//! `huff compare`, which sets huff's sizes next to the entropy and (with the `compare` feature) gzip's and zstd's
+
+use crate::args::Args;
+use crate::json::Json;
+use crate::{Failure, STDIO};
+use huffman::freq::FrequencyMap;
+use huffman::{container, EncodeOptions};
+use std::fmt::Write as _;
+use std::fs::File;
+#[cfg(feature = "compare")]
+use std::io::Write;
+use std::io::{self, Read};
+
+/// A general-purpose compressor `compare` sets huff next to: what to call it, and what runs it
+type Compared = (&'static str, fn(&[u8]) -> io::Result<Vec<u8>>);
+
+/// The general-purpose compressors `compare` tries, each as hard as it can go
+#[cfg(feature = "compare")]
+const COMPARED: &[Compared] = &[("gzip -9", gzip_best), ("zstd -19", zstd_best)];
+
+/// Without the `compare` feature, there's nothing to set huff next to
+#[cfg(not(feature = "compare"))]
+const COMPARED: &[Compared] = &[];
+
+/// `huff compare`
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn compare(args: &Args) -> Result<(), Failure> {
+    let src = args.input()?;
+    let mut data = Vec::new();
+    match src {
+        STDIO => io::stdin().lock().read_to_end(&mut data),
+        src => File::open(src).and_then(|mut input| input.read_to_end(&mut data)),
+    }
+    .map_err(|err| Failure::on(src, err.into()))?;
+    let freqs =
+        FrequencyMap::from_reader_bytes(&data[..]).map_err(|err| Failure::on(src, err.into()))?;
+    let streaming = EncodeOptions {
+        streaming: true,
+        ..EncodeOptions::default()
+    };
+    let huffman = |options: &EncodeOptions| {
+        container::compress_bytes(&data, options)
+            .map(|compressed| compressed.len() as u64)
+            .map_err(|err| Failure::on(src, err))
+    };
+    let mut sizes = vec![
+        (
+            "entropy (no header)",
+            (freqs.entropy() * data.len() as f64 / 8.0).ceil() as u64,
+        ),
+        ("huff", huffman(&EncodeOptions::default())?),
+        ("huff (streaming)", huffman(&streaming)?),
+    ];
+    for (method, compress) in COMPARED {
+        let compressed = compress(&data).map_err(|err| Failure::on(src, err.into()))?;
+        sizes.push((method, compressed.len() as u64));
+    }
+    if args.has("json") {
+        let methods = sizes
+            .into_iter()
+            .map(|(method, size)| {
+                Json::Object(vec![("method", method.into()), ("bytes", size.into())])
+            })
+            .collect();
+        let json = Json::Object(vec![
+            ("original", data.len().into()),
+            ("methods", Json::List(methods)),
+        ]);
+        println!("{}", json);
+    } else {
+        print!("{}", describe_comparison(data.len() as u64, &sizes));
+    }
+    Ok(())
+}
+
+/// Compresses some data with flate2's gzip at its best compression, for `compare`
+///
+/// ## Arguments
+///
+/// * `data`: the data
+#[cfg(feature = "compare")]
+fn gzip_best(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Compresses some data with zstd at level 19 (the highest that isn't `--ultra`), for `compare`
+///
+/// ## Arguments
+///
+/// * `data`: the data
+#[cfg(feature = "compare")]
+fn zstd_best(data: &[u8]) -> io::Result<Vec<u8>> {
+    zstd::encode_all(data, 19)
+}
+
+/// Lays out a table of how small some data gets each way, for `compare`
+///
+/// ## Arguments
+///
+/// * `original`: how big the data is
+/// * `sizes`: each way of compressing it, and how big that made it
+fn describe_comparison(original: u64, sizes: &[(&str, u64)]) -> String {
+    let mut out = format!("{:<22} {:>12} {:>7}\n", "method", "bytes", "ratio");
+    writeln!(out, "{:<22} {:>12} {:>7}", "original", original, "100.0%").unwrap();
+    for (method, size) in sizes {
+        let ratio = match original {
+            0 => "-".to_string(),
+            original => format!("{:.1}%", *size as f64 / original as f64 * 100.0),
+        };
+        writeln!(out, "{:<22} {:>12} {:>7}", method, size, ratio).unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::describe_comparison;
+    #[cfg(feature = "compare")]
+    use super::COMPARED;
+    use crate::run;
+    use crate::test::{args, scratch_dir};
+    use std::fs;
+
+    #[test]
+    fn compare_test() {
+        let table = describe_comparison(100, &[("huff", 60), ("zstd -19", 40)]);
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[1].ends_with("  100  100.0%"));
+        assert!(lines[2].starts_with("huff ") && lines[2].ends_with("   60   60.0%"));
+        assert!(lines[3].starts_with("zstd -19 ") && lines[3].ends_with("   40   40.0%"));
+        assert!(describe_comparison(0, &[("huff", 12)]).ends_with("   12       -\n"));
+        #[cfg(feature = "compare")]
+        for (_, compress) in COMPARED {
+            let text = "dagoth ur was a hotep".repeat(100);
+            assert!(compress(text.as_bytes()).unwrap().len() < text.len() / 10);
+        }
+        let dir = scratch_dir("compare");
+        let input = dir.join("in.txt");
+        fs::write(&input, "dagoth ur was a hotep".repeat(100)).unwrap();
+        run(&args(&format!("compare {}", input.to_str().unwrap()))).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
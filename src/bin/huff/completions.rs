@@ -0,0 +1,233 @@
+//! `huff completions`, which writes shell completion scripts
+
+use crate::args::{Args, Flag};
+use crate::{Failure, COMMANDS, GLOBAL_FLAGS};
+use std::fmt::Write as _;
+
+/// The shells `completions` can write a script for
+const SHELLS: &[&str] = &["bash", "zsh", "fish", "powershell"];
+
+/// `huff completions`
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn completions(args: &Args) -> Result<(), Failure> {
+    let shells = || SHELLS.join(", ");
+    match &args.positional[..] {
+        [shell] => match completion_script(shell) {
+            Some(script) => {
+                print!("{}", script);
+                Ok(())
+            }
+            None => Err(Failure::Usage(format!(
+                "can't write completions for {} (only {})",
+                shell,
+                shells()
+            ))),
+        },
+        _ => Err(Failure::Usage(format!(
+            "completions needs a shell ({})",
+            shells()
+        ))),
+    }
+}
+
+/// Writes a completion script for a shell, or gives back `None` for a shell it doesn't know
+///
+/// ## Arguments
+///
+/// * `shell`: the shell, one of `SHELLS`
+fn completion_script(shell: &str) -> Option<String> {
+    let commands: Vec<&str> = COMMANDS.iter().map(|(name, _, _)| *name).collect();
+    let commands = commands.join(" ");
+    // every spelling of every flag, the global ones included
+    let flags = |flags: &[Flag]| {
+        let mut words = Vec::new();
+        for (long, short, _) in flags.iter().chain(GLOBAL_FLAGS) {
+            words.push(format!("--{}", long));
+            words.extend(short.map(|short| format!("-{}", short)));
+        }
+        words
+    };
+    let mut out = String::new();
+    match shell {
+        "bash" => {
+            out.push_str("_huff() {\n");
+            out.push_str("    local cur=${COMP_WORDS[COMP_CWORD]} opts\n");
+            out.push_str("    if [ \"$COMP_CWORD\" -eq 1 ]; then\n");
+            writeln!(
+                out,
+                "        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+                commands
+            )
+            .unwrap();
+            out.push_str("        return\n");
+            out.push_str("    fi\n");
+            out.push_str("    case \"${COMP_WORDS[1]}\" in\n");
+            for (name, command_flags, _) in COMMANDS {
+                writeln!(
+                    out,
+                    "        {}) opts=\"{}\" ;;",
+                    name,
+                    flags(command_flags).join(" ")
+                )
+                .unwrap();
+            }
+            out.push_str("    esac\n");
+            out.push_str("    if [[ $cur == -* ]]; then\n");
+            out.push_str("        COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))\n");
+            out.push_str("    fi\n");
+            out.push_str("}\n");
+            out.push_str("complete -o default -F _huff huff\n");
+        }
+        "zsh" => {
+            out.push_str("#compdef huff\n");
+            out.push('\n');
+            out.push_str("_huff() {\n");
+            out.push_str("    if (( CURRENT == 2 )); then\n");
+            writeln!(out, "        compadd -- {}", commands).unwrap();
+            out.push_str("        return\n");
+            out.push_str("    fi\n");
+            out.push_str("    local -a opts\n");
+            out.push_str("    case $words[2] in\n");
+            for (name, command_flags, _) in COMMANDS {
+                writeln!(
+                    out,
+                    "        {}) opts=({}) ;;",
+                    name,
+                    flags(command_flags).join(" ")
+                )
+                .unwrap();
+            }
+            out.push_str("    esac\n");
+            out.push_str("    if [[ $PREFIX == -* ]]; then\n");
+            out.push_str("        compadd -- $opts\n");
+            out.push_str("    else\n");
+            out.push_str("        _files\n");
+            out.push_str("    fi\n");
+            out.push_str("}\n");
+            out.push('\n');
+            // loaded from $fpath, the file is the function; sourced, it has to hook itself up
+            out.push_str("if [ \"$funcstack[1]\" = \"_huff\" ]; then\n");
+            out.push_str("    _huff \"$@\"\n");
+            out.push_str("else\n");
+            out.push_str("    compdef _huff huff\n");
+            out.push_str("fi\n");
+        }
+        "fish" => {
+            writeln!(
+                out,
+                "complete -c huff -n __fish_use_subcommand -f -a \"{}\"",
+                commands
+            )
+            .unwrap();
+            for (name, command_flags, _) in COMMANDS {
+                for (long, short, value) in command_flags.iter().chain(GLOBAL_FLAGS) {
+                    write!(
+                        out,
+                        "complete -c huff -n \"__fish_seen_subcommand_from {}\" -l {}",
+                        name, long
+                    )
+                    .unwrap();
+                    if let Some(short) = short {
+                        write!(out, " -s {}", short).unwrap();
+                    }
+                    if *value {
+                        out.push_str(" -r");
+                    }
+                    out.push('\n');
+                }
+            }
+        }
+        "powershell" => {
+            out.push_str("Register-ArgumentCompleter -Native -CommandName huff -ScriptBlock {\n");
+            out.push_str("    param($wordToComplete, $commandAst, $cursorPosition)\n");
+            out.push_str(
+                "    $words = @($commandAst.CommandElements | ForEach-Object { $_.ToString() })\n",
+            );
+            let quoted = |words: Vec<String>| {
+                words
+                    .iter()
+                    .map(|word| format!("'{}'", word))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            writeln!(
+                out,
+                "    $commands = @({})",
+                quoted(commands.split(' ').map(str::to_string).collect())
+            )
+            .unwrap();
+            out.push_str("    $flags = @{\n");
+            for (name, command_flags, _) in COMMANDS {
+                writeln!(
+                    out,
+                    "        '{}' = @({})",
+                    name,
+                    quoted(flags(command_flags))
+                )
+                .unwrap();
+            }
+            out.push_str("    }\n");
+            out.push_str(
+                "    if ($words.Count -lt 2 -or ($words.Count -eq 2 -and $wordToComplete -ne '')) {\n",
+            );
+            out.push_str("        $candidates = $commands\n");
+            out.push_str("    } elseif ($wordToComplete -like '-*') {\n");
+            out.push_str("        $candidates = $flags[$words[1]]\n");
+            out.push_str("    } else {\n");
+            out.push_str("        return\n");
+            out.push_str("    }\n");
+            out.push_str(
+                "    $candidates | Where-Object { $_ -like \"$wordToComplete*\" } | ForEach-Object {\n",
+            );
+            out.push_str(
+                "        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)\n",
+            );
+            out.push_str("    }\n");
+            out.push_str("}\n");
+        }
+        _ => return None,
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{completion_script, SHELLS};
+    use crate::test::args;
+    use crate::{run, Failure, COMMANDS, GLOBAL_FLAGS};
+
+    #[test]
+    fn completions_test() {
+        for shell in SHELLS {
+            let script = completion_script(shell).unwrap();
+            for (name, flags, _) in COMMANDS {
+                assert!(script.contains(name));
+                assert!(flags.iter().all(|(long, _, _)| script.contains(long)));
+            }
+            // the global flags go with every command, so every command has to offer them
+            for (long, _, _) in GLOBAL_FLAGS {
+                let spelling = match *shell {
+                    "fish" => format!("-l {}", long),
+                    _ => format!("--{}", long),
+                };
+                assert_eq!(script.matches(&spelling).count(), COMMANDS.len());
+            }
+        }
+        let bash = completion_script("bash").unwrap();
+        assert!(bash.contains("        compress) opts=\"--output -o --force -f --dict "));
+        assert!(bash.ends_with("complete -o default -F _huff huff\n"));
+        let fish = completion_script("fish").unwrap();
+        assert!(fish.contains(
+            "complete -c huff -n \"__fish_seen_subcommand_from stats\" -l top -s n -r\n"
+        ));
+        assert_eq!(completion_script("tcsh"), None);
+        assert!(matches!(
+            run(&args("completions tcsh")),
+            Err(Failure::Usage(_))
+        ));
+        assert!(matches!(run(&args("completions")), Err(Failure::Usage(_))));
+    }
+}
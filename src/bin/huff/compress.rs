@@ -0,0 +1,864 @@
+//! `huff compress`, of one file, several, or just `--estimate` of how it'd go
+
+use crate::archive::{compress_dir, compress_to_archive, walk};
+use crate::args::Args;
+use crate::files::{
+    check_overwrite, check_suffix, load_dictionary, open_output, remove_source, suffix, LazyFile,
+};
+use crate::glob::expand_globs;
+use crate::json::Json;
+use crate::progress::{describe_speed, ProgressBar};
+use crate::verify::verify_written;
+use crate::{Failure, GZIP_SUFFIX, STDIO};
+use huffman::block::BlockWriter;
+use huffman::extensions::{Extensions, Key, Value};
+use huffman::gzip::GzipWriter;
+use huffman::pipeline::Pipeline;
+use huffman::{container, EncodeOptions, Error};
+use std::fmt::Write as _;
+use std::fs;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How much of stdin gets compressed at a time, since it can't be read twice like a file can
+const PIPE_BLOCK_SIZE: usize = 1024 * 1024;
+
+/// How much of a file each thread gets to compress at a time, when there's more than one
+const PARALLEL_BLOCK_SIZE: usize = 1024 * 1024;
+
+/// The `compress` flags that only make sense for `.huf` files, so don't go with `--gzip`
+const NOT_GZIP_FLAGS: &[&str] = &[
+    "recursive",
+    "exclude",
+    "to-archive",
+    "preserve",
+    "no-index",
+    "dict",
+    "canonical",
+    "pipeline",
+    "comment",
+    "estimate",
+    "verify-on-write",
+];
+
+/// `huff compress`
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn compress(args: &Args) -> Result<(), Failure> {
+    if args.has("gzip") {
+        return compress_gzip(args);
+    }
+    if args.has("estimate") {
+        return estimate(args);
+    }
+    if args.has("recursive") {
+        return compress_dir(args);
+    }
+    if args.has("exclude") {
+        return Err(Failure::Usage(
+            "--exclude only works with --recursive".to_string(),
+        ));
+    }
+    let files = expand_globs(&args.positional)?;
+    if args.has("to-archive") {
+        return compress_to_archive(args, &files);
+    }
+    for flag in ["preserve", "no-index"] {
+        if args.has(flag) {
+            return Err(Failure::Usage(format!(
+                "--{} only works with --recursive or --to-archive",
+                flag
+            )));
+        }
+    }
+    match &files[..] {
+        [] => compress_one(args, STDIO),
+        [src] => compress_one(args, src),
+        files => compress_each(args, files),
+    }
+}
+
+/// Compresses one file (or stdin) into one `.huf` file (or stdout)
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+/// * `src`: the file (`-` for stdin)
+fn compress_one(args: &Args, src: &str) -> Result<(), Failure> {
+    let suffix = suffix(args)?;
+    let dst = match (args.value("output"), src) {
+        (Some(output), _) => output.to_string(),
+        (None, STDIO) => STDIO.to_string(),
+        (None, src) => format!("{}{}", src, suffix),
+    };
+    check_suffix(src, suffix, args)?;
+    check_overwrite(&dst, true, args)?;
+    if args.has("verify-on-write") && (src == STDIO || dst == STDIO) {
+        return Err(Failure::Usage(
+            "--verify-on-write reads the input and output back, so neither can be stdin or stdout"
+                .to_string(),
+        ));
+    }
+    let threads = compress_threads(args)?;
+    let len = match src {
+        STDIO => None,
+        src => fs::metadata(src).ok().map(|metadata| metadata.len()),
+    };
+    let nanos = Arc::new(AtomicU64::new(0));
+    let options = EncodeOptions {
+        block_nanos: Some(nanos.clone()),
+        ..encode_options(args)?
+    };
+    let options = in_blocks(options, threads, len);
+    let bar = ProgressBar::start(args, src, &dst, len.map(|len| len * passes(&options)));
+    let options = EncodeOptions {
+        progress: bar.as_ref().map(ProgressBar::counter),
+        ..options
+    };
+    let started = Instant::now();
+    on_threads(threads, || {
+        let result = match (src, dst.as_str()) {
+            (STDIO, dst) => {
+                // stdin can't be read twice, so it gets compressed a block at a time as it comes in
+                let options = EncodeOptions {
+                    block_size: options.block_size.or(Some(PIPE_BLOCK_SIZE)),
+                    ..options.clone()
+                };
+                let mut writer = BlockWriter::new(open_output(dst)?, &options);
+                io::copy(&mut io::stdin().lock(), &mut writer)
+                    .and_then(|_| writer.finish()?.flush())
+                    .map_err(Error::from)
+            }
+            (src, STDIO) => File::open(src)
+                .map_err(Error::from)
+                .and_then(|input| {
+                    container::compress(BufReader::new(input), io::stdout().lock(), &options)
+                })
+                .and_then(|mut output| Ok(output.flush()?)),
+            (src, dst) => huffman::compress_file(src, dst, &options),
+        };
+        result.map_err(|err| Failure::on(src, err))
+    })?;
+    drop(bar);
+    if args.has("verify-on-write") {
+        verify_written(&dst, &options, vec![LazyFile::new(PathBuf::from(src))])?;
+    }
+    if let (true, Some(len)) = (args.has("verbose"), len) {
+        let busy = Duration::from_nanos(nanos.load(Ordering::Relaxed));
+        let threads = if options.block_size.is_some() {
+            threads
+        } else {
+            1
+        };
+        eprintln!(
+            "{}: {}",
+            src,
+            describe_speed(len, started.elapsed(), busy, threads)
+        );
+    }
+    if args.has("json") && dst != STDIO {
+        let compressed = fs::metadata(&dst).ok().map(|metadata| metadata.len());
+        let json = Json::Object(vec![
+            ("input", src.into()),
+            ("output", dst.as_str().into()),
+            ("original", len.into()),
+            ("compressed", compressed.into()),
+        ]);
+        println!("{}", json);
+    }
+    remove_source(src, &dst, args)
+}
+
+/// `huff compress --gzip`, which compresses each file (or stdin) into a `.gz` file (or stdout) for `gunzip`
+///
+/// `--block-size`, `--checksum` and `--threads` don't mean anything for gzip, and get ignored, since they can come
+/// from the config file.
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+fn compress_gzip(args: &Args) -> Result<(), Failure> {
+    if let Some(flag) = NOT_GZIP_FLAGS.iter().find(|flag| args.has(flag)) {
+        return Err(Failure::Usage(format!(
+            "--{} doesn't work with --gzip",
+            flag
+        )));
+    }
+    let mut files = expand_globs(&args.positional)?;
+    if files.is_empty() {
+        files.push(STDIO.to_string());
+    }
+    if files.len() > 1 && args.value("output").is_some() {
+        return Err(Failure::Usage(
+            "--output only works when compressing one file (or with --to-archive)".to_string(),
+        ));
+    }
+    let suffix = match args.value("suffix") {
+        Some(_) => suffix(args)?,
+        None => GZIP_SUFFIX,
+    };
+    for src in &files {
+        let dst = match (args.value("output"), src.as_str()) {
+            (Some(output), _) => output.to_string(),
+            (None, STDIO) => STDIO.to_string(),
+            (None, src) => format!("{}{}", src, suffix),
+        };
+        check_suffix(src, suffix, args)?;
+        check_overwrite(&dst, true, args)?;
+        let mut input: Box<dyn Read> = match src.as_str() {
+            STDIO => Box::new(io::stdin().lock()),
+            src => Box::new(File::open(src).map_err(|err| Failure::on(src, err.into()))?),
+        };
+        let mut writer = GzipWriter::new(open_output(&dst)?);
+        if let Err(err) = io::copy(&mut input, &mut writer).and_then(|_| writer.finish()?.flush()) {
+            if dst != STDIO {
+                let _ = fs::remove_file(&dst);
+            }
+            return Err(Failure::on(src, err.into()));
+        }
+        remove_source(src, &dst, args)?;
+    }
+    Ok(())
+}
+
+/// Works out how to compress from `--dict`, `--block-size`, `--canonical`, `--checksum` and `--pipeline`
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn encode_options(args: &Args) -> Result<EncodeOptions, Failure> {
+    let checksum = match args.value("checksum") {
+        None | Some("crc32") => true,
+        Some("none") => false,
+        Some(other) => {
+            return Err(Failure::Usage(format!(
+                "--checksum is crc32 or none, not {}",
+                other
+            )))
+        }
+    };
+    let block_size = match args.value("block-size") {
+        Some(size) => Some(parse_size(size).ok_or_else(|| {
+            Failure::Usage(format!("--block-size needs a size in bytes, not {}", size))
+        })?),
+        None => None,
+    };
+    // whichever of --canonical and --no-canonical comes last wins
+    let canonical = args
+        .flags
+        .iter()
+        .rev()
+        .find_map(|(flag, _)| match *flag {
+            "canonical" => Some(true),
+            "no-canonical" => Some(false),
+            _ => None,
+        })
+        .unwrap_or(false);
+    let pipeline = match args.value("pipeline") {
+        Some(stages) => stages.parse().map_err(|_| {
+            Failure::Usage(format!(
+                "--pipeline is a comma list of bwt, mtf, rle, delta8, delta16, delta32, golomb8, golomb16 and \
+                 golomb32 (or bzip2), not {}",
+                stages
+            ))
+        })?,
+        None => Pipeline::new(),
+    };
+    let mut extensions = Extensions::new();
+    if let Some(comment) = args.value("comment") {
+        extensions.insert(
+            Key::Standard(huffman::extensions::COMMENT),
+            Value::Text(comment.to_string()),
+        );
+    }
+    Ok(EncodeOptions {
+        checksum,
+        block_size,
+        canonical,
+        dictionary: load_dictionary(args)?,
+        extensions,
+        pipeline,
+        ..EncodeOptions::default()
+    })
+}
+
+/// Parses a size like `4096`, `64k` or `1m` (in powers of 1024), which has to be more than 0
+///
+/// ## Arguments
+///
+/// * `size`: the size
+fn parse_size(size: &str) -> Option<usize> {
+    let (digits, unit) = match size.char_indices().last()? {
+        (k, ch) if ch.is_ascii_alphabetic() => (&size[..k], ch.to_ascii_lowercase()),
+        _ => (size, 'b'),
+    };
+    let shift = match unit {
+        'b' => 0,
+        'k' => 10,
+        'm' => 20,
+        'g' => 30,
+        _ => return None,
+    };
+    let n: usize = digits.parse().ok()?;
+    n.checked_mul(1 << shift).filter(|&n| n > 0)
+}
+
+/// Gets how many threads to compress on: `--threads`, or one per core if it's not given (just the one, without the
+/// `rayon` feature)
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+#[cfg(feature = "rayon")]
+fn compress_threads(args: &Args) -> Result<usize, Failure> {
+    threads(args)
+}
+
+/// Gets how many threads to compress on: `--threads`, or one per core if it's not given (just the one, without the
+/// `rayon` feature)
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+#[cfg(not(feature = "rayon"))]
+fn compress_threads(args: &Args) -> Result<usize, Failure> {
+    match args.value("threads") {
+        None => Ok(1),
+        Some(_) => match threads(args)? {
+            1 => Ok(1),
+            _ => Err(Failure::Usage(
+                "compressing on more than one thread needs huff built with the rayon feature"
+                    .to_string(),
+            )),
+        },
+    }
+}
+
+/// Gets how many threads `--threads` asks for, which is one per core if it's not given
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn threads(args: &Args) -> Result<usize, Failure> {
+    match args.value("threads") {
+        Some(threads) => match threads.parse() {
+            Ok(0) | Err(_) => Err(Failure::Usage(format!(
+                "--threads needs a number of threads, not {}",
+                threads
+            ))),
+            Ok(threads) => Ok(threads),
+        },
+        None => Ok(thread::available_parallelism().map_or(1, |threads| threads.get())),
+    }
+}
+
+/// Switches compression over to block mode when there's more than one thread to spread a big enough input over (unless
+/// `--block-size` already has)
+///
+/// ## Arguments
+///
+/// * `options`: how to compress otherwise
+/// * `threads`: how many threads there are
+/// * `len`: how big the input is, if that's known
+fn in_blocks(options: EncodeOptions, threads: usize, len: Option<u64>) -> EncodeOptions {
+    match len {
+        _ if options.block_size.is_some() => options,
+        Some(len) if threads > 1 && len > PARALLEL_BLOCK_SIZE as u64 => EncodeOptions {
+            block_size: Some(PARALLEL_BLOCK_SIZE),
+            ..options
+        },
+        _ => options,
+    }
+}
+
+/// Does some work on a thread pool of its own, so block compression inside it spreads over that many threads
+///
+/// ## Arguments
+///
+/// * `threads`: how many threads
+/// * `work`: the work
+#[cfg(feature = "rayon")]
+fn on_threads<T, F>(threads: usize, work: F) -> Result<T, Failure>
+where
+    T: Send,
+    F: FnOnce() -> Result<T, Failure> + Send,
+{
+    if threads <= 1 {
+        return work();
+    }
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|err| Failure::Run(format!("couldn't start {} threads: {}", threads, err)))?
+        .install(work)
+}
+
+/// Does some work (on this thread, since without the `rayon` feature there's only ever the one)
+///
+/// ## Arguments
+///
+/// * `threads`: how many threads (always 1)
+/// * `work`: the work
+#[cfg(not(feature = "rayon"))]
+fn on_threads<T, F>(_threads: usize, work: F) -> Result<T, Failure>
+where
+    F: FnOnce() -> Result<T, Failure>,
+{
+    work()
+}
+
+/// How many times compressing reads through its input: twice (once to count, once to encode), unless it's in block
+/// mode or streaming
+///
+/// ## Arguments
+///
+/// * `options`: how it's compressing
+fn passes(options: &EncodeOptions) -> u64 {
+    match options.block_size.is_some() || options.streaming {
+        true => 1,
+        false => 2,
+    }
+}
+
+/// Compresses each of several files into its own `.huf` file next to it, like gzip does, then prints a table of
+/// how each one went
+///
+/// A file that can't be compressed doesn't stop the rest from being compressed, but it does make the whole command
+/// fail at the end.
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+/// * `files`: the files
+fn compress_each(args: &Args, files: &[String]) -> Result<(), Failure> {
+    if args.value("output").is_some() {
+        return Err(Failure::Usage(
+            "--output only works when compressing one file (or with --to-archive)".to_string(),
+        ));
+    }
+    if files.iter().any(|src| src == STDIO) {
+        return Err(Failure::Usage(
+            "stdin can't be compressed along with other files".to_string(),
+        ));
+    }
+    let options = encode_options(args)?;
+    let threads = compress_threads(args)?;
+    let suffix = suffix(args)?;
+    let mut results = Vec::with_capacity(files.len());
+    let (mut failed, mut mismatched) = (0, 0);
+    for src in files {
+        let dst = format!("{}{}", src, suffix);
+        let result = check_suffix(src, suffix, args)
+            .and_then(|_| check_overwrite(&dst, true, args))
+            .and_then(|_| {
+                let len = fs::metadata(src).ok().map(|metadata| metadata.len());
+                let options = in_blocks(options.clone(), threads, len);
+                let bar =
+                    ProgressBar::start(args, src, &dst, len.map(|len| len * passes(&options)));
+                let options = EncodeOptions {
+                    progress: bar.as_ref().map(ProgressBar::counter),
+                    ..options
+                };
+                on_threads(threads, || {
+                    huffman::compress_file(src, &dst, &options).map_err(|err| Failure::on(src, err))
+                })?;
+                drop(bar);
+                if args.has("verify-on-write") {
+                    verify_written(&dst, &options, vec![LazyFile::new(PathBuf::from(src))])?;
+                }
+                let len = |path: &str| fs::metadata(path).map(|metadata| metadata.len());
+                let lens = match (len(src), len(&dst)) {
+                    (Ok(original), Ok(compressed)) => (original, compressed),
+                    (Err(err), _) | (_, Err(err)) => return Err(Failure::on(src, err.into())),
+                };
+                remove_source(src, &dst, args)?;
+                Ok(lens)
+            });
+        match result {
+            Ok((original, compressed)) => results.push((src.as_str(), original, compressed)),
+            Err(failure) => {
+                eprintln!("huff: {}", failure.why());
+                failed += 1;
+                mismatched += matches!(failure, Failure::Mismatch(_)) as usize;
+            }
+        }
+    }
+    if args.has("json") {
+        println!("{}", results_json(&results, failed));
+    } else {
+        print!("{}", describe_results(&results));
+    }
+    match (failed, mismatched) {
+        (0, _) => Ok(()),
+        (failed, 0) => Err(Failure::Run(format!(
+            "{} of {} files couldn't be compressed",
+            failed,
+            files.len()
+        ))),
+        (_, mismatched) => Err(Failure::Mismatch(format!(
+            "{} of {} files didn't decompress back to what they were",
+            mismatched,
+            files.len()
+        ))),
+    }
+}
+
+/// `huff compress --estimate`, which works out how big each file (or everything under the directory, with `-r`) would
+/// compress to, without compressing anything
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn estimate(args: &Args) -> Result<(), Failure> {
+    let files = if args.has("recursive") {
+        let src = args.input()?;
+        walk(Path::new(src), &args.values("exclude"))
+            .map_err(|err| Failure::on(src, err.into()))?
+            .into_iter()
+            .map(|(_, path)| path.to_string_lossy().into_owned())
+            .collect()
+    } else {
+        match expand_globs(&args.positional)? {
+            files if files.is_empty() => vec![STDIO.to_string()],
+            files => files,
+        }
+    };
+    let options = encode_options(args)?;
+    let threads = compress_threads(args)?;
+    let mut results = Vec::with_capacity(files.len());
+    let mut failed = 0;
+    for src in &files {
+        let result = match src.as_str() {
+            // stdin gets compressed a block at a time
+            STDIO => {
+                let options = EncodeOptions {
+                    block_size: options.block_size.or(Some(PIPE_BLOCK_SIZE)),
+                    ..options.clone()
+                };
+                container::estimate(io::stdin().lock(), &options)
+            }
+            src => File::open(src).map_err(Error::from).and_then(|input| {
+                let len = input.metadata().ok().map(|metadata| metadata.len());
+                let options = in_blocks(options.clone(), threads, len);
+                container::estimate(BufReader::new(input), &options)
+            }),
+        };
+        match result {
+            Ok((original, compressed)) => results.push((src.as_str(), original, compressed)),
+            Err(err) => {
+                eprintln!("huff: {}", Failure::on(src, err).why());
+                failed += 1;
+            }
+        }
+    }
+    if args.has("json") {
+        println!("{}", results_json(&results, failed));
+    } else {
+        print!("{}", describe_results(&results));
+    }
+    match failed {
+        0 => Ok(()),
+        failed => Err(Failure::Run(format!(
+            "{} of {} files couldn't be read",
+            failed,
+            files.len()
+        ))),
+    }
+}
+
+/// Lays out a table of how compressing each of several files went, with a total at the bottom
+///
+/// ## Arguments
+///
+/// * `results`: each file's name, original size, and compressed size
+fn describe_results(results: &[(&str, u64, u64)]) -> String {
+    let ratio = |original: u64, compressed: u64| match original {
+        0 => "-".to_string(),
+        original => format!("{:.1}%", compressed as f64 / original as f64 * 100.0),
+    };
+    let mut out = format!(
+        "{:>12} {:>12} {:>7}  name\n",
+        "original", "compressed", "ratio"
+    );
+    for &(name, original, compressed) in results {
+        let ratio = ratio(original, compressed);
+        writeln!(
+            out,
+            "{:>12} {:>12} {:>7}  {}",
+            original, compressed, ratio, name
+        )
+        .unwrap();
+    }
+    let original = results.iter().map(|result| result.1).sum();
+    let compressed = results.iter().map(|result| result.2).sum();
+    writeln!(
+        out,
+        "{:>12} {:>12} {:>7}  (total, {} files)",
+        original,
+        compressed,
+        ratio(original, compressed),
+        results.len()
+    )
+    .unwrap();
+    out
+}
+
+/// Puts how compressing each of several files went into JSON, like `describe_results()` lays it out as a table
+///
+/// ## Arguments
+///
+/// * `results`: each file's name, original size, and compressed size
+/// * `failed`: how many other files couldn't be compressed
+pub(crate) fn results_json(results: &[(&str, u64, u64)], failed: usize) -> Json {
+    let files = results
+        .iter()
+        .map(|&(name, original, compressed)| {
+            Json::Object(vec![
+                ("input", name.into()),
+                ("original", original.into()),
+                ("compressed", compressed.into()),
+            ])
+        })
+        .collect();
+    Json::Object(vec![
+        ("files", Json::List(files)),
+        ("failed", failed.into()),
+        (
+            "original",
+            results.iter().map(|result| result.1).sum::<u64>().into(),
+        ),
+        (
+            "compressed",
+            results.iter().map(|result| result.2).sum::<u64>().into(),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{describe_results, parse_size};
+    use crate::decompress::cat_to;
+    use crate::glob::expand_globs;
+    use crate::inspect::read_members;
+    use crate::progress::describe_speed;
+    use crate::test::{args, scratch_dir};
+    use crate::{run, Failure};
+    use huffman::container;
+    use huffman::pipeline::Pipeline;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[test]
+    fn many_files_test() {
+        let dir = scratch_dir("many-files");
+        for (name, text) in [
+            ("a.log", "dagoth ur"),
+            ("b.log", "was a hotep"),
+            ("c.txt", "n'wah"),
+        ] {
+            fs::write(dir.join(name), text).unwrap();
+        }
+        let pattern = format!("{}/*.log", dir.to_str().unwrap());
+        let files = expand_globs(std::slice::from_ref(&pattern)).unwrap();
+        assert_eq!(files.len(), 2);
+        assert!(files[0].ends_with("/a.log") && files[1].ends_with("/b.log"));
+        run(&args(&format!("compress --keep {}", pattern))).unwrap();
+        assert!(dir.join("a.log.huf").exists() && dir.join("b.log.huf").exists());
+        assert!(dir.join("a.log").exists());
+        assert!(!dir.join("c.txt.huf").exists());
+        // they're all there already now
+        assert!(matches!(
+            run(&args(&format!("compress {}", pattern))),
+            Err(Failure::Run(_))
+        ));
+        assert!(matches!(
+            run(&args(&format!("compress {} -o x.huf", pattern))),
+            Err(Failure::Usage(_))
+        ));
+        assert!(matches!(
+            expand_globs(&[format!("{}/*.nope", dir.to_str().unwrap())]),
+            Err(Failure::Run(_))
+        ));
+        let table = describe_results(&[("a", 100, 50), ("b", 0, 6)]);
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[1].ends_with("  50.0%  a"));
+        assert!(lines[2].ends_with("      -  b"));
+        assert!(lines[3].ends_with("  56.0%  (total, 2 files)"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn threads_test() {
+        let dir = scratch_dir("threads");
+        let input = dir.join("big.txt");
+        // a few blocks' worth
+        let text: Vec<u8> = (0..3_500_000u32)
+            .map(|k| b"dagoth ur"[(k % 9) as usize] ^ (k % 7) as u8)
+            .collect();
+        fs::write(&input, &text).unwrap();
+        let input = input.to_str().unwrap();
+        let compressed = format!("{}.huf", input);
+        if cfg!(feature = "rayon") {
+            run(&args(&format!("compress -k -v --threads 4 {}", input))).unwrap();
+            let blocks = container::split_points(std::io::BufReader::new(
+                fs::File::open(&compressed).unwrap(),
+            ))
+            .unwrap();
+            assert_eq!(blocks.len(), 4);
+            let parallel = fs::read(&compressed).unwrap();
+            // however many threads there are, the blocks come out the same, in the same order
+            run(&args(&format!("compress -k -f --threads=3 {}", input))).unwrap();
+            assert_eq!(fs::read(&compressed).unwrap(), parallel);
+        } else {
+            assert!(matches!(
+                run(&args(&format!("compress -k --threads 4 {}", input))),
+                Err(Failure::Usage(_))
+            ));
+            run(&args(&format!("compress -k --threads 1 {}", input))).unwrap();
+        }
+        run(&args(&format!("decompress -k -f -T 4 {}", compressed))).unwrap();
+        assert_eq!(fs::read(input).unwrap(), text);
+        assert!(matches!(
+            run(&args(&format!("decompress -f -T 0 {}", compressed))),
+            Err(Failure::Usage(_))
+        ));
+        let speed = describe_speed(
+            2 * 1024 * 1024,
+            Duration::from_secs(1),
+            Duration::from_secs(3),
+            4,
+        );
+        assert_eq!(
+            speed,
+            "2097152 bytes in 1.00s (2.0 MiB/s) on 4 threads, 3.0x as fast as one thread"
+        );
+        assert_eq!(
+            describe_speed(0, Duration::ZERO, Duration::ZERO, 1),
+            "0 bytes in 0.00s (0.0 MiB/s)"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tuning_test() {
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("64k"), Some(64 * 1024));
+        assert_eq!(parse_size("1M"), Some(1024 * 1024));
+        assert_eq!(parse_size("0"), None);
+        assert_eq!(parse_size("k"), None);
+        assert_eq!(parse_size("12x"), None);
+        let dir = scratch_dir("tuning");
+        let input = dir.join("in.txt");
+        fs::write(&input, "dagoth ur was a hotep").unwrap();
+        let input = input.to_str().unwrap();
+        run(&args(&format!(
+            "compress -k --block-size 8 --canonical --checksum none {}",
+            input
+        )))
+        .unwrap();
+        let compressed = fs::read(format!("{}.huf", input)).unwrap();
+        let (members, _) = read_members(std::io::Cursor::new(&compressed)).unwrap();
+        // 21 bytes in blocks of 8, canonical and without checksums
+        assert_eq!(members.len(), 3);
+        assert!(members.iter().all(|member| member.flags == 1 << 6));
+        run(&args(&format!("decompress -f {}.huf", input))).unwrap();
+        assert_eq!(fs::read_to_string(input).unwrap(), "dagoth ur was a hotep");
+        // the last of --canonical and --no-canonical wins
+        run(&args(&format!(
+            "compress -k -f --canonical --no-canonical {}",
+            input
+        )))
+        .unwrap();
+        let compressed = fs::read(format!("{}.huf", input)).unwrap();
+        let (members, _) = read_members(std::io::Cursor::new(&compressed)).unwrap();
+        assert_eq!(members[0].flags, 1);
+        for bad in [
+            "--checksum md5",
+            "--block-size 0",
+            "--no-index",
+            "--pipeline lzma",
+        ] {
+            assert!(matches!(
+                run(&args(&format!("compress -k -f {} {}", bad, input))),
+                Err(Failure::Usage(_))
+            ));
+        }
+        let archive = dir.join("all.huffa");
+        let archive = archive.to_str().unwrap();
+        run(&args(&format!(
+            "compress --to-archive --no-index -o {} {} {}.huf",
+            archive, input, input
+        )))
+        .unwrap();
+        assert!(matches!(
+            run(&args(&format!("list {}", archive))),
+            Err(Failure::Run(_))
+        ));
+        let mut out = Vec::new();
+        cat_to(&[archive.to_string()], &Default::default(), &mut out).unwrap();
+        assert!(out.starts_with(b"dagoth ur was a hotepHUFF"));
+        // a pipeline goes in the header, so decompress knows to undo it
+        fs::write(input, "dagoth ur was a hotep".repeat(20)).unwrap();
+        run(&args(&format!("compress -k -f --pipeline bzip2 {}", input))).unwrap();
+        let compressed = fs::read(format!("{}.huf", input)).unwrap();
+        let (members, _) = read_members(std::io::Cursor::new(&compressed)).unwrap();
+        assert_eq!(
+            members[0].header.extensions.pipeline().unwrap(),
+            Pipeline::bzip2()
+        );
+        run(&args(&format!("decompress -f {}.huf", input))).unwrap();
+        assert_eq!(
+            fs::read_to_string(input).unwrap(),
+            "dagoth ur was a hotep".repeat(20)
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn estimate_test() {
+        let dir = scratch_dir("estimate");
+        let input = dir.join("in.txt");
+        fs::write(&input, "dagoth ur was a hotep".repeat(100)).unwrap();
+        let input = input.to_str().unwrap();
+        run(&args(&format!("compress --estimate --canonical {}", input))).unwrap();
+        // nothing got written, or deleted
+        assert!(PathBuf::from(input).exists());
+        assert!(!PathBuf::from(format!("{}.huf", input)).exists());
+        assert!(matches!(
+            run(&args(&format!(
+                "compress --estimate {} {}/nope",
+                input,
+                dir.display()
+            ))),
+            Err(Failure::Run(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn gzip_test() {
+        let dir = scratch_dir("gzip");
+        let input = dir.join("in.txt");
+        let data = "dagoth ur was a hotep\n".repeat(100);
+        fs::write(&input, &data).unwrap();
+        let input = input.to_str().unwrap();
+        run(&args(&format!("compress --gzip {}", input))).unwrap();
+        assert!(!std::path::Path::new(input).exists());
+        let gz = fs::File::open(format!("{}.gz", input)).unwrap();
+        let decompressed = std::io::read_to_string(flate2::read::GzDecoder::new(gz)).unwrap();
+        assert_eq!(decompressed, data);
+        assert!(matches!(
+            run(&args(&format!("compress --gzip --canonical {}.gz", input))),
+            Err(Failure::Usage(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,221 @@
+//! Defaults for flags out of `~/.config/huff/config.toml`, which get put in front of the command line
+
+use crate::args::Flag;
+use crate::{Failure, COMMANDS};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A default from a config file: the command it's for (if it's not for all of them), the flag, and its value
+type ConfigDefault = (Option<String>, &'static str, String);
+
+/// The flags a config file can give defaults for
+const CONFIG_KEYS: &[&str] = &["threads", "block-size", "suffix", "checksum"];
+
+/// Takes `--config` out of a command line, giving back its value (if it was given) and everything else
+///
+/// ## Arguments
+///
+/// * `args`: the command line (after the subcommand)
+pub(crate) fn split_config(args: &[String]) -> Result<(Option<String>, Vec<String>), Failure> {
+    let (mut config, mut rest) = (None, Vec::with_capacity(args.len()));
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--" => {
+                rest.push(arg.clone());
+                rest.extend(args.by_ref().cloned());
+            }
+            "--config" => {
+                config = Some(
+                    args.next()
+                        .cloned()
+                        .ok_or_else(|| Failure::Usage("--config needs a value".to_string()))?,
+                )
+            }
+            arg => match arg.strip_prefix("--config=") {
+                Some(path) => config = Some(path.to_string()),
+                None => rest.push(arg.to_string()),
+            },
+        }
+    }
+    Ok((config, rest))
+}
+
+/// Gets the default flags a config file gives a command, as command-line arguments
+///
+/// The config file's `--config`, if that was given, and otherwise `huff/config.toml` in `$XDG_CONFIG_HOME` (or
+/// `~/.config`), where it's fine for there to be no such file.
+///
+/// ## Arguments
+///
+/// * `config`: the config file `--config` gave, if it was given
+/// * `command`: the command being run
+/// * `flags`: the flags it takes
+pub(crate) fn config_defaults(
+    config: Option<&str>,
+    command: &str,
+    flags: &[Flag],
+) -> Result<Vec<String>, Failure> {
+    let (path, text) = match config {
+        Some(path) => (
+            PathBuf::from(path),
+            fs::read_to_string(path).map_err(|err| Failure::on(path, err.into()))?,
+        ),
+        None => {
+            let dir = match (env::var_os("XDG_CONFIG_HOME"), env::var_os("HOME")) {
+                (Some(dir), _) if !dir.is_empty() => PathBuf::from(dir),
+                (_, Some(home)) => Path::new(&home).join(".config"),
+                _ => return Ok(Vec::new()),
+            };
+            let path = dir.join("huff").join("config.toml");
+            match fs::read_to_string(&path) {
+                Ok(text) => (path, text),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(err) => return Err(Failure::on(&path.to_string_lossy(), err.into())),
+            }
+        }
+    };
+    let defaults =
+        parse_config(&text).map_err(|why| Failure::Run(format!("{}: {}", path.display(), why)))?;
+    Ok(defaults
+        .into_iter()
+        // a top-level key is for any command that takes it
+        .filter(|(table, key, _)| {
+            table.as_ref().is_none_or(|table| table == command)
+                && flags.iter().any(|flag| flag.0 == *key)
+        })
+        .map(|(_, key, value)| format!("--{}={}", key, value))
+        .collect())
+}
+
+/// Parses a config file, giving back every default in it, in order
+///
+/// Only the simplest bits of TOML are needed: `key = value` lines, where the value's a number or a string, `[command]`
+/// tables, and `#` comments.
+///
+/// ## Arguments
+///
+/// * `text`: what's in the config file
+fn parse_config(text: &str) -> Result<Vec<ConfigDefault>, String> {
+    let mut table = None;
+    let mut defaults = Vec::new();
+    for (k, line) in text.lines().enumerate() {
+        let at = |why: &str| format!("line {}: {}", k + 1, why);
+        let line = match line.find('#') {
+            // a # inside a string isn't a comment
+            Some(hash) if line[..hash].matches('"').count() % 2 == 0 => &line[..hash],
+            _ => line,
+        }
+        .trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            let name = name.trim();
+            if !COMMANDS.iter().any(|(command, _, _)| *command == name) {
+                return Err(at(&format!("there's no {} command", name)));
+            }
+            table = Some(name.to_string());
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| at("expected key = value"))?;
+        let key = key.trim().replace('_', "-");
+        let key = CONFIG_KEYS
+            .iter()
+            .find(|known| **known == key)
+            .ok_or_else(|| at(&format!("{} can't be given a default", key)))?;
+        let value = value.trim();
+        let value = match value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+        {
+            Some(value) if !value.contains(['"', '\\']) => value.to_string(),
+            Some(_) => return Err(at("strings can't have quotes or escapes in them")),
+            None if !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_digit()) => {
+                value.to_string()
+            }
+            None => return Err(at("expected a number or a \"string\"")),
+        };
+        defaults.push((table.clone(), *key, value));
+    }
+    Ok(defaults)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{config_defaults, parse_config};
+    use crate::test::{args, scratch_dir};
+    use crate::{run, Failure, COMPRESS_FLAGS, FILE_FLAGS};
+    use std::fs;
+
+    #[test]
+    fn config_test() {
+        let text = "# defaults\nthreads = 1\nsuffix = \".hf\" # a # in a comment\n\n[ compress ]\nblock_size = \"1m\"\n";
+        assert_eq!(
+            parse_config(text).unwrap(),
+            vec![
+                (None, "threads", "1".to_string()),
+                (None, "suffix", ".hf".to_string()),
+                (Some("compress".to_string()), "block-size", "1m".to_string()),
+            ]
+        );
+        for (bad, why) in [
+            ("output = \"x\"", "line 1: output can't be given a default"),
+            ("\n[squash]", "line 2: there's no squash command"),
+            ("threads", "line 1: expected key = value"),
+            (
+                "threads = four",
+                "line 1: expected a number or a \"string\"",
+            ),
+            (
+                "suffix = \"a\\\"b\"",
+                "line 1: strings can't have quotes or escapes in them",
+            ),
+        ] {
+            assert_eq!(parse_config(bad).unwrap_err(), why);
+        }
+        let dir = scratch_dir("config");
+        let config = dir.join("config.toml");
+        fs::write(&config, text).unwrap();
+        let config = config.to_str().unwrap();
+        // only the defaults a command takes get given to it
+        assert_eq!(
+            config_defaults(Some(config), "compress", COMPRESS_FLAGS).unwrap(),
+            ["--threads=1", "--suffix=.hf", "--block-size=1m"]
+        );
+        assert_eq!(
+            config_defaults(Some(config), "decompress", FILE_FLAGS).unwrap(),
+            ["--threads=1", "--suffix=.hf"]
+        );
+        assert!(config_defaults(Some(config), "stats", &[])
+            .unwrap()
+            .is_empty());
+        // and the command line's own flags win
+        let input = dir.join("in.txt");
+        fs::write(&input, "dagoth ur was a hotep").unwrap();
+        let input = input.to_str().unwrap();
+        run(&args(&format!(
+            "compress -k --config {} -S .x {}",
+            config, input
+        )))
+        .unwrap();
+        assert!(fs::metadata(format!("{}.x", input)).is_ok());
+        run(&args(&format!("compress -k --config={} {}", config, input))).unwrap();
+        assert!(fs::metadata(format!("{}.hf", input)).is_ok());
+        assert!(matches!(
+            run(&args(&format!(
+                "compress --config {}",
+                dir.join("missing.toml").display()
+            ))),
+            Err(Failure::Run(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
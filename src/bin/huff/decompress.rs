@@ -0,0 +1,184 @@
+//! `huff decompress` and `huff cat`
+
+use crate::args::Args;
+use crate::compress::threads;
+use crate::files::{check_overwrite, load_dictionary, open_output, remove_source, suffix};
+use crate::json::Json;
+use crate::progress::{describe_speed, ProgressBar};
+use crate::{Failure, STDIO};
+use huffman::{container, DecodeOptions, Error};
+use std::fs;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::time::{Duration, Instant};
+
+/// `huff decompress`
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn decompress(args: &Args) -> Result<(), Failure> {
+    let src = args.input()?;
+    let dst = match (args.value("output"), src) {
+        (Some(output), _) => output.to_string(),
+        (None, STDIO) => STDIO.to_string(),
+        (None, src) => match src.strip_suffix(suffix(args)?) {
+            Some(stem) if !stem.is_empty() => stem.to_string(),
+            _ => {
+                return Err(Failure::Usage(format!(
+                    "{} doesn't end in {}, so give an --output",
+                    src,
+                    suffix(args)?
+                )))
+            }
+        },
+    };
+    check_overwrite(&dst, false, args)?;
+    let threads = threads(args)?;
+    let len = fs::metadata(src).ok().map(|metadata| metadata.len());
+    let bar = ProgressBar::start(args, src, &dst, len);
+    let options = DecodeOptions {
+        dictionary: load_dictionary(args)?,
+        progress: bar.as_ref().map(ProgressBar::counter),
+        ..DecodeOptions::default()
+    };
+    let started = Instant::now();
+    let result = match (src, dst.as_str()) {
+        (STDIO, dst) => container::decompress_with(io::stdin().lock(), open_output(dst)?, &options)
+            .and_then(|mut output| Ok(output.flush()?)),
+        (src, STDIO) => File::open(src)
+            .map_err(Error::from)
+            .and_then(|input| {
+                container::decompress_with(BufReader::new(input), io::stdout().lock(), &options)
+            })
+            .and_then(|mut output| Ok(output.flush()?)),
+        (src, dst) if threads > 1 => huffman::decompress_file_parallel(src, dst, threads, &options),
+        (src, dst) => huffman::decompress_file_with(src, dst, &options),
+    };
+    result.map_err(|err| Failure::on(src, err))?;
+    drop(bar);
+    if let (true, Ok(metadata)) = (args.has("verbose"), fs::metadata(&dst)) {
+        let elapsed = started.elapsed();
+        let speed = describe_speed(metadata.len(), elapsed, Duration::ZERO, threads);
+        eprintln!("{}: {}", src, speed);
+    }
+    if args.has("json") && dst != STDIO {
+        let original = fs::metadata(&dst).ok().map(|metadata| metadata.len());
+        let json = Json::Object(vec![
+            ("input", src.into()),
+            ("output", dst.as_str().into()),
+            ("compressed", len.into()),
+            ("original", original.into()),
+        ]);
+        println!("{}", json);
+    }
+    remove_source(src, &dst, args)
+}
+
+/// `huff cat`
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn cat(args: &Args) -> Result<(), Failure> {
+    let files = match &args.positional[..] {
+        [] => vec![STDIO.to_string()],
+        files => files.to_vec(),
+    };
+    let options = DecodeOptions {
+        dictionary: load_dictionary(args)?,
+        ..DecodeOptions::default()
+    };
+    cat_to(&files, &options, BufWriter::new(io::stdout().lock()))
+}
+
+/// Decompresses files one after another into the same output, byte for byte
+///
+/// A file that can't be decompressed gets skipped (after whatever of it did decode), but makes the whole thing fail at
+/// the end. The output going away (e.g. `huff cat log.huf | head`) just stops everything, quietly.
+///
+/// ## Arguments
+///
+/// * `files`: the files (`-` for stdin)
+/// * `options`: how to decompress
+/// * `output`: where it all goes
+pub(crate) fn cat_to<W: Write>(
+    files: &[String],
+    options: &DecodeOptions,
+    mut output: W,
+) -> Result<(), Failure> {
+    let mut failed = 0;
+    for src in files {
+        let result = match src.as_str() {
+            STDIO => container::decompress_with(io::stdin().lock(), &mut output, options),
+            src => File::open(src).map_err(Error::from).and_then(|input| {
+                container::decompress_with(BufReader::new(input), &mut output, options)
+            }),
+        };
+        match result {
+            Ok(_) => {}
+            Err(Error::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            Err(err) => {
+                eprintln!("huff: {}", Failure::on(src, err).why());
+                failed += 1;
+            }
+        }
+    }
+    match output.flush() {
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+        result => result.map_err(|err| Failure::Run(format!("stdout: {}", err)))?,
+    }
+    match failed {
+        0 => Ok(()),
+        failed => Err(Failure::Run(format!(
+            "{} of {} files couldn't be decompressed",
+            failed,
+            files.len()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::cat_to;
+    use crate::test::scratch_dir;
+    use crate::Failure;
+    use huffman::container::compress_bytes;
+    use huffman::EncodeOptions;
+    use std::fs;
+
+    #[test]
+    fn cat_test() {
+        let dir = scratch_dir("cat");
+        let (first, second) = (dir.join("a.huf"), dir.join("b.huf"));
+        // two containers back to back, like `cat a.huf b.huf > ab.huf` would make
+        let mut members = compress_bytes(b"dagoth ur ", &EncodeOptions::default()).unwrap();
+        members.extend(compress_bytes(&[0, 255, b'\n'], &EncodeOptions::default()).unwrap());
+        fs::write(&first, members).unwrap();
+        fs::write(
+            &second,
+            compress_bytes(b"was a hotep", &EncodeOptions::default()).unwrap(),
+        )
+        .unwrap();
+        let files = vec![
+            first.to_str().unwrap().to_string(),
+            second.to_str().unwrap().to_string(),
+        ];
+        let mut out = Vec::new();
+        cat_to(&files, &Default::default(), &mut out).unwrap();
+        assert_eq!(out, b"dagoth ur \x00\xff\nwas a hotep");
+        // a bad file in the middle doesn't stop the rest
+        let files = vec![
+            files[0].clone(),
+            dir.join("nope.huf").to_str().unwrap().to_string(),
+            files[1].clone(),
+        ];
+        let mut out = Vec::new();
+        assert!(matches!(
+            cat_to(&files, &Default::default(), &mut out),
+            Err(Failure::Run(_))
+        ));
+        assert_eq!(out, b"dagoth ur \x00\xff\nwas a hotep");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+//! Naming, opening and cleaning up the files commands read and write
+
+use crate::args::Args;
+use crate::{Failure, STDIO, SUFFIX};
+use huffman::{Dictionary, Error};
+use std::fs;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A file that doesn't get opened until it's first read from, so archiving a big directory doesn't mean having all
+/// of it open at once
+pub(crate) struct LazyFile {
+    /// Where the file is
+    path: PathBuf,
+    /// The file, once it's been opened
+    file: Option<BufReader<File>>,
+}
+
+impl LazyFile {
+    /// Makes a reader for a file, without opening it yet
+    ///
+    /// ## Arguments
+    ///
+    /// * `path`: where the file is
+    pub(crate) fn new(path: PathBuf) -> Self {
+        LazyFile { path, file: None }
+    }
+}
+
+impl io::Read for LazyFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = match &mut self.file {
+            Some(file) => file.read(buf),
+            None => File::open(&self.path).and_then(|file| {
+                let file = self.file.insert(BufReader::new(file));
+                file.read(buf)
+            }),
+        };
+        // the archive's errors don't know which file they came from otherwise
+        result
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", self.path.display(), err)))
+    }
+}
+
+/// Gets the suffix compressed files get, which is `.huf` unless `--suffix` says otherwise
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn suffix(args: &Args) -> Result<&str, Failure> {
+    match args.value("suffix") {
+        Some("") => Err(Failure::Usage("--suffix can't be empty".to_string())),
+        Some(suffix) => Ok(suffix),
+        None => Ok(SUFFIX),
+    }
+}
+
+/// Makes sure a file that looks compressed already (it ends in the suffix) doesn't get compressed again, without
+/// `--force`
+///
+/// ## Arguments
+///
+/// * `src`: the file (`-` for stdin)
+/// * `suffix`: the suffix
+/// * `args`: the command's arguments
+pub(crate) fn check_suffix(src: &str, suffix: &str, args: &Args) -> Result<(), Failure> {
+    if src != STDIO && src.ends_with(suffix) && !args.has("force") {
+        return Err(Failure::Run(format!(
+            "{} already ends in {}, so it's been left alone (use --force to compress it anyway)",
+            src, suffix
+        )));
+    }
+    Ok(())
+}
+
+/// Deletes a file that's just been (de)compressed into another one, the way gzip does, unless there's `--keep`
+///
+/// Nothing gets deleted when the input was stdin or the output went to stdout.
+///
+/// ## Arguments
+///
+/// * `src`: the input (`-` for stdin)
+/// * `dst`: the output (`-` for stdout)
+/// * `args`: the command's arguments
+pub(crate) fn remove_source(src: &str, dst: &str, args: &Args) -> Result<(), Failure> {
+    if args.has("keep") || src == STDIO || dst == STDIO {
+        return Ok(());
+    }
+    fs::remove_file(src).map_err(|err| Failure::on(src, err.into()))
+}
+
+/// Loads the dictionary named by `--dict`, if there is one
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn load_dictionary(args: &Args) -> Result<Option<Arc<Dictionary>>, Failure> {
+    let path = match args.value("dict") {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    File::open(path)
+        .map_err(Error::from)
+        .and_then(|file| Dictionary::read_from(&mut BufReader::new(file)))
+        .map(|dict| Some(Arc::new(dict)))
+        .map_err(|err| Failure::on(path, err))
+}
+
+/// Opens where a command's output goes: stdout for `-`, otherwise a new file
+///
+/// ## Arguments
+///
+/// * `dst`: the output path
+pub(crate) fn open_output(dst: &str) -> Result<Box<dyn Write>, Failure> {
+    match dst {
+        STDIO => Ok(Box::new(io::stdout().lock())),
+        dst => File::create(dst)
+            .map(|file| Box::new(BufWriter::new(file)) as Box<dyn Write>)
+            .map_err(|err| Failure::on(dst, err.into())),
+    }
+}
+
+/// Makes sure an output file isn't about to get clobbered, and compressed data isn't about to get splattered all
+/// over a terminal, without `--force`
+///
+/// ## Arguments
+///
+/// * `dst`: the output path (`-` for stdout)
+/// * `compressed`: whether the output is compressed (i.e. binary) data
+/// * `args`: the command's arguments
+pub(crate) fn check_overwrite(dst: &str, compressed: bool, args: &Args) -> Result<(), Failure> {
+    if args.has("force") {
+        return Ok(());
+    }
+    if dst == STDIO {
+        if compressed && io::stdout().is_terminal() {
+            return Err(Failure::Run(
+                "refusing to write compressed data to a terminal (use --force to do it anyway)"
+                    .to_string(),
+            ));
+        }
+    } else if Path::new(dst).exists() {
+        return Err(Failure::Run(format!(
+            "{} already exists (use --force to overwrite it)",
+            dst
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,104 @@
+//! Glob patterns, for `compress`'s files (when the shell didn't expand them) and `--exclude`
+
+use crate::Failure;
+use std::fs;
+use std::path::Path;
+
+/// Expands any glob patterns among the files given (in case the shell didn't, like Windows' doesn't, or the
+/// pattern was quoted), leaving everything else as it is
+///
+/// Only the last part of a path can be a pattern (`logs/*.log`, but not `*/today.log`), and an argument that names a
+/// file that exists is never treated as a pattern, even if it has a `*` or `?` in it.
+///
+/// ## Arguments
+///
+/// * `args`: the files given
+pub(crate) fn expand_globs(args: &[String]) -> Result<Vec<String>, Failure> {
+    let mut files = Vec::with_capacity(args.len());
+    for arg in args {
+        if !arg.contains(['*', '?']) || Path::new(arg).exists() {
+            files.push(arg.clone());
+            continue;
+        }
+        let (dir, pattern) = match arg.rsplit_once('/') {
+            Some((dir, pattern)) => (Some(dir), pattern),
+            None => (None, arg.as_str()),
+        };
+        let entries = fs::read_dir(match dir {
+            Some("") => "/",
+            Some(dir) => dir,
+            None => ".",
+        })
+        .map_err(|err| Failure::on(arg, err.into()))?;
+        let mut matched = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|err| Failure::on(arg, err.into()))?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // like a shell, hidden files only match a pattern that asks for them
+            let hidden = name.starts_with('.') && !pattern.starts_with('.');
+            if !hidden && glob_match(pattern, &name) && entry.path().is_file() {
+                matched.push(match dir {
+                    Some(dir) => format!("{}/{}", dir, name),
+                    None => name,
+                });
+            }
+        }
+        if matched.is_empty() {
+            return Err(Failure::Run(format!("no files match {}", arg)));
+        }
+        matched.sort_unstable();
+        files.extend(matched);
+    }
+    Ok(files)
+}
+
+/// Whether some text matches a glob pattern, where `*` is any run of characters but `/`, `?` is any one character
+/// but `/`, `**` is anything at all (and `**/` can be no directories at all), and everything else is itself
+///
+/// ## Arguments
+///
+/// * `pattern`: the glob pattern
+/// * `text`: the text
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    fn matches(pattern: &[char], text: &[char]) -> bool {
+        match pattern {
+            [] => text.is_empty(),
+            ['*', '*', rest @ ..] => {
+                (0..=text.len()).any(|k| matches(rest, &text[k..]))
+                    || matches!(rest, ['/', after @ ..] if matches(after, text))
+            }
+            ['*', rest @ ..] => (0..=text.len())
+                .take_while(|&k| k == 0 || text[k - 1] != '/')
+                .any(|k| matches(rest, &text[k..])),
+            ['?', rest @ ..] => {
+                matches!(text, [ch, after @ ..] if *ch != '/' && matches(rest, after))
+            }
+            [ch, rest @ ..] => {
+                matches!(text, [first, after @ ..] if first == ch && matches(rest, after))
+            }
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches(&pattern, &text)
+}
+
+#[cfg(test)]
+mod test {
+    use super::glob_match;
+
+    #[test]
+    fn glob_test() {
+        assert!(glob_match("*.log", "debug.log"));
+        assert!(!glob_match("*.log", "logs/debug.log"));
+        assert!(glob_match("logs/*.log", "logs/debug.log"));
+        assert!(glob_match("file?.txt", "file1.txt"));
+        assert!(!glob_match("file?.txt", "file10.txt"));
+        assert!(glob_match("build/**", "build/a/b/c.o"));
+        assert!(glob_match("**/*.o", "c.o"));
+        assert!(glob_match("**/*.o", "a/b/c.o"));
+        assert!(!glob_match("**/*.o", "a/b/c.rs"));
+        assert!(glob_match("target", "target"));
+        assert!(!glob_match("target", "targets"));
+    }
+}
@@ -0,0 +1,394 @@
+//! `huff inspect`, which says what every container's header says without decoding anything
+
+use crate::archive::entries_json;
+use crate::args::Args;
+use crate::json::Json;
+use crate::{Failure, STDIO};
+use huffman::container::{FileMetadata, Header, Payload};
+use huffman::extensions::Key;
+use huffman::Error;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+
+/// The names of the container flag bits, lowest first
+const FLAG_BITS: &[&str] = &[
+    "checksum",
+    "streaming",
+    "index",
+    "dictionary",
+    "names",
+    "metadata",
+    "canonical",
+    "extensions",
+];
+
+/// One container in a compressed file, as `huff inspect` sees it
+#[derive(Debug)]
+pub(crate) struct Member {
+    /// Where the container starts in the file
+    pub(crate) offset: u64,
+    /// The format version it was written with
+    pub(crate) version: u8,
+    /// Its flags byte, just as it was stored
+    pub(crate) flags: u8,
+    /// Its header
+    pub(crate) header: Header,
+}
+
+/// `huff inspect`
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn inspect(args: &Args) -> Result<(), Failure> {
+    let src = match args.input()? {
+        STDIO => {
+            return Err(Failure::Usage(
+                "inspect needs a file, so it can skip over the payloads".to_string(),
+            ))
+        }
+        src => src,
+    };
+    let input = File::open(src).map_err(|err| Failure::on(src, err.into()))?;
+    let (members, complete) =
+        read_members(BufReader::new(input)).map_err(|err| Failure::verifying(src, err))?;
+    if args.has("json") {
+        println!("{}", members_json(&members, complete));
+    } else {
+        print!("{}", describe_members(&members, complete));
+    }
+    Ok(())
+}
+
+/// Reads the header of every container in a file, skipping over their payloads
+///
+/// Gives back the containers, and whether that was all of them (it stops at the first streaming payload, since there's
+/// no telling where one of those ends without decoding it).
+///
+/// ## Arguments
+///
+/// * `input`: the compressed file
+pub(crate) fn read_members<R: io::BufRead + io::Seek>(
+    mut input: R,
+) -> huffman::Result<(Vec<Member>, bool)> {
+    let end = input.seek(io::SeekFrom::End(0))?;
+    input.seek(io::SeekFrom::Start(0))?;
+    let mut members = Vec::new();
+    while !input.fill_buf()?.is_empty() {
+        let offset = input.stream_position()?;
+        // the version and flags get kept as they were, rather than as the header makes of them
+        let mut prefix = Vec::new();
+        (&mut input).take(6).read_to_end(&mut prefix)?;
+        let header = Header::read_from(&mut prefix.as_slice().chain(&mut input))?;
+        let payload_len = match header.payload {
+            Payload::Static { payload_len, .. }
+            | Payload::Canonical { payload_len, .. }
+            | Payload::Dictionary { payload_len, .. } => Some(payload_len),
+            Payload::Index { .. } => Some(0),
+            Payload::Streaming => None,
+        };
+        let checksum = header.checksum;
+        members.push(Member {
+            offset,
+            version: prefix[4],
+            flags: prefix[5],
+            header,
+        });
+        let payload_len = match payload_len {
+            Some(len) => len,
+            None => return Ok((members, false)),
+        };
+        let next = payload_len
+            .checked_add(if checksum { 4 } else { 0 })
+            .and_then(|len| len.checked_add(input.stream_position().ok()?))
+            .filter(|&next| next <= end)
+            .ok_or(Error::Corrupt("payload is truncated"))?;
+        input.seek(io::SeekFrom::Start(next))?;
+    }
+    Ok((members, true))
+}
+
+/// Lays out what each container's header says, a few lines per container
+///
+/// ## Arguments
+///
+/// * `members`: the containers
+/// * `complete`: whether that's all of them
+fn describe_members(members: &[Member], complete: bool) -> String {
+    let mut out = String::new();
+    for (k, member) in members.iter().enumerate() {
+        let flags: Vec<&str> = flag_names(member.flags);
+        writeln!(out, "container {} at byte {}", k, member.offset).unwrap();
+        writeln!(out, "    version   {}", member.version).unwrap();
+        writeln!(
+            out,
+            "    flags     {:#04x} ({})",
+            member.flags,
+            flags.join(", ")
+        )
+        .unwrap();
+        let checksum = match member.header.payload {
+            Payload::Streaming => "CRC-32, in the stream's footer",
+            _ if member.header.checksum => "CRC-32",
+            _ => "none",
+        };
+        match &member.header.payload {
+            Payload::Static {
+                original_len,
+                freqs,
+                payload_len,
+            } => {
+                writeln!(out, "    payload   static, with {} symbols", freqs.len()).unwrap();
+                writeln!(out, "    original  {} bytes", original_len).unwrap();
+                writeln!(out, "    packed    {} bytes", payload_len).unwrap();
+            }
+            Payload::Canonical {
+                original_len,
+                lengths,
+                payload_len,
+            } => {
+                writeln!(
+                    out,
+                    "    payload   static, with canonical codes for {} symbols",
+                    lengths.len()
+                )
+                .unwrap();
+                writeln!(out, "    original  {} bytes", original_len).unwrap();
+                writeln!(out, "    packed    {} bytes", payload_len).unwrap();
+            }
+            Payload::Dictionary {
+                original_len,
+                id,
+                payload_len,
+            } => {
+                writeln!(out, "    payload   static, with dictionary {:08x}", id).unwrap();
+                writeln!(out, "    original  {} bytes", original_len).unwrap();
+                writeln!(out, "    packed    {} bytes", payload_len).unwrap();
+            }
+            Payload::Streaming => {
+                writeln!(out, "    payload   streaming (adaptive), of unknown length").unwrap();
+            }
+            Payload::Index { entries, names } => {
+                writeln!(out, "    payload   index of {} inputs", entries.len()).unwrap();
+                for (k, info) in entries.iter().enumerate() {
+                    let entry = names.as_ref().map(|names| &names[k]);
+                    let name = entry.map_or_else(|| format!("#{}", k), |entry| entry.name.clone());
+                    write!(
+                        out,
+                        "    input     {}: {} bytes at byte {}",
+                        name, info.original_len, info.offset
+                    )
+                    .unwrap();
+                    if let Some(entry) = entry {
+                        write!(out, ", crc32 {:08x}", entry.crc).unwrap();
+                    }
+                    if let Some(FileMetadata { mode, mtime }) =
+                        entry.and_then(|entry| entry.metadata)
+                    {
+                        write!(out, ", mode {:04o}, modified {}", mode, mtime).unwrap();
+                    }
+                    writeln!(out).unwrap();
+                }
+            }
+        }
+        if !matches!(member.header.payload, Payload::Index { .. }) {
+            writeln!(out, "    checksum  {}", checksum).unwrap();
+        }
+        for (key, value) in member.header.extensions.iter() {
+            writeln!(out, "    extension {} = {}", key, value).unwrap();
+        }
+    }
+    if !complete {
+        writeln!(
+            out,
+            "(streaming payloads don't say how long they are, so anything after that one wasn't looked at)"
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Puts what each container's header says into JSON, like `describe_members()` lays it out
+///
+/// ## Arguments
+///
+/// * `members`: the containers
+/// * `complete`: whether that's all of them
+fn members_json(members: &[Member], complete: bool) -> Json {
+    let members = members
+        .iter()
+        .map(|member| {
+            let mut fields = vec![
+                ("offset", member.offset.into()),
+                ("version", (member.version as u64).into()),
+                ("flags", (member.flags as u64).into()),
+                (
+                    "flag_names",
+                    Json::List(
+                        flag_names(member.flags)
+                            .into_iter()
+                            .map(Json::from)
+                            .collect(),
+                    ),
+                ),
+                (
+                    "checksum",
+                    (member.header.checksum
+                        && !matches!(member.header.payload, Payload::Index { .. }))
+                    .then_some("crc32")
+                    .into(),
+                ),
+            ];
+            match &member.header.payload {
+                Payload::Static {
+                    original_len,
+                    freqs,
+                    payload_len,
+                } => fields.extend(vec![
+                    ("payload", "static".into()),
+                    ("original", (*original_len).into()),
+                    ("packed", (*payload_len).into()),
+                    ("symbols", freqs.len().into()),
+                ]),
+                Payload::Canonical {
+                    original_len,
+                    lengths,
+                    payload_len,
+                } => fields.extend(vec![
+                    ("payload", "canonical".into()),
+                    ("original", (*original_len).into()),
+                    ("packed", (*payload_len).into()),
+                    ("symbols", lengths.len().into()),
+                ]),
+                Payload::Dictionary {
+                    original_len,
+                    id,
+                    payload_len,
+                } => fields.extend(vec![
+                    ("payload", "dictionary".into()),
+                    ("original", (*original_len).into()),
+                    ("packed", (*payload_len).into()),
+                    ("dictionary", format!("{:08x}", id).into()),
+                ]),
+                Payload::Streaming => fields.push(("payload", "streaming".into())),
+                Payload::Index { entries, names } => {
+                    let entries: Vec<_> = entries
+                        .iter()
+                        .cloned()
+                        .zip(
+                            names
+                                .iter()
+                                .flatten()
+                                .cloned()
+                                .map(Some)
+                                .chain(std::iter::repeat(None)),
+                        )
+                        .collect();
+                    fields.push(("payload", "index".into()));
+                    fields.push(("inputs", entries_json(&entries)));
+                }
+            }
+            if !member.header.extensions.is_empty() {
+                let extensions = member
+                    .header
+                    .extensions
+                    .iter()
+                    .map(|(key, value)| {
+                        let key = match key {
+                            Key::Standard(key) => (*key).into(),
+                            Key::Vendor(key) => key.as_str().into(),
+                        };
+                        Json::Object(vec![("key", key), ("value", value.to_string().into())])
+                    })
+                    .collect();
+                fields.push(("extensions", Json::List(extensions)));
+            }
+            Json::Object(fields)
+        })
+        .collect();
+    Json::Object(vec![
+        ("containers", Json::List(members)),
+        ("complete", complete.into()),
+    ])
+}
+
+/// Names the bits set in a container's flags byte (or gives their number, for any this build doesn't know)
+///
+/// ## Arguments
+///
+/// * `flags`: the flags byte
+fn flag_names(flags: u8) -> Vec<&'static str> {
+    (0..8)
+        .filter(|bit| flags & (1 << bit) != 0)
+        .map(|bit| FLAG_BITS.get(bit).copied().unwrap_or("unknown"))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{describe_members, members_json, read_members};
+    use huffman::container::compress_bytes;
+    use huffman::{container, EncodeOptions};
+
+    #[test]
+    fn inspect_test() {
+        let options = EncodeOptions {
+            block_size: Some(8),
+            ..EncodeOptions::default()
+        };
+        let mut file = compress_bytes(b"dagoth ur was a hotep", &options).unwrap();
+        let (members, complete) = read_members(std::io::Cursor::new(&file)).unwrap();
+        assert!(complete);
+        assert_eq!(members.len(), 3);
+        assert_eq!(members[0].offset, 0);
+        assert!(members[1].offset > 0);
+        assert!(members
+            .iter()
+            .all(|member| member.version == 1 && member.flags == 1));
+        let described = describe_members(&members, complete);
+        assert!(described.contains("container 2 at byte "));
+        assert!(described.contains("    flags     0x01 (checksum)\n"));
+        assert!(described.contains("    original  5 bytes\n"));
+        let json = members_json(&members, complete).to_string();
+        assert!(json.starts_with("{\"containers\":[{\"offset\":0,\"version\":1,\"flags\":1,"));
+        assert!(json.ends_with("],\"complete\":true}"));
+        // nothing after a streaming payload gets looked at
+        let streaming = EncodeOptions {
+            streaming: true,
+            ..EncodeOptions::default()
+        };
+        file.extend(compress_bytes(b"n'wah", &streaming).unwrap());
+        file.extend(compress_bytes(b"n'wah", &options).unwrap());
+        let (members, complete) = read_members(std::io::Cursor::new(&file)).unwrap();
+        assert!(!complete);
+        assert_eq!(members.len(), 4);
+        assert!(describe_members(&members, complete).contains("streaming (adaptive)"));
+        // a cut-off payload
+        let truncated =
+            compress_bytes(b"dagoth ur was a hotep", &EncodeOptions::default()).unwrap();
+        assert!(matches!(
+            read_members(std::io::Cursor::new(&truncated[..truncated.len() - 1])),
+            Err(huffman::Error::Corrupt(_))
+        ));
+        let archive = container::compress_archive(
+            vec![("dir/ur.txt".to_string(), &b"dagoth ur"[..])],
+            Vec::new(),
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        let (members, _) = read_members(std::io::Cursor::new(&archive)).unwrap();
+        let described = describe_members(&members, true);
+        assert!(described.contains("(index, names)"));
+        assert!(described.contains("    input     dir/ur.txt: 9 bytes at byte 0, crc32 "));
+        let canonical = EncodeOptions {
+            canonical: true,
+            ..EncodeOptions::default()
+        };
+        let file = compress_bytes(b"dagoth ur", &canonical).unwrap();
+        let (members, _) = read_members(std::io::Cursor::new(&file)).unwrap();
+        assert!(
+            describe_members(&members, true).contains("    flags     0x41 (checksum, canonical)\n")
+        );
+    }
+}
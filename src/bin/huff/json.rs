@@ -0,0 +1,170 @@
+//! Just enough JSON to print what `--json` asks for
+
+use std::fmt;
+
+/// A JSON value, for `--json`
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Json {
+    /// `null`
+    Null,
+    /// `true` or `false`
+    Bool(bool),
+    /// A whole number
+    Int(u64),
+    /// Any other number (`null` if it's infinite or NaN, which JSON can't say)
+    Float(f64),
+    /// A string
+    Str(String),
+    /// An array
+    List(Vec<Json>),
+    /// An object, with its keys in order
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Int(n) => write!(f, "{}", n),
+            Json::Float(x) if x.is_finite() => write!(f, "{}", x),
+            Json::Float(_) => write!(f, "null"),
+            Json::Str(s) => {
+                write!(f, "\"")?;
+                for ch in s.chars() {
+                    match ch {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\t' => write!(f, "\\t")?,
+                        ch if (ch as u32) < 0x20 => write!(f, "\\u{:04x}", ch as u32)?,
+                        ch => write!(f, "{}", ch)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Json::List(items) => {
+                write!(f, "[")?;
+                for (k, item) in items.iter().enumerate() {
+                    if k > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (k, (key, value)) in fields.iter().enumerate() {
+                    if k > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", Json::from(*key), value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Json::Bool(b)
+    }
+}
+
+impl From<u64> for Json {
+    fn from(n: u64) -> Self {
+        Json::Int(n)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Self {
+        Json::Int(n as u64)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(x: f64) -> Self {
+        Json::Float(x)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Json::Str(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Json::Str(s)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Json;
+    use crate::archive::entries_json;
+    use crate::compress::results_json;
+    use crate::run;
+    use crate::stats::{stats_json, tree_json};
+    use crate::test::args;
+    use huffman::btree::HuffTree;
+    use huffman::freq::FrequencyMap;
+    use huffman::{container, EncodeOptions};
+
+    #[test]
+    fn json_test() {
+        let json = Json::Object(vec![
+            ("name", "dir/\"ur\"\n.txt".into()),
+            ("bytes", 21u64.into()),
+            ("ratio", 0.5.into()),
+            ("crc32", None::<String>.into()),
+            ("ok", true.into()),
+            (
+                "list",
+                Json::List(vec![Json::Null, f64::NAN.into(), "\u{1}".into()]),
+            ),
+        ]);
+        assert_eq!(
+            json.to_string(),
+            r#"{"name":"dir/\"ur\"\n.txt","bytes":21,"ratio":0.5,"crc32":null,"ok":true,"list":[null,null,"\u0001"]}"#
+        );
+        let stats = stats_json(&FrequencyMap::from("aaaabbcd"), 1).to_string();
+        assert!(stats.starts_with(
+            r#"{"bytes":8,"symbols":4,"entropy":1.75,"bits_per_byte":1.75,"coded_bytes":2,"#
+        ));
+        assert!(stats.ends_with(r#""most_common":[{"byte":97,"count":4,"share":0.5}]}"#));
+        let mut tree = HuffTree::new();
+        tree.populate_tree(FrequencyMap::from("aabc").as_map());
+        assert_eq!(
+            tree_json(&mut tree).to_string(),
+            r#"{"codes":[{"byte":97,"code":"0"},{"byte":98,"code":"10"},{"byte":99,"code":"11"}]}"#
+        );
+        assert_eq!(
+            results_json(&[("a", 100, 50)], 1).to_string(),
+            r#"{"files":[{"input":"a","original":100,"compressed":50}],"failed":1,"original":100,"compressed":50}"#
+        );
+        let inputs = vec![("ur.txt".to_string(), &b"dagoth ur"[..])];
+        let archive =
+            container::compress_archive(inputs, Vec::new(), &EncodeOptions::default()).unwrap();
+        let entries: Vec<_> = container::read_archive(std::io::Cursor::new(&archive))
+            .unwrap()
+            .into_iter()
+            .map(|(info, entry)| (info, Some(entry)))
+            .collect();
+        let listed = entries_json(&entries).to_string();
+        assert!(listed.starts_with(r#"[{"name":"ur.txt","original":9,"stored":"#));
+        assert!(listed.ends_with(r#","mode":null,"mtime":null}]"#));
+        assert!(run(&args("list --json --nope x")).is_err());
+    }
+}
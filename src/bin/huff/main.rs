@@ -0,0 +1,565 @@
+//! `huff`, the command-line front end: compresses and decompresses files in the container format
+//!
+//! ```text
+//! huff compress FILE          writes FILE.huf
+//! huff compress -r DIR        writes DIR.huffa, an archive of everything under DIR
+//! huff compress *.log         writes a .huf for each log, then prints how each one did
+//! huff compress --to-archive -o logs.huffa *.log
+//!                             writes one archive of all the logs
+//! huff compress --estimate *.log  prints how big each .huf would be, without writing anything
+//! huff decompress FILE.huf    writes FILE
+//! huff cat FILE.huf...        decompresses FILEs to stdout, one after another, like zcat
+//! huff stats FILE             prints how well FILE would compress
+//! huff tree FILE              prints the codes and tree FILE would get
+//! huff compare FILE           compares how small FILE gets with huff and with gzip and zstd
+//! huff train -o DICT FILE...  trains a dictionary for --dict on FILEs
+//! huff verify FILE.huf        checks FILE.huf is intact, without writing anything
+//! huff inspect FILE.huf       prints what the headers in FILE.huf say, without decoding anything
+//! huff list ARCHIVE           lists what's in a multi-file archive
+//! huff extract ARCHIVE [NAME...]  pulls files back out of an archive
+//! huff completions bash       prints a bash completion script (or zsh, fish, powershell)
+//! huff bits FILE.huf          shows FILE.huf's payload bit by bit, labeled with what each code stands for
+//! huff repl                   shows, for each line typed, how it gets Huffman coded, step by step
+//! cat log | huff compress - > log.huf
+//! huff decompress < log.huf
+//! ```
+//!
+//! `compress -r` walks the directory in name order, skipping symlinks, and names each file by its path relative to
+//! the directory (with `/` between the parts, whatever the platform). `--exclude` patterns are globs: `*` and `?`
+//! don't match `/`, `**` matches anything, a pattern without a `/` in it is checked against every file's and
+//! directory's own name (an excluded directory doesn't get looked inside at all), and one with a `/` is checked
+//! against the whole relative path. `--preserve` keeps each file's permissions and modification time in the archive,
+//! and `extract` puts them back.
+//!
+//! Patterns among `compress`'s files get expanded by `huff` itself if the shell left them alone, though only in a
+//! path's last part. A file that can't be compressed doesn't stop the rest; `compress` just fails at the end.
+//!
+//! Like gzip, compressing or decompressing a file replaces it: the input gets deleted once the output's safely
+//! written, unless there's `--keep` (or the output's stdout). `--suffix` picks something other than `.huf` to add
+//! and strip, and a file that already ends in it doesn't get compressed again without `--force`.
+//!
+//! `--threads` (one per core by default) splits a file bigger than a block (a megabyte) into blocks that each get
+//! compressed on their own thread and then stitched back together in order, so the output's the same whatever the
+//! thread count, as long as it's more than one. Compressing on more than one thread needs the `rayon` feature;
+//! decompressing on several doesn't. `--verbose` says how fast it went, and how much the threads helped (going by
+//! how long each block took, so it overstates things when there are more threads than cores).
+//!
+//! `--gzip` writes a `.gz` instead, which plain `gunzip` can open, coded with the same byte-by-byte Huffman codes
+//! (so it's about as small as a `.huf`, and nowhere near as small as what `gzip` makes). None of the `.huf`-only
+//! options (`--dict`, `--canonical`, `--comment`, archives, and so on) go with it.
+//!
+//! `--estimate` only counts, which is half the work of compressing (and none of the writing), but still gives the
+//! exact size each file would compress to with the same options.
+//!
+//! `--block-size` compresses in blocks of its own size, whatever the thread count (with a `k`, `m` or `g` for
+//! kibibytes, mebibytes or gibibytes): smaller blocks each have a header of their own, which costs space, but they
+//! can be decoded independently. `--canonical` stores just the code lengths instead of every byte's frequency, which
+//! shrinks each header a good deal (and needs a huff that knows about canonical codes to decompress). `--checksum
+//! none` leaves out the CRC-32 of each block, and `--no-index` leaves the index out of an archive, so its files can
+//! only come back out all together, like `cat` would put them. `--pipeline` runs each block through some reversible
+//! transforms before coding it: `bwt,mtf,rle` (or just `bzip2`) gets text a lot smaller than Huffman codes alone can,
+//! at the cost of a good deal more time, and `delta16` (or `delta8` or `delta32`) codes the steps between samples
+//! instead of the samples themselves, for raw audio and other streams of numbers. `golomb16` (or `golomb8` or
+//! `golomb32`) after it Rice or Golomb codes those steps, which suits them when they're mostly tiny.
+//!
+//! (De)compressing one file into another shows a progress bar on stderr, with how fast it's going and how long
+//! it's got left, as long as stderr's a terminal and there's no `--quiet`.
+//!
+//! `inspect` goes through a file's containers one after another, skipping over each payload, so it can say what's in
+//! a file that won't decode. Streaming payloads don't record how long they are, though, so it has to stop at the
+//! first one of those. Like `verify`, it exits with 3 for a bad header and 4 for a truncated payload.
+//!
+//! `bits` shows a payload the way the decoder sees it: every code on a line of its own, with where in the payload it
+//! starts and which byte it stands for, then whatever padding fills out the last byte, then the checksum. That only
+//! works where the codes are known up front, i.e. static payloads (with `--dict` for one compressed with a dictionary);
+//! anything else just gets its bytes shown in binary. Like `inspect`, it stops at the first streaming payload.
+//!
+//! `repl` is for seeing how Huffman coding works: every line typed in gets its characters counted, its tree built
+//! one merge at a time and drawn, and its codes and the bits it ends up as shown. It works on characters rather than
+//! bytes, the same as `HuffTree` itself does, and stops at the end of the input (or `:q`).
+//!
+//! `completions` builds its scripts from the same lists of commands and flags the command line gets parsed with, so
+//! they can't fall behind. They complete commands and flags, and leave everything else to the shell's own file
+//! completion; e.g. `huff completions bash > /etc/bash_completion.d/huff`, or `source <(huff completions zsh)`.
+//!
+//! `compare` runs the general-purpose compressors (`gzip -9` through flate2, and `zstd -19`) in-process, with the
+//! `compare` feature; without it, it only shows huff's own sizes next to the entropy. They're there to judge whether
+//! plain Huffman coding is good enough for some data, so `cargo install huffman --features compare` is only worth it
+//! if that's a question that comes up.
+//!
+//! With `--json`, every command prints what it would've said (or, for `compress`, `decompress` and `extract`, a
+//! summary of what it did) as one line of JSON on stdout instead, for scripts to pick apart. Nothing gets printed
+//! there when stdout's where the (de)compressed data's going, though, and a failed `verify` prints its reason as JSON
+//! before exiting with the usual code.
+//!
+//! Defaults for `--threads`, `--block-size`, `--suffix` and `--checksum` can go in `~/.config/huff/config.toml` (or
+//! under `$XDG_CONFIG_HOME`, or wherever `--config` says), which only needs the simplest bits of TOML:
+//!
+//! ```text
+//! # for every command that takes them
+//! threads = 4
+//! suffix = ".hf"
+//!
+//! # just for compress
+//! [compress]
+//! block-size = "1m"
+//! checksum = "none"
+//! ```
+//!
+//! They're just flags put in front of the command line's own, so anything given there wins.
+//!
+//! With no file (or `-`), the input's read from stdin and the output goes to stdout, unless `--output` says
+//! otherwise; `--output -` sends a file's output to stdout too.
+//!
+//! Exits with 0 if everything worked, 1 if something went wrong doing the work (a missing file, corrupt data, ...),
+//! and 2 if the command line itself didn't make sense. `verify` tells apart the two ways a compressed file can be
+//! broken: 3 if a header's bad (or it isn't compressed data at all), and 4 if a payload doesn't decode or doesn't
+//! match its checksum. `compress --verify-on-write` decompresses everything it wrote (without writing that anywhere)
+//! before deleting anything, and exits with 5 if it didn't come back out exactly as it went in, deleting the bad
+//! output instead.
+
+mod archive;
+mod args;
+mod bits;
+mod compare;
+mod completions;
+mod compress;
+mod config;
+mod decompress;
+mod files;
+mod glob;
+mod inspect;
+mod json;
+mod progress;
+mod repl;
+mod stats;
+mod train;
+mod verify;
+
+use crate::archive::{extract, list};
+use crate::args::{Args, Flag};
+use crate::bits::bits;
+use crate::compare::compare;
+use crate::completions::completions;
+use crate::compress::compress;
+use crate::config::{config_defaults, split_config};
+use crate::decompress::{cat, decompress};
+use crate::inspect::inspect;
+use crate::repl::repl;
+use crate::stats::{stats, tree};
+use crate::train::train;
+use crate::verify::verify;
+use huffman::Error;
+use std::env;
+use std::process::ExitCode;
+
+/// What compressed files get named after, unless `--output` says otherwise
+const SUFFIX: &str = ".huf";
+
+/// What archives of whole directories get named after, unless `--output` says otherwise
+const ARCHIVE_SUFFIX: &str = ".huffa";
+
+/// What `compress --gzip` names its files after, unless `--output` or `--suffix` says otherwise
+const GZIP_SUFFIX: &str = ".gz";
+
+/// The file name that means stdin (as an input) or stdout (as an output)
+const STDIO: &str = "-";
+
+/// The help text
+const USAGE: &str = "\
+usage: huff <command> [options] [FILE]
+
+commands:
+    compress FILE         compress FILE into FILE.huf (and delete FILE)
+    compress -r DIR       compress everything under DIR into DIR.huffa
+    compress FILE...      compress each FILE into its own FILE.huf, and show
+                          how each one did
+    decompress FILE.huf   decompress FILE.huf into FILE (and delete FILE.huf)
+    cat FILE.huf...       decompress each FILE.huf to stdout, one after another
+    stats FILE            show how well FILE would compress, without compressing it
+    tree FILE             show the codes and tree FILE would get
+    compare FILE          show how small FILE gets with huff, next to gzip
+                          and zstd (if built with the compare feature)
+    train -o DICT FILE... train a dictionary on FILEs, for compressing lots of
+                          small, similar files with --dict
+    verify FILE.huf       check FILE.huf decodes and matches its checksums
+                          (exits with 3 for a bad header, 4 for a bad payload)
+    inspect FILE.huf      show what FILE.huf's headers say (version, flags,
+                          sizes, dictionary, checksum, ...), without decoding it
+    bits FILE.huf         show FILE.huf's payloads bit by bit, with each code
+                          labeled with the byte it stands for
+    list ARCHIVE          list the files in a multi-file archive
+    extract ARCHIVE [NAME...]
+                          extract the NAMEd files (or all of them) from ARCHIVE,
+                          reading only their parts of it
+    completions SHELL     print a completion script for SHELL (bash, zsh,
+                          fish or powershell)
+    repl                  type in text and see how it gets Huffman coded:
+                          its counts, its tree merge by merge, its codes
+                          and its bits
+
+with no FILE, or when FILE is -, read stdin and write stdout
+
+options:
+    -o, --output PATH     write to PATH instead of the default (- for stdout)
+    -f, --force           overwrite the output if it already exists, write
+                          compressed data to a terminal, or compress a file
+                          that already ends in the suffix
+    -k, --keep            (de)compress without deleting the input afterwards
+    -S, --suffix SUF      add (or strip) SUF instead of .huf
+    -T, --threads N       (de)compress files bigger than a megabyte on N
+                          threads at once (default: one per core)
+    -v, --verbose         say how fast (de)compressing went, and how much
+                          the threads helped
+    -q, --quiet           don't show a progress bar while (de)compressing
+    --json                print results (or a summary) as JSON, for scripts
+    --dict DICT           (de)compress with a dictionary from `huff train`,
+                          which the compressed file won't have to store
+    -r, --recursive       (compress) archive a directory and everything in it
+    --exclude PATTERN     (compress -r) skip files and directories matching
+                          PATTERN, a glob like *.log or build/** (repeatable)
+    --to-archive          (compress) bundle the FILEs into one archive, named
+                          by --output, instead of compressing them one by one
+    --preserve            (compress -r or --to-archive) keep permissions and
+                          modification times, for extract to put back
+    --block-size SIZE     (compress) compress in independent blocks of SIZE
+                          bytes (or e.g. 64k, 1m), each with its own header
+    --canonical           (compress) store code lengths rather than byte
+                          frequencies, for smaller headers (--no-canonical
+                          to not)
+    --checksum KIND       (compress) crc32 (the default) or none
+    --pipeline STAGES     (compress) transform each block first, with a comma
+                          list of bwt, mtf, rle, delta8, delta16, delta32,
+                          golomb8, golomb16 and golomb32 (or bzip2 for
+                          bwt,mtf,rle)
+    --no-index            (compress -r or --to-archive) leave out the index,
+                          so the files can only be decompressed all together
+    --estimate            (compress) just show how big each file would get,
+                          without compressing or writing anything
+    --comment TEXT        (compress) put a comment in the header(s), which
+                          inspect shows
+    --gzip                (compress) write FILE.gz, which gunzip can open,
+                          instead of FILE.huf
+    --verify-on-write     (compress) decompress what got written and check it
+                          matches the input, before deleting anything (exits
+                          with 5, and deletes the output, if it doesn't)
+    -n, --top N           (stats) list the N most common bytes (default 10)
+    --from-archive        (tree) read the tree out of a compressed FILE instead
+    --dot                 (tree) print the tree as a Graphviz graph, e.g. for
+                          huff tree --dot FILE | dot -Tsvg > tree.svg
+    --latex               (tree) print the tree as LaTeX, for the forest package
+    --codes               (tree --latex) show the codes at the leaves too
+    --min-variance        (tree) break ties between equal frequencies so the
+                          tree comes out as short as it can
+    --config PATH         read default flags from PATH rather than
+                          ~/.config/huff/config.toml
+    -h, --help            show this help
+    -V, --version         show the version";
+
+/// The flags every command takes on top of its own (`run()` takes out `--config` first, and checks for `--help` last)
+const GLOBAL_FLAGS: &[Flag] = &[("help", Some('h'), false), ("config", None, true)];
+
+/// The flags `decompress` takes
+const FILE_FLAGS: &[Flag] = &[
+    ("output", Some('o'), true),
+    ("force", Some('f'), false),
+    ("dict", None, true),
+    ("keep", Some('k'), false),
+    ("suffix", Some('S'), true),
+    ("threads", Some('T'), true),
+    ("verbose", Some('v'), false),
+    ("quiet", Some('q'), false),
+    ("json", None, false),
+];
+
+/// The flags `extract` takes
+const EXTRACT_FLAGS: &[Flag] = &[
+    ("output", Some('o'), true),
+    ("force", Some('f'), false),
+    ("dict", None, true),
+    ("json", None, false),
+];
+
+/// The flags `compress` takes
+const COMPRESS_FLAGS: &[Flag] = &[
+    ("output", Some('o'), true),
+    ("force", Some('f'), false),
+    ("dict", None, true),
+    ("keep", Some('k'), false),
+    ("suffix", Some('S'), true),
+    ("threads", Some('T'), true),
+    ("verbose", Some('v'), false),
+    ("quiet", Some('q'), false),
+    ("json", None, false),
+    ("recursive", Some('r'), false),
+    ("exclude", None, true),
+    ("preserve", None, false),
+    ("to-archive", None, false),
+    ("block-size", None, true),
+    ("canonical", None, false),
+    ("no-canonical", None, false),
+    ("checksum", None, true),
+    ("pipeline", None, true),
+    ("no-index", None, false),
+    ("estimate", None, false),
+    ("verify-on-write", None, false),
+    ("comment", None, true),
+    ("gzip", None, false),
+];
+
+/// The flags `stats` takes
+const STATS_FLAGS: &[Flag] = &[("top", Some('n'), true), ("json", None, false)];
+
+/// The flags `verify` takes
+const VERIFY_FLAGS: &[Flag] = &[("dict", None, true), ("json", None, false)];
+
+/// The flags `train` takes
+const TRAIN_FLAGS: &[Flag] = &[
+    ("out", Some('o'), true),
+    ("force", Some('f'), false),
+    ("json", None, false),
+];
+
+/// The flags `tree` takes
+const TREE_FLAGS: &[Flag] = &[
+    ("from-archive", None, false),
+    ("json", None, false),
+    ("dot", None, false),
+    ("latex", None, false),
+    ("codes", None, false),
+    ("min-variance", None, false),
+];
+
+/// The flags `cat` takes
+const CAT_FLAGS: &[Flag] = &[("dict", None, true)];
+
+/// The flags `inspect` takes
+const INSPECT_FLAGS: &[Flag] = &[("json", None, false)];
+
+/// The flags `bits` takes
+const BITS_FLAGS: &[Flag] = &[("dict", None, true)];
+
+/// The flags `list` takes
+const LIST_FLAGS: &[Flag] = &[("json", None, false)];
+
+/// The flags `compare` takes
+const COMPARE_FLAGS: &[Flag] = &[("json", None, false)];
+
+/// The flags `completions` takes
+const COMPLETIONS_FLAGS: &[Flag] = &[];
+
+/// The flags `repl` takes
+const REPL_FLAGS: &[Flag] = &[];
+
+/// A command: its name, the flags it takes, and what runs it
+type Subcommand = (
+    &'static str,
+    &'static [Flag],
+    fn(&Args) -> Result<(), Failure>,
+);
+
+/// Every command, in the order the help lists them
+const COMMANDS: &[Subcommand] = &[
+    ("compress", COMPRESS_FLAGS, compress),
+    ("decompress", FILE_FLAGS, decompress),
+    ("cat", CAT_FLAGS, cat),
+    ("stats", STATS_FLAGS, stats),
+    ("tree", TREE_FLAGS, tree),
+    ("compare", COMPARE_FLAGS, compare),
+    ("train", TRAIN_FLAGS, train),
+    ("verify", VERIFY_FLAGS, verify),
+    ("inspect", INSPECT_FLAGS, inspect),
+    ("bits", BITS_FLAGS, bits),
+    ("list", LIST_FLAGS, list),
+    ("extract", EXTRACT_FLAGS, extract),
+    ("completions", COMPLETIONS_FLAGS, completions),
+    ("repl", REPL_FLAGS, repl),
+];
+
+/// Why `huff` is giving up
+#[derive(Debug)]
+enum Failure {
+    /// The command line didn't make sense (exit code 2)
+    Usage(String),
+    /// The work itself failed (exit code 1)
+    Run(String),
+    /// A compressed file's header is bad, when verifying it (exit code 3)
+    BadHeader(String),
+    /// A compressed file's payload is bad, when verifying it (exit code 4)
+    Corrupt(String),
+    /// What got compressed didn't decompress back to what it was, with `--verify-on-write` (exit code 5)
+    Mismatch(String),
+}
+
+impl Failure {
+    /// What went wrong, for printing
+    fn why(&self) -> &str {
+        match self {
+            Failure::Usage(why)
+            | Failure::Run(why)
+            | Failure::BadHeader(why)
+            | Failure::Corrupt(why)
+            | Failure::Mismatch(why) => why,
+        }
+    }
+
+    /// Wraps up a library error, saying which file it was about
+    ///
+    /// ## Arguments
+    ///
+    /// * `path`: the file (`-` for stdin or stdout)
+    /// * `err`: what went wrong with it
+    fn on(path: &str, err: Error) -> Self {
+        match path {
+            STDIO => Failure::Run(format!("stdin: {}", err)),
+            path => Failure::Run(format!("{}: {}", path, err)),
+        }
+    }
+
+    /// Wraps up a library error like `on()`, but telling bad headers and corrupt payloads apart from everything else
+    ///
+    /// ## Arguments
+    ///
+    /// * `path`: the file (`-` for stdin)
+    /// * `err`: what went wrong with it
+    fn verifying(path: &str, err: Error) -> Self {
+        let kind: fn(String) -> Failure = match err {
+            Error::BadMagic | Error::UnsupportedVersion(_) | Error::BadHeader(_) => {
+                Failure::BadHeader
+            }
+            Error::Corrupt(_) | Error::ChecksumMismatch { .. } => Failure::Corrupt,
+            _ => Failure::Run,
+        };
+        match Failure::on(path, err) {
+            Failure::Run(why) => kind(why),
+            failure => failure,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let failure = match run(&args) {
+        Ok(()) => return ExitCode::SUCCESS,
+        Err(failure) => failure,
+    };
+    let code = match failure {
+        Failure::Usage(ref why) => {
+            eprintln!("huff: {}\n\n{}", why, USAGE);
+            return ExitCode::from(2);
+        }
+        Failure::Run(_) => 1,
+        Failure::BadHeader(_) => 3,
+        Failure::Corrupt(_) => 4,
+        Failure::Mismatch(_) => 5,
+    };
+    eprintln!("huff: {}", failure.why());
+    ExitCode::from(code)
+}
+
+/// Runs a whole command line
+///
+/// ## Arguments
+///
+/// * `args`: the command line, without the program name
+fn run(args: &[String]) -> Result<(), Failure> {
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => return Err(Failure::Usage("no command given".to_string())),
+    };
+    if let Some((_, flags, run)) = COMMANDS.iter().find(|(name, _, _)| *name == command) {
+        let (config, rest) = split_config(rest)?;
+        let mut args = config_defaults(config.as_deref(), command, flags)?;
+        args.extend_from_slice(&rest);
+        // --help goes through the parser like any other flag, so a `-h` that's some other flag's value isn't one
+        let allowed = [*flags, GLOBAL_FLAGS].concat();
+        let args = Args::parse(&args, &allowed)?;
+        if args.has("help") {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        return run(&args);
+    }
+    match command {
+        "-h" | "--help" | "help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        "-V" | "--version" => {
+            println!("huff {}", env!("CARGO_PKG_VERSION"));
+            Ok(())
+        }
+        _ => Err(Failure::Usage(format!("unknown command {}", command))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{run, Failure};
+    use std::fs;
+    use std::path::PathBuf;
+
+    pub(crate) fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    pub(crate) fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("huff-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let dir = scratch_dir("round-trip");
+        let input = dir.join("in.txt");
+        fs::write(&input, "dagoth ur was a hotep".repeat(100)).unwrap();
+        let input = input.to_str().unwrap();
+        run(&args(&format!("compress --keep {}", input))).unwrap();
+        // the original's still there, so it has to be moved out of the way (or forced) to decompress over it
+        assert!(matches!(
+            run(&args(&format!("decompress {}.huf", input))),
+            Err(Failure::Run(_))
+        ));
+        run(&args(&format!("decompress -f {}.huf", input))).unwrap();
+        assert_eq!(
+            fs::read_to_string(input).unwrap(),
+            "dagoth ur was a hotep".repeat(100)
+        );
+        // without --keep, the input goes away once it's been replaced, like with gzip
+        assert!(!PathBuf::from(format!("{}.huf", input)).exists());
+        run(&args(&format!("compress -S .z {}", input))).unwrap();
+        assert!(!PathBuf::from(input).exists());
+        assert!(matches!(
+            run(&args(&format!("compress -S .z {}.z", input))),
+            Err(Failure::Run(_))
+        ));
+        run(&args(&format!("decompress --suffix=.z {}.z", input))).unwrap();
+        assert!(!PathBuf::from(format!("{}.z", input)).exists());
+        assert_eq!(
+            fs::read_to_string(input).unwrap(),
+            "dagoth ur was a hotep".repeat(100)
+        );
+        assert!(matches!(
+            run(&args(&format!("decompress {}", input))),
+            Err(Failure::Usage(_))
+        ));
+        assert!(matches!(run(&args("squash x")), Err(Failure::Usage(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn help_test() {
+        let dir = scratch_dir("help");
+        let input = dir.join("in.txt");
+        fs::write(&input, "dagoth ur was a hotep").unwrap();
+        let input = input.to_str().unwrap();
+        // -h just prints the help, wherever it is
+        run(&args(&format!("compress {} -h", input))).unwrap();
+        assert!(!PathBuf::from(format!("{}.huf", input)).exists());
+        // but not when it's some other flag's value
+        run(&args(&format!("compress --keep --comment -h {}", input))).unwrap();
+        assert!(PathBuf::from(format!("{}.huf", input)).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
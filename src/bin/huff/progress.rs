@@ -0,0 +1,188 @@
+//! The progress bar on stderr, and how fast (de)compressing went for `--verbose`
+
+use crate::args::Args;
+use crate::STDIO;
+use std::fmt::Write as _;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the progress bar gets redrawn
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How many characters wide the progress bar's bar is
+const PROGRESS_WIDTH: usize = 30;
+
+/// A progress bar on stderr, redrawn every so often on a thread of its own until it's dropped
+pub(crate) struct ProgressBar {
+    /// How much of the work is done, which whatever's doing it adds to
+    counter: Arc<AtomicU64>,
+    /// Set once the work's done, to stop the redrawing
+    done: Arc<AtomicBool>,
+    /// The thread doing the redrawing
+    drawer: Option<thread::JoinHandle<()>>,
+}
+
+impl ProgressBar {
+    /// Starts showing a progress bar for (de)compressing one file into another, unless there's `--quiet`, stderr
+    /// isn't a terminal, or how much work there is isn't known
+    ///
+    /// ## Arguments
+    ///
+    /// * `args`: the command's arguments
+    /// * `src`: the input (`-` for stdin)
+    /// * `dst`: the output (`-` for stdout)
+    /// * `total`: how much work there is, i.e. how many bytes will get read
+    pub(crate) fn start(args: &Args, src: &str, dst: &str, total: Option<u64>) -> Option<Self> {
+        let total = total.filter(|&total| total > 0)?;
+        if args.has("quiet") || src == STDIO || dst == STDIO || !io::stderr().is_terminal() {
+            return None;
+        }
+        let counter = Arc::new(AtomicU64::new(0));
+        let done = Arc::new(AtomicBool::new(false));
+        let (read, finished, label) = (counter.clone(), done.clone(), src.to_string());
+        let drawer = thread::spawn(move || {
+            let started = Instant::now();
+            let mut width = 0;
+            while !finished.load(Ordering::Relaxed) {
+                thread::sleep(PROGRESS_INTERVAL);
+                let line = render_progress(
+                    &label,
+                    read.load(Ordering::Relaxed),
+                    total,
+                    started.elapsed(),
+                );
+                width = width.max(line.chars().count());
+                eprint!("\r{}", line);
+            }
+            // wiped, so whatever gets printed next starts on a clean line
+            if width > 0 {
+                eprint!("\r{:1$}\r", "", width);
+            }
+        });
+        Some(ProgressBar {
+            counter,
+            done,
+            drawer: Some(drawer),
+        })
+    }
+
+    /// Gets the counter the work adds to as it goes
+    pub(crate) fn counter(&self) -> Arc<AtomicU64> {
+        self.counter.clone()
+    }
+}
+
+impl Drop for ProgressBar {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(drawer) = self.drawer.take() {
+            let _ = drawer.join();
+        }
+    }
+}
+
+/// Draws one line of a progress bar: the bar, how far along it is, how fast it's going, and how long it's got left
+///
+/// ## Arguments
+///
+/// * `label`: what's being worked on
+/// * `done`: how much of the work is done
+/// * `total`: how much work there is
+/// * `elapsed`: how long it's been going
+fn render_progress(label: &str, done: u64, total: u64, elapsed: Duration) -> String {
+    let fraction = (done as f64 / total.max(1) as f64).min(1.0);
+    let filled = (fraction * PROGRESS_WIDTH as f64) as usize;
+    let rate = done as f64 / elapsed.as_secs_f64().max(1e-9);
+    let eta = match done {
+        0 => "--:--".to_string(),
+        done => {
+            let secs = (total.saturating_sub(done) as f64 / rate).round() as u64;
+            match secs / 3600 {
+                0 => format!("{}:{:02}", secs / 60, secs % 60),
+                hours => format!("{}:{:02}:{:02}", hours, secs / 60 % 60, secs % 60),
+            }
+        }
+    };
+    format!(
+        "{} [{}{}] {:>3}% {:>7.1} MiB/s  ETA {}",
+        label,
+        "#".repeat(filled),
+        "-".repeat(PROGRESS_WIDTH - filled),
+        (fraction * 100.0) as u32,
+        rate / (1024.0 * 1024.0),
+        eta
+    )
+}
+
+/// Describes how fast (de)compressing went, for `--verbose`
+///
+/// ## Arguments
+///
+/// * `len`: how many bytes went in
+/// * `elapsed`: how long it took, start to finish
+/// * `busy`: how long all the threads spent working on blocks, added up (zero if that wasn't kept track of)
+/// * `threads`: how many threads there were
+pub(crate) fn describe_speed(
+    len: u64,
+    elapsed: Duration,
+    busy: Duration,
+    threads: usize,
+) -> String {
+    let secs = elapsed.as_secs_f64().max(1e-9);
+    let mut out = format!(
+        "{} bytes in {:.2}s ({:.1} MiB/s)",
+        len,
+        elapsed.as_secs_f64(),
+        len as f64 / secs / (1024.0 * 1024.0)
+    );
+    if threads > 1 {
+        write!(out, " on {} threads", threads).unwrap();
+        if !busy.is_zero() {
+            write!(
+                out,
+                ", {:.1}x as fast as one thread",
+                busy.as_secs_f64() / secs
+            )
+            .unwrap();
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{render_progress, ProgressBar};
+    use crate::args::Args;
+    use crate::test::args;
+    use crate::COMPRESS_FLAGS;
+    use std::time::Duration;
+
+    #[test]
+    fn progress_test() {
+        let line = render_progress("big.txt", 0, 100, Duration::ZERO);
+        assert_eq!(
+            line,
+            format!("big.txt [{}]   0%     0.0 MiB/s  ETA --:--", "-".repeat(30))
+        );
+        let mib = 1024 * 1024;
+        let line = render_progress("big.txt", 30 * mib, 100 * mib, Duration::from_secs(3));
+        assert!(line.starts_with(&format!(
+            "big.txt [{}{}]  30%",
+            "#".repeat(9),
+            "-".repeat(21)
+        )));
+        assert!(line.ends_with("   10.0 MiB/s  ETA 0:07"));
+        let line = render_progress("big.txt", mib, 10_000 * mib, Duration::from_secs(1));
+        assert!(line.ends_with("ETA 2:46:39"));
+        // never more than all the way, even when a counter overshoots
+        assert!(render_progress("x", 200, 100, Duration::from_secs(1)).contains("] 100%"));
+        let quiet = Args::parse(&args("-q big.txt"), COMPRESS_FLAGS).unwrap();
+        assert!(ProgressBar::start(&quiet, "big.txt", "big.txt.huf", Some(100)).is_none());
+        let loud = Args::parse(&args("big.txt"), COMPRESS_FLAGS).unwrap();
+        assert!(ProgressBar::start(&loud, "-", "big.txt.huf", Some(100)).is_none());
+        assert!(ProgressBar::start(&loud, "big.txt", "big.txt.huf", None).is_none());
+    }
+}
@@ -0,0 +1,135 @@
+//! `huff repl`, which walks through Huffman coding each line typed in
+
+use crate::args::Args;
+use crate::Failure;
+use huffman::btree::HuffTree;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+/// `huff repl`
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn repl(args: &Args) -> Result<(), Failure> {
+    if !args.positional.is_empty() {
+        return Err(Failure::Usage(
+            "repl reads what to show from stdin, a line at a time".to_string(),
+        ));
+    }
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+    if interactive {
+        println!("type something to see how it gets Huffman coded (:q or ctrl-d to quit)");
+    }
+    let mut lines = stdin.lock().lines();
+    loop {
+        if interactive {
+            print!("huff> ");
+            io::stdout()
+                .flush()
+                .map_err(|err| Failure::Run(format!("stdout: {}", err)))?;
+        }
+        let line = match lines.next() {
+            Some(line) => line.map_err(|err| Failure::Run(format!("stdin: {}", err)))?,
+            None => break,
+        };
+        match line.as_str() {
+            ":q" | ":quit" => break,
+            "" => continue,
+            line => print!("{}", lesson(line)),
+        }
+    }
+    if interactive {
+        println!();
+    }
+    Ok(())
+}
+
+/// Walks through Huffman coding some text: how often each character shows up, every merge that builds the tree, the
+/// tree itself, the codes it gives, and what the text comes out as
+///
+/// ## Arguments
+///
+/// * `input`: the text
+fn lesson(input: &str) -> String {
+    let freqs = HuffTree::find_input_freqs(input);
+    let mut tree = HuffTree::new();
+    tree.populate_tree(&freqs);
+    let codes = tree.generate_huffman_map();
+    let mut out = String::new();
+    writeln!(out, "counts (most common first):").unwrap();
+    let mut counts: Vec<(char, i32)> = freqs.iter().map(|(&ch, &freq)| (ch, freq)).collect();
+    counts.sort_unstable_by_key(|&(ch, freq)| (std::cmp::Reverse(freq), ch));
+    for (ch, freq) in &counts {
+        writeln!(out, "    {:<8} {}", format!("{:?}", ch), freq).unwrap();
+    }
+    // a merged node gets shown as everything under it
+    let node = |(chars, freq): &(Vec<char>, i32)| match chars[..] {
+        [ch] => format!("{:?} ({})", ch, freq),
+        _ => format!("{:?} ({})", chars.iter().collect::<String>(), freq),
+    };
+    writeln!(out, "building the tree, smallest two first:").unwrap();
+    let merges = tree.merges();
+    if merges.is_empty() {
+        writeln!(out, "    nothing to merge with just the one character").unwrap();
+    }
+    for (k, merge) in merges.iter().enumerate() {
+        writeln!(
+            out,
+            "    {:>3}. {} + {} -> ({})",
+            k + 1,
+            node(&merge.left),
+            node(&merge.right),
+            merge.left.1 + merge.right.1
+        )
+        .unwrap();
+    }
+    writeln!(out, "the tree (0 goes left, 1 goes right):").unwrap();
+    for line in tree.render().lines() {
+        writeln!(out, "    {}", line).unwrap();
+    }
+    writeln!(out, "codes:").unwrap();
+    for (ch, _) in &counts {
+        let code = &codes[ch];
+        let code = if code.is_empty() {
+            "(none needed)"
+        } else {
+            code
+        };
+        writeln!(out, "    {:<8} {}", format!("{:?}", ch), code).unwrap();
+    }
+    let bits: Vec<&str> = input.chars().map(|ch| codes[&ch].as_str()).collect();
+    let coded: usize = bits.iter().map(|code| code.len()).sum();
+    writeln!(out, "encoded (a space between each character's code):").unwrap();
+    writeln!(out, "    {}", bits.join(" ")).unwrap();
+    writeln!(
+        out,
+        "{} bits, against {} as UTF-8 ({:.1}%), not counting the tree",
+        coded,
+        input.len() * 8,
+        coded as f64 / (input.len() * 8) as f64 * 100.0
+    )
+    .unwrap();
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::lesson;
+
+    #[test]
+    fn lesson_test() {
+        let shown = lesson("aabc");
+        assert!(shown.contains("    'a'      2\n    'b'      1\n    'c'      1\n"));
+        assert!(shown
+            .contains("      1. 'b' (1) + 'c' (1) -> (2)\n      2. 'a' (2) + \"bc\" (2) -> (4)\n"));
+        assert!(shown.contains("    └─1─ (2)\n"));
+        assert!(shown.contains("    'a'      0\n    'b'      10\n    'c'      11\n"));
+        assert!(shown.contains("    0 0 10 11\n6 bits, against 32 as UTF-8 (18.8%)"));
+        let shown = lesson("zzz");
+        assert!(shown.contains("nothing to merge"));
+        assert!(shown.contains("(none needed)"));
+        assert!(shown.contains("0 bits, against 24"));
+    }
+}
@@ -0,0 +1,302 @@
+//! `huff stats` and `huff tree`
+
+use crate::args::Args;
+use crate::json::Json;
+use crate::{Failure, STDIO};
+use huffman::btree::{ForestOptions, HuffTree, TieBreak};
+use huffman::container::{Header, Payload};
+use huffman::freq::FrequencyMap;
+use huffman::{shannon_fano, Error};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufReader};
+
+/// How many of the most common bytes `stats` lists, unless `--top` says otherwise
+const DEFAULT_TOP: usize = 10;
+
+/// `huff stats`
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn stats(args: &Args) -> Result<(), Failure> {
+    let src = args.input()?;
+    let top = match args.value("top") {
+        Some(top) => top
+            .parse()
+            .map_err(|_| Failure::Usage(format!("--top needs a number, not {}", top)))?,
+        None => DEFAULT_TOP,
+    };
+    let freqs = match src {
+        STDIO => FrequencyMap::from_reader_bytes(io::stdin().lock()),
+        src => {
+            File::open(src).and_then(|input| FrequencyMap::from_reader_bytes(BufReader::new(input)))
+        }
+    }
+    .map_err(|err| Failure::on(src, err.into()))?;
+    if args.has("json") {
+        println!("{}", stats_json(&freqs, top));
+    } else {
+        print!("{}", describe(&freqs, top));
+    }
+    Ok(())
+}
+
+/// Sums up how well some data would compress: its entropy, how close the codes would get to it (and how close
+/// Shannon–Fano codes would, for comparison), and the most common bytes
+///
+/// ## Arguments
+///
+/// * `freqs`: how often each byte shows up in the data
+/// * `top`: how many of the most common bytes to list
+fn describe(freqs: &FrequencyMap, top: usize) -> String {
+    let total = freqs.total();
+    let mut tree = HuffTree::new();
+    tree.populate_tree(freqs.as_map());
+    let bits = HuffTree::encoded_len(freqs.as_map(), &tree.generate_huffman_map());
+    let fano_bits = HuffTree::encoded_len(
+        freqs.as_map(),
+        &shannon_fano::generate_huffman_map(freqs.as_map()),
+    );
+    // an empty input is a 0/0, which can just as well be nothing at all
+    let per_byte = |bits: f64| if total == 0 { 0.0 } else { bits / total as f64 };
+    let mut out = String::new();
+    writeln!(out, "bytes:    {}", total).unwrap();
+    writeln!(out, "symbols:  {} distinct", freqs.len()).unwrap();
+    writeln!(out, "entropy:  {:.3} bits/byte", freqs.entropy()).unwrap();
+    writeln!(
+        out,
+        "codes:    {:.3} bits/byte, {} bytes ({:.1}% of the original, before the header)",
+        per_byte(bits as f64),
+        bits.div_ceil(8),
+        per_byte(bits as f64) / 8.0 * 100.0
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "          (Shannon–Fano's would be {:.3} bits/byte, {} bytes)",
+        per_byte(fano_bits as f64),
+        fano_bits.div_ceil(8)
+    )
+    .unwrap();
+    let common = freqs.most_common(top);
+    if !common.is_empty() {
+        writeln!(out, "most common:").unwrap();
+    }
+    for (ch, count) in common {
+        let byte: String = std::ascii::escape_default(ch as u8)
+            .map(char::from)
+            .collect();
+        writeln!(
+            out,
+            "    {:<6}{:>12}  {:>5.1}%",
+            format!("'{}'", byte),
+            count,
+            per_byte(count as f64) * 100.0
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Puts how well some data would compress into JSON, like `describe()` lays it out as text
+///
+/// ## Arguments
+///
+/// * `freqs`: how often each byte shows up in the data
+/// * `top`: how many of the most common bytes to list
+pub(crate) fn stats_json(freqs: &FrequencyMap, top: usize) -> Json {
+    let total = freqs.total();
+    let mut tree = HuffTree::new();
+    tree.populate_tree(freqs.as_map());
+    let bits = HuffTree::encoded_len(freqs.as_map(), &tree.generate_huffman_map());
+    let fano_bits = HuffTree::encoded_len(
+        freqs.as_map(),
+        &shannon_fano::generate_huffman_map(freqs.as_map()),
+    );
+    let per_byte = |bits: f64| if total == 0 { 0.0 } else { bits / total as f64 };
+    let common = freqs
+        .most_common(top)
+        .into_iter()
+        .map(|(ch, count)| {
+            Json::Object(vec![
+                ("byte", (ch as u64).into()),
+                ("count", (count as u64).into()),
+                ("share", per_byte(count as f64).into()),
+            ])
+        })
+        .collect();
+    Json::Object(vec![
+        ("bytes", total.into()),
+        ("symbols", freqs.len().into()),
+        ("entropy", freqs.entropy().into()),
+        ("bits_per_byte", per_byte(bits as f64).into()),
+        ("coded_bytes", bits.div_ceil(8).into()),
+        (
+            "shannon_fano_bits_per_byte",
+            per_byte(fano_bits as f64).into(),
+        ),
+        ("most_common", Json::List(common)),
+    ])
+}
+
+/// `huff tree`
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn tree(args: &Args) -> Result<(), Failure> {
+    let src = args.input()?;
+    let input: Box<dyn io::Read> = match src {
+        STDIO => Box::new(io::stdin().lock()),
+        src => Box::new(BufReader::new(
+            File::open(src).map_err(|err| Failure::on(src, err.into()))?,
+        )),
+    };
+    let freqs = if args.has("from-archive") {
+        archive_freqs(input)
+    } else {
+        FrequencyMap::from_reader_bytes(input).map_err(Error::from)
+    }
+    .map_err(|err| Failure::on(src, err))?;
+    let tie_break = if args.has("min-variance") {
+        TieBreak::MinVariance
+    } else {
+        TieBreak::Newest
+    };
+    let mut tree = HuffTree::new();
+    tree.populate_tree_with(freqs.as_map(), tie_break);
+    if args.has("json") {
+        println!("{}", tree_json(&mut tree));
+    } else if args.has("dot") {
+        print!("{}", tree.to_dot());
+    } else if args.has("latex") {
+        let options = ForestOptions {
+            codes: args.has("codes"),
+            ..ForestOptions::default()
+        };
+        print!("{}", tree.to_forest(&options));
+    } else {
+        print!("{}", describe_tree(&mut tree));
+    }
+    Ok(())
+}
+
+/// Puts a tree's codes into JSON, in the same order `describe_tree()` lists them
+///
+/// ## Arguments
+///
+/// * `tree`: the tree
+pub(crate) fn tree_json(tree: &mut HuffTree) -> Json {
+    let mut codes: Vec<(char, String)> = tree.generate_huffman_map().into_iter().collect();
+    codes.sort_unstable_by(|(_, a), (_, b)| (a.len(), a).cmp(&(b.len(), b)));
+    let codes = codes
+        .into_iter()
+        .map(|(ch, code)| Json::Object(vec![("byte", (ch as u64).into()), ("code", code.into())]))
+        .collect();
+    Json::Object(vec![("codes", Json::List(codes))])
+}
+
+/// Gets the frequencies a container's tree was built from, out of its header
+///
+/// A file made in block mode has a tree per block, and this is the first block's; a streaming container doesn't have
+/// a fixed tree at all.
+///
+/// ## Arguments
+///
+/// * `input`: the container
+fn archive_freqs<R: io::Read>(mut input: R) -> huffman::Result<FrequencyMap> {
+    loop {
+        match Header::read_from(&mut input)?.payload {
+            Payload::Static { freqs, .. } => return Ok(freqs),
+            Payload::Streaming => {
+                return Err(Error::Unsupported(
+                    "streaming containers don't have a fixed tree",
+                ))
+            }
+            Payload::Dictionary { .. } => {
+                return Err(Error::Unsupported(
+                    "the tree came from a dictionary, which isn't in the file",
+                ))
+            }
+            Payload::Canonical { .. } => {
+                return Err(Error::Unsupported(
+                    "canonical codes only keep their lengths, not the frequencies a tree gets built from",
+                ))
+            }
+            // the first input's container comes right after the index
+            Payload::Index { .. } => continue,
+        }
+    }
+}
+
+/// Lists every code (shortest first) and then draws the tree they came from
+///
+/// ## Arguments
+///
+/// * `tree`: the tree
+fn describe_tree(tree: &mut HuffTree) -> String {
+    let mut codes: Vec<(char, String)> = tree.generate_huffman_map().into_iter().collect();
+    codes.sort_unstable_by(|(_, a), (_, b)| (a.len(), a).cmp(&(b.len(), b)));
+    let mut out = String::new();
+    writeln!(out, "codes:").unwrap();
+    for (ch, code) in codes {
+        writeln!(out, "    {:<10}{}", format!("{:?}", ch), code).unwrap();
+    }
+    write!(out, "\ntree:\n{}", tree.render()).unwrap();
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{archive_freqs, describe, describe_tree};
+    use huffman::btree::HuffTree;
+    use huffman::container::compress_bytes;
+    use huffman::freq::FrequencyMap;
+    use huffman::EncodeOptions;
+
+    #[test]
+    fn stats_test() {
+        let report = describe(&FrequencyMap::from("aaaabbcd"), 2);
+        assert!(report.contains("bytes:    8\n"));
+        assert!(report.contains("entropy:  1.750 bits/byte"));
+        // the codes hit the entropy exactly, since every frequency is a power of two
+        assert!(report.contains("codes:    1.750 bits/byte, 2 bytes"));
+        assert!(report.contains("(Shannon–Fano's would be 1.750 bits/byte, 2 bytes)"));
+        // but Shannon–Fano's splits aren't always as good
+        let report = describe(
+            &FrequencyMap::from("aaaaaaaaaaaaaaabbbbbbbccccccddddddeeeee"),
+            2,
+        );
+        assert!(report.contains("codes:    2.231 bits/byte, 11 bytes"));
+        assert!(report.contains("(Shannon–Fano's would be 2.282 bits/byte, 12 bytes)"));
+        assert!(report.contains("'a'"));
+        assert!(report.contains("'b'"));
+        assert!(!report.contains("'c'"));
+        assert!(describe(&FrequencyMap::new(), 10).contains("codes:    0.000 bits/byte, 0 bytes"));
+    }
+
+    #[test]
+    fn tree_test() {
+        let data = b"aabc";
+        let mut tree = HuffTree::new();
+        tree.populate_tree(FrequencyMap::from("aabc").as_map());
+        let described = describe_tree(&mut tree);
+        assert!(
+            described.starts_with("codes:\n    'a'       0\n    'b'       10\n    'c'       11\n")
+        );
+        assert!(described.ends_with(&tree.render()));
+        // the archive's header has the same frequencies the tree came from
+        let archive = compress_bytes(data, &EncodeOptions::default()).unwrap();
+        assert_eq!(
+            archive_freqs(&archive[..]).unwrap(),
+            FrequencyMap::from("aabc")
+        );
+        let streaming = EncodeOptions {
+            streaming: true,
+            ..EncodeOptions::default()
+        };
+        let archive = compress_bytes(data, &streaming).unwrap();
+        assert!(archive_freqs(&archive[..]).is_err());
+    }
+}
@@ -0,0 +1,102 @@
+//! `huff train`, which trains a dictionary for `--dict`
+
+use crate::args::Args;
+use crate::files::{check_overwrite, open_output};
+use crate::json::Json;
+use crate::{Failure, STDIO};
+use huffman::Dictionary;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+
+/// `huff train`
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn train(args: &Args) -> Result<(), Failure> {
+    let dst = args.value("out").ok_or_else(|| {
+        Failure::Usage("train needs an --out to save the dictionary to".to_string())
+    })?;
+    if args.positional.is_empty() {
+        return Err(Failure::Usage("no files to train on given".to_string()));
+    }
+    check_overwrite(dst, true, args)?;
+    let mut samples: Vec<Box<dyn io::Read>> = Vec::with_capacity(args.positional.len());
+    for src in &args.positional {
+        samples.push(match src.as_str() {
+            STDIO => Box::new(io::stdin().lock()),
+            src => Box::new(BufReader::new(
+                File::open(src).map_err(|err| Failure::on(src, err.into()))?,
+            )),
+        });
+    }
+    let dict = Dictionary::train(samples).map_err(|err| Failure::Run(err.to_string()))?;
+    let mut output = open_output(dst)?;
+    dict.write_to(&mut output)
+        .and_then(|_| output.flush())
+        .map_err(|err| Failure::on(dst, err.into()))?;
+    if dst != STDIO && args.has("json") {
+        let json = Json::Object(vec![
+            ("dictionary", dst.into()),
+            ("id", format!("{:08x}", dict.id()).into()),
+            ("files", args.positional.len().into()),
+        ]);
+        println!("{}", json);
+    } else if dst != STDIO {
+        println!(
+            "{}: dictionary {:08x}, trained on {} files",
+            dst,
+            dict.id(),
+            args.positional.len()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::{args, scratch_dir};
+    use crate::{run, Failure};
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn dictionary_test() {
+        let dir = scratch_dir("dictionary");
+        let corpus = dir.join("corpus.txt");
+        fs::write(&corpus, "dagoth ur was a hotep".repeat(100)).unwrap();
+        let dict = dir.join("model.huffdict");
+        let (corpus, dict) = (corpus.to_str().unwrap(), dict.to_str().unwrap());
+        run(&args(&format!(
+            "train --out {} {} {}",
+            dict, corpus, corpus
+        )))
+        .unwrap();
+        let input = dir.join("msg.txt");
+        fs::write(&input, "a hotep was dagoth").unwrap();
+        let input = input.to_str().unwrap();
+        run(&args(&format!("compress --dict {} {}", dict, input))).unwrap();
+        assert!(!PathBuf::from(input).exists());
+        let plain = huffman::container::compress_bytes(b"a hotep was dagoth", &Default::default());
+        // no frequencies in the header makes it a good bit smaller
+        assert!(
+            fs::metadata(format!("{}.huf", input)).unwrap().len() < plain.unwrap().len() as u64
+        );
+        assert!(matches!(
+            run(&args(&format!("decompress {}.huf", input))),
+            Err(Failure::Run(_))
+        ));
+        // nor does a different dictionary
+        let other = dir.join("other.huffdict");
+        let other = other.to_str().unwrap();
+        run(&args(&format!("train -o {} {}.huf", other, input))).unwrap();
+        assert!(matches!(
+            run(&args(&format!("decompress --dict {} {}.huf", other, input))),
+            Err(Failure::Run(_))
+        ));
+        assert!(PathBuf::from(format!("{}.huf", input)).exists());
+        run(&args(&format!("decompress --dict {} {}.huf", dict, input))).unwrap();
+        assert_eq!(fs::read_to_string(input).unwrap(), "a hotep was dagoth");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
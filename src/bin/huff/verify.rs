@@ -0,0 +1,280 @@
+//! `huff verify`, and checking what `--verify-on-write` just wrote decompresses back to its input
+
+use crate::args::Args;
+use crate::files::load_dictionary;
+use crate::json::Json;
+use crate::{Failure, STDIO};
+use huffman::{container, DecodeOptions, EncodeOptions, Error};
+use std::fs;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+
+/// A writer that checks everything written to it is what a series of readers gives, one after another, rather than
+/// keeping any of it
+struct Matching<I: Iterator> {
+    /// The readers still to come
+    expected: I,
+    /// The one being read from now
+    current: Option<I::Item>,
+    /// How many bytes have matched so far
+    matched: u64,
+    /// Where (and how) it stopped matching, if it has
+    mismatch: Option<String>,
+    /// Somewhere to read the expected bytes into
+    buf: Vec<u8>,
+}
+
+impl<I: Iterator<Item = R>, R: Read> Matching<I> {
+    /// Makes a writer that checks what's written is what the readers give
+    ///
+    /// ## Arguments
+    ///
+    /// * `expected`: the readers
+    fn new<T: IntoIterator<IntoIter = I>>(expected: T) -> Self {
+        let mut expected = expected.into_iter();
+        Matching {
+            current: expected.next(),
+            expected,
+            matched: 0,
+            mismatch: None,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Reads as much as there's room for, moving on from each reader to the next as it runs out, and gives back how
+    /// much there was
+    ///
+    /// ## Arguments
+    ///
+    /// * `buf`: where it goes
+    fn read_expected(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let reader = match &mut self.current {
+                Some(reader) => reader,
+                None => break,
+            };
+            match reader.read(&mut buf[filled..]) {
+                Ok(0) => self.current = self.expected.next(),
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(filled)
+    }
+
+    /// Checks there was nothing more to come, once everything's been written
+    fn finish(&mut self) -> io::Result<()> {
+        if self.mismatch.is_none() && self.read_expected(&mut [0])? != 0 {
+            self.mismatch = Some(format!(
+                "only {} bytes came back out, but there was more than that",
+                self.matched
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<I: Iterator<Item = R>, R: Read> Write for Matching<I> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.resize(data.len(), 0);
+        let n = self.read_expected(&mut buf)?;
+        let same = buf[..n]
+            .iter()
+            .zip(data)
+            .take_while(|(expected, found)| expected == found)
+            .count();
+        self.buf = buf;
+        self.matched += same as u64;
+        if same < data.len() {
+            self.mismatch = Some(match same {
+                same if same == n => format!(
+                    "more came back out than went in (which was {} bytes)",
+                    self.matched
+                ),
+                _ => format!("what came back out differs at byte {}", self.matched),
+            });
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "round trip mismatch",
+            ));
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Decompresses what just got compressed, for `--verify-on-write`, and checks it's exactly what went in
+///
+/// The output gets deleted if it isn't, so it can't be mistaken for a good one.
+///
+/// ## Arguments
+///
+/// * `dst`: the compressed file
+/// * `options`: how it got compressed
+/// * `inputs`: what went into it, in order
+pub(crate) fn verify_written<I, R>(
+    dst: &str,
+    options: &EncodeOptions,
+    inputs: I,
+) -> Result<(), Failure>
+where
+    I: IntoIterator<Item = R>,
+    R: Read,
+{
+    let options = DecodeOptions {
+        dictionary: options.dictionary.clone(),
+        ..DecodeOptions::default()
+    };
+    let mut matching = Matching::new(inputs);
+    let result = File::open(dst)
+        .map_err(Error::from)
+        .and_then(|file| container::decompress_with(BufReader::new(file), &mut matching, &options))
+        .and_then(|matching| Ok(matching.finish()?));
+    let why = match (matching.mismatch, result) {
+        (Some(why), _) => why,
+        (None, Ok(())) => return Ok(()),
+        (None, Err(err @ Error::Io(_))) => return Err(Failure::on(dst, err)),
+        (None, Err(err)) => format!("it doesn't decompress ({})", err),
+    };
+    let _ = fs::remove_file(dst);
+    Err(Failure::Mismatch(format!(
+        "{}: didn't decompress back to what was compressed, so it's been deleted: {}",
+        dst, why
+    )))
+}
+
+/// `huff verify`
+///
+/// ## Arguments
+///
+/// * `args`: the command's arguments
+pub(crate) fn verify(args: &Args) -> Result<(), Failure> {
+    let src = args.input()?;
+    let options = DecodeOptions {
+        dictionary: load_dictionary(args)?,
+        ..DecodeOptions::default()
+    };
+    let result = match src {
+        STDIO => container::verify(io::stdin().lock(), &options),
+        src => File::open(src)
+            .map_err(Error::from)
+            .and_then(|input| container::verify(BufReader::new(input), &options)),
+    };
+    let failure = match result {
+        Ok(len) if args.has("json") => {
+            let json = Json::Object(vec![
+                ("file", src.into()),
+                ("ok", true.into()),
+                ("bytes", len.into()),
+            ]);
+            println!("{}", json);
+            return Ok(());
+        }
+        Ok(len) => {
+            println!("{}: ok ({} bytes)", src, len);
+            return Ok(());
+        }
+        Err(err) => Failure::verifying(src, err),
+    };
+    if args.has("json") {
+        let problem = match failure {
+            Failure::BadHeader(_) => "bad_header",
+            Failure::Corrupt(_) => "corrupt",
+            _ => "error",
+        };
+        let json = Json::Object(vec![
+            ("file", src.into()),
+            ("ok", false.into()),
+            ("problem", problem.into()),
+            ("error", failure.why().into()),
+        ]);
+        println!("{}", json);
+    }
+    Err(failure)
+}
+
+#[cfg(test)]
+mod test {
+    use super::verify_written;
+    use crate::test::{args, scratch_dir};
+    use crate::{run, Failure};
+    use huffman::EncodeOptions;
+    use std::fs;
+
+    #[test]
+    fn verify_test() {
+        let dir = scratch_dir("verify");
+        let input = dir.join("in.txt");
+        fs::write(&input, "dagoth ur was a hotep".repeat(100)).unwrap();
+        let input = input.to_str().unwrap();
+        run(&args(&format!("compress -k {}", input))).unwrap();
+        let compressed = format!("{}.huf", input);
+        run(&args(&format!("verify {}", compressed))).unwrap();
+        let mut data = fs::read(&compressed).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        fs::write(&compressed, &data).unwrap();
+        assert!(matches!(
+            run(&args(&format!("verify {}", compressed))),
+            Err(Failure::Corrupt(_))
+        ));
+        assert!(matches!(
+            run(&args(&format!("verify {}", input))),
+            Err(Failure::BadHeader(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn verify_on_write_test() {
+        let dir = scratch_dir("verify-on-write");
+        let input = dir.join("in.txt");
+        fs::write(&input, "dagoth ur was a hotep").unwrap();
+        let input = input.to_str().unwrap();
+        run(&args(&format!("compress --verify-on-write {}", input))).unwrap();
+        let compressed = format!("{}.huf", input);
+        assert!(!std::path::Path::new(input).exists());
+        let options = EncodeOptions::default();
+        verify_written(&compressed, &options, vec![&b"dagoth ur was a hotep"[..]]).unwrap();
+        // split up any which way, it's still the same
+        verify_written(
+            &compressed,
+            &options,
+            vec![&b"dagoth"[..], b"", b" ur was a hotep"],
+        )
+        .unwrap();
+        for (wrong, why) in [
+            ("dagoth ur was a hotel", "differs at byte 20"),
+            (
+                "dagoth ur",
+                "more came back out than went in (which was 9 bytes)",
+            ),
+            ("dagoth ur was a hotep!", "only 21 bytes came back out"),
+        ] {
+            fs::copy(&compressed, dir.join("copy.huf")).unwrap();
+            let copy = dir.join("copy.huf");
+            match verify_written(copy.to_str().unwrap(), &options, vec![wrong.as_bytes()]) {
+                Err(Failure::Mismatch(found)) => assert!(found.contains(why), "{}", found),
+                other => panic!("{:?}", other),
+            }
+            // the bad output doesn't get left lying around
+            assert!(!copy.exists());
+        }
+        // stdout can't be read back
+        fs::write(input, "dagoth ur was a hotep").unwrap();
+        assert!(matches!(
+            run(&args(&format!(
+                "compress -k -o - --verify-on-write {}",
+                input
+            ))),
+            Err(Failure::Usage(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
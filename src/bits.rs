@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, BufRead};
+
+/// Somewhere for packed bytes to go
+pub(crate) trait Sink {
+    /// Appends a byte
+    fn push(&mut self, byte: u8);
+
+    /// Appends a whole 64-bit word's worth of bytes at once
+    fn push_word(&mut self, bytes: [u8; 8]) {
+        for byte in bytes {
+            self.push(byte);
+        }
+    }
+
+    /// Whether there's no room for any more bytes (never, unless the sink is a fixed size)
+    fn is_full(&self) -> bool {
+        false
+    }
+}
+
+impl Sink for Vec<u8> {
+    fn push(&mut self, byte: u8) {
+        Vec::push(self, byte);
+    }
+
+    fn push_word(&mut self, bytes: [u8; 8]) {
+        self.extend_from_slice(&bytes);
+    }
+}
+
+/// A sink that fills up a caller-provided slice
+pub(crate) struct SliceSink<'a> {
+    /// The slice being filled
+    buf: &'a mut [u8],
+    /// How much of it has been filled
+    len: usize,
+}
+
+impl<'a> SliceSink<'a> {
+    /// Creates a new sink at the start of a slice
+    pub(crate) fn new(buf: &'a mut [u8]) -> Self {
+        SliceSink { buf, len: 0 }
+    }
+
+    /// How many bytes have been pushed
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl Sink for SliceSink<'_> {
+    fn push(&mut self, byte: u8) {
+        self.buf[self.len] = byte;
+        self.len += 1;
+    }
+
+    fn push_word(&mut self, bytes: [u8; 8]) {
+        self.buf[self.len..self.len + 8].copy_from_slice(&bytes);
+        self.len += 8;
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == self.buf.len()
+    }
+}
+
+/// Packs bits into bytes, most significant bit first, for turning Huffman codes into actual compressed output
+///
+/// Bits pile up in a 64-bit register and only get written out a whole word at a time, so pushing a code is a couple
+/// of shifts rather than a loop over its bits (or even its bytes). That means up to 63 bits can be held back at any
+/// point, until `align()` writes out whatever's left.
+#[derive(Default)]
+pub(crate) struct BitWriter {
+    /// Bits that haven't been written out yet, starting from the most significant bit (everything after them is 0)
+    acc: u64,
+    /// How many bits of `acc` are filled (always under 64 between calls)
+    len: u32,
+}
+
+impl BitWriter {
+    /// Creates a new bit writer with nothing pending
+    pub(crate) fn new() -> Self {
+        BitWriter::default()
+    }
+
+    /// Pushes a code given as its bits, right-aligned in a `u64` (the first bit of the code being the most significant)
+    ///
+    /// ## Arguments
+    ///
+    /// * `bits`: the code
+    /// * `len`: how many bits long it is
+    /// * `out`: where completed bytes go
+    pub(crate) fn push_bits<S: Sink>(&mut self, bits: u64, len: u8, out: &mut S) {
+        let len = len as u32;
+        if len == 0 {
+            return;
+        }
+        let bits = bits & (u64::MAX >> (64 - len));
+        let free = 64 - self.len;
+        if len < free {
+            self.acc |= bits << (free - len);
+            self.len += len;
+        } else {
+            // fill the word up, write the whole thing out, and start the next one with whatever's left over
+            let rest = len - free;
+            self.acc |= bits >> rest;
+            out.push_word(self.acc.to_be_bytes());
+            self.acc = bits.checked_shl(64 - rest).unwrap_or(0);
+            self.len = rest;
+        }
+    }
+
+    /// Pushes the code for every byte of `input`, returning `false` (with only the bytes before it pushed) if one of
+    /// them doesn't have a code
+    ///
+    /// With the `simd` feature on an x86-64 CPU that has AVX2, the codes get looked up and joined together four at a
+    /// time; otherwise (or for codes too long to join) this is the same as calling `push_bits()` for each byte.
+    ///
+    /// ## Arguments
+    ///
+    /// * `codes`: the code for each byte
+    /// * `input`: the bytes to push the codes for
+    /// * `out`: where completed bytes go
+    pub(crate) fn push_bytes<S: Sink>(
+        &mut self,
+        codes: &ByteCodes,
+        input: &[u8],
+        out: &mut S,
+    ) -> bool {
+        #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("avx2") {
+                // SAFETY: the CPU's just been checked for AVX2
+                return unsafe { self.push_bytes_avx2(codes, input, out) };
+            }
+        }
+        self.push_bytes_scalar(codes, input, out)
+    }
+
+    /// Pushes the code for every byte of `input` one at a time (see `push_bytes()`)
+    fn push_bytes_scalar<S: Sink>(&mut self, codes: &ByteCodes, input: &[u8], out: &mut S) -> bool {
+        for &byte in input {
+            match codes.get(byte) {
+                Some((bits, len)) => self.push_bits(bits, len, out),
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Pushes the code for every byte of `input` four at a time, gathering the codes in one go and shifting them
+    /// into place side by side (see `push_bytes()`)
+    ///
+    /// Four codes only fit in one 64-bit word if none of them are over 16 bits long, so groups with a longer code in
+    /// them (or a byte with no code at all) go through `push_bytes_scalar()` instead.
+    ///
+    /// ## Safety
+    ///
+    /// The CPU has to support AVX2.
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    #[target_feature(enable = "avx2")]
+    unsafe fn push_bytes_avx2<S: Sink>(
+        &mut self,
+        codes: &ByteCodes,
+        input: &[u8],
+        out: &mut S,
+    ) -> bool {
+        use std::arch::x86_64::*;
+        let mut groups = input.chunks_exact(4);
+        for group in &mut groups {
+            let lens = [
+                codes.lens[group[0] as usize] as u64,
+                codes.lens[group[1] as usize] as u64,
+                codes.lens[group[2] as usize] as u64,
+                codes.lens[group[3] as usize] as u64,
+            ];
+            if lens.iter().any(|&len| len > 16) {
+                if !self.push_bytes_scalar(codes, group, out) {
+                    return false;
+                }
+                continue;
+            }
+            let index = _mm_set_epi32(
+                group[3] as i32,
+                group[2] as i32,
+                group[1] as i32,
+                group[0] as i32,
+            );
+            let bits = _mm256_i32gather_epi64::<8>(codes.bits.as_ptr() as *const i64, index);
+            // each code gets shifted up past all the ones after it
+            let shifts = _mm256_set_epi64x(
+                0,
+                lens[3] as i64,
+                (lens[3] + lens[2]) as i64,
+                (lens[3] + lens[2] + lens[1]) as i64,
+            );
+            let shifted = _mm256_sllv_epi64(bits, shifts);
+            let halves = _mm_or_si128(
+                _mm256_castsi256_si128(shifted),
+                _mm256_extracti128_si256::<1>(shifted),
+            );
+            let word = _mm_cvtsi128_si64(_mm_or_si128(halves, _mm_unpackhi_epi64(halves, halves)));
+            self.push_bits(word as u64, lens.iter().sum::<u64>() as u8, out);
+        }
+        self.push_bytes_scalar(codes, groups.remainder(), out)
+    }
+
+    /// Writes out everything that's been held back, padding out the last partially filled byte (if any) with zeroes
+    pub(crate) fn align<S: Sink>(&mut self, out: &mut S) {
+        for &byte in &self.acc.to_be_bytes()[..self.len.div_ceil(8) as usize] {
+            out.push(byte);
+        }
+        self.acc = 0;
+        self.len = 0;
+    }
+}
+
+/// Marks a byte with no code in `ByteCodes::lens`
+const NO_CODE: u8 = u8::MAX;
+
+/// The codes for every byte value, indexed directly by the byte, for encoding without any hashing
+pub(crate) struct ByteCodes {
+    /// The bits of each byte's code
+    bits: [u64; 256],
+    /// How many bits long each byte's code is, or `NO_CODE` if it doesn't have one
+    lens: [u8; 256],
+}
+
+impl ByteCodes {
+    /// Packs up the codes for the bytes in a Huffman map (chars 0 through 255 standing in for bytes)
+    ///
+    /// ## Arguments
+    ///
+    /// * `huffman_map`: the codes, as '0'/'1' strings (none of them over 64 bits long)
+    pub(crate) fn new(huffman_map: &HashMap<char, String>) -> Self {
+        let mut codes = ByteCodes {
+            bits: [0; 256],
+            lens: [NO_CODE; 256],
+        };
+        for (&ch, code) in huffman_map {
+            if let Ok(byte) = u8::try_from(ch) {
+                codes.bits[byte as usize] = code
+                    .bytes()
+                    .fold(0, |acc, bit| (acc << 1) | (bit == b'1') as u64);
+                codes.lens[byte as usize] = code.len() as u8;
+            }
+        }
+        codes
+    }
+
+    /// Gets the code for a byte, as the bits and how many there are
+    ///
+    /// ## Arguments
+    ///
+    /// * `byte`: the byte to look up
+    pub(crate) fn get(&self, byte: u8) -> Option<(u64, u8)> {
+        match self.lens[byte as usize] {
+            NO_CODE => None,
+            len => Some((self.bits[byte as usize], len)),
+        }
+    }
+}
+
+/// Gets the bit at position `pos` of `bytes`, counting from the most significant bit of the first byte
+pub(crate) fn bit_at(bytes: &[u8], pos: usize) -> bool {
+    (bytes[pos / 8] >> (7 - pos % 8)) & 1 == 1
+}
+
+/// Keeps a window of the next bits of a payload of known length, pulling bytes straight out of a `BufRead`'s buffer
+///
+/// This is for table-driven decoding, which needs to look at several bits before it knows how many it'll use. The
+/// window never reads past the end of the payload, so whatever comes after it is left in the reader.
+pub(crate) struct BitWindow<R: BufRead> {
+    /// Where the bytes come from
+    inner: R,
+    /// The next bits, most significant bit first, with zeroes after the last valid one
+    window: u64,
+    /// How many bits of `window` are valid
+    valid: u32,
+    /// How many bytes of the payload haven't been pulled into the window yet
+    left: u64,
+}
+
+impl<R: BufRead> BitWindow<R> {
+    /// The fewest valid bits the window holds after a refill, unless the payload's run out
+    pub(crate) const MIN_BITS: u32 = 57;
+
+    /// Creates a new bit window at the start of a payload
+    ///
+    /// ## Arguments
+    ///
+    /// * `inner`: where the bytes come from
+    /// * `len`: how many bytes long the payload is
+    pub(crate) fn new(inner: R, len: u64) -> Self {
+        BitWindow {
+            inner,
+            window: 0,
+            valid: 0,
+            left: len,
+        }
+    }
+
+    /// Tops the window up to at least `MIN_BITS` valid bits, or as many as there are left
+    pub(crate) fn refill(&mut self) -> io::Result<()> {
+        while self.valid < Self::MIN_BITS && self.left != 0 {
+            let buf = self.inner.fill_buf()?;
+            if buf.is_empty() {
+                // the payload's been cut short, which shows up as trying to use bits that aren't there
+                return Ok(());
+            }
+            let wanted = ((64 - self.valid) / 8) as usize;
+            let n = buf.len().min(wanted).min(self.left as usize);
+            for &byte in &buf[..n] {
+                self.window |= (byte as u64) << (56 - self.valid);
+                self.valid += 8;
+            }
+            self.inner.consume(n);
+            self.left -= n as u64;
+        }
+        Ok(())
+    }
+
+    /// Gets the window, i.e. the next 64 bits of the payload (zeroes past the end)
+    pub(crate) fn peek(&self) -> u64 {
+        self.window
+    }
+
+    /// Throws away the next `n` bits, returning `false` if there weren't that many
+    ///
+    /// ## Arguments
+    ///
+    /// * `n`: how many bits to throw away (at most `MIN_BITS`)
+    pub(crate) fn consume(&mut self, n: u32) -> bool {
+        if n > self.valid {
+            return false;
+        }
+        // shifting a whole 64 bits out would overflow
+        self.window = self.window.checked_shl(n).unwrap_or(0);
+        self.valid -= n;
+        true
+    }
+
+    /// Whether the whole payload has been used, give or take the padding in the last byte
+    pub(crate) fn is_finished(&self) -> bool {
+        self.left == 0 && self.valid < 8
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BitWriter, ByteCodes};
+    use std::collections::HashMap;
+
+    fn bits_match_code(codes: &[&str]) -> bool {
+        let (mut by_code, mut by_bits) = (BitWriter::new(), BitWriter::new());
+        let (mut code_out, mut bits_out) = (Vec::new(), Vec::new());
+        for code in codes {
+            // one bit at a time, vs the whole code at once
+            for bit in code.bytes() {
+                by_code.push_bits((bit == b'1') as u64, 1, &mut code_out);
+            }
+            let bits = u64::from_str_radix(code, 2).unwrap_or(0);
+            by_bits.push_bits(bits, code.len() as u8, &mut bits_out);
+        }
+        by_code.align(&mut code_out);
+        by_bits.align(&mut bits_out);
+        code_out == bits_out
+    }
+
+    #[test]
+    fn push_bits_test() {
+        assert!(bits_match_code(&["0", "1", "10", "110", "111"]));
+        assert!(bits_match_code(&["", "1", "", "0"]));
+        assert!(bits_match_code(&["1011001", "11111111", "0000000011", "1"]));
+        assert!(bits_match_code(&[&"10".repeat(32), "1", &"01".repeat(31)]));
+    }
+
+    fn bytes_match_bits(input: &[u8], huffman_map: &HashMap<char, String>) -> bool {
+        let codes = ByteCodes::new(huffman_map);
+        let (mut by_bytes, mut by_bits) = (BitWriter::new(), BitWriter::new());
+        let (mut bytes_out, mut bits_out) = (Vec::new(), Vec::new());
+        let all_coded = by_bytes.push_bytes(&codes, input, &mut bytes_out);
+        for &byte in input {
+            match codes.get(byte) {
+                Some((bits, len)) => by_bits.push_bits(bits, len, &mut bits_out),
+                None => break,
+            }
+        }
+        by_bytes.align(&mut bytes_out);
+        by_bits.align(&mut bits_out);
+        bytes_out == bits_out && all_coded == input.iter().all(|&byte| codes.get(byte).is_some())
+    }
+
+    #[test]
+    fn push_bytes_test() {
+        // a code for every byte, from 1 bit up to 40, so some groups of four fit in a word and some don't
+        let huffman_map: HashMap<char, String> = (0..=255u8)
+            .map(|byte| {
+                let len = 1 + byte as usize % 40;
+                (
+                    char::from(byte),
+                    format!("{:b}", byte).repeat(len)[..len].to_string(),
+                )
+            })
+            .collect();
+        let input: Vec<u8> = (0..=255u8).cycle().take(10000).collect();
+        assert!(bytes_match_bits(&input, &huffman_map));
+        assert!(bytes_match_bits(b"", &huffman_map));
+        let short: Vec<u8> = input.iter().map(|&byte| byte % 16).collect();
+        assert!(bytes_match_bits(&short, &huffman_map));
+        // a byte with no code stops everything at that byte
+        let mut partial = huffman_map.clone();
+        partial.remove(&'\u{7}');
+        assert!(bytes_match_bits(&short, &partial));
+    }
+}
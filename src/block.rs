@@ -0,0 +1,164 @@
+//! A writer that compresses in block mode, finishing blocks off as it goes so a reader on the other end of a pipe
+//! can decode them straight away
+
+use crate::container::{self, EncodeOptions};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// How big blocks are if the options don't say
+const DEFAULT_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Compresses everything written to it as a series of independent blocks, each one a complete container
+///
+/// A block gets finished (compressed, written out, and the wrapped writer flushed) once it's `block_size` bytes long,
+/// once it's been open for longer than the maximum delay (if one's set), or whenever `flush()` is called. That makes
+/// this the one to use for pipes and FIFOs: whatever's downstream only ever waits for the current block, never for
+/// the whole input. The output is just a multi-container file, so `decompress()` reads it like any other.
+///
+/// There's no background timer; the delay only gets checked when something is written, so a writer that's gone quiet
+/// needs a `flush()` to push its last block out.
+pub struct BlockWriter<W: Write> {
+    /// Where the compressed blocks go
+    inner: W,
+    /// How to compress each block
+    options: EncodeOptions,
+    /// How many bytes make a full block
+    block_size: usize,
+    /// The longest a block can stay open before it gets finished, if there's a limit
+    max_delay: Option<Duration>,
+    /// The current block's input
+    block: Vec<u8>,
+    /// When the current block got its first byte
+    opened: Option<Instant>,
+    /// Scratch space for the compressed block
+    out: Vec<u8>,
+    /// Whether any blocks have been written yet
+    written: bool,
+}
+
+impl<W: Write> BlockWriter<W> {
+    /// Creates a new block-mode compressor
+    ///
+    /// ## Arguments
+    ///
+    /// * `inner`: the writer the compressed blocks go to
+    /// * `options`: how to compress, with `block_size` giving the block size (64 KiB if it's not set)
+    pub fn new(inner: W, options: &EncodeOptions) -> Self {
+        let block_size = options.block_size.unwrap_or(DEFAULT_BLOCK_SIZE).max(1);
+        BlockWriter {
+            inner,
+            options: options.clone(),
+            block_size,
+            max_delay: None,
+            block: Vec::with_capacity(block_size),
+            opened: None,
+            out: Vec::new(),
+            written: false,
+        }
+    }
+
+    /// Sets the longest a block can stay open before it gets finished, or `None` for no limit (the default)
+    ///
+    /// ## Arguments
+    ///
+    /// * `max_delay`: the limit
+    pub fn set_max_delay(&mut self, max_delay: Option<Duration>) {
+        self.max_delay = max_delay;
+    }
+
+    /// Gets a shared ref to the wrapped writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Finishes off the last block and hands back the writer
+    ///
+    /// If nothing was ever written, this writes a single empty block, so there's always at least one container.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.block.is_empty() || !self.written {
+            self.finish_block()?;
+        }
+        Ok(self.inner)
+    }
+
+    /// Compresses the current block, writes it out, and flushes the wrapped writer
+    fn finish_block(&mut self) -> io::Result<()> {
+        let mut out = std::mem::take(&mut self.out);
+        out = container::compress_block(&self.block, out, &self.options)?;
+        self.inner.write_all(&out)?;
+        out.clear();
+        self.out = out;
+        self.block.clear();
+        self.opened = None;
+        self.written = true;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for BlockWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = data.len().min(self.block_size - self.block.len());
+        self.block.extend_from_slice(&data[..n]);
+        let opened = *self.opened.get_or_insert_with(Instant::now);
+        let overdue = self
+            .max_delay
+            .is_some_and(|delay| opened.elapsed() >= delay);
+        if self.block.len() == self.block_size || overdue {
+            self.finish_block()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.block.is_empty() {
+            return self.inner.flush();
+        }
+        self.finish_block()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::BlockWriter;
+    use crate::container::{decompress_bytes, split_points, EncodeOptions};
+    use std::io::{Cursor, Write};
+    use std::time::Duration;
+
+    fn block_count(input: &[u8], block_size: usize) -> usize {
+        let options = EncodeOptions {
+            block_size: Some(block_size),
+            ..EncodeOptions::default()
+        };
+        let mut writer = BlockWriter::new(Vec::new(), &options);
+        for chunk in input.chunks(777) {
+            writer.write_all(chunk).unwrap();
+        }
+        let compressed = writer.finish().unwrap();
+        assert_eq!(decompress_bytes(&compressed).unwrap(), input);
+        split_points(Cursor::new(&compressed)).unwrap().len()
+    }
+
+    #[test]
+    fn block_writer_test() {
+        let input = "dagoth ur was a hotep".repeat(1000).into_bytes();
+        assert_eq!(block_count(b"", 4096), 1);
+        assert_eq!(block_count(&input, 4096), input.len().div_ceil(4096));
+        assert_eq!(block_count(&input[..8192], 4096), 2);
+    }
+
+    #[test]
+    fn flush_and_delay_test() {
+        let mut writer = BlockWriter::new(Vec::new(), &EncodeOptions::default());
+        writer.write_all(b"dagoth ur").unwrap();
+        assert!(writer.get_ref().is_empty());
+        // everything written so far should be decodable as soon as it's flushed
+        writer.flush().unwrap();
+        assert_eq!(decompress_bytes(writer.get_ref()).unwrap(), b"dagoth ur");
+        writer.set_max_delay(Some(Duration::ZERO));
+        writer.write_all(b" was a hotep").unwrap();
+        assert_eq!(
+            decompress_bytes(writer.get_ref()).unwrap(),
+            b"dagoth ur was a hotep"
+        );
+    }
+}
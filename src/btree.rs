@@ -1,15 +1,116 @@
-use std::collections::HashMap;
+use crate::error::{Error, Result};
+use crate::fsm::DecodeFsm;
+use crate::table::DecodeTable;
+#[cfg(feature = "bitvec")]
+use bitvec::prelude::{BitOrder, BitSlice, BitStore, BitVec, Msb0};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::str::Bytes;
+use std::sync::OnceLock;
 
 /// A custom-made B-tree for doing Huffman coding
+///
+/// The nodes all live in one flat `Vec` and point at each other by index, rather than each being boxed up on its
+/// own, which keeps them together in memory (so walking the tree doesn't keep missing the cache) and means building a
+/// tree is one allocation rather than one per node. That `Vec` is an arena that outlives the tree in it: populating
+/// the same `HuffTree` again reuses it, so building trees for lots of small messages one after another doesn't need
+/// the allocator at all once it's big enough. And since no node owns any other, dropping a tree is just freeing the
+/// `Vec`, with no recursing through boxes, so even absurdly deep trees can't overflow the stack on the way out.
 pub struct HuffTree {
-    /// A pointer to the head(/root) of the tree
+    /// Every node in the tree, leaves first
+    nodes: Vec<Node>,
+    /// The index of the head(/root) of the tree
     head: Link,
+    /// The lookup table for decoding several bits at once, which only gets built if it's asked for
+    table: OnceLock<DecodeTable>,
+    /// The state machine for decoding a byte at a time (if the tree has any codes with bits in them), which also
+    /// only gets built if it's asked for
+    fsm: OnceLock<Option<DecodeFsm>>,
 }
 
-/// A type alias for a pointer to a tree node
-type Link = Option<Box<Node>>;
+/// One step of building a tree: the two smallest nodes left getting merged into one
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Merge {
+    /// The chars under the node that became the left (0) child, and its frequency
+    pub left: (Vec<char>, i32),
+    /// The chars under the node that became the right (1) child, and its frequency
+    pub right: (Vec<char>, i32),
+}
+
+/// What a `HuffTree` gets serialized as: its nodes, in the order they're kept in (leaves, then merged nodes in the order
+/// they were made), with merged nodes pointing at their children by where they are in the list
+///
+/// It's the tree's own layout written down, so the tree comes back exactly as it was, however it was built, and it
+/// doesn't change when the way trees get built does. That's for formats meant for people (like JSON); binary formats
+/// (like postcard and bincode) just get the bytes from `HuffTree::to_bytes()`.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct TreeRepr {
+    /// Every node
+    nodes: Vec<NodeRepr>,
+    /// Which one's the head (`None` for an empty tree)
+    head: Option<u32>,
+}
+
+/// One node of a `TreeRepr`
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum NodeRepr {
+    /// A char and its frequency
+    Leaf { ch: char, freq: i32 },
+    /// Two earlier nodes merged together, with their frequencies added up
+    Merged { freq: i32, left: u32, right: u32 },
+}
+
+/// What `HuffTree::to_forest()` puts in the tree it draws
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ForestOptions {
+    /// Whether every node shows its frequency (on by default)
+    pub freqs: bool,
+    /// Whether the leaves show their codes too (off by default)
+    pub codes: bool,
+}
+
+impl Default for ForestOptions {
+    fn default() -> Self {
+        ForestOptions {
+            freqs: true,
+            codes: false,
+        }
+    }
+}
+
+/// How `HuffTree::populate_tree_with()` picks between nodes of the same frequency
+///
+/// Any choice makes a tree that's just as optimal (the total encoded length comes out the same), but not just as
+/// deep: merging the newest nodes first keeps piling onto the same branch, while merging the oldest (and leaves
+/// before anything merged) keeps the tree as short as an optimal tree can be. Shorter trees mean smaller decoding
+/// tables and fewer bits to walk for the rarest chars, but every tree a container stores gets rebuilt with
+/// `Newest`, so the others are only for trees that get stored some other way (e.g. with `to_bytes()`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TieBreak {
+    /// Merged nodes before leaves, the most recently merged first (the default, which `populate_tree()` uses)
+    #[default]
+    Newest,
+    /// Leaves before merged nodes, the least recently merged first, which gives the lowest variance in code length
+    /// (and so the lowest height) of all the optimal trees
+    MinVariance,
+}
+
+/// A type alias for a pointer to a tree node, i.e. its index in `HuffTree::nodes`
+type Link = Option<u32>;
+
+/// The newest version of `HuffTree::to_bytes()`'s format this build can read and write
+pub const TREE_VERSION: u8 = 1;
+
+/// The most symbols a Huffman map can have for `encode()` to copy it into a `SmallCodebook`
+const SMALL_ALPHABET: usize = 64;
 
 /// A node struct containing frequencies, and pointers to children
+#[derive(Debug)]
 struct Node {
     /// Leaf nodes will contain a char; others will not
     ch: Option<char>,
@@ -44,10 +145,25 @@ impl Default for HuffTree {
     }
 }
 
+/// Just the nodes and the head, since the decoding tables are only ever built from them
+impl fmt::Debug for HuffTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HuffTree")
+            .field("nodes", &self.nodes)
+            .field("head", &self.head)
+            .finish()
+    }
+}
+
 impl HuffTree {
     /// Creates a new empty Huffman tree
     pub fn new() -> Self {
-        HuffTree { head: None }
+        HuffTree {
+            nodes: Vec::new(),
+            head: None,
+            table: OnceLock::new(),
+            fsm: OnceLock::new(),
+        }
     }
     /// Takes an input string and return a hash map of its characters and frequencies
     ///
@@ -73,73 +189,491 @@ impl HuffTree {
     ///
     /// * `char_map`: the hash map in question (from `find_input_freqs()`)
     pub fn populate_tree(&mut self, char_map: &HashMap<char, i32>) {
-        // set up an empty vector of nodes,
-        let mut char_freqs: Vec<Node> = Vec::new();
-        // and use a loop to push all the leaves (i.e. the elements of the hash map) into it
-        for (key, val) in char_map.iter() {
-            let node = Node::new(*key, *val);
-            char_freqs.push(node);
-        }
-        // now we sort from largest to smallest frequency, to turn the thing into a pseudo-priority queue
-        char_freqs.sort_by_key(|m| -m.freq);
-        // and while there are at least two things in the queue, repeat the following:
-        while char_freqs.len() > 1 {
-            // we pop off the smallest two nodes, keeping their frequencies set aside because
-            // the memory model hates me,
-            let right_freq = char_freqs.last().unwrap().freq;
-            let right = char_freqs.pop().map(Box::new);
-            let left_freq = char_freqs.last().unwrap().freq;
-            let left = char_freqs.pop().map(Box::new);
-            // then push their parent node onto the vector,
-            char_freqs.push(Node {
+        self.populate_tree_with(char_map, TieBreak::Newest);
+    }
+
+    /// Constructs the Huffman tree, given a map of character frequencies, breaking ties between equal frequencies the
+    /// way `tie_break` says to
+    ///
+    /// ## Arguments
+    ///
+    /// * `char_map`: the hash map in question (from `find_input_freqs()`)
+    /// * `tie_break`: which of the nodes with the same frequency get merged first
+    pub fn populate_tree_with(&mut self, char_map: &HashMap<char, i32>, tie_break: TieBreak) {
+        // sort from smallest to largest frequency, just the once---ties are broken by char so that the same map always
+        // builds the same tree, no matter what order the hash map hands its entries back in (the streaming decoder
+        // relies on this to rebuild the encoder's trees)
+        let mut char_freqs: Vec<(char, i32)> =
+            char_map.iter().map(|(&ch, &freq)| (ch, freq)).collect();
+        char_freqs.sort_unstable_by_key(|&(ch, freq)| (freq, Reverse(ch)));
+        self.populate_tree_sorted_with(&char_freqs, tie_break);
+    }
+
+    /// Constructs the Huffman tree from a list of character frequencies that's already sorted, in linear time
+    ///
+    /// This is the classic two-queue construction: the leaves wait in one queue (already in order), and the merged
+    /// nodes in another, which stays in order by itself since every merge is at least as big as the last. Among equal
+    /// frequencies, merged nodes come out before leaves, the most recently merged first, and leaves in list order
+    /// (see `populate_tree_sorted_with()` for the other way round).
+    ///
+    /// ## Arguments
+    ///
+    /// * `char_freqs`: the chars and their frequencies, sorted from smallest to largest frequency
+    pub fn populate_tree_sorted(&mut self, char_freqs: &[(char, i32)]) {
+        self.populate_tree_sorted_with(char_freqs, TieBreak::Newest);
+    }
+
+    /// Constructs the Huffman tree from a list of character frequencies that's already sorted, in linear time,
+    /// breaking ties between equal frequencies the way `tie_break` says to
+    ///
+    /// ## Arguments
+    ///
+    /// * `char_freqs`: the chars and their frequencies, sorted from smallest to largest frequency
+    /// * `tie_break`: which of the nodes with the same frequency get merged first
+    pub fn populate_tree_sorted_with(&mut self, char_freqs: &[(char, i32)], tie_break: TieBreak) {
+        // there'll be one node per leaf, plus one for every merge, and the old tree's nodes get reused for them
+        let mut nodes = std::mem::take(&mut self.nodes);
+        nodes.clear();
+        nodes.reserve((2 * char_freqs.len()).saturating_sub(1));
+        nodes.extend(char_freqs.iter().map(|&(ch, freq)| Node::new(ch, freq)));
+        let mut leaves: VecDeque<u32> = (0..nodes.len() as u32).collect();
+        // merged nodes, grouped into runs of equal frequency, so either the newest or the oldest of a run can come out
+        // first
+        let mut merged: VecDeque<VecDeque<u32>> = VecDeque::new();
+        // while there are at least two things in the queues, repeat the following:
+        let mut queued = leaves.len();
+        while queued > 1 {
+            // we take the smallest two nodes,
+            let right = take_smallest(&nodes, &mut leaves, &mut merged, tie_break).unwrap();
+            let left = take_smallest(&nodes, &mut leaves, &mut merged, tie_break).unwrap();
+            // then queue up their parent node
+            let freq = nodes[left as usize].freq + nodes[right as usize].freq;
+            nodes.push(Node {
                 ch: None,
-                freq: left_freq + right_freq,
-                left,
-                right,
+                freq,
+                left: Some(left),
+                right: Some(right),
             });
-            // then re-sort from largest to smallest to again imitate a priority queue
-            char_freqs.sort_by_key(|m| -m.freq);
+            let parent = nodes.len() as u32 - 1;
+            match merged.back_mut() {
+                Some(run) if nodes[run[0] as usize].freq == freq => run.push_back(parent),
+                _ => merged.push_back(VecDeque::from([parent])),
+            }
+            queued -= 1;
         }
-        // once we're done iterating, whatever is left in the vector of nodes must be the head of our tree
-        self.head = char_freqs.pop().map(Box::new);
+        // once we're done iterating, whatever is left must be the head of our tree
+        self.head = take_smallest(&nodes, &mut leaves, &mut merged, tie_break);
+        self.nodes = nodes;
+        // and any decoders compiled from the last tree are out of date
+        self.table = OnceLock::new();
+        self.fsm = OnceLock::new();
     }
 
-    /// Makes the Huffman coding map once the tree is constructed, using tail recursion for tree traversal
+    /// Makes the Huffman coding map once the tree is constructed
     pub fn generate_huffman_map(&mut self) -> HashMap<char, String> {
-        let mut huffman_map: HashMap<char, String> = HashMap::new();
-        // we begin the tail recursion, passing huffman_map mutably so it gets updated through the recursion
-        huffman_map_step(&self.head, String::new(), &mut huffman_map);
-        huffman_map
+        build_huffman_map(&self.nodes, self.head)
     }
 
     /// Takes the uncompressed input string and just converts it straight into its huffman coded version
     ///
+    /// Fails with `Error::UnknownSymbol` if the input has a char that isn't in the map (which would otherwise have
+    /// nothing to be encoded as).
+    ///
+    /// The exact output length gets worked out first (it's just the code lengths added up), so the output is
+    /// allocated once, up front, rather than growing over and over as it's filled in. Maps with no more than 64
+    /// symbols (DNA, hex, digits, and so on) get copied into a `SmallCodebook` on the stack first, so the codes get
+    /// looked up without any hashing.
+    ///
     /// ## Arguments
     ///
     /// `input`: a shared ref to the string to be encoded
     /// `huffman_map`: the Huffman coding map (gotten from `generate_huffman_map()`)
-    pub fn encode(input: &str, huffman_map: &HashMap<char, String>) -> String {
-        let mut encoded_str = String::new();
-        for ch in input.chars() {
-            encoded_str += huffman_map.clone().entry(ch).or_insert_with(String::new);
+    pub fn encode(input: &str, huffman_map: &HashMap<char, String>) -> Result<String> {
+        match SmallCodebook::new(huffman_map) {
+            Some(codebook) => encode_with(input, |ch| codebook.get(ch)),
+            None => encode_with(input, |ch| huffman_map.get(&ch).map(String::as_str)),
         }
-        encoded_str
     }
 
-    /// Traverses the tree to decode the huffman-coded string, using tail recursion to do so
+    /// Works out how many bits an input encodes to from just its char frequencies, without looking at the input
+    ///
+    /// ## Arguments
+    ///
+    /// `char_map`: the input's char frequencies
+    /// `huffman_map`: the Huffman coding map (gotten from `generate_huffman_map()`)
+    pub fn encoded_len(char_map: &HashMap<char, i32>, huffman_map: &HashMap<char, String>) -> u64 {
+        char_map
+            .iter()
+            .map(|(ch, &freq)| freq as u64 * huffman_map.get(ch).map_or(0, String::len) as u64)
+            .sum()
+    }
+
+    /// Traverses the tree to decode the huffman-coded string
     ///
     /// ## Arguments
     ///
     /// `encoded_str`: the Huffman-encoded string to be decoded
     pub fn decode(&self, encoded_str: &str) -> String {
         let mut decoded_str = String::new();
-        let mut encoded_str_cpy = encoded_str.to_owned();
-        while !encoded_str_cpy.is_empty() {
-            decode_step(&self.head, &mut encoded_str_cpy, &mut decoded_str);
+        // the bits get read through a cursor, so the encoded string never has to be copied or chopped up
+        let mut bits = encoded_str.bytes();
+        while bits.len() != 0 {
+            decode_step(&self.nodes, self.head, &mut bits, &mut decoded_str);
         }
         decoded_str
     }
 
+    /// Decodes a Huffman-coded string straight into a caller-provided buffer, as UTF-8, without allocating anything
+    ///
+    /// Returns how many bytes of `out` got filled. If the decoded text won't fit, this fails with
+    /// `Error::OutputLimitExceeded` (and `out` holds whatever did fit, up to the last whole char); bits that aren't
+    /// '0' or '1', or that don't make up whole codes, give `Error::Corrupt`.
+    ///
+    /// ## Arguments
+    ///
+    /// `encoded_str`: the Huffman-encoded string to be decoded
+    /// `out`: where the decoded text goes
+    pub fn decode_into_slice(&self, encoded_str: &str, out: &mut [u8]) -> Result<usize> {
+        let mut len = 0;
+        let mut walker = self.walker();
+        // whether some of a code has been read, but not all of it
+        let mut partway = false;
+        for bit in encoded_str.bytes() {
+            match bit {
+                b'0' => walker.step(false),
+                b'1' => walker.step(true),
+                _ => return Err(Error::Corrupt("bits can only be '0' or '1'")),
+            }
+            if let Some(ch) = walker.leaf() {
+                if out.len() - len < ch.len_utf8() {
+                    return Err(Error::OutputLimitExceeded {
+                        limit: out.len() as u64,
+                    });
+                }
+                len += ch.encode_utf8(&mut out[len..]).len();
+                walker = self.walker();
+                partway = false;
+            } else if walker.is_lost() {
+                return Err(Error::Corrupt("bits don't match any code"));
+            } else {
+                partway = true;
+            }
+        }
+        if partway {
+            return Err(Error::Corrupt("bits end partway through a code"));
+        }
+        Ok(len)
+    }
+
+    /// `encode()`, but into a `BitVec` (with the `bitvec` feature), a bit per bit rather than a byte per bit
+    ///
+    /// The bits are packed most significant first, the same way containers pack them.
+    ///
+    /// ## Arguments
+    ///
+    /// `input`: a shared ref to the string to be encoded
+    /// `huffman_map`: the Huffman coding map (gotten from `generate_huffman_map()`)
+    #[cfg(feature = "bitvec")]
+    pub fn encode_bits(
+        input: &str,
+        huffman_map: &HashMap<char, String>,
+    ) -> Result<BitVec<u8, Msb0>> {
+        let mut bits = BitVec::new();
+        for ch in input.chars() {
+            let code = huffman_map.get(&ch).ok_or(Error::UnknownSymbol(ch))?;
+            bits.extend(code.bytes().map(|bit| bit == b'1'));
+        }
+        Ok(bits)
+    }
+
+    /// Decodes bits held in a `BitSlice` (with the `bitvec` feature), in any order and storage
+    ///
+    /// Bits that don't make up whole codes give `Error::Corrupt`, like they do for `decode_into_slice()`.
+    ///
+    /// ## Arguments
+    ///
+    /// `bits`: the Huffman-encoded bits to be decoded
+    #[cfg(feature = "bitvec")]
+    pub fn decode_bits<T: BitStore, O: BitOrder>(&self, bits: &BitSlice<T, O>) -> Result<String> {
+        let mut decoded_str = String::new();
+        let mut walker = self.walker();
+        // whether some of a code has been read, but not all of it
+        let mut partway = false;
+        for bit in bits.iter().by_vals() {
+            walker.step(bit);
+            if let Some(ch) = walker.leaf() {
+                decoded_str.push(ch);
+                walker = self.walker();
+                partway = false;
+            } else if walker.is_lost() {
+                return Err(Error::Corrupt("bits don't match any code"));
+            } else {
+                partway = true;
+            }
+        }
+        if partway {
+            return Err(Error::Corrupt("bits end partway through a code"));
+        }
+        Ok(decoded_str)
+    }
+
+    /// Gets the lookup table for decoding several bits at a time, building it the first time it's asked for
+    pub(crate) fn decode_table(&self) -> &DecodeTable {
+        self.table
+            .get_or_init(|| DecodeTable::new(&build_huffman_map(&self.nodes, self.head)))
+    }
+
+    /// Gets the state machine for decoding a byte at a time, compiling it the first time it's asked for, or `None` if
+    /// there aren't any codes with bits in them for it to work with
+    pub(crate) fn decode_fsm(&self) -> Option<&DecodeFsm> {
+        self.fsm
+            .get_or_init(|| DecodeFsm::new(&build_huffman_map(&self.nodes, self.head)))
+            .as_ref()
+    }
+
+    /// Roughly how many bytes of heap memory the tree is using: its nodes (including any spare room in the arena),
+    /// plus whichever decoders have been compiled and cached on it
+    pub fn memory_usage(&self) -> usize {
+        self.nodes.capacity() * std::mem::size_of::<Node>()
+            + self.table.get().map_or(0, DecodeTable::memory_usage)
+            + self
+                .fsm
+                .get()
+                .and_then(Option::as_ref)
+                .map_or(0, DecodeFsm::memory_usage)
+    }
+
+    /// Draws the tree as ASCII art, one node per line, with the bit that leads to each node and every node's frequency
+    ///
+    /// The left (0) child is drawn above the right (1) one, like so:
+    ///
+    /// ```text
+    /// (4)
+    /// ├─0─ 'a' (2)
+    /// └─1─ (2)
+    ///     ├─0─ 'b' (1)
+    ///     └─1─ 'c' (1)
+    /// ```
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        // every node still to draw, along with what goes in front of it on its line and in front of its children's
+        let mut stack = vec![(self.head, String::new(), String::new())];
+        while let Some((curr, lead, indent)) = stack.pop() {
+            let node = match curr {
+                Some(i) => &self.nodes[i as usize],
+                None => continue,
+            };
+            out.push_str(&lead);
+            match node.ch {
+                Some(ch) => out.push_str(&format!("{:?} ({})\n", ch, node.freq)),
+                None => out.push_str(&format!("({})\n", node.freq)),
+            }
+            // right goes on first so left comes off (and gets drawn) first
+            stack.push((
+                node.right,
+                format!("{}└─1─ ", indent),
+                format!("{}    ", indent),
+            ));
+            stack.push((
+                node.left,
+                format!("{}├─0─ ", indent),
+                format!("{}│   ", indent),
+            ));
+        }
+        out
+    }
+
+    /// Writes the tree out as a Graphviz DOT graph, for rendering with e.g. `dot -Tsvg`
+    ///
+    /// Leaves are boxes with their char and frequency, merged nodes are circles with just their frequency, and every
+    /// edge is labeled with its bit, left (0) first:
+    ///
+    /// ```text
+    /// digraph huffman {
+    ///     node [shape=circle];
+    ///     n4 [label="4"];
+    ///     n2 [label="'a'\n2", shape=box];
+    ///     n4 -> n2 [label="0"];
+    ///     ...
+    /// }
+    /// ```
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph huffman {\n    node [shape=circle];\n");
+        let mut stack = vec![self.head];
+        while let Some(curr) = stack.pop() {
+            let i = match curr {
+                Some(i) => i,
+                None => continue,
+            };
+            let node = &self.nodes[i as usize];
+            match node.ch {
+                Some(ch) => {
+                    // DOT strings only need their quotes and backslashes escaped
+                    let ch = format!("{:?}", ch)
+                        .replace('\\', "\\\\")
+                        .replace('"', "\\\"");
+                    out.push_str(&format!(
+                        "    n{} [label=\"{}\\n{}\", shape=box];\n",
+                        i, ch, node.freq
+                    ));
+                }
+                None => out.push_str(&format!("    n{} [label=\"{}\"];\n", i, node.freq)),
+            }
+            for (bit, child) in [(0, node.left), (1, node.right)] {
+                if let Some(child) = child {
+                    out.push_str(&format!("    n{} -> n{} [label=\"{}\"];\n", i, child, bit));
+                }
+            }
+            stack.push(node.right);
+            stack.push(node.left);
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Writes the tree out as LaTeX, for the `forest` package (which draws with TikZ), to go in papers and lecture notes
+    ///
+    /// Leaves show their char, in typewriter type (with anything LaTeX would choke on, or that wouldn't show up,
+    /// written out), and every edge is labeled with its bit:
+    ///
+    /// ```text
+    /// \begin{forest}
+    ///   for tree={draw, align=center, l sep=1.5em}
+    ///   [{4}
+    ///     [{\texttt{a}\\ 2}, edge label={node[midway, fill=white, font=\scriptsize]{0}}]
+    ///     ...
+    ///   ]
+    /// \end{forest}
+    /// ```
+    ///
+    /// ## Arguments
+    ///
+    /// * `options`: whether to show frequencies and codes
+    pub fn to_forest(&self, options: &ForestOptions) -> String {
+        let mut out =
+            String::from("\\begin{forest}\n  for tree={draw, align=center, l sep=1.5em}\n");
+        // every node still to draw, with the bit and code that lead to it; `None` closes the bracket of the node at
+        // that depth once its children are done
+        let mut stack: Vec<_> = self
+            .head
+            .map(|head| (Some((head, None)), String::new()))
+            .into_iter()
+            .collect();
+        while let Some((curr, code)) = stack.pop() {
+            let indent = "  ".repeat(code.len() + 1);
+            let (i, bit) = match curr {
+                Some(curr) => curr,
+                None => {
+                    out.push_str(&format!("{}]\n", indent));
+                    continue;
+                }
+            };
+            let node = &self.nodes[i as usize];
+            let mut label = Vec::new();
+            if let Some(ch) = node.ch {
+                label.push(format!("\\texttt{{{}}}", latex_char(ch)));
+            }
+            if options.freqs {
+                label.push(node.freq.to_string());
+            }
+            if options.codes && node.ch.is_some() {
+                label.push(format!("\\texttt{{{}}}", code));
+            }
+            out.push_str(&format!("{}[{{{}}}", indent, label.join("\\\\ ")));
+            if let Some(bit) = bit {
+                out.push_str(&format!(
+                    ", edge label={{node[midway, fill=white, font=\\scriptsize]{{{}}}}}",
+                    bit
+                ));
+            }
+            match (node.left, node.right) {
+                (Some(left), Some(right)) => {
+                    out.push('\n');
+                    // the closing bracket goes on first so it comes off last, then right so left comes off first
+                    stack.push((None, code.clone()));
+                    stack.push((Some((right, Some('1'))), format!("{}1", code)));
+                    stack.push((Some((left, Some('0'))), format!("{}0", code)));
+                }
+                _ => out.push_str("]\n"),
+            }
+        }
+        out.push_str("\\end{forest}\n");
+        out
+    }
+
+    /// Lists the merges that built the tree, in the order they happened, for showing how it came together
+    pub fn merges(&self) -> Vec<Merge> {
+        // merged nodes come after the leaves, in the order they were made, and only ever point back at earlier ones
+        let mut under: Vec<Vec<char>> = Vec::with_capacity(self.nodes.len());
+        let mut merges = Vec::new();
+        for node in &self.nodes {
+            match (node.ch, node.left, node.right) {
+                (Some(ch), _, _) => under.push(vec![ch]),
+                (None, Some(left), Some(right)) => {
+                    let (left, right) = (left as usize, right as usize);
+                    under.push([under[left].as_slice(), &under[right]].concat());
+                    merges.push(Merge {
+                        left: (under[left].clone(), self.nodes[left].freq),
+                        right: (under[right].clone(), self.nodes[right].freq),
+                    });
+                }
+                _ => under.push(Vec::new()),
+            }
+        }
+        merges
+    }
+
+    /// Saves the tree, compactly: its shape takes 2 bits per leaf (less one), and then each leaf has its char and
+    /// frequency, which is enough to get back the very same codes and frequencies with `HuffTree::try_from()`
+    ///
+    /// ```text
+    /// version     1 byte    currently 1
+    /// leaves      4 bytes   how many leaves the tree has (little-endian, as is everything else)
+    /// shape       one bit per node, in preorder (a node, then its left subtree, then its right), most significant
+    ///             bit first: 1 for a leaf and 0 for a merged node, padded out to a whole byte with 0s
+    /// entries     8 bytes each, one per leaf in the same order: its char (as a u32), then its frequency (as an i32)
+    /// ```
+    ///
+    /// Merged nodes' frequencies are just their children's added up, so they don't need saving. The order the merges
+    /// happened in doesn't get saved either, so a restored tree's `merges()` come out smallest first, which may not be
+    /// quite the order the original's did.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let leaves = self.nodes.iter().filter(|node| node.ch.is_some()).count();
+        let mut shape = vec![0u8; (2 * leaves).saturating_sub(1).div_ceil(8)];
+        let mut entries = Vec::with_capacity(8 * leaves);
+        let mut stack = vec![self.head];
+        let mut bit = 0;
+        while let Some(curr) = stack.pop() {
+            let node = match curr {
+                Some(i) => &self.nodes[i as usize],
+                None => continue,
+            };
+            if let Some(ch) = node.ch {
+                shape[bit / 8] |= 0x80 >> (bit % 8);
+                entries.extend_from_slice(&(ch as u32).to_le_bytes());
+                entries.extend_from_slice(&node.freq.to_le_bytes());
+            }
+            bit += 1;
+            // right goes on first so left comes off first
+            stack.push(node.right);
+            stack.push(node.left);
+        }
+        let mut bytes = Vec::with_capacity(5 + shape.len() + entries.len());
+        bytes.push(TREE_VERSION);
+        bytes.extend_from_slice(&(leaves as u32).to_le_bytes());
+        bytes.extend_from_slice(&shape);
+        bytes.extend_from_slice(&entries);
+        bytes
+    }
+
+    /// Gets a cursor positioned at the head of the tree, for decoding one bit at a time
+    pub(crate) fn walker(&self) -> Walker<'_> {
+        Walker {
+            nodes: &self.nodes,
+            curr: self.head,
+        }
+    }
+
     /// Shitty interface wrapper function that, true to name, does it all
     ///
     /// ## Arguments
@@ -159,7 +693,8 @@ impl HuffTree {
         for (key, val) in huffman_map.clone() {
             println!("{0}: {1}", key, val);
         }
-        let encoded_str = HuffTree::encode(input, &huffman_map);
+        // the map was made from this very input, so every char is in it
+        let encoded_str = HuffTree::encode(input, &huffman_map).unwrap();
         println!("Encoded string: ");
         println!("{}", encoded_str);
         let compressed_size = encoded_str.len();
@@ -172,65 +707,624 @@ impl HuffTree {
     }
 }
 
-/// Tail recursive meat-and-potatoes of the huffman map generation
-fn huffman_map_step(curr: &Link, code: String, huffman_map: &mut HashMap<char, String>) {
-    // make sure we're not on an empty node, first---that should terminate the recursion
-    if curr.is_some() {
-        // if we're at a leaf,
-        if <&Link>::clone(&curr).as_ref().unwrap().left.is_none()
-            && <&Link>::clone(&curr).as_ref().unwrap().right.is_none()
-        {
-            // then the char in the leaf node gets mapped to the running bitstring
-            huffman_map.insert(<&Link>::clone(&curr).as_ref().unwrap().ch.unwrap(), code);
-        } else {
-            // otherwise, step down the tree, and add a 0 to the running bitstring if we go left and a 1 if right
-            huffman_map_step(
-                &(<&Link>::clone(&curr).as_ref().unwrap().left),
-                code.clone() + "0",
-                huffman_map,
-            );
-            huffman_map_step(
-                &(<&Link>::clone(&curr).as_ref().unwrap().right),
-                code + "1",
-                huffman_map,
-            );
+#[cfg(feature = "serde")]
+impl serde::Serialize for HuffTree {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return serializer.serialize_bytes(&self.to_bytes());
         }
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|node| match (node.ch, node.left, node.right) {
+                (Some(ch), _, _) => NodeRepr::Leaf {
+                    ch,
+                    freq: node.freq,
+                },
+                (None, Some(left), Some(right)) => NodeRepr::Merged {
+                    freq: node.freq,
+                    left,
+                    right,
+                },
+                // populating only ever makes leaves and nodes with both children
+                _ => unreachable!("node with neither a char nor two children"),
+            })
+            .collect();
+        TreeRepr {
+            nodes,
+            head: self.head,
+        }
+        .serialize(serializer)
     }
 }
 
-/// Tail recursive meat-and-potatoes of the decoding walking; logic is very similar to huffman map gen
-fn decode_step(curr: &Link, encoded_str: &mut String, decoded_str: &mut String) {
-    // again, empty node should end recursion
-    if curr.is_some() {
-        // if we're at a leaf,
-        if <&Link>::clone(&curr).as_ref().unwrap().left.is_none()
-            && <&Link>::clone(&curr).as_ref().unwrap().right.is_none()
+/// Checks the nodes make one whole tree (every merged node's children come before it, every node but the head is
+/// somebody's child exactly once, no char shows up twice, and every merged node's frequency adds up) before taking
+/// it, since decoding assumes all of that
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for HuffTree {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        use serde::de::Error as _;
+        if !deserializer.is_human_readable() {
+            return deserializer.deserialize_bytes(TreeBytes);
+        }
+        let repr = TreeRepr::deserialize(deserializer)?;
+        let mut nodes: Vec<Node> = Vec::with_capacity(repr.nodes.len());
+        let mut parented = vec![false; repr.nodes.len()];
+        let mut chars = std::collections::HashSet::new();
+        for node in repr.nodes {
+            nodes.push(match node {
+                NodeRepr::Leaf { ch, freq } => {
+                    if !chars.insert(ch) {
+                        return Err(D::Error::custom(format!("{:?} has more than one leaf", ch)));
+                    }
+                    Node::new(ch, freq)
+                }
+                NodeRepr::Merged { freq, left, right } => {
+                    for child in [left, right] {
+                        match parented.get_mut(child as usize) {
+                            Some(parented) if (child as usize) < nodes.len() && !*parented => {
+                                *parented = true
+                            }
+                            _ => {
+                                return Err(D::Error::custom(
+                                    "a merged node's children have to be earlier nodes that aren't already children",
+                                ))
+                            }
+                        }
+                    }
+                    let sum = nodes[left as usize].freq.checked_add(nodes[right as usize].freq);
+                    if sum != Some(freq) {
+                        return Err(D::Error::custom(
+                            "a merged node's frequency has to be its children's added up",
+                        ));
+                    }
+                    Node {
+                        ch: None,
+                        freq,
+                        left: Some(left),
+                        right: Some(right),
+                    }
+                }
+            });
+        }
+        let orphans = parented.iter().filter(|parented| !**parented).count();
+        match repr.head {
+            None if nodes.is_empty() => {}
+            Some(head) if orphans == 1 && parented.get(head as usize) == Some(&false) => {}
+            _ => {
+                return Err(D::Error::custom(
+                    "the head has to be the one node that isn't anybody's child",
+                ))
+            }
+        }
+        let mut tree = HuffTree::new();
+        tree.nodes = nodes;
+        tree.head = repr.head;
+        Ok(tree)
+    }
+}
+
+/// Restores a tree saved with `HuffTree::to_bytes()`, checking every part of it (the shape has to be one whole tree
+/// with exactly as many leaves as it says, every char valid and only there once, no frequencies adding up past
+/// `i32::MAX`, and nothing left over) so a damaged one can't make a tree that decodes wrongly
+impl TryFrom<&[u8]> for HuffTree {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        let (&version, rest) = bytes
+            .split_first()
+            .ok_or(Error::Corrupt("tree is truncated"))?;
+        if version == 0 || version > TREE_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        if rest.len() < 4 {
+            return Err(Error::Corrupt("tree is truncated"));
+        }
+        let (leaves, rest) = rest.split_at(4);
+        let leaves = u32::from_le_bytes(leaves.try_into().unwrap()) as usize;
+        // worked out in u64 so a silly leaf count can't overflow, and checked before anything gets allocated for it
+        let len = (2 * leaves as u64).saturating_sub(1);
+        let shape_len = len.div_ceil(8);
+        if rest.len() as u64 != shape_len + 8 * leaves as u64 {
+            return Err(Error::Corrupt("tree is the wrong length for its leaves"));
+        }
+        let (shape, entries) = rest.split_at(shape_len as usize);
+        let len = len as usize;
+        if (len..shape.len() * 8).any(|bit| shape[bit / 8] & (0x80 >> (bit % 8)) != 0) {
+            return Err(Error::Corrupt("tree shape's padding isn't all 0s"));
+        }
+        // first, just the shape: each node's children (as positions in preorder), making sure it's one whole tree
+        let mut children: Vec<Option<(usize, usize)>> = Vec::with_capacity(len);
+        // merged nodes still waiting on their children, and whether they've got their left one yet
+        let mut open: Vec<(usize, bool)> = Vec::new();
+        for bit in 0..len {
+            match open.pop() {
+                Some((parent, false)) => {
+                    children[parent] = Some((bit, 0));
+                    open.push((parent, true));
+                }
+                Some((parent, true)) => {
+                    if let Some((_, right)) = &mut children[parent] {
+                        *right = bit;
+                    }
+                }
+                None if bit != 0 => {
+                    return Err(Error::Corrupt("tree shape has more than one tree in it"))
+                }
+                None => {}
+            }
+            let leaf = shape[bit / 8] & (0x80 >> (bit % 8)) != 0;
+            children.push(None);
+            if !leaf {
+                open.push((bit, false));
+            }
+        }
+        if !open.is_empty() {
+            return Err(Error::Corrupt("tree shape ends partway through a tree"));
+        }
+        // then the leaves, handed out in preorder
+        let mut chars = HashSet::with_capacity(leaves);
+        let mut freqs = vec![0; len];
+        let mut nodes = Vec::with_capacity(len);
+        let mut entries = entries.chunks_exact(8);
+        for (pos, kids) in children.iter().enumerate() {
+            if kids.is_some() {
+                continue;
+            }
+            let entry = entries
+                .next()
+                .ok_or(Error::Corrupt("tree has more leaves than it says"))?;
+            let ch = char::from_u32(u32::from_le_bytes(entry[..4].try_into().unwrap()))
+                .ok_or(Error::Corrupt("tree has a leaf that isn't a valid char"))?;
+            if !chars.insert(ch) {
+                return Err(Error::Corrupt("tree has a char in more than one leaf"));
+            }
+            freqs[pos] = i32::from_le_bytes(entry[4..].try_into().unwrap());
+            nodes.push(Node::new(ch, freqs[pos]));
+        }
+        if nodes.len() != leaves {
+            return Err(Error::Corrupt("tree has fewer leaves than it says"));
+        }
+        // children always come after their parent in preorder, so going backwards adds up every merged node's
+        // frequency after its children's
+        for pos in (0..len).rev() {
+            if let Some((left, right)) = children[pos] {
+                freqs[pos] = freqs[left]
+                    .checked_add(freqs[right])
+                    .ok_or(Error::Corrupt("tree's frequencies add up to too much"))?;
+            }
+        }
+        // leaves first, then merged nodes in the order they'd have been merged in, i.e. smallest first (and children
+        // before parents, which come earlier in preorder, when they're the same)
+        let mut merged: Vec<usize> = (0..len).filter(|&pos| children[pos].is_some()).collect();
+        merged.sort_unstable_by_key(|&pos| (freqs[pos], Reverse(pos)));
+        let mut index = vec![0u32; len];
+        for (i, pos) in (0..len)
+            .filter(|&pos| children[pos].is_none())
+            .chain(merged.iter().copied())
+            .enumerate()
         {
-            // attach the just-reached character
-            decoded_str.push(<&Link>::clone(&curr).as_ref().unwrap().ch.unwrap());
-        } else {
-            // otherwise, traverse left or right depending on the just-removed leftmost bit in the carried encoded bitstring
-            if encoded_str.remove(0) == '0' {
-                decode_step(
-                    &(<&Link>::clone(&curr).as_ref().unwrap().left),
-                    encoded_str,
-                    decoded_str,
-                );
+            index[pos] = i as u32;
+        }
+        nodes.extend(merged.iter().map(|&pos| {
+            let (left, right) = children[pos].unwrap();
+            Node {
+                ch: None,
+                freq: freqs[pos],
+                left: Some(index[left]),
+                right: Some(index[right]),
+            }
+        }));
+        let mut tree = HuffTree::new();
+        tree.nodes = nodes;
+        tree.head = (len != 0).then(|| index[0]);
+        Ok(tree)
+    }
+}
+
+/// Turns the bytes a binary format hands over back into a tree, with `HuffTree::try_from()`
+#[cfg(feature = "serde")]
+struct TreeBytes;
+
+#[cfg(feature = "serde")]
+impl serde::de::Visitor<'_> for TreeBytes {
+    type Value = HuffTree;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a tree saved by HuffTree::to_bytes()")
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, bytes: &[u8]) -> std::result::Result<HuffTree, E> {
+        HuffTree::try_from(bytes).map_err(E::custom)
+    }
+}
+
+/// Writes a char so LaTeX shows it as itself: its special characters escaped, a space as a visible space, and
+/// anything that wouldn't show up (control characters and the like) as its code point
+///
+/// ## Arguments
+///
+/// * `ch`: the char
+fn latex_char(ch: char) -> String {
+    match ch {
+        '#' | '$' | '%' | '&' | '_' | '{' | '}' => format!("\\{}", ch),
+        '\\' => "\\textbackslash{}".to_string(),
+        '~' => "\\textasciitilde{}".to_string(),
+        '^' => "\\textasciicircum{}".to_string(),
+        ' ' => "\\textvisiblespace{}".to_string(),
+        ch if ch.is_control() || ch.is_whitespace() => format!("U+{:04X}", ch as u32),
+        ch => ch.to_string(),
+    }
+}
+
+/// A cursor into a `HuffTree`, for when the bits to be decoded don't all arrive at once
+pub(crate) struct Walker<'a> {
+    /// The tree's nodes
+    nodes: &'a [Node],
+    /// The node we've walked down to so far (`None` if we've fallen off the tree)
+    curr: Link,
+}
+
+impl<'a> Walker<'a> {
+    /// Steps down to the left child on a 0 and the right child on a 1
+    pub(crate) fn step(&mut self, bit: bool) {
+        self.curr = self.curr.and_then(|i| {
+            let node = &self.nodes[i as usize];
+            if bit {
+                node.right
+            } else {
+                node.left
+            }
+        });
+    }
+
+    /// Whether we've walked off the bottom of the tree, i.e. the bits so far aren't the start of any code
+    pub(crate) fn is_lost(&self) -> bool {
+        self.curr.is_none()
+    }
+
+    /// Gets the char in the current node if it's a leaf, or `None` if we still need more bits
+    pub(crate) fn leaf(&self) -> Option<char> {
+        self.curr.and_then(|i| self.nodes[i as usize].ch)
+    }
+}
+
+/// A Huffman map for a small alphabet, copied into a fixed-size array so it can live on the stack
+///
+/// Looking a char up is a scan from the front, but the codes are kept shortest first, and the shortest codes belong
+/// to the most common chars, so most lookups stop within the first few entries; for an alphabet this small that
+/// beats hashing every char.
+struct SmallCodebook<'a> {
+    /// Each char and its code, shortest code first
+    entries: [(char, &'a str); SMALL_ALPHABET],
+    /// How many entries are filled in
+    len: usize,
+}
+
+impl<'a> SmallCodebook<'a> {
+    /// Copies a Huffman map into a small codebook, or gives `None` if it has more than `SMALL_ALPHABET` symbols
+    ///
+    /// ## Arguments
+    ///
+    /// * `huffman_map`: the Huffman coding map
+    fn new(huffman_map: &'a HashMap<char, String>) -> Option<Self> {
+        if huffman_map.len() > SMALL_ALPHABET {
+            return None;
+        }
+        let mut codebook = SmallCodebook {
+            entries: [('\0', ""); SMALL_ALPHABET],
+            len: huffman_map.len(),
+        };
+        for (entry, (&ch, code)) in codebook.entries.iter_mut().zip(huffman_map) {
+            *entry = (ch, code.as_str());
+        }
+        codebook.entries[..codebook.len].sort_unstable_by_key(|&(_, code)| code.len());
+        Some(codebook)
+    }
+
+    /// Looks up the code for a char
+    ///
+    /// ## Arguments
+    ///
+    /// * `ch`: the char
+    fn get(&self, ch: char) -> Option<&'a str> {
+        self.entries[..self.len]
+            .iter()
+            .find(|&&(symbol, _)| symbol == ch)
+            .map(|&(_, code)| code)
+    }
+}
+
+/// Encodes a string with whatever lookup's been picked for the codes (see `HuffTree::encode()`)
+///
+/// ## Arguments
+///
+/// * `input`: the string to be encoded
+/// * `lookup`: gets the code for a char, if it has one
+fn encode_with<'a, F: Fn(char) -> Option<&'a str>>(input: &str, lookup: F) -> Result<String> {
+    let mut encoded_len = 0;
+    for ch in input.chars() {
+        encoded_len += lookup(ch).ok_or(Error::UnknownSymbol(ch))?.len();
+    }
+    let mut encoded_str = String::with_capacity(encoded_len);
+    for ch in input.chars() {
+        // every char was checked above
+        encoded_str += lookup(ch).unwrap();
+    }
+    Ok(encoded_str)
+}
+
+/// Takes whichever node is smallest out of the two queues of `populate_tree_sorted()`, or `None` if both are empty,
+/// breaking ties the way `tie_break` says to
+fn take_smallest(
+    nodes: &[Node],
+    leaves: &mut VecDeque<u32>,
+    merged: &mut VecDeque<VecDeque<u32>>,
+    tie_break: TieBreak,
+) -> Option<u32> {
+    let merged_freq = merged.front().map(|run| nodes[run[0] as usize].freq);
+    let leaf_freq = leaves.front().map(|&leaf| nodes[leaf as usize].freq);
+    match (merged_freq, leaf_freq) {
+        (Some(m), Some(l)) if m > l => leaves.pop_front(),
+        (Some(m), Some(l)) if m == l && tie_break == TieBreak::MinVariance => leaves.pop_front(),
+        (None, _) => leaves.pop_front(),
+        _ => {
+            let run = merged.front_mut().unwrap();
+            let node = match tie_break {
+                TieBreak::Newest => run.pop_back(),
+                TieBreak::MinVariance => run.pop_front(),
+            };
+            if run.is_empty() {
+                merged.pop_front();
+            }
+            node
+        }
+    }
+}
+
+/// Meat-and-potatoes of the huffman map generation
+///
+/// This walks the tree with an explicit stack rather than by recursing, since a lopsided enough set of frequencies
+/// can make a tree deep enough to overflow the real stack. There's just the one running bitstring, cut back to the
+/// right depth as the walk goes back up, so the only strings that ever get allocated are the codes in the map itself.
+fn build_huffman_map(nodes: &[Node], head: Link) -> HashMap<char, String> {
+    let leaves = nodes.iter().filter(|node| node.ch.is_some()).count();
+    let mut huffman_map: HashMap<char, String> = HashMap::with_capacity(leaves);
+    // the running bitstring that gets to the current node
+    let mut code = String::new();
+    // every node still to visit, along with how deep it is and the bit that gets to it from its parent
+    let mut stack = vec![(head, 0, '0')];
+    while let Some((curr, depth, bit)) = stack.pop() {
+        // make sure we're not on an empty node, first
+        if let Some(i) = curr {
+            if depth != 0 {
+                code.truncate(depth - 1);
+                code.push(bit);
+            }
+            let node = &nodes[i as usize];
+            // if we're at a leaf,
+            if let Some(ch) = node.ch {
+                // then the char in the leaf node gets mapped to the running bitstring
+                huffman_map.insert(ch, code.clone());
             } else {
-                decode_step(
-                    &(<&Link>::clone(&curr).as_ref().unwrap().right),
-                    encoded_str,
-                    decoded_str,
-                );
+                // otherwise, step down the tree, with a 0 on the running bitstring if we go left and a 1 if right
+                stack.push((node.right, depth + 1, '1'));
+                stack.push((node.left, depth + 1, '0'));
             }
         }
     }
+    huffman_map
+}
+
+/// Meat-and-potatoes of the decoding walking: walks down from the head, one bit at a time, until it hits a leaf
+fn decode_step(nodes: &[Node], head: Link, bits: &mut Bytes<'_>, decoded_str: &mut String) {
+    let mut curr = head;
+    // again, empty node should end the walk
+    while let Some(i) = curr {
+        let node = &nodes[i as usize];
+        // if we're at a leaf,
+        if let Some(ch) = node.ch {
+            // attach the just-reached character
+            decoded_str.push(ch);
+            return;
+        }
+        // otherwise, go left or right depending on the next bit under the cursor (running out partway through a
+        // code just drops the incomplete char)
+        curr = match bits.next() {
+            Some(b'0') => node.left,
+            Some(_) => node.right,
+            None => return,
+        };
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::HuffTree;
+    use super::{ForestOptions, HuffTree, Merge, TieBreak};
+    use crate::error::Error;
     use itertools::Itertools;
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn to_dot_test() {
+        let mut tree = HuffTree::new();
+        tree.populate_tree(&HuffTree::find_input_freqs("aab\""));
+        assert_eq!(
+            tree.to_dot(),
+            "digraph huffman {
+    node [shape=circle];
+    n4 [label=\"4\"];
+    n4 -> n2 [label=\"0\"];
+    n4 -> n3 [label=\"1\"];
+    n2 [label=\"'a'\\n2\", shape=box];
+    n3 [label=\"2\"];
+    n3 -> n1 [label=\"0\"];
+    n3 -> n0 [label=\"1\"];
+    n1 [label=\"'\\\"'\\n1\", shape=box];
+    n0 [label=\"'b'\\n1\", shape=box];
+}
+"
+        );
+        assert_eq!(
+            HuffTree::new().to_dot(),
+            "digraph huffman {\n    node [shape=circle];\n}\n"
+        );
+    }
+
+    #[test]
+    fn to_forest_test() {
+        let mut tree = HuffTree::new();
+        tree.populate_tree(&HuffTree::find_input_freqs("aa_\n"));
+        assert_eq!(
+            tree.to_forest(&ForestOptions::default()),
+            r"\begin{forest}
+  for tree={draw, align=center, l sep=1.5em}
+  [{4}
+    [{\texttt{a}\\ 2}, edge label={node[midway, fill=white, font=\scriptsize]{0}}]
+    [{2}, edge label={node[midway, fill=white, font=\scriptsize]{1}}
+      [{\texttt{U+000A}\\ 1}, edge label={node[midway, fill=white, font=\scriptsize]{0}}]
+      [{\texttt{\_}\\ 1}, edge label={node[midway, fill=white, font=\scriptsize]{1}}]
+    ]
+  ]
+\end{forest}
+"
+        );
+        let options = ForestOptions {
+            freqs: false,
+            codes: true,
+        };
+        let drawn = tree.to_forest(&options);
+        assert!(drawn.contains("\n  [{}\n"));
+        assert!(drawn.contains("[{\\texttt{\\_}\\\\ \\texttt{11}}, "));
+        assert_eq!(
+            HuffTree::new().to_forest(&options),
+            "\\begin{forest}\n  for tree={draw, align=center, l sep=1.5em}\n\\end{forest}\n"
+        );
+    }
+
+    #[test]
+    fn to_bytes_test() {
+        let mut tree = HuffTree::new();
+        tree.populate_tree(&HuffTree::find_input_freqs("aabc"));
+        let bytes = tree.to_bytes();
+        // shape 01011 (padded), then the leaves a, b, c
+        assert_eq!(
+            bytes,
+            [
+                &[1, 3, 0, 0, 0, 0b0101_1000][..],
+                &[b'a', 0, 0, 0, 2, 0, 0, 0],
+                &[b'b', 0, 0, 0, 1, 0, 0, 0],
+                &[b'c', 0, 0, 0, 1, 0, 0, 0],
+            ]
+            .concat()
+        );
+        let mut back = HuffTree::try_from(&bytes[..]).unwrap();
+        assert_eq!(back.generate_huffman_map(), tree.generate_huffman_map());
+        assert_eq!(back.render(), tree.render());
+        assert_eq!(back.merges(), tree.merges());
+        assert_eq!(back.to_bytes(), bytes);
+        let input = "dagoth ur was a hotep, the ünïcode kind";
+        let mut tree = HuffTree::new();
+        tree.populate_tree(&HuffTree::find_input_freqs(input));
+        let back = HuffTree::try_from(&tree.to_bytes()[..]).unwrap();
+        let encoded = HuffTree::encode(input, &tree.generate_huffman_map()).unwrap();
+        assert_eq!(back.decode(&encoded), input);
+        for tree in [HuffTree::new(), {
+            let mut tree = HuffTree::new();
+            tree.populate_tree(&HuffTree::find_input_freqs("zzz"));
+            tree
+        }] {
+            assert_eq!(
+                HuffTree::try_from(&tree.to_bytes()[..]).unwrap().render(),
+                tree.render()
+            );
+        }
+        // damage anywhere gets caught
+        let corrupt = |bytes: &[u8]| matches!(HuffTree::try_from(bytes), Err(Error::Corrupt(_)));
+        let mut bad = bytes.clone();
+        assert!(corrupt(&bad[..bad.len() - 1]));
+        bad.push(0);
+        assert!(corrupt(&bad));
+        for shape in [0b1101_1000, 0b0001_1000, 0b0101_1100] {
+            bad = bytes.clone();
+            bad[5] = shape;
+            assert!(corrupt(&bad));
+        }
+        bad = bytes.clone();
+        bad[14] = b'a';
+        assert!(corrupt(&bad));
+        bad = bytes.clone();
+        bad[6..10].copy_from_slice(&0xd800u32.to_le_bytes());
+        assert!(corrupt(&bad));
+        bad = bytes.clone();
+        bad[10..14].copy_from_slice(&i32::MAX.to_le_bytes());
+        assert!(corrupt(&bad));
+        bad = bytes.clone();
+        bad[1..5].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(corrupt(&bad));
+        bad[0] = 2;
+        assert!(matches!(
+            HuffTree::try_from(&bad[..]),
+            Err(Error::UnsupportedVersion(2))
+        ));
+        assert!(corrupt(&[]));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_test() {
+        let mut tree = HuffTree::new();
+        tree.populate_tree(&HuffTree::find_input_freqs("aabc"));
+        let json = serde_json::to_string(&tree).unwrap();
+        assert_eq!(
+            json,
+            "{\"nodes\":[{\"leaf\":{\"ch\":\"c\",\"freq\":1}},{\"leaf\":{\"ch\":\"b\",\"freq\":1}},\
+             {\"leaf\":{\"ch\":\"a\",\"freq\":2}},{\"merged\":{\"freq\":2,\"left\":1,\"right\":0}},\
+             {\"merged\":{\"freq\":4,\"left\":2,\"right\":3}}],\"head\":4}"
+        );
+        let mut back: HuffTree = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.generate_huffman_map(), tree.generate_huffman_map());
+        assert_eq!(back.render(), tree.render());
+        assert_eq!(back.decode("001011"), "aabc");
+        // binary formats get the tree's own bytes
+        let packed = postcard::to_allocvec(&tree).unwrap();
+        assert_eq!(packed[1..], tree.to_bytes()[..]);
+        let back: HuffTree = postcard::from_bytes(&packed).unwrap();
+        assert_eq!(back.render(), tree.render());
+        let back: HuffTree = bincode::deserialize(&bincode::serialize(&tree).unwrap()).unwrap();
+        assert_eq!(back.render(), tree.render());
+        assert!(postcard::from_bytes::<HuffTree>(&packed[..packed.len() - 1]).is_err());
+        let empty: HuffTree =
+            serde_json::from_str(&serde_json::to_string(&HuffTree::new()).unwrap()).unwrap();
+        assert_eq!(empty.render(), "");
+        // trees that don't hang together get turned away
+        for bad in [
+            // a child that comes after its parent
+            "{\"nodes\":[{\"leaf\":{\"ch\":\"a\",\"freq\":1}},{\"merged\":{\"freq\":2,\"left\":0,\"right\":2}},\
+             {\"leaf\":{\"ch\":\"b\",\"freq\":1}}],\"head\":1}",
+            // a child with two parents
+            "{\"nodes\":[{\"leaf\":{\"ch\":\"a\",\"freq\":1}},{\"merged\":{\"freq\":2,\"left\":0,\"right\":0}}],\
+             \"head\":1}",
+            // frequencies that don't add up
+            "{\"nodes\":[{\"leaf\":{\"ch\":\"a\",\"freq\":1}},{\"leaf\":{\"ch\":\"b\",\"freq\":1}},\
+             {\"merged\":{\"freq\":3,\"left\":0,\"right\":1}}],\"head\":2}",
+            // the same char twice
+            "{\"nodes\":[{\"leaf\":{\"ch\":\"a\",\"freq\":1}},{\"leaf\":{\"ch\":\"a\",\"freq\":1}},\
+             {\"merged\":{\"freq\":2,\"left\":0,\"right\":1}}],\"head\":2}",
+            // two trees
+            "{\"nodes\":[{\"leaf\":{\"ch\":\"a\",\"freq\":1}},{\"leaf\":{\"ch\":\"b\",\"freq\":1}}],\"head\":0}",
+            "{\"nodes\":[],\"head\":0}",
+        ] {
+            assert!(serde_json::from_str::<HuffTree>(bad).is_err(), "{}", bad);
+        }
+    }
 
     fn whole_thing_works(input: String) -> bool {
         HuffTree::do_it_all(&input).as_str() == input.clone().as_str()
@@ -266,6 +1360,261 @@ mod test {
         flag
     }
 
+    fn slice_decode_works(input: &str) -> bool {
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree(&HuffTree::find_input_freqs(input));
+        let encoded_str = HuffTree::encode(input, &hufftree.generate_huffman_map()).unwrap();
+        let mut out = vec![0; input.len()];
+        let len = hufftree.decode_into_slice(&encoded_str, &mut out).unwrap();
+        // one byte short should be too small (for anything that's more than one char's worth of bits)
+        let too_small = input.len() < 2
+            || hufftree
+                .decode_into_slice(&encoded_str, &mut out[..input.len() - 1])
+                .is_err();
+        len == input.len() && out == input.as_bytes() && too_small
+    }
+
+    #[test]
+    fn decode_into_slice_test() {
+        assert!(slice_decode_works("aaabbbbbccddd"));
+        assert!(slice_decode_works("dagoth ur was a hotep"));
+        assert!(slice_decode_works("ünïcödé ∀x∈ℝ 🦀🦀🦀"));
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree(&HuffTree::find_input_freqs("aaabbbbbccddd"));
+        let mut out = [0; 16];
+        assert!(hufftree.decode_into_slice("01x", &mut out).is_err());
+        assert!(hufftree.decode_into_slice("0", &mut out).is_err());
+    }
+
+    #[cfg(feature = "bitvec")]
+    #[test]
+    fn bits_test() {
+        use bitvec::prelude::{bits, Lsb0, Msb0};
+        let input = "dagoth ur was a hotep";
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree(&HuffTree::find_input_freqs(input));
+        let map = hufftree.generate_huffman_map();
+        let bits = HuffTree::encode_bits(input, &map).unwrap();
+        let encoded_str = HuffTree::encode(input, &map).unwrap();
+        assert_eq!(bits.len(), encoded_str.len());
+        assert!(bits
+            .iter()
+            .by_vals()
+            .eq(encoded_str.bytes().map(|bit| bit == b'1')));
+        assert_eq!(hufftree.decode_bits(&bits).unwrap(), input);
+        // any order and storage decodes the same
+        let lsb: bitvec::vec::BitVec<u16, Lsb0> = bits.iter().by_vals().collect();
+        assert_eq!(hufftree.decode_bits(&lsb).unwrap(), input);
+        assert!(matches!(
+            HuffTree::encode_bits("xyz", &map),
+            Err(Error::UnknownSymbol('x'))
+        ));
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree(&HuffTree::find_input_freqs("aaabbbbbccddd"));
+        assert!(hufftree.decode_bits(bits![u8, Msb0; 0]).is_err());
+    }
+
+    fn sorted_matches_map(input: &str) -> bool {
+        let char_map = HuffTree::find_input_freqs(input);
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree(&char_map);
+        let mut char_freqs: Vec<(char, i32)> = char_map.into_iter().collect();
+        char_freqs.sort_by_key(|&(ch, freq)| (freq, std::cmp::Reverse(ch)));
+        let mut sorted_tree = HuffTree::new();
+        sorted_tree.populate_tree_sorted(&char_freqs);
+        sorted_tree.generate_huffman_map() == hufftree.generate_huffman_map()
+    }
+
+    #[test]
+    fn sorted_construction_test() {
+        assert!(sorted_matches_map(""));
+        assert!(sorted_matches_map("a"));
+        assert!(sorted_matches_map("aaabbbbbccddd"));
+        assert!(sorted_matches_map("abcdefghijklmnop"));
+        assert!(sorted_matches_map(
+            "whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune"
+        ));
+    }
+
+    #[test]
+    fn tie_break_test() {
+        fn depths(tree: &mut HuffTree) -> (usize, usize) {
+            let huffman_map = tree.generate_huffman_map();
+            let total = huffman_map.values().map(String::len).sum();
+            (total, huffman_map.values().map(String::len).max().unwrap())
+        }
+        // a and b merge into a 2, which ties with c and d
+        let char_freqs = [('a', 1), ('b', 1), ('c', 2), ('d', 2)];
+        let mut newest = HuffTree::new();
+        newest.populate_tree_sorted(&char_freqs);
+        let mut min_variance = HuffTree::new();
+        min_variance.populate_tree_sorted_with(&char_freqs, TieBreak::MinVariance);
+        assert_eq!(depths(&mut newest), (9, 3));
+        assert_eq!(depths(&mut min_variance), (8, 2));
+        let char_map: HashMap<char, i32> = char_freqs.iter().copied().collect();
+        assert_eq!(
+            HuffTree::encoded_len(&char_map, &newest.generate_huffman_map()),
+            HuffTree::encoded_len(&char_map, &min_variance.generate_huffman_map())
+        );
+
+        // it's never any worse, and still decodes
+        let input =
+            "whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune";
+        let char_map = HuffTree::find_input_freqs(input);
+        let mut newest = HuffTree::new();
+        newest.populate_tree(&char_map);
+        let mut min_variance = HuffTree::new();
+        min_variance.populate_tree_with(&char_map, TieBreak::MinVariance);
+        let (newest_map, huffman_map) = (
+            newest.generate_huffman_map(),
+            min_variance.generate_huffman_map(),
+        );
+        assert_eq!(
+            HuffTree::encoded_len(&char_map, &newest_map),
+            HuffTree::encoded_len(&char_map, &huffman_map)
+        );
+        assert!(depths(&mut min_variance).1 <= depths(&mut newest).1);
+        let encoded = HuffTree::encode(input, &huffman_map).unwrap();
+        assert_eq!(min_variance.decode(&encoded), input);
+    }
+
+    #[test]
+    fn unknown_symbol_test() {
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree(&HuffTree::find_input_freqs("aaabbbbbccddd"));
+        let huffman_map = hufftree.generate_huffman_map();
+        assert!(matches!(
+            HuffTree::encode("abcx", &huffman_map),
+            Err(Error::UnknownSymbol('x'))
+        ));
+    }
+
+    #[test]
+    fn reuse_test() {
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree(&HuffTree::find_input_freqs("dagoth ur was a hotep"));
+        let capacity = hufftree.nodes.capacity();
+        let ptr = hufftree.nodes.as_ptr();
+        // a smaller tree should fit in the same space, and come out the same as a fresh one
+        let char_map = HuffTree::find_input_freqs("aaabbbbbccddd");
+        hufftree.populate_tree(&char_map);
+        let mut fresh = HuffTree::new();
+        fresh.populate_tree(&char_map);
+        assert_eq!(hufftree.nodes.capacity(), capacity);
+        assert_eq!(hufftree.nodes.as_ptr(), ptr);
+        assert_eq!(
+            hufftree.generate_huffman_map(),
+            fresh.generate_huffman_map()
+        );
+    }
+
+    #[test]
+    fn render_test() {
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree(&HuffTree::find_input_freqs("aabc"));
+        assert_eq!(
+            hufftree.render(),
+            "(4)\n├─0─ 'a' (2)\n└─1─ (2)\n    ├─0─ 'b' (1)\n    └─1─ 'c' (1)\n"
+        );
+        assert_eq!(HuffTree::new().render(), "");
+    }
+
+    #[test]
+    fn merges_test() {
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree(&HuffTree::find_input_freqs("aabc"));
+        assert_eq!(
+            hufftree.merges(),
+            vec![
+                Merge {
+                    left: (vec!['b'], 1),
+                    right: (vec!['c'], 1),
+                },
+                Merge {
+                    left: (vec!['a'], 2),
+                    right: (vec!['b', 'c'], 2),
+                },
+            ]
+        );
+        hufftree.populate_tree(&HuffTree::find_input_freqs("a"));
+        assert!(hufftree.merges().is_empty());
+    }
+
+    #[test]
+    fn memory_usage_test() {
+        let mut hufftree = HuffTree::new();
+        assert_eq!(hufftree.memory_usage(), 0);
+        hufftree.populate_tree(&HuffTree::find_input_freqs("dagoth ur was a hotep"));
+        let nodes = hufftree.memory_usage();
+        assert!(nodes > 0);
+        // the cached decoders count too
+        hufftree.decode_table();
+        let with_table = hufftree.memory_usage();
+        assert!(with_table > nodes);
+        hufftree.decode_fsm();
+        assert!(hufftree.memory_usage() > with_table);
+    }
+
+    #[test]
+    fn cached_decoders_test() {
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree(&HuffTree::find_input_freqs("dagoth ur was a hotep"));
+        // asking again gives the very same decoders, rather than compiling new ones
+        assert!(std::ptr::eq(
+            hufftree.decode_table(),
+            hufftree.decode_table()
+        ));
+        assert!(std::ptr::eq(
+            hufftree.decode_fsm().unwrap(),
+            hufftree.decode_fsm().unwrap()
+        ));
+        // but populating the tree again throws them away
+        hufftree.populate_tree(&HuffTree::find_input_freqs("aaaa"));
+        assert_eq!(hufftree.decode_table().lookup(0), Some(('a', 0)));
+        assert!(hufftree.decode_fsm().is_none());
+    }
+
+    /// Makes a tree that's just one long chain, `n` nodes deep, with the deepest leaf at all 1s
+    fn chain(n: u32) -> HuffTree {
+        // all-zero frequencies make every merge go straight back in at the front of the queue
+        let char_freqs: Vec<(char, i32)> = (0..n + 1)
+            .filter_map(|i| char::from_u32(i + 0x1_0000))
+            .map(|ch| (ch, 0))
+            .collect();
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree_sorted(&char_freqs);
+        hufftree
+    }
+
+    #[test]
+    fn deep_tree_test() {
+        let mut hufftree = chain(2000);
+        let huffman_map = hufftree.generate_huffman_map();
+        assert_eq!(huffman_map.values().map(String::len).max(), Some(2000));
+        // deep enough that walking it recursively would blow the stack
+        let deepest = chain(1_000_000).decode(&"1".repeat(1_000_000));
+        assert_eq!(deepest.chars().count(), 1);
+    }
+
+    #[test]
+    fn deep_drop_test() {
+        // deep enough that a recursive drop (like a tree of boxes would do) would blow the stack
+        for _ in 0..3 {
+            drop(chain(1_000_000));
+        }
+    }
+
+    #[test]
+    fn long_decode_test() {
+        let input =
+            "whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune"
+                .repeat(2000);
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree(&HuffTree::find_input_freqs(&input));
+        let encoded_str = HuffTree::encode(&input, &hufftree.generate_huffman_map()).unwrap();
+        assert_eq!(hufftree.decode(&encoded_str), input);
+    }
+
     #[test]
     fn total_and_freqmap_test() {
         assert!(whole_thing_works("aaabbbbbccddd".to_string()));
@@ -298,4 +1647,49 @@ mod test {
                 .to_string()
         ));
     }
+
+    fn len_matches(input: &str) -> bool {
+        let mut hufftree = HuffTree::new();
+        let char_map = HuffTree::find_input_freqs(input);
+        hufftree.populate_tree(&char_map);
+        let huffman_map = hufftree.generate_huffman_map();
+        let encoded_str = HuffTree::encode(input, &huffman_map).unwrap();
+        // the output should have been allocated at exactly the right size
+        HuffTree::encoded_len(&char_map, &huffman_map) == encoded_str.len() as u64
+            && encoded_str.capacity() == encoded_str.len()
+    }
+
+    #[test]
+    fn encoded_len_test() {
+        assert!(len_matches(""));
+        assert!(len_matches("aaabbbbbccddd"));
+        assert!(len_matches("dagoth ur was a hotep"));
+    }
+
+    fn encodes_like_map(input: &str) -> bool {
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree(&HuffTree::find_input_freqs(input));
+        let huffman_map = hufftree.generate_huffman_map();
+        let expected: String = input.chars().map(|ch| huffman_map[&ch].as_str()).collect();
+        HuffTree::encode(input, &huffman_map).unwrap() == expected
+    }
+
+    #[test]
+    fn small_alphabet_test() {
+        assert!(encodes_like_map("gattacacatgcgtaatcg"));
+        assert!(encodes_like_map("0123456789abcdef0123456789abcdeffff"));
+        // one over the limit, so it goes through the hash map instead
+        let big: String = (0..65u32)
+            .map(|i| char::from_u32('0' as u32 + i).unwrap())
+            .collect::<String>()
+            .repeat(3);
+        assert!(encodes_like_map(&big));
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree(&HuffTree::find_input_freqs("gattaca"));
+        let huffman_map = hufftree.generate_huffman_map();
+        assert!(matches!(
+            HuffTree::encode("gattacu", &huffman_map),
+            Err(Error::UnknownSymbol('u'))
+        ));
+    }
 }
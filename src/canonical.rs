@@ -0,0 +1,249 @@
+//! Canonical Huffman codes, worked out straight from the frequencies without ever building a tree
+//!
+//! An optimal code is really just a list of code lengths; which bits each symbol gets can be filled in afterwards,
+//! and the canonical way to do it (shortest codes first, counting up in binary, ties going in symbol order) means
+//! the lengths alone are enough to rebuild the codes. The lengths come from Moffat and Katajainen's in-place
+//! algorithm, which works over the sorted frequencies with no extra space at all: no nodes, no queues, just three
+//! passes back and forth over one array.
+//!
+//! These codes aren't the ones `HuffTree` makes (the lengths are just as good, but the bits differ), so they can't
+//! just be swapped in for a container's codes; a container coded with them (see `EncodeOptions::canonical`) says so
+//! with a flag of its own, and stores the lengths instead of the frequencies.
+
+use std::collections::HashMap;
+
+/// Turns frequencies into optimal code lengths, in place
+///
+/// The frequencies have to be sorted smallest first. Each one gets replaced by the length of its symbol's code, so
+/// the lengths come out longest first. A single symbol gets a length of 0, like the tree would give it.
+///
+/// ## Arguments
+///
+/// * `freqs`: the frequencies, sorted smallest first
+pub fn code_lengths(freqs: &mut [u64]) {
+    let n = freqs.len();
+    match n {
+        0 => return,
+        1 => {
+            freqs[0] = 0;
+            return;
+        }
+        _ => (),
+    }
+    // first pass, left to right: merge pairs, leaving each merged node's weight where the next one's built and
+    // replacing merged-away nodes with the index of their parent
+    freqs[0] += freqs[1];
+    let (mut root, mut leaf) = (0, 2);
+    for next in 1..n - 1 {
+        if leaf >= n || freqs[root] < freqs[leaf] {
+            freqs[next] = freqs[root];
+            freqs[root] = next as u64;
+            root += 1;
+        } else {
+            freqs[next] = freqs[leaf];
+            leaf += 1;
+        }
+        if leaf >= n || (root < next && freqs[root] < freqs[leaf]) {
+            freqs[next] += freqs[root];
+            freqs[root] = next as u64;
+            root += 1;
+        } else {
+            freqs[next] += freqs[leaf];
+            leaf += 1;
+        }
+    }
+    // second pass, right to left: turn the parent indexes into the depths of the internal nodes
+    freqs[n - 2] = 0;
+    for next in (0..n - 2).rev() {
+        freqs[next] = freqs[freqs[next] as usize] + 1;
+    }
+    // third pass, right to left: hand out leaf depths from however many slots each level of internal nodes leaves
+    let (mut available, mut used, mut depth) = (1, 0, 0);
+    let mut root = n as isize - 2;
+    let mut next = n as isize - 1;
+    while available > 0 {
+        while root >= 0 && freqs[root as usize] == depth {
+            used += 1;
+            root -= 1;
+        }
+        while available > used {
+            freqs[next as usize] = depth;
+            next -= 1;
+            available -= 1;
+        }
+        available = 2 * used;
+        depth += 1;
+        used = 0;
+    }
+}
+
+/// Makes sure no code is longer than `max_len`, for formats that cap code lengths (like DEFLATE, at 15 bits), keeping
+/// the code complete
+///
+/// Codes that are too long get cut down to `max_len`, which leaves too little room for them all, so then the
+/// longest codes get moved, one by one, under the longest code there is that's still shorter than `max_len`, by
+/// splitting it in two. The lengths are then handed back out longest first, so the rarest symbols still get the
+/// longest codes. That isn't always the very best limited code (package-merge finds that), but it's within a hair
+/// of it, and it's what zlib does.
+///
+/// ## Arguments
+///
+/// * `lengths`: code lengths from `code_lengths()` (i.e. longest first), with no more than `2^max_len` of them
+/// * `max_len`: the longest a code's allowed to be
+pub fn limit_lengths(lengths: &mut [u64], max_len: u64) {
+    if lengths.first().is_none_or(|&len| len <= max_len) {
+        return;
+    }
+    // how many codes there are of each length, once they're cut down to size
+    let mut counts = vec![0u64; max_len as usize + 1];
+    for &len in lengths.iter() {
+        counts[len.min(max_len) as usize] += 1;
+    }
+    // how much of the code space they take up, in units of the space a `max_len` code takes
+    let mut used: u64 = (1..=max_len)
+        .map(|len| counts[len as usize] << (max_len - len))
+        .sum();
+    while used > 1 << max_len {
+        let shorter = (1..max_len)
+            .rev()
+            .find(|&len| counts[len as usize] > 0)
+            .expect("there's always a shorter code while the code's over-full");
+        counts[max_len as usize] -= 1;
+        counts[shorter as usize] -= 1;
+        counts[shorter as usize + 1] += 2;
+        used -= 1;
+    }
+    let mut lengths = lengths.iter_mut();
+    for len in (1..=max_len).rev() {
+        for length in lengths.by_ref().take(counts[len as usize] as usize) {
+            *length = len;
+        }
+    }
+}
+
+/// Fills in canonical codes for a set of code lengths
+///
+/// ## Arguments
+///
+/// * `lengths`: each symbol's code length (in any order), which have to make a complete prefix code (like the ones
+///   from `code_lengths()` do)
+pub fn canonical_codes(lengths: &[(char, u64)]) -> HashMap<char, String> {
+    let mut sorted = lengths.to_vec();
+    sorted.sort_unstable_by_key(|&(ch, len)| (len, ch));
+    let mut huffman_map = HashMap::with_capacity(sorted.len());
+    // the next code, counted up in binary, as '0'/'1's
+    let mut code = String::new();
+    for (ch, len) in sorted {
+        while (code.len() as u64) < len {
+            code.push('0');
+        }
+        huffman_map.insert(ch, code.clone());
+        // add one, carrying through any trailing 1s
+        let ones = code.len() - code.trim_end_matches('1').len();
+        code.truncate(code.len() - ones);
+        if code.pop().is_some() {
+            code.push('1');
+            code.extend(std::iter::repeat_n('0', ones));
+        }
+    }
+    huffman_map
+}
+
+/// Makes canonical codes straight from char frequencies
+///
+/// This is the one to use when all that's needed is a set of codes, since no tree ever gets built.
+///
+/// ## Arguments
+///
+/// * `char_map`: how often each char shows up
+pub fn generate_huffman_map(char_map: &HashMap<char, i32>) -> HashMap<char, String> {
+    let mut symbols: Vec<(char, i32)> = char_map.iter().map(|(&ch, &freq)| (ch, freq)).collect();
+    symbols.sort_unstable_by_key(|&(ch, freq)| (freq, ch));
+    let mut freqs: Vec<u64> = symbols
+        .iter()
+        .map(|&(_, freq)| freq.max(0) as u64)
+        .collect();
+    code_lengths(&mut freqs);
+    let lengths: Vec<(char, u64)> = symbols
+        .iter()
+        .zip(freqs)
+        .map(|(&(ch, _), len)| (ch, len))
+        .collect();
+    canonical_codes(&lengths)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{canonical_codes, code_lengths, generate_huffman_map, limit_lengths};
+    use crate::btree::HuffTree;
+
+    fn as_good_as_tree(input: &str) -> bool {
+        let char_map = HuffTree::find_input_freqs(input);
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree(&char_map);
+        let huffman_map = generate_huffman_map(&char_map);
+        let codes: Vec<&String> = huffman_map.values().collect();
+        // no code is a prefix of another, and they're exactly as short overall as the tree's
+        let prefix_free = codes
+            .iter()
+            .all(|a| codes.iter().filter(|b| b.starts_with(a.as_str())).count() == 1);
+        prefix_free
+            && HuffTree::encoded_len(&char_map, &huffman_map)
+                == HuffTree::encoded_len(&char_map, &hufftree.generate_huffman_map())
+    }
+
+    #[test]
+    fn code_lengths_test() {
+        let mut freqs = [1, 1, 2, 3, 5, 8];
+        code_lengths(&mut freqs);
+        assert_eq!(freqs, [5, 5, 4, 3, 2, 1]);
+        let mut freqs = [7];
+        code_lengths(&mut freqs);
+        assert_eq!(freqs, [0]);
+        let mut freqs = [4, 4, 4, 4];
+        code_lengths(&mut freqs);
+        assert_eq!(freqs, [2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn limit_lengths_test() {
+        // fibonacci frequencies make the longest codes there can be
+        let mut freqs = vec![1, 1];
+        while freqs.len() < 20 {
+            freqs.push(freqs[freqs.len() - 1] + freqs[freqs.len() - 2]);
+        }
+        let mut lengths = freqs.clone();
+        code_lengths(&mut lengths);
+        assert_eq!(lengths[0], 19);
+        let unlimited = lengths.clone();
+        limit_lengths(&mut lengths, 7);
+        assert!(lengths.iter().all(|&len| len <= 7));
+        assert!(lengths.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert_eq!(
+            lengths.iter().map(|&len| 1u64 << (7 - len)).sum::<u64>(),
+            1 << 7
+        );
+        // codes that already fit are left alone
+        let mut same = unlimited.clone();
+        limit_lengths(&mut same, 19);
+        assert_eq!(same, unlimited);
+        let mut lengths = [0];
+        limit_lengths(&mut lengths, 1);
+        assert_eq!(lengths, [0]);
+    }
+
+    #[test]
+    fn canonical_test() {
+        let huffman_map = canonical_codes(&[('c', 2), ('a', 1), ('d', 3), ('b', 3)]);
+        assert_eq!(huffman_map[&'a'], "0");
+        assert_eq!(huffman_map[&'c'], "10");
+        assert_eq!(huffman_map[&'b'], "110");
+        assert_eq!(huffman_map[&'d'], "111");
+        assert!(as_good_as_tree("a"));
+        assert!(as_good_as_tree("aaabbbbbccddd"));
+        assert!(as_good_as_tree("dagoth ur was a hotep"));
+        assert!(as_good_as_tree(
+            "whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune"
+        ));
+    }
+}
@@ -0,0 +1,88 @@
+/// The CRC-32 lookup table (the usual IEEE polynomial, as used by zip, gzip, png, etc.), built at compile time
+const TABLE: [u32; 256] = make_table();
+
+/// Builds the lookup table, one byte value at a time
+const fn make_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// A running CRC-32, for checksumming data that arrives a piece at a time
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Crc32 {
+    /// The running (inverted) remainder
+    crc: u32,
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
+
+impl Crc32 {
+    /// Creates a new checksum of no data
+    pub(crate) fn new() -> Self {
+        Crc32 { crc: 0xffff_ffff }
+    }
+
+    /// Adds some more data to the checksum
+    ///
+    /// ## Arguments
+    ///
+    /// * `data`: the bytes to add
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.crc = TABLE[((self.crc ^ byte as u32) & 0xff) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    /// Gets the checksum of everything added so far
+    pub(crate) fn value(&self) -> u32 {
+        !self.crc
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Crc32;
+
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(data);
+        crc.value()
+    }
+
+    #[test]
+    fn known_values_test() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414f_a339
+        );
+    }
+
+    #[test]
+    fn piecewise_test() {
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.value(), crc32(b"123456789"));
+    }
+}
@@ -0,0 +1,527 @@
+//! A set of codes on its own, without the tree they came from
+//!
+//! A `HuffTree` is what builds the codes, but once they're built, the codes are all there is to encoding, and they're
+//! what gets stored, shown, or handed to someone else. A `Codebook` is just that: every symbol and its code.
+
+use crate::btree::HuffTree;
+use crate::error::{CodeTableError, Result};
+use crate::{alphabetic, canonical, shannon_fano};
+use std::collections::HashMap;
+use std::mem;
+
+/// Which way `Codebook::from_freqs_with()` works out the codes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Construction {
+    /// Huffman's, from a `HuffTree` (the default, and what `from_freqs()` does)
+    #[default]
+    Huffman,
+    /// Huffman's lengths with canonical bits, from the `canonical` module
+    Canonical,
+    /// Optimal codes that keep the chars' order, from the `alphabetic` module
+    Alphabetic,
+    /// Shannon–Fano's top-down splits, from the `shannon_fano` module, for comparing against Huffman's
+    ShannonFano,
+}
+
+/// Every symbol's code, as a '0'/'1' string
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Codebook {
+    /// The codes
+    codes: HashMap<char, String>,
+}
+
+impl Codebook {
+    /// Builds the codes for a set of char frequencies (the same codes `HuffTree` would give them)
+    ///
+    /// ## Arguments
+    ///
+    /// * `char_map`: how often each char shows up
+    pub fn from_freqs(char_map: &HashMap<char, i32>) -> Self {
+        let mut tree = HuffTree::new();
+        tree.populate_tree(char_map);
+        Codebook::from(tree.generate_huffman_map())
+    }
+
+    /// Builds the codes for a set of char frequencies, whichever way `construction` says to
+    ///
+    /// ## Arguments
+    ///
+    /// * `char_map`: how often each char shows up
+    /// * `construction`: how the codes get worked out
+    pub fn from_freqs_with(char_map: &HashMap<char, i32>, construction: Construction) -> Self {
+        match construction {
+            Construction::Huffman => Codebook::from_freqs(char_map),
+            Construction::Canonical => Codebook::from(canonical::generate_huffman_map(char_map)),
+            Construction::Alphabetic => Codebook::from(alphabetic::generate_huffman_map(char_map)),
+            Construction::ShannonFano => {
+                Codebook::from(shannon_fano::generate_huffman_map(char_map))
+            }
+        }
+    }
+
+    /// Takes a table of codes from somewhere else (some hardware, or a legacy format), checking that it can actually be
+    /// decoded
+    ///
+    /// The codes have to be all 0s and 1s, with no symbol listed twice, and no code the start of another. With
+    /// `complete` set, they also have to leave no bit string undecodable (i.e. their lengths meet Kraft's equality),
+    /// which is what any Huffman code does; leave it off to allow tables with codes to spare. A lone symbol can have
+    /// an empty code, like it would get from a tree.
+    ///
+    /// Fails with `Error::BadCodeTable`, saying what's wrong.
+    ///
+    /// ## Arguments
+    ///
+    /// * `pairs`: every symbol and its code
+    /// * `complete`: whether the codes have to cover every bit string
+    pub fn from_pairs<I, S>(pairs: I, complete: bool) -> Result<Self>
+    where
+        I: IntoIterator<Item = (char, S)>,
+        S: Into<String>,
+    {
+        let mut codes = HashMap::new();
+        for (ch, code) in pairs {
+            let code = code.into();
+            if code.bytes().any(|bit| bit != b'0' && bit != b'1') {
+                return Err(CodeTableError::NotBinary(ch).into());
+            }
+            if codes.insert(ch, code).is_some() {
+                return Err(CodeTableError::DuplicateSymbol(ch).into());
+            }
+        }
+        let mut sorted: Vec<(&str, char)> = codes
+            .iter()
+            .map(|(&ch, code)| (code.as_str(), ch))
+            .collect();
+        sorted.sort_unstable();
+        if sorted.len() > 1 {
+            if let Some(&(_, ch)) = sorted.iter().find(|(code, _)| code.is_empty()) {
+                return Err(CodeTableError::EmptyCode(ch).into());
+            }
+        }
+        // in sorted order, a code that's a prefix of any others is a prefix of the one right after it
+        for pair in sorted.windows(2) {
+            let ((a, prefix), (b, of)) = (pair[0], pair[1]);
+            if b.starts_with(a) {
+                return Err(CodeTableError::NotPrefixFree { prefix, of }.into());
+            }
+        }
+        // (no codes at all is what an empty input gets, so that's complete enough)
+        if complete && !codes.is_empty() && !meets_kraft(codes.values().map(String::len)) {
+            return Err(CodeTableError::Incomplete.into());
+        }
+        Ok(Codebook { codes })
+    }
+
+    /// Reads a code table saved as JSON by Python's `dahuffman` (and the tools that copy its layout), so a codebook
+    /// trained on the Python side can be used here as well
+    ///
+    /// That's `json.dump(codec.get_code_table(), ...)`: an object mapping each symbol to a `[bits, value]` pair, i.e.
+    /// the code's length and the code itself as a number:
+    ///
+    /// ```text
+    /// {"a": [1, 0], "b": [2, 2], "_EOF": [2, 3]}
+    /// ```
+    ///
+    /// Every symbol has to be a single char (for a codec trained on bytes, turn them into chars with `chr()` before
+    /// dumping). dahuffman's end-of-file symbol, dumped as `"_EOF"`, gets skipped, since containers record how long
+    /// their input was instead, which means the codes that are left are never complete, and don't have to be. They do
+    /// get checked just like `from_pairs()` checks them, though.
+    ///
+    /// ## Arguments
+    ///
+    /// * `json`: the saved code table
+    pub fn from_dahuffman_json(json: &str) -> Result<Self> {
+        Codebook::from_pairs(crate::dahuffman::parse(json)?, false)
+    }
+
+    /// Gets the code for a char, if it has one
+    ///
+    /// ## Arguments
+    ///
+    /// * `ch`: the char
+    pub fn get(&self, ch: char) -> Option<&str> {
+        self.codes.get(&ch).map(String::as_str)
+    }
+
+    /// How many symbols have codes
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    /// Whether there are no codes at all
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+
+    /// Gets a shared ref to the underlying map of codes
+    pub fn as_map(&self) -> &HashMap<char, String> {
+        &self.codes
+    }
+
+    /// Unwraps the underlying map of codes
+    pub fn into_map(self) -> HashMap<char, String> {
+        self.codes
+    }
+
+    /// Encodes a string with these codes (see `HuffTree::encode()`)
+    ///
+    /// ## Arguments
+    ///
+    /// * `input`: the string to be encoded
+    pub fn encode(&self, input: &str) -> Result<String> {
+        HuffTree::encode(input, &self.codes)
+    }
+
+    /// Encodes a string with these codes into a `BitVec` (with the `bitvec` feature; see `HuffTree::encode_bits()`)
+    ///
+    /// ## Arguments
+    ///
+    /// * `input`: the string to be encoded
+    #[cfg(feature = "bitvec")]
+    pub fn encode_bits(&self, input: &str) -> Result<bitvec::vec::BitVec<u8, bitvec::order::Msb0>> {
+        HuffTree::encode_bits(input, &self.codes)
+    }
+
+    /// Lays the codes out as a Markdown table, with how many times each symbol showed up, for READMEs and reports
+    ///
+    /// The most common symbols come first. Symbols are written the way Rust would write them as char literals, so
+    /// whitespace and control characters come out as escapes rather than as nothing at all (or a broken table):
+    ///
+    /// ```text
+    /// | symbol | count | code |
+    /// |:-------|------:|:-----|
+    /// | `'a'`  | 2     | `0`  |
+    /// | `'\n'` | 1     | `10` |
+    /// ```
+    ///
+    /// ## Arguments
+    ///
+    /// * `char_map`: how often each char shows up (a symbol that isn't in it gets a count of 0)
+    pub fn to_markdown_table(&self, char_map: &HashMap<char, i32>) -> String {
+        let mut rows: Vec<(char, i32, &str)> = self
+            .codes
+            .iter()
+            .map(|(&ch, code)| (ch, char_map.get(&ch).copied().unwrap_or(0), code.as_str()))
+            .collect();
+        rows.sort_unstable_by(|a, b| {
+            b.1.cmp(&a.1)
+                .then(a.2.len().cmp(&b.2.len()))
+                .then(a.2.cmp(b.2))
+        });
+        let mut out = String::from("| symbol | count | code |\n|:-------|------:|:-----|\n");
+        for (ch, count, code) in rows {
+            out.push_str(&format!(
+                "| {} | {} | `{}` |\n",
+                markdown_char(ch),
+                count,
+                code
+            ));
+        }
+        out
+    }
+
+    /// Roughly how many bytes of heap memory the codebook is using (the map, plus every code string in it)
+    ///
+    /// This is an estimate, since the hash map's exact layout is up to the standard library, but it's the right
+    /// ballpark for budgeting lots of codebooks.
+    pub fn memory_usage(&self) -> usize {
+        map_memory(&self.codes) + self.codes.values().map(String::capacity).sum::<usize>()
+    }
+}
+
+impl From<HashMap<char, String>> for Codebook {
+    fn from(codes: HashMap<char, String>) -> Self {
+        Codebook { codes }
+    }
+}
+
+/// Serialized as a map of chars to codes, in char order, for formats meant for people (like JSON); binary formats
+/// (like postcard and bincode) get a list of `(char, length, bits)` instead, with each code's bits packed into a
+/// `u64`, which is a good deal smaller and the same size for every code
+#[cfg(feature = "serde")]
+impl serde::Serialize for Codebook {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        let mut codes: Vec<(&char, &String)> = self.codes.iter().collect();
+        codes.sort_unstable();
+        if serializer.is_human_readable() {
+            return serializer.collect_map(codes);
+        }
+        let mut packed = Vec::with_capacity(codes.len());
+        for (&ch, code) in codes {
+            if code.len() > 64 {
+                return Err(serde::ser::Error::custom(
+                    "codes longer than 64 bits can't be packed",
+                ));
+            }
+            let bits = code
+                .bytes()
+                .fold(0u64, |acc, bit| (acc << 1) | (bit == b'1') as u64);
+            packed.push((ch, code.len() as u8, bits));
+        }
+        serializer.collect_seq(packed)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Codebook {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        if !deserializer.is_human_readable() {
+            let packed: Vec<(char, u8, u64)> = serde::Deserialize::deserialize(deserializer)?;
+            let mut codes = HashMap::with_capacity(packed.len());
+            for (ch, len, bits) in packed {
+                if len > 64 || (len < 64 && bits >> len != 0) {
+                    return Err(serde::de::Error::custom("code doesn't fit its length"));
+                }
+                let code = (0..len)
+                    .rev()
+                    .map(|bit| if bits >> bit & 1 == 1 { '1' } else { '0' })
+                    .collect();
+                if codes.insert(ch, code).is_some() {
+                    return Err(serde::de::Error::custom(format!("{:?} has two codes", ch)));
+                }
+            }
+            return Ok(Codebook { codes });
+        }
+        let codes: HashMap<char, String> = serde::Deserialize::deserialize(deserializer)?;
+        // anything else would trip up the encoders, which only ever look for '1'
+        if codes
+            .values()
+            .any(|code| code.bytes().any(|bit| bit != b'0' && bit != b'1'))
+        {
+            return Err(serde::de::Error::custom(
+                "codes can only have 0s and 1s in them",
+            ));
+        }
+        Ok(Codebook { codes })
+    }
+}
+
+/// The codes a tree would give an arbitrary `FrequencyMap`, so they're always prefix-free and complete
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Codebook {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let freqs: crate::freq::FrequencyMap = u.arbitrary()?;
+        Ok(Codebook::from_freqs(freqs.as_map()))
+    }
+}
+
+/// Whether code lengths meet Kraft's equality, i.e. the 2^-length of each of them adds up to exactly 1
+///
+/// The lengths can be too long for the powers to fit in any int, so instead of adding them up, this counts how many
+/// codes there are of each length and pairs them off from the longest up: two codes of one length are worth one of
+/// the next shorter length, and the sum is 1 exactly when that leaves nothing over and ends with one code of length 0.
+///
+/// ## Arguments
+///
+/// * `lengths`: every code's length
+fn meets_kraft<I: Iterator<Item = usize>>(lengths: I) -> bool {
+    let mut counts: Vec<usize> = Vec::new();
+    for len in lengths {
+        if counts.len() <= len {
+            counts.resize(len + 1, 0);
+        }
+        counts[len] += 1;
+    }
+    for len in (1..counts.len()).rev() {
+        if !counts[len].is_multiple_of(2) {
+            return false;
+        }
+        counts[len - 1] += counts[len] / 2;
+    }
+    counts.first() == Some(&1)
+}
+
+/// Writes a char as a char literal in a Markdown code span, safe to put in a table cell
+///
+/// ## Arguments
+///
+/// * `ch`: the char
+fn markdown_char(ch: char) -> String {
+    // (a pipe would end the cell, even in a code span)
+    let literal = format!("{:?}", ch).replace('|', "\\|");
+    if ch == '`' {
+        // a code span with a backtick in it needs a longer run of them around it
+        format!("`` {} ``", literal)
+    } else {
+        format!("`{}`", literal)
+    }
+}
+
+/// Roughly how many bytes of heap memory a hash map's own table takes up (not counting anything its entries point to)
+///
+/// ## Arguments
+///
+/// * `map`: the map
+fn map_memory<K, V>(map: &HashMap<K, V>) -> usize {
+    // each slot holds an entry plus a control byte
+    map.capacity() * (mem::size_of::<(K, V)>() + 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Codebook, Construction};
+    use crate::btree::HuffTree;
+    use crate::error::{CodeTableError, Error};
+
+    #[test]
+    fn codebook_test() {
+        let char_map = HuffTree::find_input_freqs("dagoth ur was a hotep");
+        let codebook = Codebook::from_freqs(&char_map);
+        assert_eq!(codebook.len(), char_map.len());
+        assert_eq!(
+            codebook.encode("a hotep").unwrap(),
+            HuffTree::encode("a hotep", codebook.as_map()).unwrap()
+        );
+        assert_eq!(codebook.get('z'), None);
+        // every code is at least a byte's worth of string, on top of the map's own table
+        let usage = codebook.memory_usage();
+        assert!(usage >= codebook.len() * (std::mem::size_of::<(char, String)>() + 1));
+        assert!(Codebook::default().memory_usage() < usage);
+    }
+
+    #[test]
+    fn construction_test() {
+        let char_map = HuffTree::find_input_freqs("dagoth ur was a hotep");
+        let huffman = Codebook::from_freqs_with(&char_map, Construction::Huffman);
+        assert_eq!(huffman, Codebook::from_freqs(&char_map));
+        let huffman_len = HuffTree::encoded_len(&char_map, huffman.as_map());
+        for construction in [
+            Construction::Canonical,
+            Construction::Alphabetic,
+            Construction::ShannonFano,
+        ] {
+            let codebook = Codebook::from_freqs_with(&char_map, construction);
+            // every one's a complete prefix code, none of them any shorter than Huffman's
+            assert!(Codebook::from_pairs(codebook.as_map().clone(), true).is_ok());
+            assert!(HuffTree::encoded_len(&char_map, codebook.as_map()) >= huffman_len);
+        }
+        assert_eq!(
+            HuffTree::encoded_len(
+                &char_map,
+                Codebook::from_freqs_with(&char_map, Construction::Canonical).as_map()
+            ),
+            huffman_len
+        );
+    }
+
+    #[test]
+    fn from_pairs_test() {
+        let codebook =
+            Codebook::from_pairs(vec![('a', "0"), ('b', "10"), ('c', "11")], true).unwrap();
+        assert_eq!(
+            codebook,
+            Codebook::from_freqs(&HuffTree::find_input_freqs("aabc"))
+        );
+        assert_eq!(codebook.encode("cab").unwrap(), "11010");
+        // a lone symbol can have an empty code, like a tree would give it
+        assert!(Codebook::from_pairs(vec![('a', "")], true).is_ok());
+        assert!(Codebook::from_pairs(Vec::<(char, &str)>::new(), true)
+            .unwrap()
+            .is_empty());
+        // spare codes are fine unless the table has to be complete
+        let spare = vec![('a', "0"), ('b', "10")];
+        assert!(Codebook::from_pairs(spare.clone(), false).is_ok());
+        let bad = |pairs: Vec<(char, &str)>, complete| match Codebook::from_pairs(pairs, complete) {
+            Err(Error::BadCodeTable(why)) => why,
+            other => panic!("expected a bad code table, got {:?}", other),
+        };
+        assert_eq!(bad(spare, true), CodeTableError::Incomplete);
+        assert_eq!(
+            bad(vec![('a', "0"), ('b', "12")], false),
+            CodeTableError::NotBinary('b')
+        );
+        assert_eq!(
+            bad(vec![('a', "0"), ('a', "1")], false),
+            CodeTableError::DuplicateSymbol('a')
+        );
+        assert_eq!(
+            bad(vec![('a', ""), ('b', "1")], false),
+            CodeTableError::EmptyCode('a')
+        );
+        assert_eq!(
+            bad(
+                vec![('a', "0"), ('b', "10"), ('c', "101"), ('d', "11")],
+                false
+            ),
+            CodeTableError::NotPrefixFree {
+                prefix: 'b',
+                of: 'c'
+            }
+        );
+        // far too long for 2^-length to fit anywhere, but still complete
+        let long: Vec<(char, String)> = (0..200)
+            .map(|i| {
+                (
+                    char::from_u32(0x100 + i).unwrap(),
+                    "1".repeat(i as usize) + "0",
+                )
+            })
+            .chain(std::iter::once(('z', "1".repeat(200))))
+            .collect();
+        assert!(Codebook::from_pairs(long, true).is_ok());
+    }
+
+    #[test]
+    fn from_dahuffman_json_test() {
+        let json = r#"{"a": [1, 0], "b": [2, 2], "_EOF": [2, 3]}"#;
+        assert_eq!(
+            Codebook::from_dahuffman_json(json).unwrap(),
+            Codebook::from_pairs(vec![('a', "0"), ('b', "10")], false).unwrap()
+        );
+        // the codes still have to make sense
+        assert!(matches!(
+            Codebook::from_dahuffman_json(r#"{"a": [1, 0], "b": [2, 1]}"#),
+            Err(Error::BadCodeTable(CodeTableError::NotPrefixFree { .. }))
+        ));
+    }
+
+    #[test]
+    fn to_markdown_table_test() {
+        let char_map = HuffTree::find_input_freqs("aaa\n|`");
+        let codebook = Codebook::from_freqs(&char_map);
+        let table = codebook.to_markdown_table(&char_map);
+        let mut lines = table.lines();
+        assert_eq!(lines.next(), Some("| symbol | count | code |"));
+        assert_eq!(lines.next(), Some("|:-------|------:|:-----|"));
+        assert_eq!(lines.next(), Some("| `'a'` | 3 | `0` |"));
+        let rest: Vec<&str> = lines.collect();
+        assert_eq!(rest.len(), 3);
+        assert!(rest
+            .iter()
+            .any(|line| line.starts_with("| `'\\n'` | 1 | `1")));
+        assert!(rest
+            .iter()
+            .any(|line| line.starts_with("| `'\\|'` | 1 | `1")));
+        assert!(rest
+            .iter()
+            .any(|line| line.starts_with("| `` '`' `` | 1 | `1")));
+        let codebook = Codebook::from_freqs(&HuffTree::find_input_freqs(" \u{a0}"));
+        let table = codebook.to_markdown_table(&Default::default());
+        assert!(table.contains("| `' '` | 0 |"));
+        assert!(table.contains("| `'\\u{a0}'` | 0 |"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_test() {
+        let codebook = Codebook::from_freqs(&HuffTree::find_input_freqs("aabc"));
+        let json = serde_json::to_string(&codebook).unwrap();
+        assert_eq!(json, "{\"a\":\"0\",\"b\":\"10\",\"c\":\"11\"}");
+        assert_eq!(serde_json::from_str::<Codebook>(&json).unwrap(), codebook);
+        assert!(serde_json::from_str::<Codebook>("{\"a\":\"0\",\"b\":\"12\"}").is_err());
+        // binary formats get the codes packed
+        let packed = postcard::to_allocvec(&codebook).unwrap();
+        // (postcard puts chars down as a length and then UTF-8)
+        assert_eq!(packed, [3, 1, b'a', 1, 0, 1, b'b', 2, 2, 1, b'c', 2, 3]);
+        assert_eq!(postcard::from_bytes::<Codebook>(&packed).unwrap(), codebook);
+        let packed = bincode::serialize(&codebook).unwrap();
+        assert_eq!(bincode::deserialize::<Codebook>(&packed).unwrap(), codebook);
+        assert!(postcard::from_bytes::<Codebook>(&[1, 1, b'a', 1, 2]).is_err());
+        assert!(postcard::from_bytes::<Codebook>(&[2, 1, b'a', 1, 1, 1, b'a', 1, 0]).is_err());
+    }
+}
@@ -0,0 +1,357 @@
+//! The same module layout and constructors as `flate2`, so a simple pipeline can switch over by changing its imports
+//!
+//! Everything here is the one-pass stream format from `stream` (see `HuffmanWriter` and `HuffmanReader`), under the
+//! names `flate2` would use: `read` has the types that wrap a reader, `write` the ones that wrap a writer, and
+//! `bufread` the ones that wrap a `BufRead` (so nothing past the end of the stream gets read away). Swapping
+//! `flate2::write::GzEncoder::new(out, Compression::default())` for `huffman::write::HuffEncoder::new(out,
+//! Compression::default())` is all it takes.
+//!
+//! Huffman coding has no levels to trade speed for size with, though, so the `Compression` given is ignored.
+
+/// How hard to compress, in the same shape as `flate2`'s (which is all it's for, since it doesn't change anything)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Compression(u32);
+
+impl Compression {
+    /// A level from 0 (none) to 9 (best)
+    ///
+    /// ## Arguments
+    ///
+    /// * `level`: the level
+    pub const fn new(level: u32) -> Self {
+        Compression(level)
+    }
+
+    /// Level 0
+    pub const fn none() -> Self {
+        Compression(0)
+    }
+
+    /// Level 1
+    pub const fn fast() -> Self {
+        Compression(1)
+    }
+
+    /// Level 9
+    pub const fn best() -> Self {
+        Compression(9)
+    }
+
+    /// Gets the level
+    pub fn level(&self) -> u32 {
+        self.0
+    }
+}
+
+/// Level 6, like `flate2`
+impl Default for Compression {
+    fn default() -> Self {
+        Compression(6)
+    }
+}
+
+/// Types that wrap a `BufRead`
+pub mod bufread {
+    /// Decompresses a stream as it's read, out of the wrapped reader's own buffer
+    pub type HuffDecoder<R> = crate::stream::HuffmanReader<R>;
+}
+
+/// Types that wrap a `Read`
+pub mod read {
+    use super::Compression;
+    use crate::stream::{Encoder, HuffmanReader};
+    use std::io::{self, BufReader, Read};
+
+    /// How many bytes `HuffEncoder` reads from the wrapped reader at a time
+    const CHUNK_SIZE: usize = 32 * 1024;
+
+    /// Decompresses a stream as it's read
+    ///
+    /// The wrapped reader gets buffered, so unlike `bufread::HuffDecoder`, whatever comes after the end of the stream
+    /// may already have been read out of it.
+    pub struct HuffDecoder<R: Read> {
+        /// The decompressor doing the actual work
+        inner: HuffmanReader<BufReader<R>>,
+    }
+
+    impl<R: Read> HuffDecoder<R> {
+        /// Creates a new decompressor
+        ///
+        /// ## Arguments
+        ///
+        /// * `inner`: the reader the compressed stream comes from
+        pub fn new(inner: R) -> Self {
+            HuffDecoder {
+                inner: HuffmanReader::new(BufReader::new(inner)),
+            }
+        }
+
+        /// Gets a shared ref to the wrapped reader
+        pub fn get_ref(&self) -> &R {
+            self.inner.get_ref().get_ref()
+        }
+
+        /// Hands back the wrapped reader (anything it had buffered is lost)
+        pub fn into_inner(self) -> R {
+            self.inner.into_inner().into_inner()
+        }
+    }
+
+    impl<R: Read> Read for HuffDecoder<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    /// Compresses the wrapped reader's bytes as they're read, ending the stream once the wrapped reader runs out
+    pub struct HuffEncoder<R: Read> {
+        /// Where the uncompressed bytes come from
+        inner: R,
+        /// The encoder doing the actual work
+        encoder: Encoder,
+        /// Uncompressed bytes, as they're read in
+        in_buf: Vec<u8>,
+        /// Compressed bytes that haven't been read out yet
+        out_buf: Vec<u8>,
+        /// How much of `out_buf` has been read out
+        out_pos: usize,
+        /// Whether the stream's been ended
+        finished: bool,
+    }
+
+    impl<R: Read> HuffEncoder<R> {
+        /// Creates a new compressor
+        ///
+        /// ## Arguments
+        ///
+        /// * `inner`: the reader the uncompressed bytes come from
+        /// * `level`: ignored (see the module docs)
+        pub fn new(inner: R, level: Compression) -> Self {
+            let _ = level;
+            HuffEncoder {
+                inner,
+                encoder: Encoder::new(),
+                in_buf: vec![0; CHUNK_SIZE],
+                out_buf: Vec::new(),
+                out_pos: 0,
+                finished: false,
+            }
+        }
+
+        /// Gets a shared ref to the wrapped reader
+        pub fn get_ref(&self) -> &R {
+            &self.inner
+        }
+
+        /// Hands back the wrapped reader
+        pub fn into_inner(self) -> R {
+            self.inner
+        }
+    }
+
+    impl<R: Read> Read for HuffEncoder<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            while self.out_pos == self.out_buf.len() && !self.finished {
+                self.out_buf.clear();
+                self.out_pos = 0;
+                match self.inner.read(&mut self.in_buf)? {
+                    0 => {
+                        self.encoder.finish(&mut self.out_buf);
+                        self.finished = true;
+                    }
+                    n => self.encoder.encode(&self.in_buf[..n], &mut self.out_buf),
+                }
+            }
+            let n = buf.len().min(self.out_buf.len() - self.out_pos);
+            buf[..n].copy_from_slice(&self.out_buf[self.out_pos..self.out_pos + n]);
+            self.out_pos += n;
+            Ok(n)
+        }
+    }
+}
+
+/// Types that wrap a `Write`
+pub mod write {
+    use super::Compression;
+    use crate::stream::{Decoder, HuffmanWriter};
+    use std::io::{self, Write};
+
+    /// Compresses everything written to it, writing the compressed stream to the wrapped writer
+    ///
+    /// Like `flate2`'s encoders, this ends the stream when it's dropped, if `finish()` hasn't been called (but any
+    /// error doing so gets lost, so calling `finish()` is better).
+    pub struct HuffEncoder<W: Write> {
+        /// The compressor doing the actual work (only ever `None` once it's been finished)
+        inner: Option<HuffmanWriter<W>>,
+    }
+
+    impl<W: Write> HuffEncoder<W> {
+        /// Creates a new compressor
+        ///
+        /// ## Arguments
+        ///
+        /// * `inner`: the writer the compressed stream goes to
+        /// * `level`: ignored (see the module docs)
+        pub fn new(inner: W, level: Compression) -> Self {
+            let _ = level;
+            HuffEncoder {
+                inner: Some(HuffmanWriter::new(inner)),
+            }
+        }
+
+        /// Gets a shared ref to the wrapped writer
+        pub fn get_ref(&self) -> &W {
+            self.writer().get_ref()
+        }
+
+        /// Ends the stream, and hands back the writer
+        pub fn finish(mut self) -> io::Result<W> {
+            let writer = self.inner.take();
+            writer.expect("the stream was already finished").finish()
+        }
+
+        /// Gets the compressor, which is always there until `finish()` or `drop()` takes it
+        fn writer(&self) -> &HuffmanWriter<W> {
+            self.inner
+                .as_ref()
+                .expect("the stream was already finished")
+        }
+
+        /// `writer()`, but mutable
+        fn writer_mut(&mut self) -> &mut HuffmanWriter<W> {
+            self.inner
+                .as_mut()
+                .expect("the stream was already finished")
+        }
+    }
+
+    impl<W: Write> Write for HuffEncoder<W> {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.writer_mut().write(data)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.writer_mut().flush()
+        }
+    }
+
+    impl<W: Write> Drop for HuffEncoder<W> {
+        fn drop(&mut self) {
+            if let Some(writer) = self.inner.take() {
+                let _ = writer.finish();
+            }
+        }
+    }
+
+    /// Decompresses everything written to it, writing the original bytes to the wrapped writer
+    ///
+    /// Once the end of the stream's been written, anything written after it is refused (`write()` returns 0).
+    pub struct HuffDecoder<W: Write> {
+        /// Where the decompressed bytes go
+        inner: W,
+        /// The decoder doing the actual work
+        decoder: Decoder,
+        /// Scratch space for the decoder's output
+        buf: Vec<u8>,
+    }
+
+    impl<W: Write> HuffDecoder<W> {
+        /// Creates a new decompressor
+        ///
+        /// ## Arguments
+        ///
+        /// * `inner`: the writer the decompressed bytes go to
+        pub fn new(inner: W) -> Self {
+            HuffDecoder {
+                inner,
+                decoder: Decoder::new(),
+                buf: Vec::new(),
+            }
+        }
+
+        /// Gets a shared ref to the wrapped writer
+        pub fn get_ref(&self) -> &W {
+            &self.inner
+        }
+
+        /// Checks that the whole stream got written, and hands back the writer
+        pub fn finish(mut self) -> io::Result<W> {
+            if !self.decoder.is_done() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "compressed stream ended without an end-of-stream marker",
+                ));
+            }
+            self.inner.flush()?;
+            Ok(self.inner)
+        }
+    }
+
+    impl<W: Write> Write for HuffDecoder<W> {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            if self.decoder.is_done() {
+                return Ok(0);
+            }
+            let used = self.decoder.decode(data, &mut self.buf)?;
+            self.inner.write_all(&self.buf)?;
+            self.buf.clear();
+            Ok(used)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bufread, read, write, Compression};
+    use std::io::{self, Read, Write};
+
+    #[test]
+    fn flate2_layout_test() {
+        let input = b"dagoth ur was a hotep, and so was everyone else he knew".repeat(50);
+        let mut encoder = write::HuffEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&input).unwrap();
+        let compressed = encoder.finish().unwrap();
+        // the read-side encoder makes exactly the same stream
+        let mut read_compressed = Vec::new();
+        read::HuffEncoder::new(&input[..], Compression::best())
+            .read_to_end(&mut read_compressed)
+            .unwrap();
+        assert_eq!(read_compressed, compressed);
+        // and dropping the write-side one ends the stream just like finishing it does
+        let mut dropped = Vec::new();
+        write::HuffEncoder::new(&mut dropped, Compression::fast())
+            .write_all(&input)
+            .unwrap();
+        assert_eq!(dropped, compressed);
+
+        let mut decompressed = Vec::new();
+        read::HuffDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, input);
+        let mut with_trailer = compressed.clone();
+        with_trailer.extend_from_slice(b"after");
+        let mut decoder = bufread::HuffDecoder::new(&with_trailer[..]);
+        let mut decompressed = Vec::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, input);
+        assert_eq!(decoder.into_inner(), b"after");
+
+        let mut decoder = write::HuffDecoder::new(Vec::new());
+        for chunk in compressed.chunks(7) {
+            decoder.write_all(chunk).unwrap();
+        }
+        assert_eq!(decoder.write(b"after").unwrap(), 0);
+        assert_eq!(decoder.finish().unwrap(), input);
+        let mut decoder = write::HuffDecoder::new(Vec::new());
+        decoder.write_all(&compressed[..10]).unwrap();
+        assert_eq!(
+            decoder.finish().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+}
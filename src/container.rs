@@ -0,0 +1,3566 @@
+//! The on-disk format: a small header describing the model, the packed codes, and an optional checksum trailer
+//!
+//! ```text
+//! magic       4 bytes   "HUFF"
+//! version     1 byte    1, or 2 if there's a pipeline extension
+//! flags       1 byte    bit 0: CRC-32 trailer present, bit 1: streaming (adaptive) payload, bit 2: index,
+//!                       bit 3: dictionary, bit 4: index has names, bit 5: index has file metadata,
+//!                       bit 6: canonical codes, bit 7: extensions
+//! -- with extensions only --
+//! ext length  4 bytes   how long the extensions are
+//! extensions  a CBOR map of extra fields (see the `extensions` module)
+//! -- static payloads only --
+//! length      8 bytes   original length in bytes (little-endian, as is everything else)
+//! symbols     2 bytes   how many (symbol, frequency) entries follow
+//! entries     5 bytes each: the byte value, then its frequency as a u32
+//! packed      8 bytes   how many bytes the payload takes up, so the container can be skipped without decoding it
+//! -- all payloads --
+//! payload     the packed codes, padded out to a whole byte
+//! checksum    4 bytes   CRC-32 of the original data, if the flag is set
+//! ```
+//!
+//! An index is a header with no payload, listing where each input of a multi-input file is:
+//!
+//! ```text
+//! inputs      4 bytes   how many entries follow
+//! entries     32 bytes each: compressed offset, compressed length, original offset, original length (all u64)
+//! -- archives only (index has names) --
+//! names       one per entry: the input's CRC-32 (4 bytes), its name's length (2 bytes), then the name (UTF-8),
+//!             then (only with file metadata) its Unix permissions (4 bytes) and modification time (8 bytes, in
+//!             seconds since the epoch)
+//! -- all indexes --
+//! length      8 bytes   how long the whole index is, magic included, so it can be found from the end of the file
+//! ```
+//!
+//! Extensions are what any field added from now on goes in, rather than taking up a flag of its own (there's only
+//! the one left, and it's theirs): they're a map, so a decoder can step right over whichever of them it doesn't know
+//! about. Any kind of container can have them, and a container without any doesn't set the flag, so it looks
+//! exactly like it did before they existed.
+//!
+//! A pipeline (see the `pipeline` module) is the one extension that changes what the payload means: what it decodes
+//! to has to go back through the pipeline's stages, in reverse, to get the original data back (and the checksum and
+//! the original length are of what it decodes to, before that). A header with one gets version 2, so that builds
+//! from before pipelines turn it away, rather than skipping over the extension and handing back the transformed
+//! data as if it were the original.
+//!
+//! A static payload compressed with a dictionary (see the `dict` module) has the dictionary's 4-byte ID in place of
+//! the symbols and their entries, and can only be decoded by someone who has that dictionary.
+//!
+//! A static payload coded with canonical codes (see the `canonical` module) has 2-byte entries instead: the byte
+//! value, then the length of its code. The lengths are all the decoder needs to rebuild the codes, which makes the
+//! header less than half the size.
+//!
+//! Static payloads are decoded by rebuilding the tree from the stored frequencies, so the tree-building has to be
+//! deterministic (which `HuffTree::populate_tree()` makes sure of). Streaming payloads are the one-pass adaptive
+//! streams from the `stream` module, which end with their own end-of-stream marker and CRC-32 footer; that footer
+//! stands in for the trailer, so streaming containers always have the checksum flag set.
+//!
+//! A file can hold several containers one after another (the way gzip files can have several members), which get
+//! decoded in order and concatenated. That's how block mode works: each block of input becomes a complete container
+//! of its own, and since static containers record how long they are, `split_points()` can find every block without
+//! decoding anything, so different blocks can be decoded independently (e.g. on different machines). Blocks don't
+//! all have to be the same size, either (see `Split`), since each one says how long it is.
+//!
+//! `compress_many()` does the same sort of thing with several separate inputs, and then puts an index at the very
+//! end, so any one input can be pulled back out on its own. Indexes decode to nothing, so decompressing the whole file
+//! just gives all the inputs one after another. `compress_archive()` makes the same kind of file out of named inputs
+//! (e.g. files), with each one's name and checksum in the index too, so they can be listed and pulled out by name.
+//! `compress_concatenated()` leaves the index out altogether.
+//!
+//! With the `serde` feature, headers can also be serialized, as a tree of plain fields (with the payload as a map
+//! from its kind to its fields), so tools using any serde format (JSON, CBOR, MessagePack, postcard, bincode, ...) can
+//! read and write them without parsing the binary format themselves. Deserializing checks everything
+//! `Header::read_from()` does. With the `arbitrary` feature, fuzzers can make up headers too, and every one they make
+//! up is one `Header::read_from()` could have read.
+
+use crate::bits::{BitWindow, BitWriter, ByteCodes};
+use crate::btree::HuffTree;
+use crate::canonical;
+use crate::checksum::Crc32;
+use crate::dict::Dictionary;
+use crate::error::{Error, Result};
+use crate::extensions::{self, Extensions, Key};
+use crate::freq::{count_bytes, FrequencyMap};
+use crate::fsm::DecodeFsm;
+use crate::pipeline::Pipeline;
+use crate::stream::{Decoder, Encoder};
+use crate::table::DecodeTable;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// The bytes every container starts with
+pub const MAGIC: [u8; 4] = *b"HUFF";
+/// The newest format version this build can read and write
+pub const VERSION: u8 = 2;
+/// The version of a header with no pipeline extension, which is what's written whenever there isn't one, so builds
+/// from before pipelines can still read it
+const VERSION_WITHOUT_PIPELINE: u8 = 1;
+
+/// Flag bit: a CRC-32 trailer follows the payload
+const FLAG_CHECKSUM: u8 = 1;
+/// Flag bit: the payload is a one-pass adaptive stream rather than a static one
+const FLAG_STREAMING: u8 = 1 << 1;
+/// Flag bit: this is an index of the inputs in the file, rather than a payload
+const FLAG_INDEX: u8 = 1 << 2;
+/// Flag bit: the static payload was coded with a dictionary, whose ID stands in for the frequencies
+const FLAG_DICTIONARY: u8 = 1 << 3;
+/// Flag bit: the index has a name and checksum for every input (i.e. it's an archive's)
+const FLAG_NAMES: u8 = 1 << 4;
+/// Flag bit: the index's names each have the permissions and modification time of the file they came from
+const FLAG_METADATA: u8 = 1 << 5;
+/// Flag bit: the static payload was coded with canonical codes, whose lengths stand in for the frequencies
+const FLAG_CANONICAL: u8 = 1 << 6;
+/// Flag bit: a map of extensions comes right after the flags (which is the last flag bit there is, so anything new
+/// goes in the extensions from here on)
+const FLAG_EXTENSIONS: u8 = 1 << 7;
+
+/// How many bytes get read and written at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+/// How many bytes of an in-memory input get counted at a time
+const COUNT_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+/// The longest code a canonical payload can have, which is as many bits as the decoders can look at at once
+pub(crate) const MAX_CODE_LEN: u8 = 57;
+/// The most symbols a static payload can have to get decoded by `decode_tiny()`
+const TINY_ALPHABET: usize = 4;
+/// How many bytes at a time `Split` looks at, which is also the shortest a block it ends early can be
+const SPLIT_SEGMENT: usize = 4096;
+
+/// The most bytes a static payload of just the one symbol can decode to, unless `DecodeOptions::max_output_bytes`
+/// is set
+///
+/// A lone symbol's code is empty, so its payload is too, and nothing but the header's word says how much of it
+/// there is; without a cap, a header of a few dozen bytes could decode to as many terabytes as it likes. Compressing
+/// never makes one longer than this (a longer run of one byte gets split up into several containers).
+pub const LONE_SYMBOL_LIMIT: u64 = 4 * 1024 * 1024;
+
+/// Knobs for compression
+#[derive(Clone, Debug)]
+pub struct EncodeOptions {
+    /// Whether to store a CRC-32 of the original data, so corruption gets caught on decode (on by default; streaming
+    /// payloads always have one)
+    pub checksum: bool,
+    /// Whether to compress in a single pass with the adaptive model, rather than counting frequencies first (off by
+    /// default, since two passes compress better whenever they're possible)
+    pub streaming: bool,
+    /// A flag that gets checked between chunks of input; setting it makes compression stop with `Error::Cancelled`
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// If set, the input is split into blocks of this many bytes (the last one can be shorter), and each block gets
+    /// its own tree and is written as its own independently decodable container (off by default). Input only ever
+    /// gets read a block at a time (or a block per thread, with the `rayon` feature, which compresses them in
+    /// parallel), so this works in one pass too, and overrides `streaming`.
+    pub block_size: Option<usize>,
+    /// A dictionary to code static payloads with, in place of their own frequencies, so the header doesn't have to
+    /// store any (none by default; streaming payloads don't use one)
+    pub dictionary: Option<Arc<Dictionary>>,
+    /// A counter that block mode adds how long it spent compressing each block to, in nanoseconds, summed over every
+    /// thread; next to the wall-clock time, that shows how much compressing in parallel helped (none by default)
+    pub block_nanos: Option<Arc<AtomicU64>>,
+    /// A counter that `compress_file()` adds every byte it reads from the source file to, so another thread can show
+    /// how far along it is (none by default). Compressing in two passes reads the input twice, so then it counts up
+    /// to twice the file's size.
+    pub progress: Option<Arc<AtomicU64>>,
+    /// Whether static payloads get canonical codes, which the header only has to store the lengths of, rather than
+    /// the tree's, which it needs every byte's frequency for (off by default; dictionary and streaming payloads
+    /// don't store any codes either way, so it makes no difference to them)
+    pub canonical: bool,
+    /// Extra fields for every header written, like a name or a comment (none by default, which leaves the headers
+    /// exactly as they'd be without extensions at all)
+    pub extensions: Extensions,
+    /// Stages to run the data through before coding it, like bzip2's Burrows–Wheeler transform (none by default).
+    /// Only static payloads get them; they go in each header as a `PIPELINE` extension, and each block gets run
+    /// through them on its own, in block mode. Every stage needs a whole block (or the whole input) in memory at
+    /// once, so without `block_size`, so does compressing.
+    pub pipeline: Pipeline,
+    /// Where blocks end in block mode: every `block_size` bytes (the default), or sooner, wherever the data changes
+    /// enough that a new tree would pay for itself
+    pub split: Split,
+}
+
+/// How block mode decides where each block ends
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Split {
+    /// Every block is `block_size` bytes, except maybe the last
+    #[default]
+    Fixed,
+    /// Blocks still end every `block_size` bytes, but can also end sooner: the data gets looked at 4 KiB at a time,
+    /// and whenever starting a new block (with a tree of its own) before the next 4 KiB would make the two blocks
+    /// come out more than `threshold` bytes smaller than carrying on with one, headers and all, that's where the
+    /// block ends. That catches things like text followed by base64, where one tree would fit neither. With a
+    /// pipeline, this goes by the data before it goes through the stages.
+    Drift {
+        /// How many bytes a new block has to save to be worth starting
+        threshold: u64,
+    },
+    /// Blocks still end every `block_size` bytes, but within each of those, they end wherever makes the total
+    /// smallest, headers and all, out of every way of splitting it up 4 KiB at a time (which means trying a number of
+    /// trees that goes up with the square of `block_size`, so it's for data that gets compressed once and read many
+    /// times). With a pipeline, this goes by the data before it goes through the stages.
+    Optimal,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            checksum: true,
+            streaming: false,
+            cancel: None,
+            block_size: None,
+            dictionary: None,
+            block_nanos: None,
+            progress: None,
+            canonical: false,
+            extensions: Extensions::new(),
+            pipeline: Pipeline::new(),
+            split: Split::Fixed,
+        }
+    }
+}
+
+/// Knobs for decompression
+#[derive(Clone, Debug, Default)]
+pub struct DecodeOptions {
+    /// A flag that gets checked between chunks of output; setting it makes decompression stop with
+    /// `Error::Cancelled`
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// The most decompressed bytes to allow before giving up with `Error::OutputLimitExceeded` (no limit by
+    /// default, besides `LONE_SYMBOL_LIMIT` on payloads of just one symbol, but anything decoding untrusted input
+    /// should set one)
+    pub max_output_bytes: Option<u64>,
+    /// Whether to compile each static payload's codes into a byte-at-a-time state machine before decoding it (off
+    /// by default, since compiling it takes a while; it's worth it for payloads of many megabytes)
+    pub fsm: bool,
+    /// The dictionary to decode payloads that were compressed with one (it has to be the same one, or decoding
+    /// fails with `Error::DictionaryMismatch`)
+    pub dictionary: Option<Arc<Dictionary>>,
+    /// A counter that the file functions (`decompress_file_with()` and `decompress_file_parallel()`) add every byte
+    /// they read from the compressed file to, so another thread can show how far along they are (none by default)
+    pub progress: Option<Arc<AtomicU64>>,
+}
+
+/// How the payload is coded
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Payload {
+    /// One tree for the whole input, built from frequencies stored in the header
+    Static {
+        /// How many bytes the payload decodes to
+        original_len: u64,
+        /// The byte frequencies the tree gets built from
+        freqs: FrequencyMap,
+        /// How many bytes the packed codes take up
+        payload_len: u64,
+    },
+    /// One tree for the whole input, built from a dictionary's frequencies, which the header doesn't store
+    Dictionary {
+        /// How many bytes the payload decodes to
+        original_len: u64,
+        /// The ID of the dictionary
+        id: u32,
+        /// How many bytes the packed codes take up
+        payload_len: u64,
+    },
+    /// One set of canonical codes for the whole input, rebuilt from the code lengths stored in the header
+    Canonical {
+        /// How many bytes the payload decodes to
+        original_len: u64,
+        /// Every byte value that shows up, in order, with the length of its code
+        lengths: Vec<(u8, u8)>,
+        /// How many bytes the packed codes take up
+        payload_len: u64,
+    },
+    /// A one-pass adaptive stream, which needs nothing in the header
+    Streaming,
+    /// No payload at all, just a list of where each input of a multi-input file is
+    Index {
+        /// Where each input is, in order
+        entries: Vec<BlockInfo>,
+        /// Each input's name and checksum, in the same order, if it's an archive's index
+        names: Option<Vec<EntryName>>,
+    },
+}
+
+/// Always a header `read_from()` could have read: whatever `write_to()` writes, `read_from()` reads back the same
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Header {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let byte_freqs = |u: &mut arbitrary::Unstructured<'a>| {
+            crate::freq::arbitrary_freqs(u, |u| u.arbitrary::<u8>().map(char::from))
+        };
+        let payload = match u.int_in_range(0..=4)? {
+            0 => {
+                let freqs = byte_freqs(u)?;
+                Payload::Static {
+                    // there has to be something to decode to anything
+                    original_len: if freqs.is_empty() { 0 } else { u.arbitrary()? },
+                    freqs,
+                    payload_len: u.arbitrary()?,
+                }
+            }
+            1 => Payload::Dictionary {
+                original_len: u.arbitrary()?,
+                id: u.arbitrary()?,
+                payload_len: u.arbitrary()?,
+            },
+            2 => {
+                let codes = canonical::generate_huffman_map(byte_freqs(u)?.as_map());
+                let mut lengths: Vec<(u8, u8)> = codes
+                    .iter()
+                    .map(|(&ch, code)| (ch as u8, code.len() as u8))
+                    .collect();
+                lengths.sort_unstable();
+                Payload::Canonical {
+                    original_len: if lengths.is_empty() {
+                        0
+                    } else {
+                        u.arbitrary()?
+                    },
+                    lengths,
+                    payload_len: u.arbitrary()?,
+                }
+            }
+            3 => Payload::Streaming,
+            _ => {
+                let entries: Vec<BlockInfo> = u.arbitrary()?;
+                let names = if u.arbitrary()? {
+                    // the metadata's all or nothing
+                    let metadata: bool = u.arbitrary()?;
+                    let mut names = Vec::with_capacity(entries.len());
+                    for _ in &entries {
+                        let mut name: String = u.arbitrary()?;
+                        while name.len() > u16::MAX as usize {
+                            name.pop();
+                        }
+                        names.push(EntryName {
+                            name,
+                            crc: u.arbitrary()?,
+                            metadata: if metadata { Some(u.arbitrary()?) } else { None },
+                        });
+                    }
+                    Some(names)
+                } else {
+                    None
+                };
+                Payload::Index { entries, names }
+            }
+        };
+        let mut extensions = Extensions::new();
+        for _ in 0..u.int_in_range(0..=3)? {
+            let key = match u.arbitrary()? {
+                Some(key) => extensions::Key::Standard(key),
+                None => extensions::Key::Vendor(u.arbitrary()?),
+            };
+            let value = match u.int_in_range(0..=2)? {
+                0 => extensions::Value::Uint(u.arbitrary()?),
+                1 => extensions::Value::Text(u.arbitrary()?),
+                _ => extensions::Value::Bytes(u.arbitrary()?),
+            };
+            extensions.insert(key, value);
+        }
+        Ok(Header {
+            checksum: u.arbitrary()?,
+            payload,
+            extensions,
+        })
+    }
+}
+
+/// What an archive's index records about an input besides where it is
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EntryName {
+    /// The input's name (for a file, its path relative to wherever the archive was made from, with `/` between each
+    /// part)
+    pub name: String,
+    /// The CRC-32 of the input
+    pub crc: u32,
+    /// The permissions and modification time of the file the input came from, if the archive kept them
+    pub metadata: Option<FileMetadata>,
+}
+
+/// The parts of a file's metadata an archive can keep
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FileMetadata {
+    /// The Unix permission bits (e.g. `0o644`)
+    pub mode: u32,
+    /// When the file was last modified, in seconds since the Unix epoch
+    pub mtime: u64,
+}
+
+/// A container's header
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Header {
+    /// Whether a CRC-32 trailer follows the payload
+    pub checksum: bool,
+    /// How the payload is coded
+    pub payload: Payload,
+    /// Any extra fields (see the `extensions` module)
+    pub extensions: Extensions,
+}
+
+impl Header {
+    /// Parses a header, leaving the reader at the start of the payload
+    ///
+    /// ## Arguments
+    ///
+    /// * `reader`: where the container comes from
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Header> {
+        if read_array::<_, 4>(reader)? != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let [version, flags] = read_array(reader)?;
+        if version == 0 || version > VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        if (flags & FLAG_STREAMING != 0) as u8
+            + (flags & FLAG_INDEX != 0) as u8
+            + (flags & FLAG_DICTIONARY != 0) as u8
+            > 1
+        {
+            return Err(Error::BadHeader(
+                "more than one of the streaming, index, and dictionary flags are set",
+            ));
+        }
+        if flags & FLAG_NAMES != 0 && flags & FLAG_INDEX == 0 {
+            return Err(Error::BadHeader(
+                "names flag is set on something other than an index",
+            ));
+        }
+        if flags & FLAG_METADATA != 0 && flags & FLAG_NAMES == 0 {
+            return Err(Error::BadHeader(
+                "metadata flag is set on an index without names",
+            ));
+        }
+        if flags & FLAG_CANONICAL != 0
+            && flags & (FLAG_STREAMING | FLAG_INDEX | FLAG_DICTIONARY) != 0
+        {
+            return Err(Error::BadHeader(
+                "canonical flag is set on something other than a static payload",
+            ));
+        }
+        let (extensions, extensions_len) = if flags & FLAG_EXTENSIONS != 0 {
+            let len = u32::from_le_bytes(read_array(reader)?);
+            if len as usize > extensions::MAX_LEN {
+                return Err(Error::BadHeader("extensions are too long"));
+            }
+            let mut cbor = vec![0; len as usize];
+            reader
+                .read_exact(&mut cbor)
+                .map_err(|_| Error::BadHeader("header is truncated"))?;
+            (Extensions::from_cbor(&cbor)?, 4 + len as u64)
+        } else {
+            (Extensions::new(), 0)
+        };
+        let payload = if flags & FLAG_STREAMING != 0 {
+            Payload::Streaming
+        } else if flags & FLAG_INDEX != 0 {
+            let inputs = u32::from_le_bytes(read_array(reader)?);
+            let mut entries = Vec::new();
+            for _ in 0..inputs {
+                let mut fields = [0; 4];
+                for field in &mut fields {
+                    *field = u64::from_le_bytes(read_array(reader)?);
+                }
+                let [offset, len, original_offset, original_len] = fields;
+                entries.push(BlockInfo {
+                    offset,
+                    len,
+                    original_offset,
+                    original_len,
+                });
+            }
+            let names = if flags & FLAG_NAMES != 0 {
+                let mut names = Vec::with_capacity(entries.len());
+                for _ in 0..entries.len() {
+                    let crc = u32::from_le_bytes(read_array(reader)?);
+                    let len = u16::from_le_bytes(read_array(reader)?);
+                    let mut name = vec![0; len as usize];
+                    reader
+                        .read_exact(&mut name)
+                        .map_err(|_| Error::BadHeader("header is truncated"))?;
+                    let name = String::from_utf8(name)
+                        .map_err(|_| Error::BadHeader("input name isn't valid UTF-8"))?;
+                    let metadata = if flags & FLAG_METADATA != 0 {
+                        let mode = u32::from_le_bytes(read_array(reader)?);
+                        let mtime = u64::from_le_bytes(read_array(reader)?);
+                        Some(FileMetadata { mode, mtime })
+                    } else {
+                        None
+                    };
+                    names.push(EntryName {
+                        name,
+                        crc,
+                        metadata,
+                    });
+                }
+                Some(names)
+            } else {
+                None
+            };
+            if u64::from_le_bytes(read_array(reader)?)
+                != index_len(entries.len(), names.as_deref(), extensions_len)
+            {
+                return Err(Error::BadHeader("index length doesn't match its entries"));
+            }
+            Payload::Index { entries, names }
+        } else if flags & FLAG_CANONICAL != 0 {
+            let original_len = u64::from_le_bytes(read_array(reader)?);
+            let symbols = u16::from_le_bytes(read_array(reader)?);
+            if symbols > 256 {
+                return Err(Error::BadHeader("more symbols than there are byte values"));
+            }
+            let mut lengths = Vec::with_capacity(symbols as usize);
+            for _ in 0..symbols {
+                let [sym, len] = read_array(reader)?;
+                lengths.push((sym, len));
+            }
+            check_lengths(&lengths)?;
+            if original_len != 0 && lengths.is_empty() {
+                return Err(Error::BadHeader("no symbols for a non-empty payload"));
+            }
+            let payload_len = u64::from_le_bytes(read_array(reader)?);
+            Payload::Canonical {
+                original_len,
+                lengths,
+                payload_len,
+            }
+        } else if flags & FLAG_DICTIONARY != 0 {
+            let original_len = u64::from_le_bytes(read_array(reader)?);
+            let id = u32::from_le_bytes(read_array(reader)?);
+            let payload_len = u64::from_le_bytes(read_array(reader)?);
+            Payload::Dictionary {
+                original_len,
+                id,
+                payload_len,
+            }
+        } else {
+            let original_len = u64::from_le_bytes(read_array(reader)?);
+            let symbols = u16::from_le_bytes(read_array(reader)?);
+            if symbols > 256 {
+                return Err(Error::BadHeader("more symbols than there are byte values"));
+            }
+            let mut map = HashMap::new();
+            let mut total = 0u64;
+            for _ in 0..symbols {
+                let [sym, f0, f1, f2, f3] = read_array(reader)?;
+                let freq = u32::from_le_bytes([f0, f1, f2, f3]);
+                if freq == 0 || freq > i32::MAX as u32 {
+                    return Err(Error::BadHeader("symbol frequency out of range"));
+                }
+                // the tree adds them all up, in an i32
+                total += freq as u64;
+                if total > i32::MAX as u64 {
+                    return Err(Error::BadHeader("symbol frequencies add up to too much"));
+                }
+                if map.insert(char::from(sym), freq as i32).is_some() {
+                    return Err(Error::BadHeader("symbol listed twice"));
+                }
+            }
+            let freqs = FrequencyMap::from(map);
+            if original_len != 0 && freqs.is_empty() {
+                return Err(Error::BadHeader("no symbols for a non-empty payload"));
+            }
+            let payload_len = u64::from_le_bytes(read_array(reader)?);
+            Payload::Static {
+                original_len,
+                freqs,
+                payload_len,
+            }
+        };
+        Ok(Header {
+            checksum: flags & FLAG_CHECKSUM != 0,
+            payload,
+            extensions,
+        })
+    }
+
+    /// Writes out the header
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` if the extensions are longer than `extensions::MAX_LEN` once they're
+    /// encoded.
+    ///
+    /// ## Arguments
+    ///
+    /// * `writer`: where the container's going
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut flags = 0;
+        if self.checksum {
+            flags |= FLAG_CHECKSUM;
+        }
+        let cbor = self.extensions.to_cbor();
+        if cbor.len() > extensions::MAX_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "header extensions are too long",
+            ));
+        }
+        let extensions_len = if self.extensions.is_empty() {
+            0
+        } else {
+            flags |= FLAG_EXTENSIONS;
+            4 + cbor.len() as u64
+        };
+        match self.payload {
+            Payload::Static { .. } => {}
+            Payload::Canonical { .. } => flags |= FLAG_CANONICAL,
+            Payload::Dictionary { .. } => flags |= FLAG_DICTIONARY,
+            Payload::Streaming => flags |= FLAG_STREAMING,
+            Payload::Index { names: None, .. } => flags |= FLAG_INDEX,
+            Payload::Index {
+                names: Some(ref names),
+                ..
+            } => {
+                flags |= FLAG_INDEX | FLAG_NAMES;
+                if has_metadata(names) {
+                    flags |= FLAG_METADATA;
+                }
+            }
+        }
+        let version = match self.extensions.get(&Key::Standard(extensions::PIPELINE)) {
+            Some(_) => VERSION,
+            None => VERSION_WITHOUT_PIPELINE,
+        };
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[version, flags])?;
+        if extensions_len != 0 {
+            writer.write_all(&(cbor.len() as u32).to_le_bytes())?;
+            writer.write_all(&cbor)?;
+        }
+        if let Payload::Index { entries, names } = &self.payload {
+            writer.write_all(&(entries.len() as u32).to_le_bytes())?;
+            for entry in entries {
+                writer.write_all(&entry.offset.to_le_bytes())?;
+                writer.write_all(&entry.len.to_le_bytes())?;
+                writer.write_all(&entry.original_offset.to_le_bytes())?;
+                writer.write_all(&entry.original_len.to_le_bytes())?;
+            }
+            // checked when the names are first given to `compress_archive()`, so they all fit
+            let metadata = names.as_deref().is_some_and(has_metadata);
+            for entry in names.iter().flatten() {
+                writer.write_all(&entry.crc.to_le_bytes())?;
+                writer.write_all(&(entry.name.len() as u16).to_le_bytes())?;
+                writer.write_all(entry.name.as_bytes())?;
+                if metadata {
+                    let FileMetadata { mode, mtime } = entry.metadata.unwrap_or_default();
+                    writer.write_all(&mode.to_le_bytes())?;
+                    writer.write_all(&mtime.to_le_bytes())?;
+                }
+            }
+            writer.write_all(
+                &index_len(entries.len(), names.as_deref(), extensions_len).to_le_bytes(),
+            )?;
+        }
+        if let Payload::Static {
+            original_len,
+            freqs,
+            payload_len,
+        } = &self.payload
+        {
+            writer.write_all(&original_len.to_le_bytes())?;
+            // sorted, so that the same input always makes the same file
+            let mut entries: Vec<(char, i32)> = freqs
+                .as_map()
+                .iter()
+                .map(|(&ch, &freq)| (ch, freq))
+                .collect();
+            entries.sort_unstable();
+            writer.write_all(&(entries.len() as u16).to_le_bytes())?;
+            for (ch, freq) in entries {
+                writer.write_all(&[ch as u8])?;
+                writer.write_all(&(freq as u32).to_le_bytes())?;
+            }
+            writer.write_all(&payload_len.to_le_bytes())?;
+        }
+        if let Payload::Canonical {
+            original_len,
+            lengths,
+            payload_len,
+        } = &self.payload
+        {
+            writer.write_all(&original_len.to_le_bytes())?;
+            writer.write_all(&(lengths.len() as u16).to_le_bytes())?;
+            for &(sym, len) in lengths {
+                writer.write_all(&[sym, len])?;
+            }
+            writer.write_all(&payload_len.to_le_bytes())?;
+        }
+        if let Payload::Dictionary {
+            original_len,
+            id,
+            payload_len,
+        } = &self.payload
+        {
+            writer.write_all(&original_len.to_le_bytes())?;
+            writer.write_all(&id.to_le_bytes())?;
+            writer.write_all(&payload_len.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// A header's fields as they come out of a deserializer, before they've been checked
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct HeaderFields {
+    /// Whether a CRC-32 trailer follows the payload
+    checksum: bool,
+    /// How the payload is coded
+    payload: Payload,
+    /// Any extra fields (which can be left out)
+    #[serde(default)]
+    extensions: Extensions,
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Header {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let HeaderFields {
+            checksum,
+            payload,
+            extensions,
+        } = HeaderFields::deserialize(deserializer)?;
+        let header = Header {
+            checksum,
+            payload,
+            extensions,
+        };
+        header.check().map_err(serde::de::Error::custom)?;
+        Ok(header)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Header {
+    /// Makes sure a header that didn't come from `read_from()` is one it could have come from, i.e. that writing it
+    /// out and reading it back in would give the same header
+    fn check(&self) -> Result<()> {
+        if self.extensions.to_cbor().len() > extensions::MAX_LEN {
+            return Err(Error::BadHeader("extensions are too long"));
+        }
+        match &self.payload {
+            Payload::Static {
+                original_len,
+                freqs,
+                ..
+            } => {
+                // a byte value per symbol, so there can't be more than 256 of them
+                if freqs.as_map().keys().any(|&ch| ch as u32 > 0xff) {
+                    return Err(Error::BadHeader("symbol isn't a byte value"));
+                }
+                if freqs.as_map().values().any(|&freq| freq <= 0) {
+                    return Err(Error::BadHeader("symbol frequency out of range"));
+                }
+                if *original_len != 0 && freqs.is_empty() {
+                    return Err(Error::BadHeader("no symbols for a non-empty payload"));
+                }
+            }
+            Payload::Canonical {
+                original_len,
+                lengths,
+                ..
+            } => {
+                check_lengths(lengths)?;
+                if *original_len != 0 && lengths.is_empty() {
+                    return Err(Error::BadHeader("no symbols for a non-empty payload"));
+                }
+            }
+            Payload::Index {
+                entries,
+                names: Some(names),
+            } => {
+                if names.len() != entries.len() {
+                    return Err(Error::BadHeader(
+                        "index doesn't have a name for every input",
+                    ));
+                }
+                if names
+                    .iter()
+                    .any(|entry| entry.name.len() > u16::MAX as usize)
+                {
+                    return Err(Error::BadHeader("input name is too long"));
+                }
+                // the flag's for the whole index, so it's all of them or none
+                if has_metadata(names) && names.iter().any(|entry| entry.metadata.is_none()) {
+                    return Err(Error::BadHeader("only some inputs have metadata"));
+                }
+            }
+            Payload::Dictionary { .. } | Payload::Streaming | Payload::Index { .. } => {}
+        }
+        Ok(())
+    }
+}
+
+/// Makes sure a canonical payload's code lengths make a code that can be decoded: each byte value listed once, and
+/// (unless there's just the one, whose code is empty) lengths that use up every code without running out of them
+///
+/// ## Arguments
+///
+/// * `lengths`: the byte values and their code lengths
+pub(crate) fn check_lengths(lengths: &[(u8, u8)]) -> Result<()> {
+    let mut seen = [false; 256];
+    for &(sym, _) in lengths {
+        if std::mem::replace(&mut seen[sym as usize], true) {
+            return Err(Error::BadHeader("symbol listed twice"));
+        }
+    }
+    let complete = match lengths {
+        [] => true,
+        [(_, len)] => *len == 0,
+        lengths => {
+            // each code of length `len` uses up 2^-len of the code space, which has to come to exactly 1 (added up in a
+            // u128, since 256 codes of length 1 come to more than a u64 holds)
+            let mut used = 0u128;
+            for &(_, len) in lengths {
+                if len == 0 || len > MAX_CODE_LEN {
+                    return Err(Error::BadHeader("code length out of range"));
+                }
+                used += 1 << (MAX_CODE_LEN - len);
+            }
+            used == 1 << MAX_CODE_LEN
+        }
+    };
+    match complete {
+        true => Ok(()),
+        false => Err(Error::BadHeader("code lengths don't make a complete code")),
+    }
+}
+
+/// Compresses everything from a seekable reader into a container
+///
+/// Unless `options.streaming` is set, this reads the input twice (once to count, once to encode), seeking back to
+/// where it started in between.
+///
+/// If this gets cancelled, `output` will have been given a prefix of the container, cut off at a chunk boundary
+/// (possibly nothing at all, if it was still counting); the file APIs throw that away rather than leave it around.
+///
+/// ## Arguments
+///
+/// * `input`: the data to compress
+/// * `output`: where the container goes
+/// * `options`: how to compress
+pub fn compress<R: Read + Seek, W: Write>(
+    mut input: R,
+    mut output: W,
+    options: &EncodeOptions,
+) -> Result<W> {
+    if let Some(block_size) = options.block_size {
+        return compress_blocks(input, output, block_size, options);
+    }
+    if options.streaming {
+        return compress_streaming(input, output, options);
+    }
+    if !options.pipeline.is_empty() {
+        // the stages need all of it at once
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        return compress_static_slice(&data, output, options);
+    }
+    let mut buf = vec![0; CHUNK_SIZE];
+    // first pass: count everything up
+    let start = input.stream_position()?;
+    let mut counts = [0u64; 256];
+    let mut original_len = 0u64;
+    let mut crc = Crc32::new();
+    loop {
+        check_cancelled(&options.cancel)?;
+        let n = read_chunk(&mut input, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        add_counts(&mut counts, &buf[..n]);
+        crc.update(&buf[..n]);
+        original_len += n as u64;
+    }
+    if is_lone_symbol(&counts, options) && original_len > LONE_SYMBOL_LIMIT {
+        input.seek(SeekFrom::Start(start))?;
+        return compress_blocks(input, output, LONE_SYMBOL_LIMIT as usize, options);
+    }
+    let mut encoder = StaticEncoder::start(&mut output, &counts, options)?;
+    // second pass: encode it
+    input.seek(SeekFrom::Start(start))?;
+    let mut encoded_len = 0u64;
+    loop {
+        check_cancelled(&options.cancel)?;
+        let n = read_chunk(&mut input, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        encoder.encode(&buf[..n], &mut output)?;
+        encoded_len += n as u64;
+    }
+    if encoded_len != original_len {
+        return Err(input_changed());
+    }
+    encoder.finish(&mut output, options.checksum.then(|| crc.value()))?;
+    Ok(output)
+}
+
+/// Works out exactly how big `compress()` would make the input, from the counting pass alone, without encoding any
+/// of it
+///
+/// Returns how long the input is, and how long its compressed form would be.
+///
+/// The input only gets read once, so it doesn't need to seek. Streaming payloads can't be sized without actually
+/// compressing them, which gives `Error::Unsupported` (unless `options.block_size` is set, which overrides
+/// streaming).
+///
+/// ## Arguments
+///
+/// * `input`: the data that would be compressed
+/// * `options`: how it would be compressed
+pub fn estimate<R: Read>(mut input: R, options: &EncodeOptions) -> Result<(u64, u64)> {
+    if options.streaming && options.block_size.is_none() {
+        return Err(Error::Unsupported(
+            "streaming payloads can't be sized without compressing them",
+        ));
+    }
+    let block_size = options
+        .block_size
+        .map_or(u64::MAX, |block_size| block_size.max(1) as u64);
+    let staged = staged_options(options);
+    // the block itself only gets kept if it has to be split up or go through the pipeline
+    let keep = staged.is_some() || (options.split != Split::Fixed && options.block_size.is_some());
+    let mut buf = vec![0; CHUNK_SIZE];
+    let (mut original, mut total) = (0, 0);
+    let mut first = true;
+    loop {
+        let mut counts = [0u64; 256];
+        let mut len = 0u64;
+        let mut block = Vec::new();
+        while len < block_size {
+            check_cancelled(&options.cancel)?;
+            let wanted = (block_size - len).min(CHUNK_SIZE as u64) as usize;
+            let n = read_chunk(&mut input, &mut buf[..wanted])?;
+            if n == 0 {
+                break;
+            }
+            match keep {
+                false => add_counts(&mut counts, &buf[..n]),
+                true => block.extend_from_slice(&buf[..n]),
+            }
+            len += n as u64;
+        }
+        // an empty input still gets one (empty) container, like `compress_blocks()` gives it
+        if len == 0 && !first {
+            return Ok((original, total));
+        }
+        first = false;
+        original += len;
+        let pieces = match (keep, options.block_size) {
+            (false, _) => {
+                total += container_len(&counts, options)?;
+                Vec::new()
+            }
+            (true, Some(_)) => split_block(&block, options)?,
+            (true, None) => vec![&block[..]],
+        };
+        for piece in pieces {
+            total += match &staged {
+                Some(staged) => {
+                    let transformed = options.pipeline.forward(piece);
+                    let counts = count_bytes(&transformed);
+                    match transformed.len() as u64 > LONE_SYMBOL_LIMIT
+                        && is_lone_symbol(&counts, options)
+                    {
+                        // which goes without the stages, as in `compress_static_slice()`
+                        true => container_len(&count_bytes(piece), options)?,
+                        false => container_len(&counts, staged)?,
+                    }
+                }
+                None => container_len(&count_bytes(piece), options)?,
+            };
+        }
+        if len < block_size {
+            return Ok((original, total));
+        }
+    }
+}
+
+/// Works out exactly how big a static container would be, header, payload, checksum and all
+///
+/// ## Arguments
+///
+/// * `counts`: how many times each byte value comes up in what goes in it
+/// * `options`: how it'd be compressed
+fn container_len(counts: &[u64; 256], options: &EncodeOptions) -> Result<u64> {
+    let total: u64 = counts.iter().sum();
+    if is_lone_symbol(counts, options) && total > LONE_SYMBOL_LIMIT {
+        // split up into full containers and whatever's left over
+        let byte = counts.iter().position(|&n| n > 0).unwrap();
+        let mut full = [0; 256];
+        full[byte] = LONE_SYMBOL_LIMIT;
+        let mut rest = [0; 256];
+        rest[byte] = total % LONE_SYMBOL_LIMIT;
+        let rest_len = match rest[byte] {
+            0 => 0,
+            _ => container_len(&rest, options)?,
+        };
+        return Ok(total / LONE_SYMBOL_LIMIT * container_len(&full, options)? + rest_len);
+    }
+    let (header, _) = static_header(counts, options);
+    let payload_len = match header.payload {
+        Payload::Static { payload_len, .. }
+        | Payload::Canonical { payload_len, .. }
+        | Payload::Dictionary { payload_len, .. } => payload_len,
+        Payload::Streaming | Payload::Index { .. } => 0,
+    };
+    let mut header_len = Counted::new(io::sink());
+    header.write_to(&mut header_len)?;
+    Ok(header_len.count + payload_len + if options.checksum { 4 } else { 0 })
+}
+
+/// Splits a block up further, wherever `options.split` says a new one should start (not at all with
+/// `Split::Fixed`)
+///
+/// An empty block comes back as one empty piece, so it still gets a container.
+///
+/// ## Arguments
+///
+/// * `block`: the block
+/// * `options`: how it's being compressed
+fn split_block<'a>(block: &'a [u8], options: &EncodeOptions) -> Result<Vec<&'a [u8]>> {
+    let threshold = match options.split {
+        Split::Fixed => return Ok(vec![block]),
+        Split::Drift { threshold } => threshold,
+        Split::Optimal => return split_optimally(block, options),
+    };
+    let len_of = |counts: &[u64; 256]| container_len(counts, options);
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    // the byte counts of the piece so far, and how big it'd come out on its own
+    let mut counts = [0u64; 256];
+    let mut len = 0;
+    for (k, segment) in block.chunks(SPLIT_SEGMENT).enumerate() {
+        check_cancelled(&options.cancel)?;
+        let mut segment_counts = [0u64; 256];
+        for &byte in segment {
+            segment_counts[byte as usize] += 1;
+        }
+        let mut together = counts;
+        for (n, m) in together.iter_mut().zip(&segment_counts) {
+            *n += m;
+        }
+        let together_len = len_of(&together)?;
+        let at = k * SPLIT_SEGMENT;
+        if at > start {
+            let segment_len = len_of(&segment_counts)?;
+            if together_len > len + segment_len + threshold {
+                pieces.push(&block[start..at]);
+                start = at;
+                counts = segment_counts;
+                len = segment_len;
+                continue;
+            }
+        }
+        counts = together;
+        len = together_len;
+    }
+    pieces.push(&block[start..]);
+    Ok(pieces)
+}
+
+/// Splits a block up wherever makes the total smallest, trying every split point `SPLIT_SEGMENT` bytes apart
+///
+/// The best way to split up the first `j` segments is the best way to split up the first `i`, for some `i`, plus a
+/// block of everything from there to `j`, so going through every `j` in order and every `i` before it finds the
+/// best way to split up the whole thing.
+///
+/// ## Arguments
+///
+/// * `block`: the block
+/// * `options`: how it's being compressed
+fn split_optimally<'a>(block: &'a [u8], options: &EncodeOptions) -> Result<Vec<&'a [u8]>> {
+    let segments: Vec<&[u8]> = block.chunks(SPLIT_SEGMENT).collect();
+    if segments.len() < 2 {
+        return Ok(vec![block]);
+    }
+    // the byte counts of every run of segments from the start, so any run's counts are just a subtraction
+    let mut prefixes = vec![[0u64; 256]; segments.len() + 1];
+    for (k, segment) in segments.iter().enumerate() {
+        prefixes[k + 1] = prefixes[k];
+        for &byte in *segment {
+            prefixes[k + 1][byte as usize] += 1;
+        }
+    }
+    // the smallest the first j segments can come out, and where the last block of that starts
+    let mut best = vec![(0u64, 0usize); segments.len() + 1];
+    for j in 1..=segments.len() {
+        check_cancelled(&options.cancel)?;
+        best[j] = (u64::MAX, 0);
+        for i in 0..j {
+            let mut counts = prefixes[j];
+            for (n, m) in counts.iter_mut().zip(&prefixes[i]) {
+                *n -= m;
+            }
+            let len = best[i].0 + container_len(&counts, options)?;
+            if len < best[j].0 {
+                best[j] = (len, i);
+            }
+        }
+    }
+    let mut starts = Vec::new();
+    let mut j = segments.len();
+    while j > 0 {
+        j = best[j].1;
+        starts.push(j * SPLIT_SEGMENT);
+    }
+    starts.reverse();
+    starts.push(block.len());
+    Ok(starts
+        .windows(2)
+        .map(|bounds| &block[bounds[0]..bounds[1]])
+        .collect())
+}
+
+/// Compresses a block-mode block into its own container, or several, if `options.split` splits it up further
+///
+/// ## Arguments
+///
+/// * `block`: the block
+/// * `output`: where the container(s) go
+/// * `options`: how to compress
+pub(crate) fn compress_block<W: Write>(
+    block: &[u8],
+    mut output: W,
+    options: &EncodeOptions,
+) -> Result<W> {
+    for piece in split_block(block, options)? {
+        output = compress_static_slice(piece, output, options)?;
+    }
+    Ok(output)
+}
+
+/// Compresses a byte slice into a container, with both passes running straight over the slice
+///
+/// This is the one to use for data that's already in memory (or memory-mapped), since nothing gets copied into an
+/// intermediate buffer on the way in.
+///
+/// ## Arguments
+///
+/// * `data`: the data to compress
+/// * `output`: where the container goes
+/// * `options`: how to compress
+pub fn compress_slice<W: Write>(data: &[u8], mut output: W, options: &EncodeOptions) -> Result<W> {
+    if let Some(block_size) = options.block_size {
+        let mut blocks: Vec<&[u8]> = data.chunks(block_size.max(1)).collect();
+        // an empty input still gets one (empty) block, so there's always a container
+        if blocks.is_empty() {
+            blocks.push(data);
+        }
+        for batch in blocks.chunks(blocks_at_once()) {
+            output = compress_batch(batch, output, options)?;
+        }
+        return Ok(output);
+    }
+    if options.streaming {
+        streaming_header(options).write_to(&mut output)?;
+        let mut encoder = Encoder::new();
+        let mut out = Vec::with_capacity(CHUNK_SIZE);
+        for chunk in data.chunks(CHUNK_SIZE) {
+            check_cancelled(&options.cancel)?;
+            encoder.encode(chunk, &mut out);
+            output.write_all(&out)?;
+            out.clear();
+        }
+        encoder.finish(&mut out);
+        output.write_all(&out)?;
+        return Ok(output);
+    }
+    compress_static_slice(data, output, options)
+}
+
+/// Compresses a byte slice into a single static container
+///
+/// ## Arguments
+///
+/// * `data`: the data to compress
+/// * `output`: where the container goes
+/// * `options`: how to compress
+pub(crate) fn compress_static_slice<W: Write>(
+    data: &[u8],
+    mut output: W,
+    options: &EncodeOptions,
+) -> Result<W> {
+    if let Some(staged) = staged_options(options) {
+        check_cancelled(&options.cancel)?;
+        let transformed = options.pipeline.forward(data);
+        // too much of one symbol to go in one container, which can't be split up either, since the stages have to
+        // be undone on all of it at once, so it goes without them
+        if transformed.len() as u64 > LONE_SYMBOL_LIMIT
+            && is_lone_symbol(&count_bytes(&transformed), options)
+        {
+            let unstaged = EncodeOptions {
+                pipeline: Pipeline::new(),
+                ..options.clone()
+            };
+            return compress_static_slice(data, output, &unstaged);
+        }
+        return compress_static_slice(&transformed, output, &staged);
+    }
+    let mut crc = Crc32::new();
+    let mut counts = [0u64; 256];
+    // counted in big pieces, since that's what makes counting in parallel (with the `rayon` feature) worth it
+    for chunk in data.chunks(COUNT_CHUNK_SIZE) {
+        check_cancelled(&options.cancel)?;
+        crc.update(chunk);
+        add_counts(&mut counts, chunk);
+    }
+    if is_lone_symbol(&counts, options) && data.len() as u64 > LONE_SYMBOL_LIMIT {
+        for piece in data.chunks(LONE_SYMBOL_LIMIT as usize) {
+            output = compress_static_slice(piece, output, options)?;
+        }
+        return Ok(output);
+    }
+    let crc = options.checksum.then(|| crc.value());
+    let mut encoder = StaticEncoder::start(&mut output, &counts, options)?;
+    // a lone symbol's code is empty, so there'd be nothing to show for going through it all again
+    if !is_lone_symbol(&counts, options) {
+        for chunk in data.chunks(CHUNK_SIZE) {
+            check_cancelled(&options.cancel)?;
+            encoder.encode(chunk, &mut output)?;
+        }
+    }
+    encoder.finish(&mut output, crc)?;
+    Ok(output)
+}
+
+/// The options for coding what comes out of `options.pipeline`, which are the same but with the pipeline moved into
+/// the extensions, or `None` if there isn't a pipeline
+///
+/// ## Arguments
+///
+/// * `options`: how to compress
+fn staged_options(options: &EncodeOptions) -> Option<EncodeOptions> {
+    if options.pipeline.is_empty() {
+        return None;
+    }
+    let mut staged = EncodeOptions {
+        pipeline: Pipeline::new(),
+        ..options.clone()
+    };
+    staged.extensions.insert(
+        Key::Standard(extensions::PIPELINE),
+        options.pipeline.to_value(),
+    );
+    Some(staged)
+}
+
+/// Encodes with one fixed tree, once the frequencies are known
+struct StaticEncoder {
+    /// The codes for every byte in the input
+    codes: ByteCodes,
+    /// Holds bits that haven't made up a whole byte yet
+    bits: BitWriter,
+    /// Scratch space for encoded bytes on their way to the output
+    out: Vec<u8>,
+}
+
+impl StaticEncoder {
+    /// Builds the tree (or the canonical codes) and writes out the header
+    ///
+    /// ## Arguments
+    ///
+    /// * `output`: where the container goes
+    /// * `counts`: how many times each byte value comes up in the whole input
+    /// * `options`: how to compress
+    fn start<W: Write>(
+        output: &mut W,
+        counts: &[u64; 256],
+        options: &EncodeOptions,
+    ) -> Result<Self> {
+        let (header, codes) = static_header(counts, options);
+        header.write_to(output)?;
+        Ok(StaticEncoder {
+            codes: ByteCodes::new(&codes),
+            bits: BitWriter::new(),
+            out: Vec::with_capacity(CHUNK_SIZE),
+        })
+    }
+
+    /// Encodes the next chunk of input
+    ///
+    /// ## Arguments
+    ///
+    /// * `chunk`: the input
+    /// * `output`: where the container goes
+    fn encode<W: Write>(&mut self, chunk: &[u8], output: &mut W) -> Result<()> {
+        if !self.bits.push_bytes(&self.codes, chunk, &mut self.out) {
+            return Err(input_changed());
+        }
+        output.write_all(&self.out)?;
+        self.out.clear();
+        Ok(())
+    }
+
+    /// Pads out the last byte and writes the checksum trailer (if there is one)
+    ///
+    /// ## Arguments
+    ///
+    /// * `output`: where the container goes
+    /// * `crc`: the checksum of the whole input, if it's to be stored
+    fn finish<W: Write>(mut self, output: &mut W, crc: Option<u32>) -> Result<()> {
+        self.bits.align(&mut self.out);
+        write_trailer(output, &self.out, crc)
+    }
+}
+
+/// Builds the tree (from the dictionary's frequencies, if there is one) or the canonical codes (if `options.canonical`
+/// says to), and works out what the header will be, giving back the codes along with it
+///
+/// ## Arguments
+///
+/// * `counts`: how many times each byte value comes up in the whole input
+/// * `options`: how to compress
+fn static_header(counts: &[u64; 256], options: &EncodeOptions) -> (Header, HashMap<char, String>) {
+    let original_len = counts.iter().sum();
+    let freqs = tree_freqs(counts);
+    let canonical = options.canonical && options.dictionary.is_none();
+    let codes = if canonical {
+        canonical::generate_huffman_map(freqs.as_map())
+    } else {
+        let mut tree = HuffTree::new();
+        let model = options
+            .dictionary
+            .as_ref()
+            .map_or(&freqs, |dict| dict.freqs());
+        tree.populate_tree(model.as_map());
+        tree.generate_huffman_map()
+    };
+    // from the real counts, since the tree's frequencies can be scaled down
+    let payload_len = (0..=255u8)
+        .zip(counts.iter())
+        .filter(|&(_, &n)| n > 0)
+        .map(|(byte, &n)| n * codes[&char::from(byte)].len() as u64)
+        .sum::<u64>()
+        .div_ceil(8);
+    let payload = match &options.dictionary {
+        Some(dict) => Payload::Dictionary {
+            original_len,
+            id: dict.id(),
+            payload_len,
+        },
+        None if canonical => {
+            let mut lengths: Vec<(u8, u8)> = codes
+                .iter()
+                .map(|(&ch, code)| (ch as u8, code.len() as u8))
+                .collect();
+            lengths.sort_unstable();
+            Payload::Canonical {
+                original_len,
+                lengths,
+                payload_len,
+            }
+        }
+        None => Payload::Static {
+            original_len,
+            freqs,
+            payload_len,
+        },
+    };
+    let header = Header {
+        checksum: options.checksum,
+        payload,
+        extensions: options.extensions.clone(),
+    };
+    (header, codes)
+}
+
+/// Turns byte counts into the frequencies a tree gets built from (and a static header stores), scaled down (keeping
+/// every byte that comes up at least 1) if they'd add up to more than `i32::MAX`, which is as far as a tree's
+/// frequencies go
+///
+/// ## Arguments
+///
+/// * `counts`: how many times each byte value comes up
+fn tree_freqs(counts: &[u64; 256]) -> FrequencyMap {
+    let total: u64 = counts.iter().sum();
+    // room for every byte value to get rounded up to 1 and still fit
+    let max_total = i32::MAX as u64 - 256;
+    let freqs: HashMap<char, i32> = (0..=255u8)
+        .zip(counts.iter())
+        .filter(|&(_, &n)| n > 0)
+        .map(|(byte, &n)| {
+            let freq = match total > i32::MAX as u64 {
+                true => (n as u128 * max_total as u128 / total as u128) as i32,
+                false => n as i32,
+            };
+            (char::from(byte), freq.max(1))
+        })
+        .collect();
+    FrequencyMap::from(freqs)
+}
+
+/// Whether just the one byte value comes up, and there's no dictionary, so that it gets an empty code
+///
+/// ## Arguments
+///
+/// * `counts`: how many times each byte value comes up
+/// * `options`: how it's being compressed
+fn is_lone_symbol(counts: &[u64; 256], options: &EncodeOptions) -> bool {
+    options.dictionary.is_none() && counts.iter().filter(|&&n| n > 0).count() == 1
+}
+
+/// Adds up how many times each byte value comes up in some more of the input
+///
+/// ## Arguments
+///
+/// * `counts`: the counts so far
+/// * `data`: the input
+fn add_counts(counts: &mut [u64; 256], data: &[u8]) {
+    for (total, count) in counts.iter_mut().zip(count_bytes(data).iter()) {
+        *total += count;
+    }
+}
+
+/// Writes the last of the payload, followed by the checksum (if there is one)
+fn write_trailer<W: Write>(output: &mut W, last: &[u8], crc: Option<u32>) -> Result<()> {
+    output.write_all(last)?;
+    if let Some(crc) = crc {
+        output.write_all(&crc.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Compresses a block at a time, with each block becoming its own static container
+///
+/// Only a batch of blocks (just the one, without the `rayon` feature) is ever held in memory, and the input only gets
+/// read once, so this doesn't need to seek.
+fn compress_blocks<R: Read, W: Write>(
+    mut input: R,
+    mut output: W,
+    block_size: usize,
+    options: &EncodeOptions,
+) -> Result<W> {
+    let block_size = block_size.max(1);
+    let mut bufs = vec![vec![0; block_size]; blocks_at_once()];
+    let mut first = true;
+    loop {
+        let mut lens = Vec::with_capacity(bufs.len());
+        let mut last = false;
+        for buf in &mut bufs {
+            let n = read_chunk(&mut input, buf)?;
+            // an empty input still gets one (empty) block, so there's always a container
+            if n != 0 || first {
+                lens.push(n);
+            }
+            first = false;
+            if n < block_size {
+                last = true;
+                break;
+            }
+        }
+        let batch: Vec<&[u8]> = bufs.iter().zip(&lens).map(|(buf, &n)| &buf[..n]).collect();
+        output = compress_batch(&batch, output, options)?;
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+/// How many blocks get compressed at a time, which is one per thread with the `rayon` feature
+#[cfg(feature = "rayon")]
+fn blocks_at_once() -> usize {
+    rayon::current_num_threads()
+}
+
+/// How many blocks get compressed at a time, which is one per thread with the `rayon` feature
+#[cfg(not(feature = "rayon"))]
+fn blocks_at_once() -> usize {
+    1
+}
+
+/// Compresses a batch of blocks into a container each, writing them out in order
+///
+/// ## Arguments
+///
+/// * `blocks`: the blocks
+/// * `output`: where the containers go
+/// * `options`: how to compress
+#[cfg(not(feature = "rayon"))]
+fn compress_batch<W: Write>(blocks: &[&[u8]], mut output: W, options: &EncodeOptions) -> Result<W> {
+    for block in blocks {
+        output = compress_timed(block, output, options)?;
+    }
+    Ok(output)
+}
+
+/// Compresses a batch of blocks into a container each, writing them out in order
+///
+/// Every block is independent (its own tree, its own byte-aligned payload), so they all get compressed at once on
+/// rayon's thread pool and then just written out one after another.
+///
+/// ## Arguments
+///
+/// * `blocks`: the blocks
+/// * `output`: where the containers go
+/// * `options`: how to compress
+#[cfg(feature = "rayon")]
+fn compress_batch<W: Write>(blocks: &[&[u8]], mut output: W, options: &EncodeOptions) -> Result<W> {
+    use rayon::prelude::*;
+    let compressed = blocks
+        .par_iter()
+        .map(|block| compress_timed(block, Vec::new(), options))
+        .collect::<Result<Vec<_>>>()?;
+    for block in compressed {
+        output.write_all(&block)?;
+    }
+    Ok(output)
+}
+
+/// Compresses one block into its own container, adding how long that took to `options.block_nanos` (if it's set)
+///
+/// ## Arguments
+///
+/// * `block`: the block
+/// * `output`: where the container goes
+/// * `options`: how to compress
+fn compress_timed<W: Write>(block: &[u8], output: W, options: &EncodeOptions) -> Result<W> {
+    let started = Instant::now();
+    let output = compress_block(block, output, options)?;
+    if let Some(nanos) = &options.block_nanos {
+        nanos.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+    Ok(output)
+}
+
+/// How far a block-mode compression had gotten, so it can pick up from there rather than starting over
+///
+/// Checkpoints are only ever taken between blocks, where there's no half-written byte or model state to save: each
+/// block is a complete container, so everything before a checkpoint is done for good.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// How much of the input has been compressed
+    pub input_offset: u64,
+    /// How much output that's made
+    pub output_offset: u64,
+}
+
+impl Checkpoint {
+    /// How long a serialized checkpoint is
+    pub const LEN: usize = 16;
+
+    /// Serializes this checkpoint, e.g. to save it somewhere that survives a crash
+    pub fn to_bytes(&self) -> [u8; Checkpoint::LEN] {
+        let mut bytes = [0; Checkpoint::LEN];
+        bytes[..8].copy_from_slice(&self.input_offset.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.output_offset.to_le_bytes());
+        bytes
+    }
+
+    /// Reads back a checkpoint made by `to_bytes()`
+    ///
+    /// ## Arguments
+    ///
+    /// * `bytes`: the serialized checkpoint
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != Checkpoint::LEN {
+            return Err(Error::Corrupt("checkpoint is the wrong length"));
+        }
+        Ok(Checkpoint {
+            input_offset: u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+            output_offset: u64::from_le_bytes(bytes[8..].try_into().unwrap()),
+        })
+    }
+}
+
+/// Compresses in block mode, handing out a checkpoint after every block so the job can be resumed after a crash
+///
+/// `on_checkpoint` gets called once each block has been written out (and `output` flushed); if it returns an error,
+/// compression stops with that error. To resume, seek or truncate the output back to the last checkpoint's
+/// `output_offset` and call this again with that checkpoint and the same input and options; the finished output is
+/// exactly what `compress()` would have made in one go. Offsets are counted from the very start of `input` and
+/// `output`.
+///
+/// This only works in block mode, so `options.block_size` has to be set (or it's `Error::Unsupported`).
+///
+/// ## Arguments
+///
+/// * `input`: the data to compress
+/// * `output`: where the containers go, positioned at the checkpoint's `output_offset`
+/// * `options`: how to compress
+/// * `resume`: the checkpoint to pick up from, or `None` to start from the beginning
+/// * `on_checkpoint`: what to do with each new checkpoint (i.e. save it somewhere)
+pub fn compress_resumable<R, W, F>(
+    mut input: R,
+    mut output: W,
+    options: &EncodeOptions,
+    resume: Option<Checkpoint>,
+    mut on_checkpoint: F,
+) -> Result<W>
+where
+    R: Read + Seek,
+    W: Write,
+    F: FnMut(Checkpoint) -> Result<()>,
+{
+    let block_size = options
+        .block_size
+        .ok_or(Error::Unsupported("checkpoints need block mode"))?
+        .max(1);
+    let mut checkpoint = resume.unwrap_or_default();
+    input.seek(SeekFrom::Start(checkpoint.input_offset))?;
+    let mut buf = vec![0; block_size];
+    let mut block = Vec::new();
+    loop {
+        let n = read_chunk(&mut input, &mut buf)?;
+        // an empty input still gets one (empty) block, so there's always a container
+        if n == 0 && checkpoint.output_offset != 0 {
+            return Ok(output);
+        }
+        block = compress_block(&buf[..n], block, options)?;
+        output.write_all(&block)?;
+        output.flush()?;
+        checkpoint.input_offset += n as u64;
+        checkpoint.output_offset += block.len() as u64;
+        block.clear();
+        on_checkpoint(checkpoint)?;
+        if n < block_size {
+            return Ok(output);
+        }
+    }
+}
+
+/// Compresses in one pass with the adaptive model, so the input never gets read twice
+fn compress_streaming<R: Read, W: Write>(
+    mut input: R,
+    mut output: W,
+    options: &EncodeOptions,
+) -> Result<W> {
+    streaming_header(options).write_to(&mut output)?;
+    let mut encoder = Encoder::new();
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut out = Vec::with_capacity(CHUNK_SIZE);
+    loop {
+        check_cancelled(&options.cancel)?;
+        let n = read_chunk(&mut input, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        encoder.encode(&buf[..n], &mut out);
+        output.write_all(&out)?;
+        out.clear();
+    }
+    encoder.finish(&mut out);
+    output.write_all(&out)?;
+    Ok(output)
+}
+
+/// The header for a streaming payload, which always carries its own checksum
+pub(crate) fn streaming_header(options: &EncodeOptions) -> Header {
+    Header {
+        checksum: true,
+        payload: Payload::Streaming,
+        extensions: options.extensions.clone(),
+    }
+}
+
+/// Decompresses a container, checking the checksum (if there is one) once everything's been decoded
+///
+/// Note that decoded data gets written to `output` as it's produced, so on a checksum error the bad data has
+/// already been written; `decompress_file()` takes care of throwing it away.
+///
+/// There's no limit on how much it decompresses to, except that a payload of just the one symbol (whose length
+/// only the header vouches for) can't be more than `LONE_SYMBOL_LIMIT` bytes; anything decoding untrusted input
+/// should use `decompress_with()` with `DecodeOptions::max_output_bytes` set.
+///
+/// ## Arguments
+///
+/// * `input`: the container
+/// * `output`: where the decompressed data goes
+pub fn decompress<R: BufRead, W: Write>(input: R, output: W) -> Result<W> {
+    decompress_with(input, output, &DecodeOptions::default())
+}
+
+/// Decompresses a container, like `decompress()`, but with options
+///
+/// If the input holds several containers one after another (e.g. from block mode), they all get decoded, in order;
+/// anything else after the first container is an error. The output limit applies to all of them put together.
+///
+/// If this gets cancelled or hits the output limit, `output` will have been given a prefix of the decompressed data
+/// (everything up to the last whole chunk decoded before stopping, and never more than the limit) and nothing else.
+///
+/// ## Arguments
+///
+/// * `input`: the container
+/// * `output`: where the decompressed data goes
+/// * `options`: how to decompress
+pub fn decompress_with<R: BufRead, W: Write>(
+    mut input: R,
+    mut output: W,
+    options: &DecodeOptions,
+) -> Result<W> {
+    let mut written = 0u64;
+    loop {
+        let header = Header::read_from(&mut input)?;
+        decode_member(header, &mut input, &mut output, options, &mut written)?;
+        if input.fill_buf()?.is_empty() {
+            return Ok(output);
+        }
+    }
+}
+
+/// Checks that a file of containers is intact, decoding everything and checking every checksum, without writing any
+/// of it anywhere
+///
+/// Returns how many bytes it all decodes to. A header that can't be parsed gives `Error::BadMagic`,
+/// `Error::UnsupportedVersion`, or `Error::BadHeader`; a payload that doesn't decode (or doesn't match its checksum)
+/// gives `Error::Corrupt` or `Error::ChecksumMismatch`.
+///
+/// ## Arguments
+///
+/// * `input`: the containers
+/// * `options`: how to decompress
+pub fn verify<R: BufRead>(input: R, options: &DecodeOptions) -> Result<u64> {
+    let output = decompress_with(input, Counted::new(io::sink()), options)?;
+    Ok(output.count)
+}
+
+/// Decodes the payload (and checks the trailer) of one container, whose header has already been read
+///
+/// ## Arguments
+///
+/// * `header`: the container's header
+/// * `input`: the rest of the container
+/// * `output`: where the decompressed data goes
+/// * `options`: how to decompress
+/// * `written`: how much has been decompressed so far, across all containers
+fn decode_member<R: BufRead, W: Write>(
+    header: Header,
+    input: &mut R,
+    output: &mut W,
+    options: &DecodeOptions,
+    written: &mut u64,
+) -> Result<()> {
+    let limit = options.max_output_bytes.unwrap_or(u64::MAX);
+    let pipeline = header.extensions.pipeline()?;
+    if !pipeline.is_empty() {
+        // what the payload decodes to has to be held on to, to go back through the pipeline, which it can't be much
+        // bigger than what it's undone into
+        let left = limit - *written;
+        let staged_options = DecodeOptions {
+            max_output_bytes: options.max_output_bytes.map(|_| pipeline.max_len(left)),
+            ..options.clone()
+        };
+        let mut header = header;
+        header
+            .extensions
+            .remove(&Key::Standard(extensions::PIPELINE));
+        let mut staged = Vec::new();
+        decode_member(header, input, &mut staged, &staged_options, &mut 0).map_err(
+            |err| match err {
+                Error::OutputLimitExceeded { .. } if options.max_output_bytes.is_some() => {
+                    Error::OutputLimitExceeded { limit }
+                }
+                err => err,
+            },
+        )?;
+        let data = pipeline.inverse(&staged)?;
+        if data.len() as u64 > left {
+            return Err(Error::OutputLimitExceeded { limit });
+        }
+        *written += data.len() as u64;
+        output.write_all(&data)?;
+        return Ok(());
+    }
+    let mut crc = Crc32::new();
+    let mut out = Vec::with_capacity(CHUNK_SIZE);
+    let mut tree = HuffTree::new();
+    // canonical codes don't come from a tree, so they get decoders of their own
+    let mut canonical_codes = None;
+    let (original_len, symbols, payload_len) = match &header.payload {
+        Payload::Static {
+            original_len,
+            freqs,
+            payload_len,
+        } => {
+            tree.populate_tree(freqs.as_map());
+            (*original_len, freqs.len(), *payload_len)
+        }
+        Payload::Dictionary {
+            original_len,
+            id,
+            payload_len,
+        } => {
+            let freqs = find_dictionary(options, *id)?.freqs();
+            tree.populate_tree(freqs.as_map());
+            (*original_len, freqs.len(), *payload_len)
+        }
+        Payload::Canonical {
+            original_len,
+            lengths,
+            payload_len,
+        } => {
+            let lengths: Vec<(char, u64)> = lengths
+                .iter()
+                .map(|&(sym, len)| (char::from(sym), len as u64))
+                .collect();
+            canonical_codes = Some(canonical::canonical_codes(&lengths));
+            (*original_len, lengths.len(), *payload_len)
+        }
+        Payload::Streaming => {
+            let mut decoder = Decoder::new();
+            while !decoder.is_done() {
+                check_cancelled(&options.cancel)?;
+                let buf = input.fill_buf()?;
+                if buf.is_empty() {
+                    return Err(Error::Corrupt("payload is truncated"));
+                }
+                let used = decoder.decode(buf, &mut out)?;
+                input.consume(used);
+                *written += out.len() as u64;
+                if *written > limit {
+                    return Err(Error::OutputLimitExceeded { limit });
+                }
+                output.write_all(&out)?;
+                out.clear();
+            }
+            // the stream's own footer has already been checked
+            return Ok(());
+        }
+        // there's nothing to an index but its header
+        Payload::Index { .. } => return Ok(()),
+    };
+    // static payloads say up front how big they are, so there's no need to decode anything to know
+    if original_len > limit - *written {
+        return Err(Error::OutputLimitExceeded { limit });
+    }
+    if symbols == 1 && options.max_output_bytes.is_none() && original_len > LONE_SYMBOL_LIMIT {
+        return Err(Error::OutputLimitExceeded {
+            limit: LONE_SYMBOL_LIMIT,
+        });
+    }
+    let mut emit = |byte: u8| -> Result<()> {
+        out.push(byte);
+        if out.len() == CHUNK_SIZE {
+            check_cancelled(&options.cancel)?;
+            crc.update(&out);
+            output.write_all(&out)?;
+            out.clear();
+        }
+        Ok(())
+    };
+    let (canonical_fsm, canonical_table);
+    let fsm = match (options.fsm, &canonical_codes) {
+        (true, _) if symbols <= TINY_ALPHABET => None,
+        (true, Some(codes)) => {
+            canonical_fsm = DecodeFsm::new(codes);
+            canonical_fsm.as_ref()
+        }
+        (true, None) => tree.decode_fsm(),
+        (false, _) => None,
+    };
+    match fsm {
+        _ if symbols == 1 => {
+            // a lone symbol's code is empty, so there are no bits to decode, just the one byte over and over
+            if payload_len != 0 {
+                return Err(Error::Corrupt("payload length doesn't match the header"));
+            }
+            let huffman_map = canonical_codes.unwrap_or_else(|| tree.generate_huffman_map());
+            let byte = *huffman_map.keys().next().unwrap() as u8;
+            let mut left = original_len;
+            while left > 0 {
+                let n = left.min(CHUNK_SIZE as u64) as usize;
+                out.resize(n, byte);
+                left -= n as u64;
+                if n == CHUNK_SIZE {
+                    check_cancelled(&options.cancel)?;
+                    crc.update(&out);
+                    output.write_all(&out)?;
+                    out.clear();
+                }
+            }
+        }
+        Some(fsm) => decode_fsm(fsm, input, original_len, payload_len, &mut emit)?,
+        None if symbols <= TINY_ALPHABET => {
+            let huffman_map = canonical_codes.unwrap_or_else(|| tree.generate_huffman_map());
+            decode_tiny(&huffman_map, input, original_len, payload_len, &mut emit)?
+        }
+        None => {
+            let table = match &canonical_codes {
+                Some(codes) => {
+                    canonical_table = DecodeTable::new(codes);
+                    &canonical_table
+                }
+                None => tree.decode_table(),
+            };
+            decode_table(table, input, original_len, payload_len, &mut emit)?
+        }
+    }
+    *written += original_len;
+    crc.update(&out);
+    output.write_all(&out)?;
+    if header.checksum {
+        let expected = u32::from_le_bytes(
+            read_array(input).map_err(|_| Error::Corrupt("checksum is missing"))?,
+        );
+        if expected != crc.value() {
+            return Err(Error::ChecksumMismatch {
+                expected,
+                found: crc.value(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Gets the dictionary a payload was compressed with, out of the options, making sure it's the right one
+///
+/// ## Arguments
+///
+/// * `options`: how to decompress
+/// * `id`: the ID of the dictionary the payload was compressed with
+fn find_dictionary(options: &DecodeOptions, id: u32) -> Result<&Dictionary> {
+    match &options.dictionary {
+        Some(dict) if dict.id() == id => Ok(dict),
+        other => Err(Error::DictionaryMismatch {
+            expected: id,
+            found: other.as_ref().map(|dict| dict.id()),
+        }),
+    }
+}
+
+/// Decodes a static payload with a lookup table
+///
+/// ## Arguments
+///
+/// * `table`: the lookup table for the codes the payload was coded with
+/// * `input`: the payload
+/// * `original_len`: how many bytes it decodes to
+/// * `payload_len`: how many bytes long it is
+/// * `emit`: where the decoded bytes go
+fn decode_table<R, F>(
+    table: &DecodeTable,
+    input: &mut R,
+    original_len: u64,
+    payload_len: u64,
+    emit: &mut F,
+) -> Result<()>
+where
+    R: BufRead,
+    F: FnMut(u8) -> Result<()>,
+{
+    let mut bits = BitWindow::new(input, payload_len);
+    for _ in 0..original_len {
+        bits.refill()?;
+        let (ch, len) = table
+            .lookup(bits.peek())
+            .ok_or(Error::Corrupt("bits don't match any code"))?;
+        if !bits.consume(len) {
+            return Err(Error::Corrupt("payload is truncated"));
+        }
+        emit(ch as u8)?;
+    }
+    // the rest of the last byte is padding
+    if !bits.is_finished() {
+        return Err(Error::Corrupt("payload length doesn't match the header"));
+    }
+    Ok(())
+}
+
+/// Decodes a static payload with no more than `TINY_ALPHABET` symbols
+///
+/// Four symbols never need a code longer than 3 bits, so every code can be found by indexing an 8-entry array with
+/// the next 3 bits, with no tree, no multi-level table, and no failed lookups to check for. The window only needs
+/// topping up when a code runs off the end of it, so the loop is a lookup, a shift, and one mostly-untaken branch.
+///
+/// ## Arguments
+///
+/// * `huffman_map`: the codes the payload was coded with
+/// * `input`: the payload
+/// * `original_len`: how many bytes it decodes to
+/// * `payload_len`: how many bytes long it is
+/// * `emit`: where the decoded bytes go
+fn decode_tiny<R, F>(
+    huffman_map: &HashMap<char, String>,
+    input: &mut R,
+    original_len: u64,
+    payload_len: u64,
+    emit: &mut F,
+) -> Result<()>
+where
+    R: BufRead,
+    F: FnMut(u8) -> Result<()>,
+{
+    // the symbol (and code length) for every possible next 3 bits
+    let mut codes = [(0u8, 0u32); 8];
+    for (&ch, code) in huffman_map {
+        let spare = 3 - code.len() as u32;
+        let first = code
+            .bytes()
+            .fold(0, |acc, bit| (acc << 1) | (bit == b'1') as usize)
+            << spare;
+        for entry in &mut codes[first..first + (1 << spare)] {
+            *entry = (ch as u8, code.len() as u32);
+        }
+    }
+    let mut bits = BitWindow::new(input, payload_len);
+    for _ in 0..original_len {
+        let (byte, len) = codes[(bits.peek() >> 61) as usize];
+        if !bits.consume(len) {
+            bits.refill()?;
+            let (byte, len) = codes[(bits.peek() >> 61) as usize];
+            if !bits.consume(len) {
+                return Err(Error::Corrupt("payload is truncated"));
+            }
+            emit(byte)?;
+            continue;
+        }
+        emit(byte)?;
+    }
+    // the rest of the last byte is padding
+    if !bits.is_finished() {
+        return Err(Error::Corrupt("payload length doesn't match the header"));
+    }
+    Ok(())
+}
+
+/// Decodes a static payload a byte at a time with a compiled state machine
+///
+/// ## Arguments
+///
+/// * `fsm`: the state machine for the codes the payload was coded with
+/// * `input`: the payload
+/// * `original_len`: how many bytes it decodes to
+/// * `payload_len`: how many bytes long it is
+/// * `emit`: where the decoded bytes go
+fn decode_fsm<R, F>(
+    fsm: &DecodeFsm,
+    input: &mut R,
+    original_len: u64,
+    payload_len: u64,
+    emit: &mut F,
+) -> Result<()>
+where
+    R: BufRead,
+    F: FnMut(u8) -> Result<()>,
+{
+    let mut state = fsm.start();
+    let mut remaining = original_len;
+    let mut left = payload_len;
+    while remaining != 0 {
+        if left == 0 {
+            return Err(Error::Corrupt("payload length doesn't match the header"));
+        }
+        let buf = input.fill_buf()?;
+        if buf.is_empty() {
+            return Err(Error::Corrupt("payload is truncated"));
+        }
+        let mut used = 0;
+        for &byte in &buf[..buf.len().min(left as usize)] {
+            used += 1;
+            let (next, symbols) = fsm.step(state, byte);
+            state = next;
+            // whatever comes after the last symbol in its byte is padding, which may look like more symbols
+            for &ch in symbols.iter().take(remaining.min(8) as usize) {
+                emit(ch as u8)?;
+            }
+            remaining -= remaining.min(symbols.len() as u64);
+            if remaining == 0 {
+                break;
+            }
+        }
+        input.consume(used);
+        left -= used as u64;
+    }
+    if left != 0 {
+        return Err(Error::Corrupt("payload length doesn't match the header"));
+    }
+    Ok(())
+}
+
+/// Where one block of a multi-container file is, and what it decodes to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct BlockInfo {
+    /// Where the block's container starts in the compressed file
+    pub offset: u64,
+    /// How long the block's container is (header and trailer included)
+    pub len: u64,
+    /// Where the block's data starts in the decompressed output
+    pub original_offset: u64,
+    /// How long the block's data is once decompressed
+    pub original_len: u64,
+}
+
+/// Finds every block in a file of static containers (e.g. from block mode), without decoding any of them
+///
+/// Indexes aren't blocks, so they get skipped over.
+///
+/// Each block is a complete container, so `offset..offset + len` of the file can be handed to `decompress()` on its
+/// own, anywhere, and will decode to `original_offset..original_offset + original_len` of the whole thing. Streaming
+/// containers don't record how long they are, and a container with a pipeline only records how long the data is
+/// after its stages, not what it decodes to, so a file with either of those in it gives `Error::Unsupported`.
+///
+/// ## Arguments
+///
+/// * `input`: the compressed file
+pub fn split_points<R: BufRead + Seek>(mut input: R) -> Result<Vec<BlockInfo>> {
+    let start = input.stream_position()?;
+    let end = input.seek(SeekFrom::End(0))?;
+    input.seek(SeekFrom::Start(start))?;
+    let mut blocks = Vec::new();
+    let mut original_offset = 0;
+    let mut offset = start;
+    while !input.fill_buf()?.is_empty() {
+        let header = Header::read_from(&mut input)?;
+        if !header.extensions.pipeline()?.is_empty() {
+            return Err(Error::Unsupported(
+                "containers with a pipeline don't record how long they decode to",
+            ));
+        }
+        let (original_len, payload_len) = match header.payload {
+            Payload::Static {
+                original_len,
+                payload_len,
+                ..
+            }
+            | Payload::Canonical {
+                original_len,
+                payload_len,
+                ..
+            }
+            | Payload::Dictionary {
+                original_len,
+                payload_len,
+                ..
+            } => (original_len, payload_len),
+            Payload::Streaming => {
+                return Err(Error::Unsupported("streaming containers can't be split"))
+            }
+            Payload::Index { .. } => {
+                offset = input.stream_position()?;
+                continue;
+            }
+        };
+        let trailer_len = if header.checksum { 4 } else { 0 };
+        let next = payload_len
+            .checked_add(trailer_len)
+            .and_then(|len| len.checked_add(input.stream_position().ok()?))
+            .filter(|&next| next <= end)
+            .ok_or(Error::Corrupt("payload is truncated"))?;
+        blocks.push(BlockInfo {
+            offset,
+            len: next - offset,
+            original_offset,
+            original_len,
+        });
+        input.seek(SeekFrom::Start(next))?;
+        original_offset = original_offset
+            .checked_add(original_len)
+            .ok_or(Error::Corrupt(
+                "blocks' lengths add up to more than 64 bits",
+            ))?;
+        offset = next;
+    }
+    Ok(blocks)
+}
+
+/// Compresses several inputs into one file, one after another, with an index at the end saying where each one is
+///
+/// Each input is compressed separately (in block mode, unless `options.streaming` is set, since the inputs can't be
+/// read twice), so decompressing the whole file gives all the inputs concatenated, and `extract()` can pull any
+/// single one back out without touching the rest.
+///
+/// ## Arguments
+///
+/// * `inputs`: the inputs, in order
+/// * `output`: where the file goes
+/// * `options`: how to compress each input
+pub fn compress_many<I, R, W>(inputs: I, output: W, options: &EncodeOptions) -> Result<W>
+where
+    I: IntoIterator<Item = R>,
+    R: Read,
+    W: Write,
+{
+    let inputs = inputs.into_iter().map(|input| (String::new(), None, input));
+    compress_indexed(inputs, output, options, IndexKind::Unnamed)
+}
+
+/// Compresses several inputs into one file, one after another, like `compress_many()` but with no index at the end
+///
+/// That saves the index's 32-odd bytes per input, but then the only way to get at any one input is to decompress
+/// everything before it too.
+///
+/// ## Arguments
+///
+/// * `inputs`: the inputs, in order
+/// * `output`: where the file goes
+/// * `options`: how to compress each input
+pub fn compress_concatenated<I, R, W>(inputs: I, output: W, options: &EncodeOptions) -> Result<W>
+where
+    I: IntoIterator<Item = R>,
+    R: Read,
+    W: Write,
+{
+    let inputs = inputs.into_iter().map(|input| (String::new(), None, input));
+    compress_indexed(inputs, output, options, IndexKind::None)
+}
+
+/// Compresses several named inputs (e.g. files) into an archive, like `compress_many()`, but with every input's name
+/// and checksum in the index as well
+///
+/// Names can be up to 65535 bytes long; a longer one fails with `Error::Unsupported` before its input is read.
+///
+/// ## Arguments
+///
+/// * `inputs`: the inputs and their names, in order
+/// * `output`: where the archive goes
+/// * `options`: how to compress each input
+pub fn compress_archive<I, R, W>(inputs: I, output: W, options: &EncodeOptions) -> Result<W>
+where
+    I: IntoIterator<Item = (String, R)>,
+    R: Read,
+    W: Write,
+{
+    let inputs = inputs.into_iter().map(|(name, input)| (name, None, input));
+    compress_indexed(inputs, output, options, IndexKind::Named)
+}
+
+/// Compresses several files into an archive, like `compress_archive()`, keeping each one's permissions and
+/// modification time in the index too, so they can be put back when it's extracted
+///
+/// ## Arguments
+///
+/// * `inputs`: the inputs, with their names and metadata, in order
+/// * `output`: where the archive goes
+/// * `options`: how to compress each input
+pub fn compress_archive_with_metadata<I, R, W>(
+    inputs: I,
+    output: W,
+    options: &EncodeOptions,
+) -> Result<W>
+where
+    I: IntoIterator<Item = (String, FileMetadata, R)>,
+    R: Read,
+    W: Write,
+{
+    let inputs = inputs
+        .into_iter()
+        .map(|(name, metadata, input)| (name, Some(metadata), input));
+    compress_indexed(inputs, output, options, IndexKind::Named)
+}
+
+/// What `compress_indexed()` puts after the inputs
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum IndexKind {
+    /// Nothing at all
+    None,
+    /// An index of where each input is
+    Unnamed,
+    /// An index of where each input is, with its name (and maybe its metadata)
+    Named,
+}
+
+/// Compresses several inputs one after another, followed by whatever kind of index `index` says
+fn compress_indexed<I, R, W>(
+    inputs: I,
+    output: W,
+    options: &EncodeOptions,
+    index: IndexKind,
+) -> Result<W>
+where
+    I: IntoIterator<Item = (String, Option<FileMetadata>, R)>,
+    R: Read,
+    W: Write,
+{
+    let mut output = Counted::new(output);
+    let mut entries = Vec::new();
+    let mut names = Vec::new();
+    let mut original_offset = 0;
+    for (name, metadata, input) in inputs {
+        if name.len() > u16::MAX as usize {
+            return Err(Error::Unsupported(
+                "input names can't be longer than 65535 bytes",
+            ));
+        }
+        let offset = output.count;
+        let mut input = Counted::new(input);
+        output = if options.streaming && options.block_size.is_none() {
+            compress_streaming(&mut input, output, options)?
+        } else {
+            let block_size = options.block_size.unwrap_or(CHUNK_SIZE);
+            compress_blocks(&mut input, output, block_size, options)?
+        };
+        entries.push(BlockInfo {
+            offset,
+            len: output.count - offset,
+            original_offset,
+            original_len: input.count,
+        });
+        names.push(EntryName {
+            name,
+            crc: input.crc.value(),
+            metadata,
+        });
+        original_offset += input.count;
+    }
+    if index != IndexKind::None {
+        Header {
+            checksum: false,
+            payload: Payload::Index {
+                entries,
+                names: (index == IndexKind::Named).then_some(names),
+            },
+            extensions: options.extensions.clone(),
+        }
+        .write_to(&mut output)?;
+    }
+    Ok(output.inner)
+}
+
+/// Reads the index from the end of a file made by `compress_many()`
+///
+/// ## Arguments
+///
+/// * `input`: the compressed file
+pub fn read_index<R: Read + Seek>(input: R) -> Result<Vec<BlockInfo>> {
+    read_index_header(input).map(|(entries, _)| entries)
+}
+
+/// Reads the index from the end of an archive made by `compress_archive()`, giving back every input's name and
+/// checksum along with where it is
+///
+/// A file made by `compress_many()` has no names in its index, which gives `Error::Unsupported`.
+///
+/// ## Arguments
+///
+/// * `input`: the archive
+pub fn read_archive<R: Read + Seek>(input: R) -> Result<Vec<(BlockInfo, EntryName)>> {
+    match read_index_header(input)? {
+        (entries, Some(names)) => Ok(entries.into_iter().zip(names).collect()),
+        (_, None) => Err(Error::Unsupported("file's index has no names")),
+    }
+}
+
+/// Finds and reads the index at the end of a file
+fn read_index_header<R: Read + Seek>(
+    mut input: R,
+) -> Result<(Vec<BlockInfo>, Option<Vec<EntryName>>)> {
+    let missing = || Error::Unsupported("file has no index");
+    let end = input.seek(SeekFrom::End(0))?;
+    if end < index_len(0, None, 0) {
+        return Err(missing());
+    }
+    input.seek(SeekFrom::End(-8))?;
+    let len = u64::from_le_bytes(read_array(&mut input)?);
+    if len < index_len(0, None, 0) || len > end {
+        return Err(missing());
+    }
+    input.seek(SeekFrom::Start(end - len))?;
+    match Header::read_from(&mut input) {
+        Ok(Header {
+            payload: Payload::Index { entries, names },
+            ..
+        }) => Ok((entries, names)),
+        _ => Err(missing()),
+    }
+}
+
+/// Decompresses just one of the inputs of a file made by `compress_many()`
+///
+/// ## Arguments
+///
+/// * `input`: the compressed file
+/// * `k`: which input to decompress, counting from 0
+/// * `output`: where the decompressed input goes
+/// * `options`: how to decompress
+pub fn extract<R: BufRead + Seek, W: Write>(
+    mut input: R,
+    k: usize,
+    output: W,
+    options: &DecodeOptions,
+) -> Result<W> {
+    let entries = read_index(&mut input)?;
+    let entry = entries.get(k).ok_or(Error::NoSuchInput(k))?;
+    input.seek(SeekFrom::Start(entry.offset))?;
+    decompress_with(input.take(entry.len), output, options)
+}
+
+/// Decompresses just one of the inputs of an archive made by `compress_archive()`, found by its name
+///
+/// Only that input's part of the archive gets read (after the index), so this takes about as long no matter how big
+/// the rest of the archive is. An archive with no input by that name gives `Error::NoSuchEntry`.
+///
+/// ## Arguments
+///
+/// * `input`: the archive
+/// * `name`: the name of the input to decompress
+/// * `output`: where the decompressed input goes
+/// * `options`: how to decompress
+pub fn extract_named<R: BufRead + Seek, W: Write>(
+    mut input: R,
+    name: &str,
+    output: W,
+    options: &DecodeOptions,
+) -> Result<W> {
+    let entries = read_archive(&mut input)?;
+    let (info, _) = entries
+        .iter()
+        .find(|(_, entry)| entry.name == name)
+        .ok_or_else(|| Error::NoSuchEntry(name.to_string()))?;
+    input.seek(SeekFrom::Start(info.offset))?;
+    decompress_with(input.take(info.len), output, options)
+}
+
+/// Counts the bytes going through a reader or writer (and checksums the ones read)
+struct Counted<T> {
+    /// The reader or writer
+    inner: T,
+    /// How many bytes have gone through it
+    count: u64,
+    /// The CRC-32 of everything read through it
+    crc: Crc32,
+}
+
+impl<T> Counted<T> {
+    /// Starts counting from zero
+    fn new(inner: T) -> Self {
+        Counted {
+            inner,
+            count: 0,
+            crc: Crc32::new(),
+        }
+    }
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// How long an index with `entries` entries (and `names`, if it has them) is, start to end, when its extensions take
+/// up `extensions_len` bytes (their length included)
+fn index_len(entries: usize, names: Option<&[EntryName]>, extensions_len: u64) -> u64 {
+    let names = names.unwrap_or_default();
+    let metadata = if has_metadata(names) { 4 + 8 } else { 0 };
+    let names: u64 = names
+        .iter()
+        .map(|entry| 4 + 2 + entry.name.len() as u64 + metadata)
+        .sum();
+    6 + extensions_len + 4 + 32 * entries as u64 + names + 8
+}
+
+/// Whether an index's names get file metadata stored with them, which they all do if any of them has some
+fn has_metadata(names: &[EntryName]) -> bool {
+    names.iter().any(|entry| entry.metadata.is_some())
+}
+
+/// Compresses a byte slice into a new container, all in memory
+///
+/// Since it's all just memory, the only way this can fail is by being cancelled.
+///
+/// ## Arguments
+///
+/// * `data`: the data to compress
+/// * `options`: how to compress
+pub fn compress_bytes(data: &[u8], options: &EncodeOptions) -> Result<Vec<u8>> {
+    compress_slice(data, Vec::new(), options)
+}
+
+/// Decompresses a container held in memory
+///
+/// ## Arguments
+///
+/// * `data`: the container
+pub fn decompress_bytes(data: &[u8]) -> Result<Vec<u8>> {
+    decompress(data, Vec::new())
+}
+
+/// Fails with `Error::Cancelled` if the cancellation flag (if there is one) has been set
+fn check_cancelled(cancel: &Option<Arc<AtomicBool>>) -> Result<()> {
+    match cancel {
+        Some(flag) if flag.load(Ordering::Relaxed) => Err(Error::Cancelled),
+        _ => Ok(()),
+    }
+}
+
+/// Reads as much as will fit into `buf` (stopping early only at the end of the input), returning how much was read
+fn read_chunk<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Reads exactly `N` bytes, treating running out partway as a truncated header
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N]> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => Error::BadHeader("header is truncated"),
+        _ => Error::Io(e),
+    })?;
+    Ok(buf)
+}
+
+/// The error for when the input differs between the counting pass and the encoding pass
+fn input_changed() -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "input changed while it was being compressed",
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        compress, compress_archive, compress_archive_with_metadata, compress_bytes,
+        compress_concatenated, compress_many, compress_resumable, decompress_bytes,
+        decompress_with, estimate, extract, extract_named, read_archive, read_index, split_points,
+        static_header, verify, Checkpoint, DecodeOptions, EncodeOptions, FileMetadata, Header,
+        Payload, Split, LONE_SYMBOL_LIMIT, SPLIT_SEGMENT,
+    };
+    use crate::checksum::Crc32;
+    use crate::dict::Dictionary;
+    use crate::error::Error;
+    use crate::extensions::Extensions;
+    use crate::freq::FrequencyMap;
+    use crate::pipeline::Pipeline;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn arbitrary_test() {
+        use crate::codebook::Codebook;
+        use crate::freq::FrequencyMap;
+        use arbitrary::{Arbitrary, Unstructured};
+        // some made-up fuzzer data
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let data: Vec<u8> = (0..1 << 16)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let mut kinds = [false; 5];
+        for start in (0..data.len()).step_by(509) {
+            let mut u = Unstructured::new(&data[start..]);
+            let header = Header::arbitrary(&mut u).unwrap();
+            kinds[match header.payload {
+                Payload::Static { .. } => 0,
+                Payload::Dictionary { .. } => 1,
+                Payload::Canonical { .. } => 2,
+                Payload::Streaming => 3,
+                Payload::Index { .. } => 4,
+            }] = true;
+            let mut written = Vec::new();
+            header.write_to(&mut written).unwrap();
+            assert_eq!(Header::read_from(&mut &written[..]).unwrap(), header);
+            let freqs = FrequencyMap::arbitrary(&mut u).unwrap();
+            assert!(freqs.as_map().values().all(|&freq| freq > 0));
+            assert!(freqs.total() <= 1 << 24);
+            let codebook = Codebook::arbitrary(&mut u).unwrap();
+            assert!(Codebook::from_pairs(codebook.into_map(), true).is_ok());
+        }
+        assert_eq!(kinds, [true; 5]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_test() {
+        // every kind of payload comes back just as it went
+        let archive = compress_archive_with_metadata(
+            vec![(
+                "ur.txt".to_string(),
+                FileMetadata {
+                    mode: 0o644,
+                    mtime: 7,
+                },
+                &b"dagoth ur"[..],
+            )],
+            Vec::new(),
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        let mut headers = Vec::new();
+        let mut input = Cursor::new(&archive);
+        headers.push(Header::read_from(&mut input).unwrap());
+        input.set_position(read_index(Cursor::new(&archive)).unwrap()[0].offset);
+        headers.push(Header::read_from(&mut input).unwrap());
+        let canonical = EncodeOptions {
+            canonical: true,
+            ..EncodeOptions::default()
+        };
+        let streaming = EncodeOptions {
+            streaming: true,
+            ..EncodeOptions::default()
+        };
+        for options in [canonical, streaming] {
+            let compressed = compress_bytes(b"was a hotep", &options).unwrap();
+            headers.push(Header::read_from(&mut &compressed[..]).unwrap());
+        }
+        for header in &headers {
+            let json = serde_json::to_string(header).unwrap();
+            assert_eq!(&serde_json::from_str::<Header>(&json).unwrap(), header);
+            let packed = postcard::to_allocvec(header).unwrap();
+            assert_eq!(&postcard::from_bytes::<Header>(&packed).unwrap(), header);
+            let packed = bincode::serialize(header).unwrap();
+            assert_eq!(&bincode::deserialize::<Header>(&packed).unwrap(), header);
+        }
+        assert_eq!(
+            serde_json::to_string(&headers[3]).unwrap(),
+            "{\"checksum\":true,\"payload\":\"streaming\",\"extensions\":[]}"
+        );
+        assert!(serde_json::to_string(&headers[1]).unwrap().starts_with(
+            "{\"checksum\":true,\"payload\":{\"static\":{\"original_len\":9,\"freqs\":{\" \":1,"
+        ));
+        // and ones that couldn't have been read from a file get turned away
+        for bad in [
+            "{\"checksum\":true,\"payload\":{\"static\":{\"original_len\":1,\"freqs\":{\"\u{100}\":1},\"payload_len\":1}}}",
+            "{\"checksum\":true,\"payload\":{\"static\":{\"original_len\":1,\"freqs\":{},\"payload_len\":1}}}",
+            "{\"checksum\":true,\"payload\":{\"canonical\":{\"original_len\":1,\"lengths\":[[97,1]],\"payload_len\":1}}}",
+            "{\"checksum\":false,\"payload\":{\"index\":{\"entries\":[],\"names\":[{\"name\":\"x\",\"crc\":0,\"metadata\":null}]}}}",
+        ] {
+            assert!(serde_json::from_str::<Header>(bad).is_err(), "{}", bad);
+        }
+    }
+
+    fn round_trip(input: &[u8], options: &EncodeOptions) -> bool {
+        let compressed = compress_bytes(input, options).unwrap();
+        // the reader path and the slice path should make exactly the same container
+        compress(Cursor::new(input), Vec::new(), options).unwrap() == compressed
+            && decompress_bytes(&compressed).unwrap() == input
+    }
+
+    fn all_options() -> Vec<EncodeOptions> {
+        let mut all = Vec::new();
+        for &checksum in &[false, true] {
+            for &streaming in &[false, true] {
+                for &block_size in &[None, Some(1000)] {
+                    for &canonical in &[false, true] {
+                        all.push(EncodeOptions {
+                            checksum,
+                            streaming,
+                            block_size,
+                            canonical,
+                            ..EncodeOptions::default()
+                        });
+                    }
+                }
+            }
+        }
+        all
+    }
+
+    #[test]
+    fn container_round_trip_test() {
+        for options in all_options() {
+            assert!(round_trip(b"", &options));
+            assert!(round_trip(b"a", &options));
+            assert!(round_trip(b"aaaaaaaa", &options));
+            assert!(round_trip(b"aaabbbbbccddd", &options));
+            // a whole number of blocks, with nothing left over for a last short one
+            assert!(round_trip(&[b'x'; 3000], &options));
+            assert!(round_trip(
+                &(0..=255u8).cycle().take(100000).collect::<Vec<u8>>(),
+                &options
+            ));
+        }
+    }
+
+    #[test]
+    fn fsm_decode_test() {
+        let options = DecodeOptions {
+            fsm: true,
+            ..DecodeOptions::default()
+        };
+        for input in &[
+            &b""[..],
+            b"a",
+            b"aaaaaaaa",
+            b"aaabbbbbccddd",
+            &(0..=255u8).cycle().take(100000).collect::<Vec<u8>>(),
+        ] {
+            for (checksum, canonical) in [(false, false), (true, false), (true, true)] {
+                let encode_options = EncodeOptions {
+                    checksum,
+                    canonical,
+                    ..EncodeOptions::default()
+                };
+                let compressed = compress_bytes(input, &encode_options).unwrap();
+                let decompressed = decompress_with(compressed.as_slice(), Vec::new(), &options);
+                assert_eq!(&decompressed.unwrap(), input);
+                assert!(
+                    decompress_with(&compressed[..compressed.len() - 5], Vec::new(), &options)
+                        .is_err()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn split_points_test() {
+        let input = "dagoth ur was a hotep".repeat(1000).into_bytes();
+        let nanos = Arc::new(AtomicU64::new(0));
+        let options = EncodeOptions {
+            block_size: Some(4096),
+            block_nanos: Some(nanos.clone()),
+            ..EncodeOptions::default()
+        };
+        let compressed = compress_bytes(&input, &options).unwrap();
+        assert!(nanos.load(Ordering::Relaxed) > 0);
+        let blocks = split_points(Cursor::new(&compressed)).unwrap();
+        assert_eq!(blocks.len(), input.len().div_ceil(4096));
+        for block in &blocks {
+            let compressed =
+                &compressed[block.offset as usize..(block.offset + block.len) as usize];
+            let original = &input[block.original_offset as usize
+                ..(block.original_offset + block.original_len) as usize];
+            assert_eq!(decompress_bytes(compressed).unwrap(), original);
+        }
+        let last = blocks.last().unwrap();
+        assert_eq!(last.offset + last.len, compressed.len() as u64);
+        let streaming = compress_bytes(
+            &input,
+            &EncodeOptions {
+                streaming: true,
+                ..EncodeOptions::default()
+            },
+        )
+        .unwrap();
+        assert!(matches!(
+            split_points(Cursor::new(&streaming)),
+            Err(Error::Unsupported(_))
+        ));
+        let pipelined = compress_bytes(
+            &input,
+            &EncodeOptions {
+                block_size: Some(4096),
+                pipeline: Pipeline::bzip2(),
+                ..EncodeOptions::default()
+            },
+        )
+        .unwrap();
+        assert!(matches!(
+            split_points(Cursor::new(&pipelined)),
+            Err(Error::Unsupported(_))
+        ));
+        // headers that say their blocks decode to more than can be added up
+        let mut huge = Vec::new();
+        for _ in 0..2 {
+            Header {
+                checksum: false,
+                payload: Payload::Static {
+                    original_len: 1 << 63,
+                    freqs: FrequencyMap::from("ab"),
+                    payload_len: 1,
+                },
+                extensions: Extensions::new(),
+            }
+            .write_to(&mut huge)
+            .unwrap();
+            huge.push(0);
+        }
+        assert!(matches!(
+            split_points(Cursor::new(&huge)),
+            Err(Error::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn resume_test() {
+        let input = "dagoth ur was a hotep".repeat(1000).into_bytes();
+        let options = EncodeOptions {
+            block_size: Some(4096),
+            ..EncodeOptions::default()
+        };
+        // "crash" partway through the third block
+        let mut output = Vec::new();
+        let mut saved = None;
+        let crashed = compress_resumable(Cursor::new(&input), &mut output, &options, None, |c| {
+            if c.input_offset > 8192 {
+                return Err(Error::Cancelled);
+            }
+            saved = Some(c.to_bytes());
+            Ok(())
+        });
+        assert!(matches!(crashed, Err(Error::Cancelled)));
+        let checkpoint = Checkpoint::from_bytes(&saved.unwrap()).unwrap();
+        assert_eq!(checkpoint.input_offset, 8192);
+        output.truncate(checkpoint.output_offset as usize);
+        let resumed = compress_resumable(
+            Cursor::new(&input),
+            output,
+            &options,
+            Some(checkpoint),
+            |_| Ok(()),
+        )
+        .unwrap();
+        assert_eq!(resumed, compress_bytes(&input, &options).unwrap());
+        let no_blocks = compress_resumable(
+            Cursor::new(&input),
+            Vec::new(),
+            &EncodeOptions::default(),
+            None,
+            |_| Ok(()),
+        );
+        assert!(matches!(no_blocks, Err(Error::Unsupported(_))));
+    }
+
+    #[test]
+    fn many_inputs_test() {
+        let inputs: Vec<Vec<u8>> = vec![
+            "dagoth ur was a hotep".repeat(5000).into_bytes(),
+            Vec::new(),
+            b"aaabbbbbccddd".to_vec(),
+        ];
+        for &streaming in &[false, true] {
+            let options = EncodeOptions {
+                streaming,
+                ..EncodeOptions::default()
+            };
+            let compressed =
+                compress_many(inputs.iter().map(|i| i.as_slice()), Vec::new(), &options).unwrap();
+            assert_eq!(decompress_bytes(&compressed).unwrap(), inputs.concat());
+            for (k, input) in inputs.iter().enumerate() {
+                let extracted = extract(
+                    Cursor::new(&compressed),
+                    k,
+                    Vec::new(),
+                    &DecodeOptions::default(),
+                )
+                .unwrap();
+                assert_eq!(&extracted, input);
+            }
+            assert!(matches!(
+                extract(
+                    Cursor::new(&compressed),
+                    3,
+                    Vec::new(),
+                    &DecodeOptions::default()
+                ),
+                Err(Error::NoSuchInput(3))
+            ));
+            let concatenated =
+                compress_concatenated(inputs.iter().map(|i| i.as_slice()), Vec::new(), &options)
+                    .unwrap();
+            assert!(concatenated.len() < compressed.len());
+            assert_eq!(decompress_bytes(&concatenated).unwrap(), inputs.concat());
+            assert!(matches!(
+                read_index(Cursor::new(&concatenated)),
+                Err(Error::Unsupported(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn estimate_test() {
+        let input = "dagoth ur was a hotep".repeat(1000).into_bytes();
+        for options in all_options() {
+            let estimated = estimate(input.as_slice(), &options);
+            if options.streaming && options.block_size.is_none() {
+                assert!(matches!(estimated, Err(Error::Unsupported(_))));
+                continue;
+            }
+            let compressed = compress_bytes(&input, &options).unwrap();
+            assert_eq!(
+                estimated.unwrap(),
+                (input.len() as u64, compressed.len() as u64)
+            );
+            let compressed = compress_bytes(b"", &options).unwrap();
+            assert_eq!(
+                estimate(&b""[..], &options).unwrap(),
+                (0, compressed.len() as u64)
+            );
+        }
+    }
+
+    #[test]
+    fn canonical_test() {
+        let input = "dagoth ur was a hotep".repeat(100).into_bytes();
+        let canonical = EncodeOptions {
+            canonical: true,
+            ..EncodeOptions::default()
+        };
+        let compressed = compress_bytes(&input, &canonical).unwrap();
+        // the same codes' lengths, but 2 bytes a symbol in the header instead of 5
+        let plain = compress_bytes(&input, &EncodeOptions::default()).unwrap();
+        assert_eq!(plain.len() - compressed.len(), 3 * 13);
+        assert_eq!(decompress_bytes(&compressed).unwrap(), input);
+        let header = Header::read_from(&mut compressed.as_slice()).unwrap();
+        assert!(
+            matches!(header.payload, Payload::Canonical { ref lengths, .. } if lengths.len() == 13)
+        );
+        // lengths that leave a gap in the code (the first symbol's length is at offset 4 + 2 + 8 + 2 + 1)
+        let mut broken = compressed.clone();
+        broken[17] += 1;
+        assert!(matches!(
+            decompress_bytes(&broken),
+            Err(Error::BadHeader(_))
+        ));
+        // far too many short codes, which adds up past what 64 bits can hold
+        let mut overfull = Vec::new();
+        Header {
+            checksum: false,
+            payload: Payload::Canonical {
+                original_len: 1,
+                lengths: (0..=255).map(|byte| (byte, 1)).collect(),
+                payload_len: 1,
+            },
+            extensions: Extensions::new(),
+        }
+        .write_to(&mut overfull)
+        .unwrap();
+        overfull.push(0);
+        assert!(matches!(
+            decompress_bytes(&overfull),
+            Err(Error::BadHeader(_))
+        ));
+        // a dictionary doesn't store any codes, canonical or not
+        let dict = Arc::new(Dictionary::train(vec![&input[..]]).unwrap());
+        let with_dict = EncodeOptions {
+            dictionary: Some(dict),
+            ..canonical
+        };
+        let compressed = compress_bytes(&input, &with_dict).unwrap();
+        let header = Header::read_from(&mut compressed.as_slice()).unwrap();
+        assert!(matches!(header.payload, Payload::Dictionary { .. }));
+    }
+
+    #[test]
+    fn archive_test() {
+        let inputs = vec![
+            ("dir/ur.txt".to_string(), &b"dagoth ur was a hotep"[..]),
+            ("empty".to_string(), &b""[..]),
+            ("ünïcödé".to_string(), &b"aaabbbbbccddd"[..]),
+        ];
+        let compressed =
+            compress_archive(inputs.clone(), Vec::new(), &EncodeOptions::default()).unwrap();
+        assert_eq!(
+            decompress_bytes(&compressed).unwrap(),
+            inputs.iter().map(|i| i.1).collect::<Vec<_>>().concat()
+        );
+        let listed = read_archive(Cursor::new(&compressed)).unwrap();
+        assert_eq!(listed.len(), 3);
+        for ((info, entry), (name, input)) in listed.iter().zip(&inputs) {
+            assert_eq!(&entry.name, name);
+            let mut crc = Crc32::new();
+            crc.update(input);
+            assert_eq!(entry.crc, crc.value());
+            assert_eq!(info.original_len, input.len() as u64);
+        }
+        assert_eq!(read_index(Cursor::new(&compressed)).unwrap().len(), 3);
+        let extracted = extract_named(
+            Cursor::new(&compressed),
+            "ünïcödé",
+            Vec::new(),
+            &DecodeOptions::default(),
+        );
+        assert_eq!(extracted.unwrap(), b"aaabbbbbccddd");
+        assert!(matches!(
+            extract_named(
+                Cursor::new(&compressed),
+                "ur.txt",
+                Vec::new(),
+                &DecodeOptions::default()
+            ),
+            Err(Error::NoSuchEntry(_))
+        ));
+        assert!(listed.iter().all(|(_, entry)| entry.metadata.is_none()));
+        let metadata = FileMetadata {
+            mode: 0o640,
+            mtime: 1_500_000_000,
+        };
+        let inputs = vec![("a".to_string(), metadata, &b"abc"[..])];
+        let compressed =
+            compress_archive_with_metadata(inputs, Vec::new(), &EncodeOptions::default()).unwrap();
+        let listed = read_archive(Cursor::new(&compressed)).unwrap();
+        assert_eq!(listed[0].1.metadata, Some(metadata));
+        let unnamed =
+            compress_many(vec![&b"abc"[..]], Vec::new(), &EncodeOptions::default()).unwrap();
+        assert!(matches!(
+            read_archive(Cursor::new(&unnamed)),
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn extensions_test() {
+        use crate::extensions::{Extensions, Key, Value, COMMENT};
+        let mut extensions = Extensions::new();
+        extensions.insert(Key::Standard(COMMENT), Value::Text("hi".to_string()));
+        extensions.insert(Key::Vendor("com.example.build".to_string()), Value::Uint(7));
+        let input = &b"dagoth ur was a hotep"[..];
+        let plain = compress_bytes(input, &EncodeOptions::default()).unwrap();
+        // nothing changes for containers without any
+        assert_eq!(plain[5] & 0x80, 0);
+        for streaming in [false, true] {
+            let options = EncodeOptions {
+                streaming,
+                extensions: extensions.clone(),
+                ..EncodeOptions::default()
+            };
+            let compressed = compress_bytes(input, &options).unwrap();
+            let header = Header::read_from(&mut &compressed[..]).unwrap();
+            assert_eq!(header.extensions, extensions);
+            assert_eq!(header.extensions.comment(), Some("hi"));
+            assert_eq!(decompress_bytes(&compressed).unwrap(), input);
+        }
+        // indexes have them too, and can still be found from the end of the file
+        let options = EncodeOptions {
+            extensions: extensions.clone(),
+            ..EncodeOptions::default()
+        };
+        let inputs = vec![("a".to_string(), input), ("b".to_string(), input)];
+        let compressed = compress_archive(inputs, Vec::new(), &options).unwrap();
+        assert_eq!(read_archive(Cursor::new(&compressed)).unwrap().len(), 2);
+        // a decoder that doesn't know a key just carries it along
+        let mut header = Header::read_from(&mut &plain[..]).unwrap();
+        header
+            .extensions
+            .insert(Key::Standard(1000), Value::Array(vec![Value::Null]));
+        let mut written = Vec::new();
+        header.write_to(&mut written).unwrap();
+        assert_eq!(Header::read_from(&mut &written[..]).unwrap(), header);
+        // and ones that wouldn't fit don't get written at all
+        header
+            .extensions
+            .insert(Key::Standard(COMMENT), Value::Bytes(vec![0; 1 << 16]));
+        assert!(header.write_to(&mut Vec::new()).is_err());
+        written[10] = 0xff;
+        assert!(matches!(
+            Header::read_from(&mut &written[..]),
+            Err(Error::BadHeader(_))
+        ));
+    }
+
+    #[test]
+    fn split_test() {
+        // text, then a base64-ish blob, whose bytes the text's tree gives long codes to
+        let mut seed = 12345u64;
+        let alphabet = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let text = b"whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune ";
+        let mut input: Vec<u8> = text
+            .iter()
+            .copied()
+            .cycle()
+            .take(5 * SPLIT_SEGMENT)
+            .collect();
+        input.extend((0..5 * SPLIT_SEGMENT).map(|_| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            alphabet[(seed >> 58) as usize]
+        }));
+        let block_lens = |compressed: &[u8]| -> Vec<u64> {
+            split_points(Cursor::new(compressed))
+                .unwrap()
+                .iter()
+                .map(|block| block.original_len)
+                .collect()
+        };
+        let fixed = EncodeOptions {
+            block_size: Some(1 << 20),
+            ..EncodeOptions::default()
+        };
+        let fixed_compressed = compress_bytes(&input, &fixed).unwrap();
+        assert_eq!(block_lens(&fixed_compressed).len(), 1);
+        for canonical in [false, true] {
+            let drift = EncodeOptions {
+                split: Split::Drift { threshold: 64 },
+                canonical,
+                ..fixed.clone()
+            };
+            let compressed = compress_bytes(&input, &drift).unwrap();
+            // the block ends right where the text does
+            assert_eq!(
+                block_lens(&compressed),
+                vec![5 * SPLIT_SEGMENT as u64, 5 * SPLIT_SEGMENT as u64]
+            );
+            assert_eq!(decompress_bytes(&compressed).unwrap(), input);
+            assert_eq!(
+                estimate(input.as_slice(), &drift).unwrap(),
+                (input.len() as u64, compressed.len() as u64)
+            );
+            if !canonical {
+                assert!(compressed.len() < fixed_compressed.len());
+            }
+            // from a reader too
+            assert_eq!(
+                compress(Cursor::new(&input), Vec::new(), &drift).unwrap(),
+                compressed
+            );
+        }
+        // data that doesn't change doesn't get split, and a threshold too high to meet doesn't split anything
+        let same = text.repeat(1000);
+        let drift = EncodeOptions {
+            split: Split::Drift { threshold: 0 },
+            ..fixed.clone()
+        };
+        assert_eq!(block_lens(&compress_bytes(&same, &drift).unwrap()).len(), 1);
+        let drift = EncodeOptions {
+            split: Split::Drift {
+                threshold: u64::MAX / 2,
+            },
+            ..fixed.clone()
+        };
+        assert_eq!(
+            block_lens(&compress_bytes(&input, &drift).unwrap()).len(),
+            1
+        );
+        // and an empty input still gets a container
+        assert_eq!(
+            decompress_bytes(&compress_bytes(b"", &drift).unwrap()).unwrap(),
+            b""
+        );
+        // block_size still has the last word
+        let small = EncodeOptions {
+            split: Split::Drift { threshold: 64 },
+            block_size: Some(3 * SPLIT_SEGMENT),
+            ..EncodeOptions::default()
+        };
+        let lens: Vec<u64> = [3, 2, 1, 3, 1]
+            .iter()
+            .map(|&n| n * SPLIT_SEGMENT as u64)
+            .collect();
+        assert_eq!(block_lens(&compress_bytes(&input, &small).unwrap()), lens);
+    }
+
+    #[test]
+    fn optimal_split_test() {
+        // text, then a base64-ish blob, then text again
+        let mut seed = 12345u64;
+        let alphabet = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let text = b"whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune ";
+        let text: Vec<u8> = text
+            .iter()
+            .copied()
+            .cycle()
+            .take(2 * SPLIT_SEGMENT)
+            .collect();
+        let mut input = text.clone();
+        input.extend((0..3 * SPLIT_SEGMENT).map(|_| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            alphabet[(seed >> 58) as usize]
+        }));
+        input.extend_from_slice(&text[..SPLIT_SEGMENT + 100]);
+        let options = |split| EncodeOptions {
+            split,
+            block_size: Some(1 << 20),
+            ..EncodeOptions::default()
+        };
+        let optimal = options(Split::Optimal);
+        let compressed = compress_bytes(&input, &optimal).unwrap();
+        assert_eq!(decompress_bytes(&compressed).unwrap(), input);
+        assert_eq!(
+            estimate(input.as_slice(), &optimal).unwrap(),
+            (input.len() as u64, compressed.len() as u64)
+        );
+        // nothing beats it, splitting wherever else
+        let segments = input.len().div_ceil(SPLIT_SEGMENT);
+        for cuts in 0..1u32 << (segments - 1) {
+            let mut len = 0;
+            let mut start = 0;
+            for k in 1..=segments {
+                if k == segments || cuts & 1 << (k - 1) != 0 {
+                    let end = (k * SPLIT_SEGMENT).min(input.len());
+                    len += compress_bytes(&input[start..end], &EncodeOptions::default())
+                        .unwrap()
+                        .len();
+                    start = end;
+                }
+            }
+            assert!(compressed.len() <= len);
+        }
+        let drift = compress_bytes(&input, &options(Split::Drift { threshold: 0 })).unwrap();
+        assert!(compressed.len() <= drift.len());
+        // and the blob gets a block of its own
+        let blocks: Vec<u64> = split_points(Cursor::new(&compressed))
+            .unwrap()
+            .iter()
+            .map(|block| block.original_offset)
+            .collect();
+        assert!(blocks.contains(&(2 * SPLIT_SEGMENT as u64)));
+        assert!(blocks.contains(&(5 * SPLIT_SEGMENT as u64)));
+        assert_eq!(
+            decompress_bytes(&compress_bytes(b"", &optimal).unwrap()).unwrap(),
+            b""
+        );
+    }
+
+    #[test]
+    fn pipeline_test() {
+        use crate::extensions::{Key, Value, PIPELINE};
+        let input =
+            "whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune"
+                .repeat(100)
+                .into_bytes();
+        let plain = compress_bytes(&input, &EncodeOptions::default()).unwrap();
+        assert_eq!(plain[4], 1);
+        for block_size in [None, Some(1000)] {
+            for canonical in [false, true] {
+                let options = EncodeOptions {
+                    block_size,
+                    canonical,
+                    pipeline: Pipeline::bzip2(),
+                    ..EncodeOptions::default()
+                };
+                let compressed = compress_bytes(&input, &options).unwrap();
+                assert_eq!(decompress_bytes(&compressed).unwrap(), input);
+                assert_eq!(
+                    compress(Cursor::new(&input), Vec::new(), &options).unwrap(),
+                    compressed
+                );
+                assert_eq!(
+                    estimate(input.as_slice(), &options).unwrap(),
+                    (input.len() as u64, compressed.len() as u64)
+                );
+                // every header says what to undo, and only those headers get the new version
+                let header = Header::read_from(&mut &compressed[..]).unwrap();
+                assert_eq!(header.extensions.pipeline().unwrap(), Pipeline::bzip2());
+                assert_eq!(compressed[4], 2);
+                if block_size.is_none() {
+                    assert!(compressed.len() < plain.len() / 3);
+                }
+            }
+        }
+        // streaming payloads don't get one
+        let streaming = EncodeOptions {
+            streaming: true,
+            pipeline: Pipeline::bzip2(),
+            ..EncodeOptions::default()
+        };
+        let compressed = compress_bytes(&input, &streaming).unwrap();
+        assert!(Header::read_from(&mut &compressed[..])
+            .unwrap()
+            .extensions
+            .is_empty());
+        // the limit's on what comes out of the pipeline, not what goes into it
+        let options = EncodeOptions {
+            pipeline: Pipeline::bzip2(),
+            ..EncodeOptions::default()
+        };
+        let compressed = compress_bytes(&input, &options).unwrap();
+        let decode_with_limit = |limit: usize| {
+            let options = DecodeOptions {
+                max_output_bytes: Some(limit as u64),
+                ..DecodeOptions::default()
+            };
+            decompress_with(compressed.as_slice(), Vec::new(), &options)
+        };
+        assert_eq!(decode_with_limit(input.len()).unwrap(), input);
+        assert!(matches!(
+            decode_with_limit(input.len() - 1),
+            Err(Error::OutputLimitExceeded { limit }) if limit == input.len() as u64 - 1
+        ));
+        // a stage this build doesn't know about can't be undone
+        let mut header = Header::read_from(&mut &compressed[..]).unwrap();
+        header
+            .extensions
+            .insert(Key::Standard(PIPELINE), Value::Array(vec![Value::Uint(99)]));
+        let mut unknown = Vec::new();
+        header.write_to(&mut unknown).unwrap();
+        unknown.extend_from_slice(&compressed[unknown.len()..]);
+        assert!(matches!(
+            decompress_bytes(&unknown),
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn corruption_test() {
+        let compressed =
+            compress_bytes(b"dagoth ur was a hotep", &EncodeOptions::default()).unwrap();
+        assert!(matches!(decompress_bytes(b"nope"), Err(Error::BadMagic)));
+        assert!(matches!(
+            decompress_bytes(&compressed[..7]),
+            Err(Error::BadHeader(_))
+        ));
+        assert!(matches!(
+            decompress_bytes(&compressed[..compressed.len() - 6]),
+            Err(Error::Corrupt(_))
+        ));
+        let mut flipped = compressed.clone();
+        let last = flipped.len() - 1;
+        flipped[last] ^= 0x10;
+        assert!(matches!(
+            decompress_bytes(&flipped),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+        let mut newer = compressed;
+        newer[4] = 99;
+        assert!(matches!(
+            decompress_bytes(&newer),
+            Err(Error::UnsupportedVersion(99))
+        ));
+    }
+
+    #[test]
+    fn freq_total_test() {
+        // every frequency's in range, but they add up past what a tree can hold
+        let mut crafted = Vec::new();
+        Header {
+            checksum: false,
+            payload: Payload::Static {
+                original_len: 2,
+                freqs: FrequencyMap::from(
+                    [('a', i32::MAX), ('b', i32::MAX)]
+                        .iter()
+                        .copied()
+                        .collect::<HashMap<char, i32>>(),
+                ),
+                payload_len: 1,
+            },
+            extensions: Extensions::new(),
+        }
+        .write_to(&mut crafted)
+        .unwrap();
+        crafted.push(0b0100_0000);
+        assert!(matches!(
+            decompress_bytes(&crafted),
+            Err(Error::BadHeader(_))
+        ));
+        // counts from more than 2 GiB get scaled down for the tree, but the payload's length still comes from them
+        let mut counts = [0u64; 256];
+        counts[b'a' as usize] = 3 << 30;
+        counts[b'b' as usize] = 1 << 30;
+        counts[b'c' as usize] = 1;
+        let (header, codes) = static_header(&counts, &EncodeOptions::default());
+        let mut written = Vec::new();
+        header.write_to(&mut written).unwrap();
+        match Header::read_from(&mut &written[..]).unwrap().payload {
+            Payload::Static {
+                original_len,
+                freqs,
+                payload_len,
+            } => {
+                assert_eq!(original_len, (4 << 30) + 1);
+                assert!(freqs.total() <= i32::MAX as u64);
+                assert_eq!(freqs.get('c'), 1);
+                assert_eq!(codes[&'a'].len(), 1);
+                assert_eq!(payload_len, ((3u64 << 30) + 2 * (1 << 30) + 2).div_ceil(8));
+            }
+            _ => panic!("not a static payload"),
+        }
+    }
+
+    #[test]
+    fn tiny_alphabet_test() {
+        for input in &["ab", "abc", "abcd", "aaaaaaaaaab", "ddddcccbba"] {
+            let input = input.repeat(1000).into_bytes();
+            let compressed = compress_bytes(&input, &EncodeOptions::default()).unwrap();
+            assert_eq!(decompress_bytes(&compressed).unwrap(), input);
+            // decoding with the state machine turned on goes the same way
+            let fsm = DecodeOptions {
+                fsm: true,
+                ..DecodeOptions::default()
+            };
+            assert_eq!(
+                decompress_with(&compressed[..], Vec::new(), &fsm).unwrap(),
+                input
+            );
+            assert!(matches!(
+                decompress_bytes(&compressed[..compressed.len() - 6]),
+                Err(Error::Corrupt(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn cancellation_test() {
+        let input = "dagoth ur was a hotep".repeat(10000).into_bytes();
+        let flag = Arc::new(AtomicBool::new(true));
+        for &streaming in &[false, true] {
+            let options = EncodeOptions {
+                streaming,
+                cancel: Some(flag.clone()),
+                ..EncodeOptions::default()
+            };
+            assert!(matches!(
+                compress_bytes(&input, &options),
+                Err(Error::Cancelled)
+            ));
+            let compressed = compress_bytes(
+                &input,
+                &EncodeOptions {
+                    streaming,
+                    ..EncodeOptions::default()
+                },
+            )
+            .unwrap();
+            let options = DecodeOptions {
+                cancel: Some(flag.clone()),
+                ..DecodeOptions::default()
+            };
+            assert!(matches!(
+                decompress_with(compressed.as_slice(), Vec::new(), &options),
+                Err(Error::Cancelled)
+            ));
+        }
+    }
+
+    #[test]
+    fn output_limit_test() {
+        let input = "dagoth ur was a hotep".repeat(10000).into_bytes();
+        for &streaming in &[false, true] {
+            let options = EncodeOptions {
+                streaming,
+                ..EncodeOptions::default()
+            };
+            let compressed = compress_bytes(&input, &options).unwrap();
+            let decode_with_limit = |limit: usize| {
+                let options = DecodeOptions {
+                    max_output_bytes: Some(limit as u64),
+                    ..DecodeOptions::default()
+                };
+                decompress_with(compressed.as_slice(), Vec::new(), &options)
+            };
+            assert_eq!(decode_with_limit(input.len()).unwrap(), input);
+            match decode_with_limit(input.len() - 1) {
+                Err(Error::OutputLimitExceeded { limit }) => {
+                    assert_eq!(limit, input.len() as u64 - 1)
+                }
+                _ => panic!("limit wasn't enforced"),
+            }
+        }
+    }
+
+    #[test]
+    fn lone_symbol_test() {
+        // nothing but the header says how long a lone symbol's payload is
+        let mut crafted = Vec::new();
+        Header {
+            checksum: false,
+            payload: Payload::Static {
+                original_len: 1 << 44,
+                freqs: FrequencyMap::from("a"),
+                payload_len: 0,
+            },
+            extensions: Extensions::new(),
+        }
+        .write_to(&mut crafted)
+        .unwrap();
+        assert!(matches!(
+            decompress_bytes(&crafted),
+            Err(Error::OutputLimitExceeded {
+                limit: LONE_SYMBOL_LIMIT
+            })
+        ));
+        let limited = DecodeOptions {
+            max_output_bytes: Some(1000),
+            ..DecodeOptions::default()
+        };
+        assert!(matches!(
+            decompress_with(&crafted[..], Vec::new(), &limited),
+            Err(Error::OutputLimitExceeded { limit: 1000 })
+        ));
+        // so compressing a longer run than that splits it up, however it's compressed
+        let input = vec![0u8; LONE_SYMBOL_LIMIT as usize + 5];
+        let delta: Pipeline = "delta8".parse().unwrap();
+        for pipeline in [Pipeline::new(), delta] {
+            let options = EncodeOptions {
+                pipeline,
+                ..EncodeOptions::default()
+            };
+            let compressed = compress_bytes(&input, &options).unwrap();
+            let blocks = split_points(Cursor::new(&compressed)).unwrap();
+            assert_eq!(blocks.len(), 2);
+            assert_eq!(blocks[1].original_len, 5);
+            assert_eq!(
+                estimate(&input[..], &options).unwrap(),
+                (input.len() as u64, compressed.len() as u64)
+            );
+            assert_eq!(decompress_bytes(&compressed).unwrap(), input);
+        }
+        let read = compress(Cursor::new(&input), Vec::new(), &EncodeOptions::default()).unwrap();
+        assert_eq!(split_points(Cursor::new(&read)).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn dictionary_test() {
+        let dict = Arc::new(Dictionary::train(vec![&b"dagoth ur was a hotep"[..]]).unwrap());
+        let input = b"a hotep was dagoth, and then some \xff";
+        for &block_size in &[None, Some(10)] {
+            let options = EncodeOptions {
+                dictionary: Some(dict.clone()),
+                block_size,
+                ..EncodeOptions::default()
+            };
+            let compressed = compress_bytes(input, &options).unwrap();
+            let decode = |dictionary| {
+                let options = DecodeOptions {
+                    dictionary,
+                    ..DecodeOptions::default()
+                };
+                decompress_with(&compressed[..], Vec::new(), &options)
+            };
+            assert_eq!(decode(Some(dict.clone())).unwrap(), input);
+            assert_eq!(
+                split_points(Cursor::new(&compressed)).unwrap().len(),
+                input.len().div_ceil(block_size.unwrap_or(input.len()))
+            );
+            assert!(matches!(
+                decode(None),
+                Err(Error::DictionaryMismatch { found: None, .. })
+            ));
+            let other = Arc::new(Dictionary::train(vec![&b"something else"[..]]).unwrap());
+            assert!(matches!(
+                decode(Some(other)),
+                Err(Error::DictionaryMismatch { found: Some(_), .. })
+            ));
+        }
+        // without the frequencies, the header's just its fixed fields
+        let options = EncodeOptions {
+            dictionary: Some(dict),
+            ..EncodeOptions::default()
+        };
+        let compressed = compress_bytes(b"a hotep", &options).unwrap();
+        let plain = compress_bytes(b"a hotep", &EncodeOptions::default()).unwrap();
+        assert!(compressed.len() < plain.len());
+    }
+
+    #[test]
+    fn verify_test() {
+        let mut compressed =
+            compress_bytes(b"dagoth ur was a hotep", &EncodeOptions::default()).unwrap();
+        let options = DecodeOptions::default();
+        assert_eq!(verify(&compressed[..], &options).unwrap(), 21);
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        assert!(matches!(
+            verify(&compressed[..], &options),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+        compressed[5] = 0xff;
+        assert!(matches!(
+            verify(&compressed[..], &options),
+            Err(Error::BadHeader(_))
+        ));
+    }
+}
@@ -0,0 +1,348 @@
+//! Order-1 context modelling: a separate set of codes for every context, picked by the byte before
+//!
+//! Plain Huffman coding looks at each byte on its own, but in most data what comes next depends a lot on what came
+//! just before: in English, a 'q' is nearly always followed by a 'u', and a space by the start of a word. Giving
+//! each previous byte its own codes (`Contexts::PreviousByte`) lets every code fit just the bytes that follow it,
+//! which usually takes text a good way below what one set of codes can do. The catch is the header, which has to
+//! hold a set of code lengths for every context that shows up, so small inputs can come out bigger than they would
+//! with one tree. `Contexts::Class` only tells apart a handful of kinds of previous byte (lowercase letters,
+//! uppercase letters, digits, whitespace, punctuation and everything else), for much smaller headers but less of a
+//! gain.
+//!
+//! The codes are canonical (see the `canonical` module), so only their lengths go in the header:
+//!
+//! ```text
+//! contexts    1 byte    0: one per previous byte, 1: one per class of previous byte
+//! length      8 bytes   original length in bytes (little-endian, as is everything else)
+//! tables      2 bytes   how many contexts have codes
+//! -- for each context with codes, in order --
+//! context     1 byte    which context it is
+//! symbols     2 bytes   how many (symbol, length) entries follow
+//! entries     2 bytes each: the byte value, then the length of its code in this context
+//! -- then --
+//! payload     the packed codes, most significant bit first, padded out to a whole byte
+//! ```
+//!
+//! The first byte has no byte before it, so it gets coded as if it came after a 0. There's no checksum; put the
+//! output in something that has one if it's going anywhere unreliable.
+//!
+//! A context that only ever has the one byte after it gives that byte an empty code, so a run of them takes no
+//! payload at all, and a tiny input can honestly decode to gigabytes. Anything decoding untrusted input should use
+//! `decompress_with_limit()`, which checks the header's length before decoding anything.
+
+use crate::bits::{BitWindow, BitWriter, ByteCodes};
+use crate::canonical::{canonical_codes, code_lengths, limit_lengths};
+use crate::container::{check_lengths, MAX_CODE_LEN};
+use crate::error::{Error, Result};
+use crate::table::DecodeTable;
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+/// What picks the codes for each byte
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Contexts {
+    /// The whole previous byte, for up to 256 sets of codes
+    #[default]
+    PreviousByte,
+    /// Just what kind of byte the previous one was, for up to 6 sets of codes
+    Class,
+}
+
+impl Contexts {
+    /// How many contexts there are
+    fn count(self) -> usize {
+        match self {
+            Contexts::PreviousByte => 256,
+            Contexts::Class => 6,
+        }
+    }
+
+    /// Which context a byte gets coded in
+    ///
+    /// ## Arguments
+    ///
+    /// * `prev`: the byte before it
+    fn of(self, prev: u8) -> usize {
+        match self {
+            Contexts::PreviousByte => prev as usize,
+            Contexts::Class => match prev {
+                b'a'..=b'z' => 0,
+                b'A'..=b'Z' => 1,
+                b'0'..=b'9' => 2,
+                _ if prev.is_ascii_whitespace() => 3,
+                _ if prev.is_ascii_punctuation() => 4,
+                _ => 5,
+            },
+        }
+    }
+
+    /// The byte the header stores it as
+    fn to_byte(self) -> u8 {
+        match self {
+            Contexts::PreviousByte => 0,
+            Contexts::Class => 1,
+        }
+    }
+
+    /// Reads it back out of the header
+    ///
+    /// ## Arguments
+    ///
+    /// * `byte`: the byte the header stores it as
+    fn from_byte(byte: u8) -> Result<Self> {
+        match byte {
+            0 => Ok(Contexts::PreviousByte),
+            1 => Ok(Contexts::Class),
+            _ => Err(Error::BadHeader("unknown kind of context")),
+        }
+    }
+}
+
+/// Compresses a whole byte slice with a set of codes for every previous byte
+///
+/// ## Arguments
+///
+/// * `data`: the bytes to compress
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    compress_with(data, Contexts::default())
+}
+
+/// Compresses a whole byte slice with a set of codes for every context of a given kind
+///
+/// ## Arguments
+///
+/// * `data`: the bytes to compress
+/// * `contexts`: what picks the codes for each byte
+pub fn compress_with(data: &[u8], contexts: Contexts) -> Vec<u8> {
+    // first pass: how often each byte comes up in each context
+    let mut counts = vec![[0u64; 256]; contexts.count()];
+    let mut prev = 0;
+    for &byte in data {
+        counts[contexts.of(prev)][byte as usize] += 1;
+        prev = byte;
+    }
+    let mut out = Vec::with_capacity(data.len() / 2);
+    out.push(contexts.to_byte());
+    out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    let used = counts.iter().filter(|counts| counts.iter().any(|&n| n > 0));
+    out.extend_from_slice(&(used.count() as u16).to_le_bytes());
+    let mut codes: Vec<Option<ByteCodes>> = Vec::with_capacity(counts.len());
+    for (context, counts) in counts.iter().enumerate() {
+        let lengths = lengths_for(counts);
+        if lengths.is_empty() {
+            codes.push(None);
+            continue;
+        }
+        out.push(context as u8);
+        out.extend_from_slice(&(lengths.len() as u16).to_le_bytes());
+        for &(byte, len) in &lengths {
+            out.extend_from_slice(&[byte, len]);
+        }
+        codes.push(Some(ByteCodes::new(&codes_for(&lengths))));
+    }
+    // second pass: the codes themselves
+    let mut bits = BitWriter::new();
+    let mut prev = 0;
+    for &byte in data {
+        let codes = codes[contexts.of(prev)].as_ref();
+        let (code, len) = codes.and_then(|codes| codes.get(byte)).unwrap();
+        bits.push_bits(code, len, &mut out);
+        prev = byte;
+    }
+    bits.align(&mut out);
+    out
+}
+
+/// Decompresses what `compress()` or `compress_with()` made, however big the header says it is
+///
+/// ## Arguments
+///
+/// * `data`: the compressed bytes
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    decompress_with_limit(data, u64::MAX)
+}
+
+/// Decompresses what `compress()` or `compress_with()` made, failing with `Error::OutputLimitExceeded` (before
+/// decoding anything) if the header says it's more than a given number of bytes
+///
+/// ## Arguments
+///
+/// * `data`: the compressed bytes
+/// * `max_output_bytes`: the most decompressed bytes to allow
+pub fn decompress_with_limit(data: &[u8], max_output_bytes: u64) -> Result<Vec<u8>> {
+    let mut input = data;
+    let contexts = Contexts::from_byte(take::<1>(&mut input)?[0])?;
+    let original_len = u64::from_le_bytes(take(&mut input)?);
+    if original_len > max_output_bytes {
+        return Err(Error::OutputLimitExceeded {
+            limit: max_output_bytes,
+        });
+    }
+    let tables = u16::from_le_bytes(take(&mut input)?);
+    let mut decoders: Vec<Option<DecodeTable>> = (0..contexts.count()).map(|_| None).collect();
+    let mut last = None;
+    for _ in 0..tables {
+        let context = take::<1>(&mut input)?[0] as usize;
+        if context >= decoders.len() || last.is_some_and(|last| context <= last) {
+            return Err(Error::BadHeader("contexts out of range or out of order"));
+        }
+        last = Some(context);
+        let symbols = u16::from_le_bytes(take(&mut input)?);
+        if symbols == 0 || symbols > 256 {
+            return Err(Error::BadHeader("symbol count out of range"));
+        }
+        let lengths = (0..symbols)
+            .map(|_| take::<2>(&mut input).map(|[byte, len]| (byte, len)))
+            .collect::<Result<Vec<(u8, u8)>>>()?;
+        check_lengths(&lengths)?;
+        decoders[context] = Some(DecodeTable::new(&codes_for(&lengths)));
+    }
+    let mut out = Vec::with_capacity(original_len.min(data.len() as u64 * 8) as usize);
+    let mut bits = BitWindow::new(input, input.len() as u64);
+    let mut prev = 0;
+    for _ in 0..original_len {
+        let table = decoders[contexts.of(prev)]
+            .as_ref()
+            .ok_or(Error::Corrupt("byte comes after one with no codes"))?;
+        bits.refill()?;
+        let (ch, len) = table
+            .lookup(bits.peek())
+            .ok_or(Error::Corrupt("bits don't match any code"))?;
+        if !bits.consume(len) {
+            return Err(Error::Corrupt("payload is truncated"));
+        }
+        prev = ch as u8;
+        out.push(prev);
+    }
+    // the rest of the last byte is padding
+    if !bits.is_finished() {
+        return Err(Error::Corrupt("payload length doesn't match the header"));
+    }
+    Ok(out)
+}
+
+/// Works out the code lengths for one context, in byte order, leaving out bytes that never come up in it
+///
+/// ## Arguments
+///
+/// * `counts`: how often each byte comes up in the context
+fn lengths_for(counts: &[u64; 256]) -> Vec<(u8, u8)> {
+    let mut symbols: Vec<(u8, u64)> = (0..=255u8)
+        .zip(counts.iter().copied())
+        .filter(|&(_, n)| n > 0)
+        .collect();
+    symbols.sort_unstable_by_key(|&(byte, n)| (n, byte));
+    let mut lengths: Vec<u64> = symbols.iter().map(|&(_, n)| n).collect();
+    code_lengths(&mut lengths);
+    limit_lengths(&mut lengths, MAX_CODE_LEN as u64);
+    let mut lengths: Vec<(u8, u8)> = symbols
+        .iter()
+        .zip(lengths)
+        .map(|(&(byte, _), len)| (byte, len as u8))
+        .collect();
+    lengths.sort_unstable();
+    lengths
+}
+
+/// Fills in the canonical codes for one context's code lengths
+///
+/// ## Arguments
+///
+/// * `lengths`: the byte values and their code lengths
+fn codes_for(lengths: &[(u8, u8)]) -> HashMap<char, String> {
+    let lengths: Vec<(char, u64)> = lengths
+        .iter()
+        .map(|&(byte, len)| (byte as char, len as u64))
+        .collect();
+    canonical_codes(&lengths)
+}
+
+/// Takes the next `N` bytes off the front of the input
+///
+/// ## Arguments
+///
+/// * `input`: what's left of the input
+fn take<const N: usize>(input: &mut &[u8]) -> Result<[u8; N]> {
+    if input.len() < N {
+        return Err(Error::BadHeader("header is truncated"));
+    }
+    let (bytes, rest) = input.split_at(N);
+    *input = rest;
+    Ok(bytes.try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip_test() {
+        let text = "whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune, \
+                    or to take arms against a sea of troubles, and by opposing end them"
+            .repeat(50);
+        let random: Vec<u8> = (0..20_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        for contexts in [Contexts::PreviousByte, Contexts::Class] {
+            for data in [
+                &b""[..],
+                b"a",
+                b"aaaa",
+                b"abababab",
+                text.as_bytes(),
+                &random,
+            ] {
+                let compressed = compress_with(data, contexts);
+                assert_eq!(decompress(&compressed).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn ratio_test() {
+        let text = "whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune, \
+                    or to take arms against a sea of troubles, and by opposing end them"
+            .repeat(50);
+        let plain = crate::container::compress_bytes(text.as_bytes(), &Default::default()).unwrap();
+        let by_byte = compress(text.as_bytes());
+        let by_class = compress_with(text.as_bytes(), Contexts::Class);
+        // the previous byte says a lot about English, and its class still says something
+        assert!(by_byte.len() < plain.len() * 2 / 3);
+        assert!(by_class.len() < plain.len());
+        // but with hardly anything to code, the tables are most of it
+        let short = b"dagoth ur was a hotep";
+        assert!(compress(short).len() > compress_with(short, Contexts::Class).len());
+    }
+
+    #[test]
+    fn corrupt_test() {
+        let data = b"dagoth ur was a hotep".repeat(10);
+        let compressed = compress(&data);
+        assert!(matches!(
+            decompress(&compressed[..5]),
+            Err(Error::BadHeader(_))
+        ));
+        assert!(matches!(
+            decompress(&compressed[..compressed.len() - 1]),
+            Err(Error::Corrupt(_))
+        ));
+        let mut unknown = compressed.clone();
+        unknown[0] = 2;
+        assert!(matches!(decompress(&unknown), Err(Error::BadHeader(_))));
+        let mut longer = compressed;
+        longer.push(0);
+        assert!(matches!(decompress(&longer), Err(Error::Corrupt(_))));
+        // every context here has just the one byte after it, so any length at all decodes from no payload
+        let mut bomb = compress(b"aaaaaaaa");
+        bomb[1..9].copy_from_slice(&(1u64 << 36).to_le_bytes());
+        assert!(matches!(
+            decompress_with_limit(&bomb, 1 << 20),
+            Err(Error::OutputLimitExceeded { limit }) if limit == 1 << 20
+        ));
+        bomb[1..9].copy_from_slice(&1000u64.to_le_bytes());
+        assert_eq!(
+            decompress_with_limit(&bomb, 1000).unwrap(),
+            vec![b'a'; 1000]
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! Reading code tables saved by Python's `dahuffman` (see `Codebook::from_dahuffman_json()`)
+//!
+//! Only the one shape of JSON those tables come in gets understood, an object of symbols to `[bits, value]` pairs, so
+//! this is a lot less than a JSON parser, and doesn't need one as a dependency.
+
+use crate::error::{CodeTableError, Result};
+
+/// What dahuffman's end-of-file symbol gets dumped as
+const EOF_SYMBOL: &str = "_EOF";
+
+/// Reads every symbol and its code out of a saved table, leaving out the end-of-file symbol
+///
+/// ## Arguments
+///
+/// * `json`: the saved table
+pub(crate) fn parse(json: &str) -> Result<Vec<(char, String)>> {
+    let mut parser = Parser { json, pos: 0 };
+    let mut pairs = Vec::new();
+    parser.expect('{')?;
+    if !parser.eat('}') {
+        loop {
+            let symbol = parser.string()?;
+            parser.expect(':')?;
+            parser.expect('[')?;
+            let bits = parser.number()?;
+            parser.expect(',')?;
+            let value = parser.number()?;
+            parser.expect(']')?;
+            if bits > 64 || (bits < 64 && value >> bits != 0) {
+                return Err(malformed("a code doesn't fit its length"));
+            }
+            if symbol != EOF_SYMBOL {
+                let mut chars = symbol.chars();
+                let ch = match (chars.next(), chars.next()) {
+                    (Some(ch), None) => ch,
+                    _ => return Err(malformed("symbols have to be single characters")),
+                };
+                let code = (0..bits)
+                    .rev()
+                    .map(|bit| if value >> bit & 1 == 1 { '1' } else { '0' })
+                    .collect();
+                pairs.push((ch, code));
+            }
+            if parser.eat('}') {
+                break;
+            }
+            parser.expect(',')?;
+        }
+    }
+    parser.skip_whitespace();
+    if parser.pos != json.len() {
+        return Err(malformed("there's more after the table"));
+    }
+    Ok(pairs)
+}
+
+/// Wraps up what's wrong with the JSON as an error
+fn malformed(why: &'static str) -> crate::error::Error {
+    CodeTableError::Malformed(why).into()
+}
+
+/// Where reading the JSON is up to
+struct Parser<'a> {
+    /// The JSON
+    json: &'a str,
+    /// How far into it (in bytes) reading has gotten
+    pos: usize,
+}
+
+impl Parser<'_> {
+    /// What's left to read
+    fn rest(&self) -> &str {
+        &self.json[self.pos..]
+    }
+
+    /// Reads past any whitespace
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    /// Reads past a char (after any whitespace) if it's next, saying whether it was
+    fn eat(&mut self, ch: char) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(ch) {
+            self.pos += ch.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Reads past a char (after any whitespace) that has to be next
+    fn expect(&mut self, ch: char) -> Result<()> {
+        if self.eat(ch) {
+            Ok(())
+        } else {
+            Err(malformed("not a dahuffman code table"))
+        }
+    }
+
+    /// Reads a non-negative whole number
+    fn number(&mut self) -> Result<u64> {
+        self.skip_whitespace();
+        let digits = self.rest().len()
+            - self
+                .rest()
+                .trim_start_matches(|c: char| c.is_ascii_digit())
+                .len();
+        let number = self.rest()[..digits].parse().map_err(|_| {
+            malformed("code lengths and values have to be whole numbers that fit in 64 bits")
+        })?;
+        self.pos += digits;
+        Ok(number)
+    }
+
+    /// Reads a string, escapes and all
+    fn string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut out = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, ch)) = chars.next() {
+            match ch {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(out);
+                }
+                '\\' => {
+                    let escaped = match chars.next().map(|(_, ch)| ch) {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            let high = hex4(&mut chars)?;
+                            // chars outside the BMP come as a surrogate pair, i.e. two escapes in a row
+                            let code = if (0xd800..0xdc00).contains(&high) {
+                                match (chars.next(), chars.next()) {
+                                    (Some((_, '\\')), Some((_, 'u'))) => {}
+                                    _ => {
+                                        return Err(malformed("a string has half a surrogate pair"))
+                                    }
+                                }
+                                let low = hex4(&mut chars)?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(malformed("a string has half a surrogate pair"));
+                                }
+                                0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                            } else {
+                                high
+                            };
+                            char::from_u32(code)
+                                .ok_or_else(|| malformed("a string has half a surrogate pair"))?
+                        }
+                        _ => return Err(malformed("a string has a bad escape in it")),
+                    };
+                    out.push(escaped);
+                }
+                ch if (ch as u32) < 0x20 => {
+                    return Err(malformed("a string has a control character in it"))
+                }
+                ch => out.push(ch),
+            }
+        }
+        Err(malformed("a string never ends"))
+    }
+}
+
+/// Reads the four hex digits of a `\u` escape
+///
+/// ## Arguments
+///
+/// * `chars`: the string being read, just after the `\u`
+fn hex4(chars: &mut std::str::CharIndices<'_>) -> Result<u32> {
+    let mut code = 0;
+    for _ in 0..4 {
+        let digit = chars
+            .next()
+            .and_then(|(_, ch)| ch.to_digit(16))
+            .ok_or_else(|| malformed("a string has a bad escape in it"))?;
+        code = code * 16 + digit;
+    }
+    Ok(code)
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+    use crate::error::{CodeTableError, Error};
+
+    #[test]
+    fn parse_test() {
+        assert_eq!(
+            parse(
+                "{\"a\": [1, 0], \"_EOF\": [3, 6], \"\\n\": [3,7],\n \"\\ud83d\\ude00\": [2, 2]}"
+            )
+            .unwrap(),
+            vec![
+                ('a', "0".to_string()),
+                ('\n', "111".to_string()),
+                ('\u{1f600}', "10".to_string())
+            ]
+        );
+        assert_eq!(parse(" {} ").unwrap(), vec![]);
+        for bad in [
+            "",
+            "[]",
+            "{\"a\": [1, 0]",
+            "{\"a\": [1, 0]} {}",
+            "{\"ab\": [1, 0]}",
+            "{\"a\": [1, 2]}",
+            "{\"a\": [65, 0]}",
+            "{\"a\": [1, -1]}",
+            "{\"a\": \"0\"}",
+            "{\"\\ud83d\": [1, 0]}",
+            "{\"\\x\": [1, 0]}",
+        ] {
+            assert!(
+                matches!(
+                    parse(bad),
+                    Err(Error::BadCodeTable(CodeTableError::Malformed(_)))
+                ),
+                "{:?}",
+                bad
+            );
+        }
+    }
+}
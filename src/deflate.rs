@@ -0,0 +1,496 @@
+//! Reading and writing DEFLATE (RFC 1951) dynamic-Huffman blocks that hold nothing but literals, for handing
+//! Huffman-coded data to (or taking it from) anything that speaks zlib, gzip or zip
+//!
+//! A dynamic block is a canonical Huffman code over the byte values (plus an end-of-block marker, and the LZ77 match
+//! lengths this crate never uses), with no code longer than 15 bits, followed by the coded data. The code's lengths
+//! are stored run-length coded, and coded in turn with a second canonical code of at most 7 bits, whose own lengths
+//! come first. With no matches in it, a block is just an ordinary Huffman coding of its bytes, which is what zlib
+//! makes with its `Z_HUFFMAN_ONLY` strategy.
+//!
+//! Bits get packed least significant first, except for the codes themselves, which go in first bit first.
+
+use crate::canonical::{canonical_codes, code_lengths, limit_lengths};
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+
+/// The longest a literal's code can be
+const MAX_CODE_LEN: u64 = 15;
+/// The longest a code-length code can be
+const MAX_CODE_LEN_CODE_LEN: u64 = 7;
+/// The end-of-block marker, right after the byte values
+const END_OF_BLOCK: usize = 256;
+/// The most bytes a stored block can hold
+pub(crate) const MAX_STORED_LEN: usize = 65535;
+/// How many literal/length codes a block can have lengths for
+const MAX_LITERAL_CODES: usize = 286;
+/// How many distance codes a block can have lengths for
+const MAX_DISTANCE_CODES: usize = 30;
+/// The order the code-length code's lengths get stored in (rarest last, so they can be left off)
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Compresses some bytes into a single, final DEFLATE dynamic-Huffman block, with no LZ77 matches
+///
+/// The block is a complete raw DEFLATE stream on its own (padded out to a whole byte), so zlib's `inflate()` (with
+/// negative window bits), or anything else that reads DEFLATE, can decompress it.
+///
+/// ## Arguments
+///
+/// * `data`: the bytes to compress
+pub fn encode_literal_block(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    write_dynamic_block(&mut bits, data, true);
+    bits.finish()
+}
+
+/// Writes a block of (at most `MAX_STORED_LEN`) bytes as whichever of a stored block, a fixed-Huffman block or a
+/// literal-only dynamic-Huffman block comes out smallest
+///
+/// ## Arguments
+///
+/// * `bits`: where the block goes
+/// * `data`: the bytes
+/// * `last`: whether this is the stream's last block
+pub(crate) fn write_block(bits: &mut BitWriter, data: &[u8], last: bool) {
+    let mut dynamic = BitWriter::default();
+    write_dynamic_block(&mut dynamic, data, last);
+    let dynamic_len = dynamic.bit_len();
+    let fixed_len = 3
+        + data
+            .iter()
+            .map(|&byte| fixed_length(byte as usize))
+            .sum::<u64>()
+        + 7;
+    // a stored block's header gets padded out to the next byte, and then there's its length (and that again, inverted)
+    let stored_len = 3 + (8 - (bits.bit_len() + 3) % 8) % 8 + 32 + 8 * data.len() as u64;
+    if stored_len <= fixed_len.min(dynamic_len) {
+        write_stored_block(bits, data, last);
+    } else if fixed_len <= dynamic_len {
+        write_fixed_block(bits, data, last);
+    } else {
+        bits.append(dynamic);
+    }
+}
+
+/// Writes a block of (at most `MAX_STORED_LEN`) bytes just as they are
+fn write_stored_block(bits: &mut BitWriter, data: &[u8], last: bool) {
+    bits.push(last as u32, 1);
+    bits.push(0, 2);
+    bits.align();
+    bits.push(data.len() as u32, 16);
+    bits.push(!data.len() as u32 & 0xffff, 16);
+    for &byte in data {
+        bits.push(byte as u32, 8);
+    }
+}
+
+/// Writes a block coded with DEFLATE's own fixed codes, which cost nothing to store but only really suit text
+fn write_fixed_block(bits: &mut BitWriter, data: &[u8], last: bool) {
+    let lengths: Vec<u64> = (0..288).map(fixed_length).collect();
+    let codes = codes_for(&lengths);
+    bits.push(last as u32, 1);
+    bits.push(1, 2);
+    for &byte in data {
+        bits.push_code(&codes[&char::from(byte)]);
+    }
+    bits.push_code(&codes[&symbol_char(END_OF_BLOCK)]);
+}
+
+/// How long a symbol's fixed code is
+fn fixed_length(symbol: usize) -> u64 {
+    match symbol {
+        0..=143 => 8,
+        144..=255 => 9,
+        256..=279 => 7,
+        _ => 8,
+    }
+}
+
+/// Writes a dynamic-Huffman block with nothing but literals in it, coded with the best codes for them
+fn write_dynamic_block(bits: &mut BitWriter, data: &[u8], last: bool) {
+    let mut freqs = [0u64; END_OF_BLOCK + 1];
+    for &byte in data {
+        freqs[byte as usize] += 1;
+    }
+    freqs[END_OF_BLOCK] = 1;
+    let literal_lengths = limited_lengths(&freqs, MAX_CODE_LEN);
+    let literal_codes = codes_for(&literal_lengths);
+    // a single distance code with no bits means there are no distances at all
+    let mut all_lengths = literal_lengths;
+    all_lengths.push(0);
+    let runs = run_lengths(&all_lengths);
+
+    let mut code_length_freqs = [0u64; 19];
+    for &(symbol, _) in &runs {
+        code_length_freqs[symbol as usize] += 1;
+    }
+    let code_length_lengths = limited_lengths(&code_length_freqs, MAX_CODE_LEN_CODE_LEN);
+    let code_length_codes = codes_for(&code_length_lengths);
+    // trailing lengths of 0 can be left off, so long as there are at least 4
+    let stored_lengths = CODE_LENGTH_ORDER
+        .iter()
+        .rposition(|&symbol| code_length_lengths[symbol] != 0)
+        .map_or(4, |last| (last + 1).max(4));
+
+    bits.push(last as u32, 1);
+    bits.push(2, 2);
+    bits.push((END_OF_BLOCK + 1 - 257) as u32, 5);
+    bits.push(0, 5);
+    bits.push((stored_lengths - 4) as u32, 4);
+    for &symbol in &CODE_LENGTH_ORDER[..stored_lengths] {
+        bits.push(code_length_lengths[symbol] as u32, 3);
+    }
+    for &(symbol, extra) in &runs {
+        bits.push_code(&code_length_codes[&char::from(symbol)]);
+        match symbol {
+            16 => bits.push(extra as u32, 2),
+            17 => bits.push(extra as u32, 3),
+            18 => bits.push(extra as u32, 7),
+            _ => (),
+        }
+    }
+    for &byte in data {
+        bits.push_code(&literal_codes[&char::from(byte)]);
+    }
+    bits.push_code(&literal_codes[&symbol_char(END_OF_BLOCK)]);
+}
+
+/// Decompresses a single, final DEFLATE dynamic-Huffman block, like `encode_literal_block()` (or zlib's
+/// `Z_HUFFMAN_ONLY`) makes
+///
+/// Blocks with LZ77 matches in them, stored or fixed-Huffman blocks, and streams with more than one block fail with
+/// `Error::Unsupported`; anything that isn't valid DEFLATE fails with `Error::Corrupt`. Whatever comes after the
+/// block is ignored.
+///
+/// ## Arguments
+///
+/// * `block`: the raw DEFLATE stream
+pub fn decode_literal_block(block: &[u8]) -> Result<Vec<u8>> {
+    let mut bits = BitReader::new(block);
+    if bits.take(1)? != 1 {
+        return Err(Error::Unsupported("more than one DEFLATE block"));
+    }
+    if bits.take(2)? != 2 {
+        return Err(Error::Unsupported("not a dynamic-Huffman DEFLATE block"));
+    }
+    let literal_count = bits.take(5)? as usize + 257;
+    let distance_count = bits.take(5)? as usize + 1;
+    let stored_lengths = bits.take(4)? as usize + 4;
+    if literal_count > MAX_LITERAL_CODES || distance_count > MAX_DISTANCE_CODES {
+        return Err(Error::Corrupt("too many codes in DEFLATE block"));
+    }
+    let mut code_length_lengths = [0u64; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..stored_lengths] {
+        code_length_lengths[symbol] = bits.take(3)? as u64;
+    }
+    let code_length_code = CanonicalDecoder::new(&code_length_lengths)?;
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (len, repeat) = match code_length_code.decode(&mut bits)? {
+            len @ 0..=15 => (len as u64, 1),
+            16 => {
+                let previous = *lengths.last().ok_or(Error::Corrupt(
+                    "DEFLATE block repeats a code length before there is one",
+                ))?;
+                (previous, 3 + bits.take(2)?)
+            }
+            17 => (0, 3 + bits.take(3)?),
+            _ => (0, 11 + bits.take(7)?),
+        };
+        if lengths.len() + repeat as usize > literal_count + distance_count {
+            return Err(Error::Corrupt("DEFLATE block has too many code lengths"));
+        }
+        lengths.extend(std::iter::repeat_n(len, repeat as usize));
+    }
+    if lengths[END_OF_BLOCK] == 0 {
+        return Err(Error::Corrupt("DEFLATE block has no end-of-block code"));
+    }
+    let literal_code = CanonicalDecoder::new(&lengths[..literal_count])?;
+
+    let mut data = Vec::new();
+    loop {
+        match literal_code.decode(&mut bits)? {
+            END_OF_BLOCK => return Ok(data),
+            byte @ 0..=255 => data.push(byte as u8),
+            _ => return Err(Error::Unsupported("DEFLATE block has LZ77 matches")),
+        }
+    }
+}
+
+/// Works out code lengths of no more than `max_len` bits for every symbol with a nonzero frequency (the rest get 0)
+fn limited_lengths(freqs: &[u64], max_len: u64) -> Vec<u64> {
+    let mut symbols: Vec<usize> = (0..freqs.len())
+        .filter(|&symbol| freqs[symbol] > 0)
+        .collect();
+    // DEFLATE has no codes without any bits, so a lone symbol gets a partner, which just never gets used
+    if symbols.len() == 1 {
+        symbols.push(if symbols[0] == 0 { 1 } else { 0 });
+    }
+    symbols.sort_unstable_by_key(|&symbol| (freqs[symbol], symbol));
+    let mut symbol_lengths: Vec<u64> = symbols.iter().map(|&symbol| freqs[symbol]).collect();
+    code_lengths(&mut symbol_lengths);
+    limit_lengths(&mut symbol_lengths, max_len);
+    let mut lengths = vec![0; freqs.len()];
+    for (symbol, len) in symbols.into_iter().zip(symbol_lengths) {
+        lengths[symbol] = len;
+    }
+    lengths
+}
+
+/// The canonical codes for every symbol with a nonzero length, with symbols stood in for by chars
+fn codes_for(lengths: &[u64]) -> HashMap<char, String> {
+    let lengths: Vec<(char, u64)> = lengths
+        .iter()
+        .enumerate()
+        .filter(|&(_, &len)| len > 0)
+        .map(|(symbol, &len)| (symbol_char(symbol), len))
+        .collect();
+    canonical_codes(&lengths)
+}
+
+/// The char a symbol gets stood in for by (every DEFLATE symbol is well under the surrogates)
+fn symbol_char(symbol: usize) -> char {
+    char::from_u32(symbol as u32).expect("DEFLATE symbols are all valid chars")
+}
+
+/// Run-length codes a list of code lengths, as code-length symbols and their extra bits
+///
+/// Symbols 0 through 15 are lengths, 16 repeats the last length 3-6 times, 17 repeats a 0 3-10 times, and 18
+/// repeats a 0 11-138 times.
+fn run_lengths(lengths: &[u64]) -> Vec<(u8, u8)> {
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let len = lengths[i];
+        let run = lengths[i..]
+            .iter()
+            .take_while(|&&other| other == len)
+            .count();
+        if len == 0 && run >= 11 {
+            let run = run.min(138);
+            runs.push((18, (run - 11) as u8));
+            i += run;
+        } else if len == 0 && run >= 3 {
+            runs.push((17, (run - 3) as u8));
+            i += run;
+        } else if run >= 4 {
+            let repeats = (run - 1).min(6);
+            runs.push((len as u8, 0));
+            runs.push((16, (repeats - 3) as u8));
+            i += 1 + repeats;
+        } else {
+            runs.push((len as u8, 0));
+            i += 1;
+        }
+    }
+    runs
+}
+
+/// Packs bits into bytes, least significant bit first, the way DEFLATE does
+#[derive(Default)]
+pub(crate) struct BitWriter {
+    /// The bytes so far
+    out: Vec<u8>,
+    /// Bits that haven't made up a whole byte yet, starting from the least significant
+    pending: u32,
+    /// How many bits are pending
+    count: u32,
+}
+
+impl BitWriter {
+    /// Pushes the low `len` bits of `value`, least significant first
+    pub(crate) fn push(&mut self, value: u32, len: u32) {
+        self.pending |= value << self.count;
+        self.count += len;
+        while self.count >= 8 {
+            self.out.push(self.pending as u8);
+            self.pending >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Pushes a code, as '0'/'1's, first bit first
+    fn push_code(&mut self, code: &str) {
+        for bit in code.bytes() {
+            self.push((bit == b'1') as u32, 1);
+        }
+    }
+
+    /// Pads out the byte in progress with 0s
+    pub(crate) fn align(&mut self) {
+        if self.count > 0 {
+            self.push(0, 8 - self.count);
+        }
+    }
+
+    /// How many bits have been pushed
+    fn bit_len(&self) -> u64 {
+        self.out.len() as u64 * 8 + self.count as u64
+    }
+
+    /// Pushes everything another writer has had pushed
+    fn append(&mut self, other: BitWriter) {
+        for byte in other.out {
+            self.push(byte as u32, 8);
+        }
+        self.push(other.pending, other.count);
+    }
+
+    /// Takes the whole bytes so far, leaving any bits of the byte in progress
+    pub(crate) fn take_bytes(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.out)
+    }
+
+    /// Pads out the last byte with 0s and hands back the bytes
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.out
+    }
+}
+
+/// Reads bits out of bytes, least significant bit first
+struct BitReader<'a> {
+    /// The bytes
+    bytes: &'a [u8],
+    /// Which bit's next, counting from the least significant bit of the first byte
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        BitReader { bytes, pos: 0 }
+    }
+
+    /// Takes the next `len` bits, as a number whose least significant bit came first
+    fn take(&mut self, len: u32) -> Result<u32> {
+        let mut value = 0;
+        for i in 0..len {
+            let byte = self
+                .bytes
+                .get(self.pos / 8)
+                .ok_or(Error::Corrupt("DEFLATE block is truncated"))?;
+            value |= ((byte >> (self.pos % 8)) as u32 & 1) << i;
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+}
+
+/// Decodes a canonical code from just its lengths, a bit at a time
+struct CanonicalDecoder {
+    /// How many codes there are of each length
+    counts: [u16; MAX_CODE_LEN as usize + 1],
+    /// The symbols that have codes, shortest code first (and in symbol order within a length)
+    symbols: Vec<usize>,
+}
+
+impl CanonicalDecoder {
+    /// Sets up the decoder, failing if the lengths take up more room than there is (an incomplete code is fine, as
+    /// long as nothing ever uses the room it leaves)
+    fn new(lengths: &[u64]) -> Result<Self> {
+        let mut counts = [0; MAX_CODE_LEN as usize + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        let mut left: i64 = 1;
+        for &count in &counts[1..] {
+            left = left * 2 - count as i64;
+            if left < 0 {
+                return Err(Error::Corrupt("DEFLATE block's code lengths are over-full"));
+            }
+        }
+        let mut symbols: Vec<usize> = (0..lengths.len())
+            .filter(|&symbol| lengths[symbol] > 0)
+            .collect();
+        symbols.sort_by_key(|&symbol| lengths[symbol]);
+        Ok(CanonicalDecoder { counts, symbols })
+    }
+
+    /// Decodes the next symbol
+    fn decode(&self, bits: &mut BitReader) -> Result<usize> {
+        // the code read so far, the first code of its length, and where that code's symbol is
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for &count in &self.counts[1..] {
+            code |= bits.take(1)? as usize;
+            let count = count as usize;
+            if code < first + count {
+                return Ok(self.symbols[index + code - first]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::Corrupt("bits don't match any code in DEFLATE block"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_literal_block, encode_literal_block};
+    use crate::error::Error;
+    use flate2::read::DeflateDecoder;
+    use std::io::Read;
+
+    fn inflate(block: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        DeflateDecoder::new(block).read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn encode_test() {
+        // (fibonacci counts force codes longer than 15 bits, which have to be cut down)
+        let mut fibonacci = Vec::new();
+        let (mut a, mut b) = (1, 1);
+        for byte in 0..25u8 {
+            fibonacci.extend(std::iter::repeat_n(byte, a));
+            (a, b) = (b, a + b);
+        }
+        let everything: Vec<u8> = (0..=255).cycle().take(5000).collect();
+        for data in [
+            &b""[..],
+            b"a",
+            b"aaaaaaaa",
+            b"dagoth ur was a hotep",
+            &everything,
+            &fibonacci,
+        ] {
+            let block = encode_literal_block(data);
+            assert_eq!(inflate(&block), data);
+            assert_eq!(decode_literal_block(&block).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn decode_test() {
+        // zlib's own output, from
+        // zlib.compressobj(9, zlib.DEFLATED, -15, 9, zlib.Z_HUFFMAN_ONLY) on b"dagoth ur was a hotep, and so was vivec. " * 3
+        let zlib = [
+            0x05, 0xc1, 0xd1, 0x0d, 0x40, 0x11, 0x10, 0x45, 0xc1, 0x56, 0x4e, 0x01, 0xa2, 0xa7,
+            0x1b, 0x84, 0xf7, 0x63, 0xc5, 0x2e, 0xda, 0x7f, 0x33, 0x55, 0xdd, 0x62, 0x70, 0x36,
+            0x4f, 0x8e, 0x18, 0x16, 0x6d, 0x25, 0x34, 0x2b, 0x6e, 0x3c, 0x39, 0xf7, 0xbb, 0xad,
+            0x64, 0xaa, 0xba, 0xc5, 0xe0, 0x6c, 0x9e, 0x1c, 0x31, 0x2c, 0xda, 0x4a, 0x68, 0x56,
+            0xdc, 0x78, 0x72, 0xee, 0x77, 0x5b, 0xc9, 0x54, 0x75, 0x8b, 0xc1, 0xd9, 0x3c, 0x39,
+            0x62, 0x58, 0xb4, 0x95, 0xd0, 0xac, 0xb8, 0xf1, 0xe4, 0xdc, 0xef, 0xb6, 0x92, 0xf9,
+            0x01,
+        ];
+        assert_eq!(
+            decode_literal_block(&zlib).unwrap(),
+            b"dagoth ur was a hotep, and so was vivec. ".repeat(3)
+        );
+        // and the same data comes out (about) as small from here
+        let block = encode_literal_block(&b"dagoth ur was a hotep, and so was vivec. ".repeat(3));
+        assert!(block.len() <= zlib.len() + 2);
+        assert!(matches!(
+            decode_literal_block(&zlib[..40]),
+            Err(Error::Corrupt(_))
+        ));
+        // a fixed-Huffman block
+        assert!(matches!(
+            decode_literal_block(&[0x03, 0x00]),
+            Err(Error::Unsupported(_))
+        ));
+    }
+}
@@ -0,0 +1,207 @@
+//! Dictionaries: byte frequencies trained ahead of time on a corpus, for compressing lots of small, similar inputs
+//!
+//! A static container stores its frequencies in its header, which costs up to a kilobyte or so, and for inputs of a
+//! few hundred bytes that's more than the codes save. If the compressor and the decompressor both already have the
+//! frequencies, though, there's no need to store them at all: a container compressed with a dictionary just records
+//! the dictionary's ID, and whoever decompresses it has to hand over the same dictionary.
+//!
+//! Every byte value has a frequency of at least 1 in a dictionary, so any input at all can be compressed with one
+//! (bytes the corpus never had just get long codes). Dictionaries get saved like so:
+//!
+//! ```text
+//! magic       4 bytes   "HUFD"
+//! version     1 byte    currently 1
+//! freqs       256 * 4 bytes: every byte value's frequency, in order, as a u32 (little-endian)
+//! ```
+//!
+//! The ID is the CRC-32 of the frequencies, exactly as they're stored.
+
+use crate::checksum::Crc32;
+use crate::error::{Error, Result};
+use crate::freq::{count_bytes, FrequencyMap};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+/// The bytes every saved dictionary starts with
+pub const DICT_MAGIC: [u8; 4] = *b"HUFD";
+/// The newest dictionary format version this build can read and write
+pub const DICT_VERSION: u8 = 1;
+
+/// The biggest all the frequencies can add up to (training on more than this scales them down to fit), which keeps
+/// the tree's merged frequencies well clear of overflowing
+const MAX_TOTAL: u64 = 1 << 24;
+/// How many bytes of a sample get read at a time when training
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A set of byte frequencies shared between compressor and decompressor
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dictionary {
+    /// Every byte value's frequency (chars 0 through 255 standing in for bytes)
+    freqs: FrequencyMap,
+    /// The ID containers compressed with this dictionary record
+    id: u32,
+}
+
+impl Dictionary {
+    /// Trains a dictionary on some samples, adding up their byte frequencies
+    ///
+    /// ## Arguments
+    ///
+    /// * `samples`: the samples, which are read through to the end, one after another
+    pub fn train<I, R>(samples: I) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = R>,
+        R: Read,
+    {
+        let mut counts = [0u64; 256];
+        let mut buf = vec![0; CHUNK_SIZE];
+        for mut sample in samples {
+            loop {
+                let n = match sample.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                for (total, count) in counts.iter_mut().zip(count_bytes(&buf[..n]).iter()) {
+                    *total += count;
+                }
+            }
+        }
+        Ok(Dictionary::from_counts(&counts))
+    }
+
+    /// Makes a dictionary out of byte counts, scaling them down if they add up to too much, and giving any byte
+    /// value that was never counted a frequency of 1
+    ///
+    /// ## Arguments
+    ///
+    /// * `counts`: how many times each byte value showed up
+    pub fn from_counts(counts: &[u64; 256]) -> Self {
+        let total: u64 = counts.iter().sum();
+        let freqs: [u32; 256] = std::array::from_fn(|byte| {
+            let count = counts[byte];
+            let scaled = if total > MAX_TOTAL {
+                (count as u128 * MAX_TOTAL as u128 / total as u128) as u32
+            } else {
+                count as u32
+            };
+            scaled.max(1)
+        });
+        Dictionary::from_freqs(freqs)
+    }
+
+    /// Builds the dictionary from its final frequencies (every one at least 1), working out its ID
+    fn from_freqs(freqs: [u32; 256]) -> Self {
+        let mut crc = Crc32::new();
+        for freq in &freqs {
+            crc.update(&freq.to_le_bytes());
+        }
+        let map: HashMap<char, i32> = (0..=255u8)
+            .zip(freqs.iter())
+            .map(|(byte, &freq)| (char::from(byte), freq as i32))
+            .collect();
+        Dictionary {
+            freqs: FrequencyMap::from(map),
+            id: crc.value(),
+        }
+    }
+
+    /// The ID that containers compressed with this dictionary record
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Gets the frequencies, i.e. what the tree gets built from
+    pub fn freqs(&self) -> &FrequencyMap {
+        &self.freqs
+    }
+
+    /// Reads a saved dictionary
+    ///
+    /// ## Arguments
+    ///
+    /// * `reader`: where the dictionary comes from
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut buf = vec![0; 4 + 1 + 256 * 4];
+        reader.read_exact(&mut buf).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => Error::BadHeader("dictionary is truncated"),
+            _ => Error::Io(e),
+        })?;
+        if buf[..4] != DICT_MAGIC {
+            return Err(Error::BadHeader("not a dictionary (bad magic bytes)"));
+        }
+        if buf[4] == 0 || buf[4] > DICT_VERSION {
+            return Err(Error::UnsupportedVersion(buf[4]));
+        }
+        let mut freqs = [0; 256];
+        for (freq, bytes) in freqs.iter_mut().zip(buf[5..].chunks_exact(4)) {
+            *freq = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            if *freq == 0 || *freq as u64 > MAX_TOTAL {
+                return Err(Error::BadHeader("dictionary frequency out of range"));
+            }
+        }
+        Ok(Dictionary::from_freqs(freqs))
+    }
+
+    /// Saves the dictionary
+    ///
+    /// ## Arguments
+    ///
+    /// * `writer`: where the dictionary goes
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&DICT_MAGIC)?;
+        writer.write_all(&[DICT_VERSION])?;
+        for byte in 0..=255u8 {
+            writer.write_all(&(self.freqs.get(char::from(byte)) as u32).to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Dictionary;
+    use crate::error::Error;
+
+    #[test]
+    fn train_test() {
+        let dict = Dictionary::train(vec![&b"dagoth ur"[..], b" was a hotep"]).unwrap();
+        assert_eq!(dict.freqs().len(), 256);
+        assert_eq!(dict.freqs().get('a'), 3);
+        // bytes that never showed up still get a code
+        assert_eq!(dict.freqs().get('z'), 1);
+        let mut saved = Vec::new();
+        dict.write_to(&mut saved).unwrap();
+        assert_eq!(saved.len(), 4 + 1 + 256 * 4);
+        assert_eq!(Dictionary::read_from(&mut &saved[..]).unwrap(), dict);
+        assert_ne!(
+            Dictionary::train(vec![&b"something else"[..]])
+                .unwrap()
+                .id(),
+            dict.id()
+        );
+        saved[0] = b'X';
+        assert!(matches!(
+            Dictionary::read_from(&mut &saved[..]),
+            Err(Error::BadHeader(_))
+        ));
+        assert!(matches!(
+            Dictionary::read_from(&mut &saved[..100]),
+            Err(Error::BadHeader(_))
+        ));
+    }
+
+    #[test]
+    fn scaling_test() {
+        let mut counts = [0; 256];
+        counts[0] = u64::MAX / 2;
+        counts[1] = u64::MAX / 4;
+        counts[2] = 3;
+        let dict = Dictionary::from_counts(&counts);
+        // the proportions stay (about) the same, and nothing drops to 0
+        assert!((dict.freqs().get('\u{0}') - 2 * dict.freqs().get('\u{1}')).abs() <= 1);
+        assert_eq!(dict.freqs().get('\u{2}'), 1);
+        assert!(dict.freqs().total() <= (1 << 24) + 256);
+    }
+}
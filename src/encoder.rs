@@ -0,0 +1,206 @@
+//! Encoding lots of short messages against one shared set of codes
+//!
+//! Building a tree and packing up its codes costs far more than encoding a message of a few dozen bytes, so for
+//! something like a service that encodes millions of small messages, the codes get built once (from frequencies
+//! gathered up front, i.e. a shared dictionary) and then every message reuses them, along with the same output
+//! buffer. Messages are just the packed codes padded out to a whole byte, with no header, so whoever decodes them
+//! needs the same frequencies and each message's length.
+//!
+//! For the hottest paths (or places with no allocator to speak of), `encode_into_slice()` does the same thing with
+//! nothing but what the caller hands it: a codebook, the input, and a buffer to fill.
+
+use crate::bits::{BitWriter, ByteCodes, SliceSink};
+use crate::btree::HuffTree;
+use crate::error::{Error, Result};
+use crate::freq::FrequencyMap;
+
+/// Encodes message after message with one fixed set of codes, without allocating anything per message
+pub struct HuffmanEncoder {
+    /// The code for each byte
+    codes: ByteCodes,
+    /// Holds bits that haven't made up a whole byte yet
+    bits: BitWriter,
+    /// The current message's packed bytes
+    out: Vec<u8>,
+}
+
+impl HuffmanEncoder {
+    /// Builds the codes for a shared dictionary
+    ///
+    /// ## Arguments
+    ///
+    /// * `freqs`: the byte frequencies to build the codes from (chars 0 through 255 standing in for bytes); any byte
+    ///   that doesn't show up in them can't be encoded
+    pub fn new(freqs: &FrequencyMap) -> Self {
+        let mut tree = HuffTree::new();
+        tree.populate_tree(freqs.as_map());
+        HuffmanEncoder {
+            codes: ByteCodes::new(&tree.generate_huffman_map()),
+            bits: BitWriter::new(),
+            out: Vec::new(),
+        }
+    }
+
+    /// Encodes some more of the current message
+    ///
+    /// Fails with `Error::UnknownSymbol` if a byte has no code, in which case everything before it has still been
+    /// encoded.
+    ///
+    /// ## Arguments
+    ///
+    /// * `input`: the bytes to encode
+    pub fn encode(&mut self, input: &[u8]) -> Result<()> {
+        if self.bits.push_bytes(&self.codes, input, &mut self.out) {
+            return Ok(());
+        }
+        let missing = input
+            .iter()
+            .find(|&&byte| self.codes.get(byte).is_none())
+            .unwrap();
+        Err(Error::UnknownSymbol(char::from(*missing)))
+    }
+
+    /// Pads out the current message to a whole byte and gives back all of it
+    ///
+    /// The message stays put until `reset()`, so calling this again just gives the same bytes.
+    pub fn finish(&mut self) -> &[u8] {
+        self.bits.align(&mut self.out);
+        &self.out
+    }
+
+    /// Throws away the current message to start on the next one, keeping the codes and the buffer's memory
+    pub fn reset(&mut self) {
+        self.bits = BitWriter::new();
+        self.out.clear();
+    }
+
+    /// Gets the code for every byte value (as the bits, right-aligned, and how many there are), or `None` for bytes
+    /// without one, in the form `encode_into_slice()` takes
+    pub fn codebook(&self) -> [Option<(u64, u8)>; 256] {
+        std::array::from_fn(|byte| self.codes.get(byte as u8))
+    }
+
+    /// Roughly how many bytes of heap memory the encoder is using (just its output buffer; the codes are kept inline)
+    pub fn memory_usage(&self) -> usize {
+        self.out.capacity()
+    }
+
+    /// Encodes a whole message in one go, i.e. `reset()`, `encode()`, and `finish()`
+    ///
+    /// ## Arguments
+    ///
+    /// * `message`: the message
+    pub fn encode_message(&mut self, message: &[u8]) -> Result<&[u8]> {
+        self.reset();
+        self.encode(message)?;
+        Ok(self.finish())
+    }
+}
+
+/// Encodes a message with a caller-provided codebook straight into a caller-provided buffer, without allocating
+/// anything
+///
+/// Returns how many bits got written; the last byte is padded out with zeroes. A byte with no code (or past the end
+/// of the codebook) fails with `Error::UnknownSymbol`, and running out of room fails with
+/// `Error::OutputLimitExceeded`, either way with `out` holding however much got encoded before that.
+///
+/// ## Arguments
+///
+/// * `codebook`: the code for each byte value, indexed by the byte (e.g. from `HuffmanEncoder::codebook()`)
+/// * `input`: the message
+/// * `out`: where the packed codes go
+pub fn encode_into_slice(
+    codebook: &[Option<(u64, u8)>],
+    input: &[u8],
+    out: &mut [u8],
+) -> Result<usize> {
+    let room = out.len() as u64 * 8;
+    let mut written = 0u64;
+    let mut bits = BitWriter::new();
+    let mut sink = SliceSink::new(out);
+    for &byte in input {
+        let (code, len) = codebook
+            .get(byte as usize)
+            .copied()
+            .flatten()
+            .ok_or(Error::UnknownSymbol(char::from(byte)))?;
+        // checking before every push means the sink can never overflow, padding included
+        if written + len as u64 > room {
+            bits.align(&mut sink);
+            return Err(Error::OutputLimitExceeded { limit: room / 8 });
+        }
+        bits.push_bits(code, len, &mut sink);
+        written += len as u64;
+    }
+    bits.align(&mut sink);
+    Ok(written as usize)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode_into_slice, HuffmanEncoder};
+    use crate::bits::BitWindow;
+    use crate::btree::HuffTree;
+    use crate::error::Error;
+    use crate::freq::FrequencyMap;
+
+    fn decode(freqs: &FrequencyMap, encoded: &[u8], len: usize) -> Vec<u8> {
+        let mut tree = HuffTree::new();
+        tree.populate_tree(freqs.as_map());
+        let table = tree.decode_table();
+        let mut bits = BitWindow::new(encoded, encoded.len() as u64);
+        let mut decoded = Vec::new();
+        while decoded.len() < len {
+            bits.refill().unwrap();
+            let (ch, n) = table.lookup(bits.peek()).unwrap();
+            assert!(bits.consume(n));
+            decoded.push(ch as u8);
+        }
+        decoded
+    }
+
+    #[test]
+    fn shared_codes_test() {
+        let mut freqs = FrequencyMap::new();
+        freqs.add_bytes(b"dagoth ur was a hotep, whether 'tis nobler in the end");
+        let mut encoder = HuffmanEncoder::new(&freqs);
+        for message in &[&b"a hotep"[..], b"dagoth", b"", b"the end was nobler"] {
+            let encoded = encoder.encode_message(message).unwrap().to_vec();
+            assert_eq!(decode(&freqs, &encoded, message.len()), *message);
+        }
+        // piece by piece is the same as all at once
+        encoder.reset();
+        encoder.encode(b"dagoth ").unwrap();
+        encoder.encode(b"ur").unwrap();
+        let pieces = encoder.finish().to_vec();
+        assert_eq!(encoder.encode_message(b"dagoth ur").unwrap(), &pieces[..]);
+        assert!(matches!(
+            encoder.encode_message(b"dagoth ur!"),
+            Err(Error::UnknownSymbol('!'))
+        ));
+    }
+
+    #[test]
+    fn encode_into_slice_test() {
+        let mut freqs = FrequencyMap::new();
+        freqs.add_bytes(b"dagoth ur was a hotep");
+        let mut encoder = HuffmanEncoder::new(&freqs);
+        let codebook = encoder.codebook();
+        let expected = encoder
+            .encode_message(b"a hotep was dagoth")
+            .unwrap()
+            .to_vec();
+        let mut out = [0xff; 64];
+        let bits = encode_into_slice(&codebook, b"a hotep was dagoth", &mut out).unwrap();
+        assert_eq!(bits.div_ceil(8), expected.len());
+        assert_eq!(&out[..expected.len()], &expected[..]);
+        assert!(matches!(
+            encode_into_slice(&codebook, b"a hotep was dagoth", &mut out[..2]),
+            Err(Error::OutputLimitExceeded { limit: 2 })
+        ));
+        assert!(matches!(
+            encode_into_slice(&codebook[..b'u' as usize], b"dagoth ur", &mut out),
+            Err(Error::UnknownSymbol('u'))
+        ));
+    }
+}
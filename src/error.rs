@@ -0,0 +1,157 @@
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong compressing or decompressing
+#[derive(Debug)]
+pub enum Error {
+    /// Reading the input or writing the output failed
+    Io(io::Error),
+    /// The input doesn't start with the container's magic bytes, so it probably isn't compressed data at all
+    BadMagic,
+    /// The container was written with a format version this build doesn't understand
+    UnsupportedVersion(u8),
+    /// The container's header is malformed
+    BadHeader(&'static str),
+    /// The compressed payload doesn't decode properly
+    Corrupt(&'static str),
+    /// The payload decoded, but not to the data the checksum was computed from
+    ChecksumMismatch {
+        /// The checksum stored in the container
+        expected: u32,
+        /// The checksum of what actually got decoded
+        found: u32,
+    },
+    /// The caller asked for the work to stop partway through
+    Cancelled,
+    /// Decoding would have produced more output than the caller allowed
+    OutputLimitExceeded {
+        /// The limit that was set
+        limit: u64,
+    },
+    /// The operation doesn't make sense for this kind of container
+    Unsupported(&'static str),
+    /// A multi-input file was asked for an input it doesn't have
+    NoSuchInput(usize),
+    /// An archive was asked for an input by a name it doesn't have
+    NoSuchEntry(String),
+    /// The input has a symbol that the code map doesn't
+    UnknownSymbol(char),
+    /// The container was compressed with a dictionary, and it wasn't the one given (if one was given at all)
+    DictionaryMismatch {
+        /// The ID of the dictionary the container was compressed with
+        expected: u32,
+        /// The ID of the dictionary that was given
+        found: Option<u32>,
+    },
+    /// A table of codes handed in from outside can't be used as it is
+    BadCodeTable(CodeTableError),
+}
+
+/// What can be wrong with a table of codes handed in from outside (see `Codebook::from_pairs()`)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CodeTableError {
+    /// A code has something other than 0s and 1s in it
+    NotBinary(char),
+    /// A symbol shows up more than once
+    DuplicateSymbol(char),
+    /// A symbol's code is empty, and it isn't the only symbol
+    EmptyCode(char),
+    /// One symbol's code starts with another's, so there'd be no telling them apart
+    NotPrefixFree {
+        /// The symbol whose code is the shorter one
+        prefix: char,
+        /// The symbol whose code starts with it
+        of: char,
+    },
+    /// There are bit strings that don't start with any code, i.e. the lengths don't satisfy Kraft's equality
+    Incomplete,
+    /// The table couldn't be read in the first place
+    Malformed(&'static str),
+}
+
+/// A `Result` with this crate's error type
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::BadMagic => write!(f, "not a Huffman-compressed file (bad magic bytes)"),
+            Error::UnsupportedVersion(v) => write!(f, "unsupported format version {}", v),
+            Error::BadHeader(why) => write!(f, "bad header: {}", why),
+            Error::Corrupt(why) => write!(f, "corrupt data: {}", why),
+            Error::ChecksumMismatch { expected, found } => write!(
+                f,
+                "checksum mismatch (expected {:08x}, found {:08x})",
+                expected, found
+            ),
+            Error::Cancelled => write!(f, "cancelled"),
+            Error::OutputLimitExceeded { limit } => {
+                write!(f, "decompressed data exceeds the limit of {} bytes", limit)
+            }
+            Error::Unsupported(why) => write!(f, "unsupported: {}", why),
+            Error::NoSuchInput(k) => write!(f, "there's no input #{} in this file", k),
+            Error::NoSuchEntry(name) => write!(f, "there's no {:?} in this archive", name),
+            Error::UnknownSymbol(ch) => write!(f, "{:?} has no code", ch),
+            Error::DictionaryMismatch {
+                expected,
+                found: None,
+            } => write!(f, "needs dictionary {:08x}, but none was given", expected),
+            Error::DictionaryMismatch {
+                expected,
+                found: Some(found),
+            } => write!(
+                f,
+                "needs dictionary {:08x}, but was given {:08x}",
+                expected, found
+            ),
+            Error::BadCodeTable(why) => write!(f, "bad code table: {}", why),
+        }
+    }
+}
+
+impl fmt::Display for CodeTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodeTableError::NotBinary(ch) => write!(f, "{:?}'s code isn't all 0s and 1s", ch),
+            CodeTableError::DuplicateSymbol(ch) => write!(f, "{:?} has more than one code", ch),
+            CodeTableError::EmptyCode(ch) => write!(f, "{:?} has an empty code", ch),
+            CodeTableError::NotPrefixFree { prefix, of } => {
+                write!(f, "{:?}'s code is a prefix of {:?}'s", prefix, of)
+            }
+            CodeTableError::Incomplete => write!(f, "the codes don't cover every bit string"),
+            CodeTableError::Malformed(why) => write!(f, "{}", why),
+        }
+    }
+}
+
+impl From<CodeTableError> for Error {
+    fn from(e: CodeTableError) -> Self {
+        Error::BadCodeTable(e)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            Error::Cancelled => io::Error::other(e),
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
@@ -0,0 +1,513 @@
+//! Extensions: extra fields a container's header can carry, as a small CBOR map
+//!
+//! The rest of the header has a fixed layout, so every new field means a new flag or a new version, and old decoders
+//! turning new files away. Extensions get around that: they're a CBOR (RFC 8949) map, so a decoder can read all of
+//! them without knowing what any of them mean, and just skip over the ones it doesn't know. Keys that are unsigned
+//! ints are standard ones, defined here (and any added later); keys that are text strings belong to whoever's using
+//! them, and should be namespaced so they don't collide, like a reversed domain name (`"com.example.build-id"`).
+//!
+//! Only the plain parts of CBOR are supported: ints, byte and text strings, arrays, maps, `true`, `false` and `null`,
+//! with definite lengths. Maps get written with their keys in order (standard ones first) and every length as short
+//! as it can be, so the same extensions always come out as the same bytes.
+
+use crate::error::{Error, Result};
+use crate::pipeline::Pipeline;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::iter::FromIterator;
+
+/// Standard key: a name for the data, e.g. the name of the file it came from (text)
+pub const NAME: u64 = 0;
+/// Standard key: when the data was last modified, in seconds since the Unix epoch (unsigned int)
+pub const MTIME: u64 = 1;
+/// Standard key: a comment, for people to read (text)
+pub const COMMENT: u64 = 2;
+/// Standard key: the stages the data went through before it was coded, which decoding has to undo (an array of
+/// stage IDs; see the `pipeline` module)
+pub const PIPELINE: u64 = 3;
+
+/// The longest an encoded set of extensions can be, which is plenty for what they're for, while keeping a corrupt
+/// header from asking for an absurd amount of memory
+pub const MAX_LEN: usize = 64 * 1024;
+/// How deeply arrays and maps can be nested in a value
+const MAX_DEPTH: usize = 16;
+
+/// An extension's key
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Key {
+    /// One of the standard keys, like `NAME`
+    Standard(u64),
+    /// Anybody else's key
+    Vendor(String),
+}
+
+/// An extension's value
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Value {
+    /// An unsigned int
+    Uint(u64),
+    /// A negative int, stored the way CBOR does: `n` stands for `-1 - n`
+    Negative(u64),
+    /// A byte string
+    Bytes(Vec<u8>),
+    /// A text string
+    Text(String),
+    /// An array
+    Array(Vec<Value>),
+    /// A map, with its entries in the order they were written
+    Map(Vec<(Value, Value)>),
+    /// `true` or `false`
+    Bool(bool),
+    /// `null`
+    Null,
+}
+
+/// Written in CBOR's diagnostic notation (RFC 8949 section 8), e.g. `[1, -2, h'00ff', "text", {true: null}]`
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Uint(n) => write!(f, "{}", n),
+            Value::Negative(n) => write!(f, "{}", -1 - *n as i128),
+            Value::Bytes(bytes) => {
+                write!(f, "h'")?;
+                for byte in bytes {
+                    write!(f, "{:02x}", byte)?;
+                }
+                write!(f, "'")
+            }
+            Value::Text(text) => write!(f, "{:?}", text),
+            Value::Array(items) => {
+                write!(f, "[")?;
+                for (k, item) in items.iter().enumerate() {
+                    write!(f, "{}{}", if k == 0 { "" } else { ", " }, item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Map(entries) => {
+                write!(f, "{{")?;
+                for (k, (key, value)) in entries.iter().enumerate() {
+                    write!(f, "{}{}: {}", if k == 0 { "" } else { ", " }, key, value)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Null => write!(f, "null"),
+        }
+    }
+}
+
+/// Standard keys by name (`name`, `mtime`, `comment` or `pipeline`, or their number if they're newer than that), and
+/// anybody else's as a quoted string
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Standard(NAME) => write!(f, "name"),
+            Key::Standard(MTIME) => write!(f, "mtime"),
+            Key::Standard(COMMENT) => write!(f, "comment"),
+            Key::Standard(PIPELINE) => write!(f, "pipeline"),
+            Key::Standard(key) => write!(f, "{}", key),
+            Key::Vendor(key) => write!(f, "{:?}", key),
+        }
+    }
+}
+
+/// A header's extensions, which is usually none at all
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Extensions {
+    /// Every extension, in key order
+    entries: BTreeMap<Key, Value>,
+}
+
+impl Extensions {
+    /// Creates a new, empty set of extensions
+    pub fn new() -> Self {
+        Extensions::default()
+    }
+
+    /// How many extensions there are
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether there are no extensions at all (in which case the header doesn't get any)
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Gets an extension's value, if it's there
+    ///
+    /// ## Arguments
+    ///
+    /// * `key`: the extension's key
+    pub fn get(&self, key: &Key) -> Option<&Value> {
+        self.entries.get(key)
+    }
+
+    /// Sets an extension, handing back what it was before (if anything)
+    ///
+    /// ## Arguments
+    ///
+    /// * `key`: the extension's key
+    /// * `value`: its value
+    pub fn insert(&mut self, key: Key, value: Value) -> Option<Value> {
+        self.entries.insert(key, value)
+    }
+
+    /// Takes an extension out, handing back what it was (if anything)
+    ///
+    /// ## Arguments
+    ///
+    /// * `key`: the extension's key
+    pub fn remove(&mut self, key: &Key) -> Option<Value> {
+        self.entries.remove(key)
+    }
+
+    /// Goes through every extension, in key order
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Value)> {
+        self.entries.iter()
+    }
+
+    /// Gets the `NAME` extension, if there is one and it's text
+    pub fn name(&self) -> Option<&str> {
+        self.text(NAME)
+    }
+
+    /// Gets the `MTIME` extension, if there is one and it's an unsigned int
+    pub fn mtime(&self) -> Option<u64> {
+        match self.get(&Key::Standard(MTIME)) {
+            Some(&Value::Uint(mtime)) => Some(mtime),
+            _ => None,
+        }
+    }
+
+    /// Gets the `COMMENT` extension, if there is one and it's text
+    pub fn comment(&self) -> Option<&str> {
+        self.text(COMMENT)
+    }
+
+    /// Gets the `PIPELINE` extension, which is an empty pipeline if there isn't one
+    ///
+    /// Fails with `Error::BadHeader` if it isn't an array of stage IDs, or `Error::Unsupported` if it has a stage
+    /// this build doesn't know about.
+    pub fn pipeline(&self) -> Result<Pipeline> {
+        match self.get(&Key::Standard(PIPELINE)) {
+            Some(value) => Pipeline::from_value(value),
+            None => Ok(Pipeline::new()),
+        }
+    }
+
+    /// Gets a standard extension that ought to be text, if it's there and it is
+    fn text(&self, key: u64) -> Option<&str> {
+        match self.get(&Key::Standard(key)) {
+            Some(Value::Text(text)) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Encodes the extensions as a CBOR map
+    pub fn to_cbor(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_head(&mut out, 5, self.entries.len() as u64);
+        for (key, value) in &self.entries {
+            match key {
+                Key::Standard(key) => write_head(&mut out, 0, *key),
+                Key::Vendor(key) => write_text(&mut out, key),
+            }
+            // nested values go on a stack rather than recursing, so there's no depth to worry about here
+            let mut stack = vec![value];
+            while let Some(value) = stack.pop() {
+                match value {
+                    Value::Uint(n) => write_head(&mut out, 0, *n),
+                    Value::Negative(n) => write_head(&mut out, 1, *n),
+                    Value::Bytes(bytes) => {
+                        write_head(&mut out, 2, bytes.len() as u64);
+                        out.extend_from_slice(bytes);
+                    }
+                    Value::Text(text) => write_text(&mut out, text),
+                    Value::Array(items) => {
+                        write_head(&mut out, 4, items.len() as u64);
+                        stack.extend(items.iter().rev());
+                    }
+                    Value::Map(entries) => {
+                        write_head(&mut out, 5, entries.len() as u64);
+                        for (key, value) in entries.iter().rev() {
+                            stack.push(value);
+                            stack.push(key);
+                        }
+                    }
+                    Value::Bool(false) => out.push(0xf4),
+                    Value::Bool(true) => out.push(0xf5),
+                    Value::Null => out.push(0xf6),
+                }
+            }
+        }
+        out
+    }
+
+    /// Decodes extensions from a CBOR map, all of which has to be used up
+    ///
+    /// Fails with `Error::BadHeader` if it isn't a map, or has anything in it that isn't supported (see the module
+    /// docs), or has a key that isn't an unsigned int or text, or has the same key twice.
+    ///
+    /// ## Arguments
+    ///
+    /// * `cbor`: the encoded map
+    pub fn from_cbor(cbor: &[u8]) -> Result<Self> {
+        let mut reader = Reader { cbor, pos: 0 };
+        let len = match reader.head()? {
+            (5, len) => len,
+            _ => return Err(Error::BadHeader("extensions aren't a CBOR map")),
+        };
+        let mut entries = BTreeMap::new();
+        for _ in 0..len {
+            let key = match reader.value(0)? {
+                Value::Uint(key) => Key::Standard(key),
+                Value::Text(key) => Key::Vendor(key),
+                _ => return Err(Error::BadHeader("extension key isn't an int or text")),
+            };
+            let value = reader.value(0)?;
+            if entries.insert(key, value).is_some() {
+                return Err(Error::BadHeader("extension key is there twice"));
+            }
+        }
+        if reader.pos != cbor.len() {
+            return Err(Error::BadHeader("extensions have more after their map"));
+        }
+        Ok(Extensions { entries })
+    }
+}
+
+impl FromIterator<(Key, Value)> for Extensions {
+    fn from_iter<I: IntoIterator<Item = (Key, Value)>>(iter: I) -> Self {
+        Extensions {
+            entries: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Serialized as a list of `(key, value)` pairs, in key order (since most formats only allow maps to have string
+/// keys)
+#[cfg(feature = "serde")]
+impl serde::Serialize for Extensions {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_seq(&self.entries)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Extensions {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let entries: Vec<(Key, Value)> = serde::Deserialize::deserialize(deserializer)?;
+        let len = entries.len();
+        let extensions: Extensions = entries.into_iter().collect();
+        if extensions.len() != len {
+            return Err(serde::de::Error::custom("extension key is there twice"));
+        }
+        Ok(extensions)
+    }
+}
+
+/// Writes the first byte (and any length bytes after it) of a CBOR item, as short as it'll go
+///
+/// ## Arguments
+///
+/// * `out`: where it goes
+/// * `major`: the item's major type
+/// * `n`: its argument (the int itself, or a length)
+fn write_head(out: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    match n {
+        0..=23 => out.push(major | n as u8),
+        24..=0xff => out.extend_from_slice(&[major | 24, n as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend_from_slice(&(n as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+}
+
+/// Writes a CBOR text string
+fn write_text(out: &mut Vec<u8>, text: &str) {
+    write_head(out, 3, text.len() as u64);
+    out.extend_from_slice(text.as_bytes());
+}
+
+/// Where reading some CBOR is up to
+struct Reader<'a> {
+    /// The CBOR
+    cbor: &'a [u8],
+    /// How far into it reading has gotten
+    pos: usize,
+}
+
+impl Reader<'_> {
+    /// Reads some bytes that have to be there
+    fn take(&mut self, n: u64) -> Result<&[u8]> {
+        if n > (self.cbor.len() - self.pos) as u64 {
+            return Err(Error::BadHeader("extensions are truncated"));
+        }
+        let bytes = &self.cbor[self.pos..self.pos + n as usize];
+        self.pos += n as usize;
+        Ok(bytes)
+    }
+
+    /// Reads the start of an item, giving back its major type and its argument (for simple values, i.e. major type
+    /// 7, that's just the low 5 bits)
+    fn head(&mut self) -> Result<(u8, u64)> {
+        let first = self.take(1)?[0];
+        let (major, info) = (first >> 5, first & 0x1f);
+        let n = match info {
+            0..=23 => info as u64,
+            _ if major == 7 => return Err(Error::BadHeader("extensions have a float in them")),
+            24..=27 => self
+                .take(1 << (info - 24))?
+                .iter()
+                .fold(0, |n, &byte| n << 8 | byte as u64),
+            31 => return Err(Error::BadHeader("extensions have an indefinite length")),
+            _ => return Err(Error::BadHeader("extensions aren't valid CBOR")),
+        };
+        Ok((major, n))
+    }
+
+    /// Reads a whole value (the only place reading recurses, and only as deep as `MAX_DEPTH`)
+    ///
+    /// ## Arguments
+    ///
+    /// * `depth`: how many arrays and maps this value is inside of
+    fn value(&mut self, depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            return Err(Error::BadHeader("extensions are nested too deeply"));
+        }
+        Ok(match self.head()? {
+            (0, n) => Value::Uint(n),
+            (1, n) => Value::Negative(n),
+            (2, len) => Value::Bytes(self.take(len)?.to_vec()),
+            (3, len) => Value::Text(
+                String::from_utf8(self.take(len)?.to_vec())
+                    .map_err(|_| Error::BadHeader("extension text isn't valid UTF-8"))?,
+            ),
+            (4, len) => {
+                // every item takes at least a byte, which keeps a bogus length from allocating much
+                let mut items =
+                    Vec::with_capacity(len.min((self.cbor.len() - self.pos) as u64) as usize);
+                for _ in 0..len {
+                    items.push(self.value(depth + 1)?);
+                }
+                Value::Array(items)
+            }
+            (5, len) => {
+                let mut entries =
+                    Vec::with_capacity(len.min((self.cbor.len() - self.pos) as u64) as usize);
+                for _ in 0..len {
+                    entries.push((self.value(depth + 1)?, self.value(depth + 1)?));
+                }
+                Value::Map(entries)
+            }
+            (6, _) => return Err(Error::BadHeader("extensions have a tag in them")),
+            (7, 20) => Value::Bool(false),
+            (7, 21) => Value::Bool(true),
+            (7, 22) => Value::Null,
+            _ => {
+                return Err(Error::BadHeader(
+                    "extensions have an unsupported simple value",
+                ))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Extensions, Key, Value, COMMENT, MTIME, NAME};
+    use crate::error::Error;
+
+    #[test]
+    fn cbor_test() {
+        let mut extensions = Extensions::new();
+        extensions.insert(Key::Standard(NAME), Value::Text("a".to_string()));
+        extensions.insert(Key::Standard(MTIME), Value::Uint(1000));
+        extensions.insert(
+            Key::Vendor("x".to_string()),
+            Value::Array(vec![Value::Negative(0), Value::Bool(true), Value::Null]),
+        );
+        let cbor = extensions.to_cbor();
+        // {0: "a", 1: 1000, "x": [-1, true, null]}
+        assert_eq!(
+            cbor,
+            [0xa3, 0x00, 0x61, b'a', 0x01, 0x19, 0x03, 0xe8, 0x61, b'x', 0x83, 0x20, 0xf5, 0xf6]
+        );
+        assert_eq!(
+            extensions
+                .iter()
+                .map(|(key, value)| format!("{} = {}", key, value))
+                .collect::<Vec<_>>(),
+            ["name = \"a\"", "mtime = 1000", "\"x\" = [-1, true, null]"]
+        );
+        let back = Extensions::from_cbor(&cbor).unwrap();
+        assert_eq!(back, extensions);
+        assert_eq!(back.name(), Some("a"));
+        assert_eq!(back.mtime(), Some(1000));
+        assert_eq!(back.comment(), None);
+        // a key nobody here knows about still comes through untouched
+        let mut nested = Extensions::new();
+        nested.insert(Key::Standard(COMMENT), Value::Text("hi".to_string()));
+        nested.insert(
+            Key::Standard(99),
+            Value::Map(vec![(Value::Bytes(vec![1, 2]), Value::Map(vec![]))]),
+        );
+        assert_eq!(Extensions::from_cbor(&nested.to_cbor()).unwrap(), nested);
+        assert_eq!(
+            nested.get(&Key::Standard(99)).unwrap().to_string(),
+            "{h'0102': {}}"
+        );
+        assert_eq!(Extensions::from_cbor(&[0xa0]).unwrap(), Extensions::new());
+        for bad in [
+            &[][..],
+            &[0x80],
+            &[0xa1, 0x00],
+            &[0xa1, 0x40, 0x00],
+            &[0xa2, 0x00, 0x00, 0x00, 0x00],
+            &[0xa1, 0x00, 0xc0, 0x00],
+            &[0xa1, 0x00, 0xf9, 0x00, 0x00],
+            &[0xa1, 0x00, 0x5f],
+            &[0xa1, 0x00, 0x61, 0xff],
+            &[
+                0xa1, 0x00, 0x5b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            ],
+            &[0xa0, 0x00],
+            &[
+                0xa1, 0x00, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81,
+                0x81, 0x81, 0x81, 0x81, 0x81, 0x00,
+            ],
+        ] {
+            assert!(
+                matches!(Extensions::from_cbor(bad), Err(Error::BadHeader(_))),
+                "{:x?}",
+                bad
+            );
+        }
+    }
+}
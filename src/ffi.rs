@@ -0,0 +1,212 @@
+//! A C ABI (with the `ffi` feature) for streaming compression, for firmware and C servers that get their data a piece
+//! at a time and can't hold all of it in one buffer
+//!
+//! The compressed bytes get pushed out through a callback as they're made, so the compressed output never piles up:
+//! all a stream holds on to is its adaptive model (a few fixed-size tables, made once when the stream's created) and
+//! a few bytes of pending bits. The output's a streaming container, just like `compress()` makes for
+//! `EncodeOptions { streaming: true, .. }`, so it comes back out with `decompress()` or `huff decompress`. Build a
+//! library to link against with e.g. `cargo rustc --lib --release --features ffi --crate-type staticlib` (or
+//! `cdylib`), and declare:
+//!
+//! ```text
+//! typedef struct HuffStream HuffStream;
+//! typedef int (*huff_write_fn)(void *user_data, const uint8_t *data, size_t len);
+//!
+//! HuffStream *huff_stream_new(huff_write_fn write, void *user_data);
+//! int huff_stream_feed(HuffStream *stream, const uint8_t *data, size_t len);
+//! int huff_stream_finish(HuffStream *stream);
+//! ```
+//!
+//! The callback returns 0 if it took the bytes, and anything else if it couldn't. `feed` and `finish` return
+//! `HUFF_OK` (0) if everything went, `HUFF_BAD_ARGUMENT` (-1) if they got a null pointer, or `HUFF_WRITE_FAILED` (-2)
+//! if the callback failed, after which the stream should just be finished (to free it) and its output thrown away.
+
+use crate::container::{streaming_header, EncodeOptions};
+use crate::stream::Encoder;
+use std::os::raw::{c_int, c_void};
+use std::slice;
+
+/// Everything went
+pub const HUFF_OK: c_int = 0;
+/// A pointer that shouldn't have been null was
+pub const HUFF_BAD_ARGUMENT: c_int = -1;
+/// The output callback said it couldn't take the bytes
+pub const HUFF_WRITE_FAILED: c_int = -2;
+
+/// The output callback: gets handed `user_data` and some compressed bytes, and returns 0 if it took them
+pub type HuffWriteFn = extern "C" fn(user_data: *mut c_void, data: *const u8, len: usize) -> c_int;
+
+/// A compression stream in progress, which C only ever sees a pointer to
+pub struct HuffStream {
+    /// The encoder doing the actual work
+    encoder: Encoder,
+    /// Scratch space for the encoder's output (which starts out holding the container header, for the first call
+    /// to hand over)
+    buf: Vec<u8>,
+    /// Where the output goes
+    write: HuffWriteFn,
+    /// Handed to `write` every time, for the caller to keep its own state in
+    user_data: *mut c_void,
+    /// Whether `write` has failed, in which case nothing more gets written
+    failed: bool,
+}
+
+impl HuffStream {
+    /// Hands whatever's in the buffer to the callback, and empties it
+    fn drain(&mut self) -> c_int {
+        if self.failed {
+            return HUFF_WRITE_FAILED;
+        }
+        if !self.buf.is_empty()
+            && (self.write)(self.user_data, self.buf.as_ptr(), self.buf.len()) != 0
+        {
+            self.failed = true;
+            return HUFF_WRITE_FAILED;
+        }
+        self.buf.clear();
+        HUFF_OK
+    }
+}
+
+/// Starts a new compression stream, returning null if `write` is
+///
+/// ## Arguments
+///
+/// * `write`: where the compressed bytes go
+/// * `user_data`: passed along to every call to `write`, untouched
+#[no_mangle]
+pub extern "C" fn huff_stream_new(
+    write: Option<HuffWriteFn>,
+    user_data: *mut c_void,
+) -> *mut HuffStream {
+    let write = match write {
+        Some(write) => write,
+        None => return std::ptr::null_mut(),
+    };
+    let mut buf = Vec::new();
+    streaming_header(&EncodeOptions::default())
+        .write_to(&mut buf)
+        .expect("writing to a Vec can't fail");
+    Box::into_raw(Box::new(HuffStream {
+        encoder: Encoder::new(),
+        buf,
+        write,
+        user_data,
+        failed: false,
+    }))
+}
+
+/// Compresses some more data, passing whatever output that makes to the callback (which might be nothing, since
+/// bits are held on to until there are enough of them)
+///
+/// ## Arguments
+///
+/// * `stream`: the stream, from `huff_stream_new()`
+/// * `data`: the bytes to compress (which can be null if `len` is 0)
+/// * `len`: how many bytes there are
+///
+/// # Safety
+///
+/// `stream` has to have come from `huff_stream_new()` and not been finished yet, and `data` has to point to at least
+/// `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn huff_stream_feed(
+    stream: *mut HuffStream,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    let stream = match stream.as_mut() {
+        Some(stream) => stream,
+        None => return HUFF_BAD_ARGUMENT,
+    };
+    if stream.failed {
+        return HUFF_WRITE_FAILED;
+    }
+    if len == 0 {
+        return HUFF_OK;
+    }
+    if data.is_null() {
+        return HUFF_BAD_ARGUMENT;
+    }
+    stream
+        .encoder
+        .encode(slice::from_raw_parts(data, len), &mut stream.buf);
+    stream.drain()
+}
+
+/// Ends the stream, passing the last of the output (the end-of-stream marker and the checksum footer) to the
+/// callback, and frees it
+///
+/// ## Arguments
+///
+/// * `stream`: the stream, from `huff_stream_new()`, which can't be used again afterwards
+///
+/// # Safety
+///
+/// `stream` has to have come from `huff_stream_new()` and not been finished already.
+#[no_mangle]
+pub unsafe extern "C" fn huff_stream_finish(stream: *mut HuffStream) -> c_int {
+    if stream.is_null() {
+        return HUFF_BAD_ARGUMENT;
+    }
+    let mut stream = Box::from_raw(stream);
+    if stream.failed {
+        return HUFF_WRITE_FAILED;
+    }
+    stream.encoder.finish(&mut stream.buf);
+    stream.drain()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::container::decompress;
+
+    extern "C" fn collect(user_data: *mut c_void, data: *const u8, len: usize) -> c_int {
+        let out = unsafe { &mut *(user_data as *mut Vec<u8>) };
+        out.extend_from_slice(unsafe { slice::from_raw_parts(data, len) });
+        0
+    }
+
+    extern "C" fn refuse(_: *mut c_void, _: *const u8, _: usize) -> c_int {
+        1
+    }
+
+    #[test]
+    fn stream_test() {
+        let data = b"dagoth ur was a hotep".repeat(100);
+        let mut out = Vec::new();
+        unsafe {
+            let stream = huff_stream_new(Some(collect), &mut out as *mut Vec<u8> as *mut c_void);
+            for chunk in data.chunks(7) {
+                assert_eq!(
+                    huff_stream_feed(stream, chunk.as_ptr(), chunk.len()),
+                    HUFF_OK
+                );
+            }
+            assert_eq!(huff_stream_feed(stream, std::ptr::null(), 0), HUFF_OK);
+            assert_eq!(huff_stream_finish(stream), HUFF_OK);
+        }
+        assert_eq!(decompress(&out[..], Vec::new()).unwrap(), data);
+
+        assert!(huff_stream_new(None, std::ptr::null_mut()).is_null());
+        unsafe {
+            assert_eq!(
+                huff_stream_feed(std::ptr::null_mut(), data.as_ptr(), 1),
+                HUFF_BAD_ARGUMENT
+            );
+            assert_eq!(huff_stream_finish(std::ptr::null_mut()), HUFF_BAD_ARGUMENT);
+            // once the callback fails, the stream stays failed
+            let stream = huff_stream_new(Some(refuse), std::ptr::null_mut());
+            assert_eq!(
+                huff_stream_feed(stream, data.as_ptr(), data.len()),
+                HUFF_WRITE_FAILED
+            );
+            assert_eq!(
+                huff_stream_feed(stream, data.as_ptr(), 1),
+                HUFF_WRITE_FAILED
+            );
+            assert_eq!(huff_stream_finish(stream), HUFF_WRITE_FAILED);
+        }
+    }
+}
@@ -0,0 +1,440 @@
+use crate::container::{self, BlockInfo, DecodeOptions, EncodeOptions};
+use crate::error::{Error, Result};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Roughly how much decompressed data `decompress_file_parallel()` holds in memory at once
+const PARALLEL_ROUND_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Compresses one file into another
+///
+/// The output gets written to a temporary file next to `dst`, which is only renamed into place once everything has
+/// been written and synced, so `dst` never ends up holding a half-written container. If anything goes wrong
+/// (including being cancelled through `options.cancel`), the temporary file is deleted and `dst` is left untouched.
+///
+/// ## Arguments
+///
+/// * `src`: the file to compress
+/// * `dst`: where to put the compressed file
+/// * `options`: how to compress
+pub fn compress_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+    options: &EncodeOptions,
+) -> Result<()> {
+    let input = BufReader::new(Progress::new(File::open(src)?, &options.progress));
+    write_atomically(dst.as_ref(), |output| {
+        container::compress(input, output, options)?;
+        Ok(())
+    })
+}
+
+/// Compresses one file into another, reading the source through a memory map
+///
+/// Both the frequency pass and the encoding pass run directly over the mapped file, so nothing gets copied through a
+/// read buffer; this is worth it for really big (multi-gigabyte) inputs. Otherwise it behaves like `compress_file()`.
+///
+/// The source mustn't be modified (and especially not truncated) by anything else while this is running---that's
+/// the usual caveat with memory maps, and doing so can crash the process outright rather than just erroring.
+///
+/// ## Arguments
+///
+/// * `src`: the file to compress
+/// * `dst`: where to put the compressed file
+/// * `options`: how to compress
+#[cfg(feature = "mmap")]
+pub fn compress_file_mmap<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+    options: &EncodeOptions,
+) -> Result<()> {
+    let file = File::open(src)?;
+    // SAFETY: the map is only ever read from, and the caveat about outside modification is documented above
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    write_atomically(dst.as_ref(), |output| {
+        container::compress_slice(&map, output, options)?;
+        Ok(())
+    })
+}
+
+/// Decompresses one file into another
+///
+/// As with `compress_file()`, the output only appears at `dst` once it's been completely written, and only if it
+/// decoded cleanly and matched its checksum.
+///
+/// ## Arguments
+///
+/// * `src`: the compressed file
+/// * `dst`: where to put the decompressed file
+pub fn decompress_file<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<()> {
+    decompress_file_with(src, dst, &DecodeOptions::default())
+}
+
+/// Decompresses one file into another, like `decompress_file()`, but with options
+///
+/// ## Arguments
+///
+/// * `src`: the compressed file
+/// * `dst`: where to put the decompressed file
+/// * `options`: how to decompress
+pub fn decompress_file_with<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+    options: &DecodeOptions,
+) -> Result<()> {
+    let input = BufReader::new(Progress::new(File::open(src)?, &options.progress));
+    write_atomically(dst.as_ref(), |output| {
+        container::decompress_with(input, output, options)?;
+        Ok(())
+    })
+}
+
+/// Decompresses one file into another, decoding its blocks on several threads at once
+///
+/// This is for files made in block mode (or by `compress_many()`): every block is found up front without decoding
+/// anything, and then the blocks get handed out to `threads` threads, each decoding into a buffer of its own that
+/// grows as the block decodes (rather than being sized up front from its header, which could say anything). The
+/// output goes through memory a round of blocks at a time (about 64 MiB), so it never all has to fit at once. A file
+/// with streaming containers or pipelines in it can't be split up (see `container::split_points()`), so it just gets
+/// decompressed on this thread like `decompress_file_with()` would.
+///
+/// ## Arguments
+///
+/// * `src`: the compressed file
+/// * `dst`: where to put the decompressed file
+/// * `threads`: how many threads to decode on
+/// * `options`: how to decompress
+pub fn decompress_file_parallel<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+    threads: usize,
+    options: &DecodeOptions,
+) -> Result<()> {
+    let mut input = BufReader::new(Progress::new(File::open(src.as_ref())?, &options.progress));
+    let blocks = match container::split_points(&mut input) {
+        Ok(blocks) => blocks,
+        Err(Error::Unsupported(_)) => return decompress_file_with(src, dst, options),
+        Err(err) => return Err(err),
+    };
+    let total = blocks
+        .iter()
+        .try_fold(0u64, |total, block| total.checked_add(block.original_len))
+        .ok_or(Error::Corrupt(
+            "blocks' lengths add up to more than 64 bits",
+        ))?;
+    if let Some(limit) = options.max_output_bytes {
+        if total > limit {
+            return Err(Error::OutputLimitExceeded { limit });
+        }
+    }
+    let threads = threads.max(1);
+    write_atomically(dst.as_ref(), |output| {
+        let mut compressed = Vec::new();
+        let mut rest = &blocks[..];
+        while !rest.is_empty() {
+            // as many blocks as fit in a round, but always at least one
+            let mut size = rest[0].original_len;
+            let mut n = 1;
+            while n < rest.len() {
+                match size.checked_add(rest[n].original_len) {
+                    Some(next) if next <= PARALLEL_ROUND_SIZE => size = next,
+                    _ => break,
+                }
+                n += 1;
+            }
+            let (round, next) = rest.split_at(n);
+            rest = next;
+            let (first, last) = (round[0], round[n - 1]);
+            input.seek(SeekFrom::Start(first.offset))?;
+            compressed.resize((last.offset + last.len - first.offset) as usize, 0);
+            input.read_exact(&mut compressed)?;
+            for decompressed in decode_round(round, &compressed, threads, options)? {
+                output.write_all(&decompressed)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Decodes a run of consecutive blocks on several threads, giving back what each one decoded to, in order
+///
+/// ## Arguments
+///
+/// * `round`: the blocks
+/// * `compressed`: the part of the file the blocks are in, starting at the first one
+/// * `threads`: how many threads to decode on
+/// * `options`: how to decompress
+fn decode_round(
+    round: &[BlockInfo],
+    compressed: &[u8],
+    threads: usize,
+    options: &DecodeOptions,
+) -> Result<Vec<Vec<u8>>> {
+    let mut decompressed: Vec<Vec<u8>> = round
+        .iter()
+        .map(|block| {
+            // every byte but a lone symbol's costs at least a bit, so anything past that is left to grow into
+            Vec::with_capacity(block.original_len.min(block.len.saturating_mul(8)) as usize)
+        })
+        .collect();
+    let mut jobs = Vec::with_capacity(round.len());
+    for (block, out) in round.iter().zip(decompressed.iter_mut()) {
+        let start = (block.offset - round[0].offset) as usize;
+        jobs.push((block, &compressed[start..start + block.len as usize], out));
+    }
+    // each thread just keeps taking the next block until there aren't any left
+    let jobs = Mutex::new(jobs.into_iter());
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.min(round.len()))
+            .map(|_| {
+                scope.spawn(|| loop {
+                    let job = jobs.lock().unwrap().next();
+                    let (block, input, out) = match job {
+                        Some(job) => job,
+                        None => return Ok(()),
+                    };
+                    container::decompress_with(input, &mut *out, options)?;
+                    if out.len() as u64 != block.original_len {
+                        return Err(Error::Corrupt(
+                            "block doesn't decode to what its header says",
+                        ));
+                    }
+                })
+            })
+            .collect();
+        workers.into_iter().try_for_each(|worker| {
+            worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    })?;
+    drop(jobs);
+    Ok(decompressed)
+}
+
+/// A reader that adds every byte it reads to a progress counter (if there is one)
+struct Progress<R> {
+    /// Where the bytes come from
+    inner: R,
+    /// What they get added to
+    counter: Option<Arc<AtomicU64>>,
+}
+
+impl<R> Progress<R> {
+    /// Wraps a reader
+    ///
+    /// ## Arguments
+    ///
+    /// * `inner`: the reader
+    /// * `counter`: the progress counter from the options, if they have one
+    fn new(inner: R, counter: &Option<Arc<AtomicU64>>) -> Self {
+        Progress {
+            inner,
+            counter: counter.clone(),
+        }
+    }
+}
+
+impl<R: Read> Read for Progress<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(counter) = &self.counter {
+            counter.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for Progress<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Runs `write` against a temporary file beside `dst`, renaming it over `dst` if it succeeds and deleting it if not
+fn write_atomically<F>(dst: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<()>,
+{
+    let tmp = temp_path(dst);
+    let result = (|| {
+        let mut output = BufWriter::new(File::create(&tmp)?);
+        write(&mut output)?;
+        output.flush()?;
+        output.get_ref().sync_all()?;
+        drop(output);
+        fs::rename(&tmp, dst)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        // best effort; the original error is the one worth reporting
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Gets the temporary path to write to before renaming into `dst` (same directory, so the rename stays atomic)
+fn temp_path(dst: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(dst.file_name().unwrap_or_else(|| "huffman".as_ref()));
+    name.push(".tmp");
+    dst.with_file_name(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compress_file, decompress_file, decompress_file_with};
+    use crate::container::{DecodeOptions, EncodeOptions, Header, Payload};
+    use crate::error::Error;
+    use crate::extensions::Extensions;
+    use crate::freq::FrequencyMap;
+    use crate::pipeline::Pipeline;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("huffman-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn file_round_trip_test() {
+        let dir = scratch_dir("file-round-trip");
+        let input =
+            "whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune"
+                .repeat(1000);
+        fs::write(dir.join("in.txt"), &input).unwrap();
+        let read = Arc::new(AtomicU64::new(0));
+        let options = EncodeOptions {
+            progress: Some(read.clone()),
+            ..EncodeOptions::default()
+        };
+        compress_file(dir.join("in.txt"), dir.join("in.txt.huf"), &options).unwrap();
+        // once to count, once to encode
+        assert_eq!(read.load(Ordering::Relaxed), 2 * input.len() as u64);
+        decompress_file(dir.join("in.txt.huf"), dir.join("out.txt")).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("out.txt")).unwrap(), input);
+        let compressed = fs::metadata(dir.join("in.txt.huf")).unwrap().len();
+        assert!(compressed < input.len() as u64);
+        let read = Arc::new(AtomicU64::new(0));
+        let options = DecodeOptions {
+            progress: Some(read.clone()),
+            ..DecodeOptions::default()
+        };
+        decompress_file_with(dir.join("in.txt.huf"), dir.join("out.txt"), &options).unwrap();
+        assert_eq!(read.load(Ordering::Relaxed), compressed);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap_round_trip_test() {
+        let dir = scratch_dir("mmap-round-trip");
+        let input = "dagoth ur was a hotep".repeat(5000);
+        fs::write(dir.join("in.txt"), &input).unwrap();
+        fs::write(dir.join("empty.txt"), "").unwrap();
+        for name in &["in.txt", "empty.txt"] {
+            let options = EncodeOptions::default();
+            super::compress_file_mmap(dir.join(name), dir.join("mapped.huf"), &options).unwrap();
+            compress_file(dir.join(name), dir.join("read.huf"), &options).unwrap();
+            assert_eq!(
+                fs::read(dir.join("mapped.huf")).unwrap(),
+                fs::read(dir.join("read.huf")).unwrap()
+            );
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn parallel_decompress_test() {
+        let dir = scratch_dir("parallel-decompress");
+        let input = "dagoth ur was a hotep".repeat(5000);
+        fs::write(dir.join("in.txt"), &input).unwrap();
+        let cases = [
+            (Some(1000), false, Pipeline::new()),
+            (None, false, Pipeline::new()),
+            (None, true, Pipeline::new()),
+            // what pipelined blocks decode to isn't in their headers, so they still come out in order
+            (Some(1000), false, Pipeline::bzip2()),
+            (Some(1000), false, "rle".parse().unwrap()),
+        ];
+        for (block_size, streaming, pipeline) in &cases {
+            let options = EncodeOptions {
+                block_size: *block_size,
+                streaming: *streaming,
+                pipeline: pipeline.clone(),
+                ..EncodeOptions::default()
+            };
+            compress_file(dir.join("in.txt"), dir.join("in.txt.huf"), &options).unwrap();
+            for &threads in &[0, 1, 4] {
+                super::decompress_file_parallel(
+                    dir.join("in.txt.huf"),
+                    dir.join("out.txt"),
+                    threads,
+                    &DecodeOptions::default(),
+                )
+                .unwrap();
+                assert_eq!(fs::read_to_string(dir.join("out.txt")).unwrap(), input);
+            }
+        }
+        let limited = DecodeOptions {
+            max_output_bytes: Some(1000),
+            ..DecodeOptions::default()
+        };
+        assert!(matches!(
+            super::decompress_file_parallel(
+                dir.join("in.txt.huf"),
+                dir.join("out.txt"),
+                4,
+                &limited
+            ),
+            Err(Error::OutputLimitExceeded { limit: 1000 })
+        ));
+        // what a header says a block decodes to isn't allocated up front
+        let claiming = |original_len| {
+            let mut block = Vec::new();
+            Header {
+                checksum: false,
+                payload: Payload::Static {
+                    original_len,
+                    freqs: FrequencyMap::from("ab"),
+                    payload_len: 1,
+                },
+                extensions: Extensions::new(),
+            }
+            .write_to(&mut block)
+            .unwrap();
+            block.push(0b0110_1001);
+            block
+        };
+        fs::write(dir.join("bomb.huf"), claiming(1 << 44)).unwrap();
+        assert!(matches!(
+            super::decompress_file_parallel(
+                dir.join("bomb.huf"),
+                dir.join("out.txt"),
+                4,
+                &DecodeOptions::default()
+            ),
+            Err(Error::Corrupt(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_decompress_leaves_nothing_test() {
+        let dir = scratch_dir("failed-decompress");
+        fs::write(dir.join("bad.huf"), b"not compressed at all").unwrap();
+        assert!(matches!(
+            decompress_file(dir.join("bad.huf"), dir.join("out.txt")),
+            Err(Error::BadMagic)
+        ));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,397 @@
+use crate::btree::HuffTree;
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+/// How many bytes get read at a time when counting from a reader
+const CHUNK_SIZE: usize = 64 * 1024;
+/// How many bytes each thread counts at a time when counting in parallel (anything smaller isn't worth splitting up)
+#[cfg(feature = "rayon")]
+const PARALLEL_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// A map of characters to how often they showed up, i.e. the model a Huffman tree gets built from
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrequencyMap {
+    /// The actual counts
+    counts: HashMap<char, i32>,
+}
+
+impl FrequencyMap {
+    /// Creates a new, empty frequency map
+    pub fn new() -> Self {
+        FrequencyMap::default()
+    }
+
+    /// Counts the characters coming out of a reader, which has to be producing UTF-8
+    ///
+    /// The input gets read and counted in fixed-size chunks, so it never has to all be in memory at once; characters
+    /// that straddle two chunks are carried over and counted whole. Input that isn't valid UTF-8 gives an
+    /// `InvalidData` error.
+    ///
+    /// ## Arguments
+    ///
+    /// * `reader`: where the input comes from
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut freqs = FrequencyMap::new();
+        let mut buf = vec![0; CHUNK_SIZE];
+        // how many bytes at the front of `buf` are left over from the last chunk (at most 3, for a partial char)
+        let mut carried = 0;
+        loop {
+            let n = match reader.read(&mut buf[carried..]) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if n == 0 {
+                if carried != 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "input ends partway through a UTF-8 sequence",
+                    ));
+                }
+                return Ok(freqs);
+            }
+            let filled = carried + n;
+            // count everything up to the last complete char, and carry over whatever's after it
+            let valid = match std::str::from_utf8(&buf[..filled]) {
+                Ok(chunk) => chunk.len(),
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            };
+            freqs.add_str(std::str::from_utf8(&buf[..valid]).unwrap());
+            buf.copy_within(valid..filled, 0);
+            carried = filled - valid;
+        }
+    }
+
+    /// Counts the bytes coming out of a reader, with each byte standing in as the char of the same value
+    ///
+    /// This is the mode to use for binary data (or text in some encoding other than UTF-8).
+    ///
+    /// ## Arguments
+    ///
+    /// * `reader`: where the input comes from
+    pub fn from_reader_bytes<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut freqs = FrequencyMap::new();
+        let mut buf = vec![0; CHUNK_SIZE];
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if n == 0 {
+                return Ok(freqs);
+            }
+            freqs.add_bytes(&buf[..n]);
+        }
+    }
+
+    /// Adds the characters of a string to the counts
+    ///
+    /// ## Arguments
+    ///
+    /// * `input`: the string to count
+    pub fn add_str(&mut self, input: &str) {
+        // ASCII is one byte per char, so it can take the fast path for bytes
+        if input.is_ascii() {
+            self.add_bytes(input.as_bytes());
+            return;
+        }
+        for ch in input.chars() {
+            self.add(ch);
+        }
+    }
+
+    /// Adds some bytes to the counts, with each byte standing in as the char of the same value
+    ///
+    /// ## Arguments
+    ///
+    /// * `input`: the bytes to count
+    pub fn add_bytes(&mut self, input: &[u8]) {
+        // counting into a plain array keeps hashing out of the loop; the hash map only gets touched once per byte
+        // value at the end
+        let counts = count_bytes(input);
+        for (byte, &count) in counts.iter().enumerate() {
+            if count != 0 {
+                let cnt = self.counts.entry(char::from(byte as u8)).or_insert(0);
+                // saturating, same as `add()`
+                *cnt = cnt.saturating_add(count.min(i32::MAX as u64) as i32);
+            }
+        }
+    }
+
+    /// Adds a single occurrence of a character
+    ///
+    /// ## Arguments
+    ///
+    /// * `ch`: the character to count
+    pub fn add(&mut self, ch: char) {
+        let cnt = self.counts.entry(ch).or_insert(0);
+        // saturating, since a wrapped-around count would be a lot more wrong than a stuck one
+        *cnt = cnt.saturating_add(1);
+    }
+
+    /// Gets how many times a character has been counted
+    ///
+    /// ## Arguments
+    ///
+    /// * `ch`: the character to look up
+    pub fn get(&self, ch: char) -> i32 {
+        self.counts.get(&ch).copied().unwrap_or(0)
+    }
+
+    /// How many distinct characters have been counted
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Whether nothing's been counted yet
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// Gets the underlying hash map, e.g. to hand to `HuffTree::populate_tree()`
+    pub fn as_map(&self) -> &HashMap<char, i32> {
+        &self.counts
+    }
+
+    /// Turns this into the underlying hash map
+    pub fn into_map(self) -> HashMap<char, i32> {
+        self.counts
+    }
+
+    /// How many characters have been counted altogether
+    pub fn total(&self) -> u64 {
+        self.counts.values().map(|&count| count.max(0) as u64).sum()
+    }
+
+    /// The Shannon entropy of the counts, in bits per character, i.e. the fewest bits per character any code built
+    /// from them could average
+    pub fn entropy(&self) -> f64 {
+        let total = self.total() as f64;
+        self.counts
+            .values()
+            .filter(|&&count| count > 0)
+            .map(|&count| {
+                let p = count as f64 / total;
+                -p * p.log2()
+            })
+            .sum()
+    }
+
+    /// Gets the most common characters with their counts, most common first (ties going in char order)
+    ///
+    /// ## Arguments
+    ///
+    /// * `n`: how many to get, at most
+    pub fn most_common(&self, n: usize) -> Vec<(char, i32)> {
+        let mut counts: Vec<(char, i32)> = self
+            .counts
+            .iter()
+            .map(|(&ch, &count)| (ch, count))
+            .collect();
+        counts.sort_unstable_by_key(|&(ch, count)| (std::cmp::Reverse(count), ch));
+        counts.truncate(n);
+        counts
+    }
+}
+
+/// Counts up how many times each byte value shows up
+#[cfg(not(feature = "rayon"))]
+pub(crate) fn count_bytes(input: &[u8]) -> [u64; 256] {
+    let mut counts = [0; 256];
+    for &byte in input {
+        counts[byte as usize] += 1;
+    }
+    counts
+}
+
+/// Counts up how many times each byte value shows up, splitting big inputs up to count on every core at once
+#[cfg(feature = "rayon")]
+pub(crate) fn count_bytes(input: &[u8]) -> [u64; 256] {
+    use rayon::prelude::*;
+    input
+        .par_chunks(PARALLEL_CHUNK_SIZE)
+        .map(|chunk| {
+            let mut counts = [0; 256];
+            for &byte in chunk {
+                counts[byte as usize] += 1;
+            }
+            counts
+        })
+        .reduce(
+            || [0; 256],
+            |mut total, counts| {
+                for (total, count) in total.iter_mut().zip(counts.iter()) {
+                    *total += count;
+                }
+                total
+            },
+        )
+}
+
+impl From<HashMap<char, i32>> for FrequencyMap {
+    fn from(counts: HashMap<char, i32>) -> Self {
+        FrequencyMap { counts }
+    }
+}
+
+impl From<&str> for FrequencyMap {
+    fn from(input: &str) -> Self {
+        FrequencyMap::from(HuffTree::find_input_freqs(input))
+    }
+}
+
+/// Serialized as a map of chars to counts, in char order, so the same counts always come out the same
+#[cfg(feature = "serde")]
+impl serde::Serialize for FrequencyMap {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        let mut counts: Vec<(&char, &i32)> = self.counts.iter().collect();
+        counts.sort_unstable();
+        serializer.collect_map(counts)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for FrequencyMap {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        HashMap::deserialize(deserializer).map(FrequencyMap::from)
+    }
+}
+
+/// Every frequency is at least 1, and they add up to no more than 2^24, so the trees built from them stay well clear
+/// of overflowing (and of the longest codes containers allow)
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for FrequencyMap {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        arbitrary_freqs(u, |u| u.arbitrary())
+    }
+}
+
+/// Makes up frequencies for `Arbitrary`, with the symbols coming from wherever the caller says
+///
+/// ## Arguments
+///
+/// * `u`: the fuzzer's data
+/// * `symbol`: makes up a symbol
+#[cfg(feature = "arbitrary")]
+pub(crate) fn arbitrary_freqs<'a, F>(
+    u: &mut arbitrary::Unstructured<'a>,
+    mut symbol: F,
+) -> arbitrary::Result<FrequencyMap>
+where
+    F: FnMut(&mut arbitrary::Unstructured<'a>) -> arbitrary::Result<char>,
+{
+    const MAX_TOTAL: i64 = 1 << 24;
+    let mut counts = HashMap::new();
+    let mut total = 0;
+    for _ in 0..u.arbitrary_len::<(char, u16)>()? {
+        let ch = symbol(u)?;
+        let freq = u.int_in_range(1..=u16::MAX as i32)?;
+        if total + freq as i64 > MAX_TOTAL {
+            break;
+        }
+        total += freq as i64;
+        *counts.entry(ch).or_insert(0) += freq;
+    }
+    Ok(FrequencyMap { counts })
+}
+
+#[cfg(test)]
+mod test {
+    use super::FrequencyMap;
+    use std::io::{self, Read};
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_test() {
+        let freqs = FrequencyMap::from("dagoth ur");
+        let json = serde_json::to_string(&freqs).unwrap();
+        assert!(json.starts_with("{\" \":1,\"a\":1,\"d\":1,\"g\":1,\"h\":1,"));
+        assert_eq!(serde_json::from_str::<FrequencyMap>(&json).unwrap(), freqs);
+        let packed = postcard::to_allocvec(&freqs).unwrap();
+        assert_eq!(
+            postcard::from_bytes::<FrequencyMap>(&packed).unwrap(),
+            freqs
+        );
+        let packed = bincode::serialize(&freqs).unwrap();
+        assert_eq!(
+            bincode::deserialize::<FrequencyMap>(&packed).unwrap(),
+            freqs
+        );
+    }
+
+    /// A reader that only ever hands back one byte at a time, to split up every multi-byte char
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    fn reader_matches_str(input: &str) -> bool {
+        let expected = FrequencyMap::from(input);
+        FrequencyMap::from_reader(input.as_bytes()).unwrap() == expected
+            && FrequencyMap::from_reader(Trickle(input.as_bytes())).unwrap() == expected
+    }
+
+    #[test]
+    fn from_reader_test() {
+        assert!(reader_matches_str(""));
+        assert!(reader_matches_str("aaabbbbbccddd"));
+        assert!(reader_matches_str("dagoth ur was a hotep"));
+        assert!(reader_matches_str("ünïcödé ∀x∈ℝ 🦀🦀🦀"));
+        assert!(reader_matches_str(&"ü🦀".repeat(50000)));
+    }
+
+    #[test]
+    fn bad_utf8_test() {
+        assert!(FrequencyMap::from_reader(&b"abc\xffdef"[..]).is_err());
+        // the first three bytes of a four-byte char, and then nothing
+        assert!(FrequencyMap::from_reader(&b"abc\xf0\x9f\xa6"[..]).is_err());
+    }
+
+    #[test]
+    fn add_bytes_test() {
+        // big enough to be split up when counting in parallel
+        let input: Vec<u8> = (0..=255u8).cycle().take(3 * 1024 * 1024 + 17).collect();
+        let mut freqs = FrequencyMap::new();
+        freqs.add_bytes(&input);
+        freqs.add_bytes(b"\x00");
+        assert_eq!(freqs.len(), 256);
+        assert_eq!(freqs.get('\u{0}'), 12290);
+        assert_eq!(freqs.get('\u{10}'), 12289);
+        assert_eq!(freqs.get('\u{11}'), 12288);
+    }
+
+    #[test]
+    fn from_reader_bytes_test() {
+        let freqs = FrequencyMap::from_reader_bytes(&b"ab\xff\xffa"[..]).unwrap();
+        assert_eq!(freqs.get('a'), 2);
+        assert_eq!(freqs.get('b'), 1);
+        assert_eq!(freqs.get('\u{ff}'), 2);
+        assert_eq!(freqs.len(), 3);
+    }
+
+    #[test]
+    fn summary_test() {
+        let freqs = FrequencyMap::from("aaaabbcd");
+        assert_eq!(freqs.total(), 8);
+        // 1/2 * 1 + 1/4 * 2 + 1/8 * 3 * 2
+        assert!((freqs.entropy() - 1.75).abs() < 1e-9);
+        assert_eq!(freqs.most_common(3), [('a', 4), ('b', 2), ('c', 1)]);
+        assert_eq!(FrequencyMap::from("aaaa").entropy(), 0.0);
+        assert_eq!(FrequencyMap::new().entropy(), 0.0);
+    }
+}
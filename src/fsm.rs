@@ -0,0 +1,170 @@
+//! Compiles a set of codes into a state machine that decodes a whole byte per step
+//!
+//! The states are the internal nodes of the tree (i.e. "partway through a code, having read these bits"), and each
+//! state has a transition for every possible byte, saying which state reading those eight bits ends up in and which
+//! symbols got finished along the way. Decoding is then one table lookup per input byte, with no per-bit branching
+//! at all; the price is building 256 transitions per internal node up front, which only pays off on big payloads.
+
+use std::collections::HashMap;
+
+/// A child in the trie the machine gets compiled from
+#[derive(Clone, Copy)]
+enum Child {
+    /// Nothing there (only while the trie's being built)
+    Empty,
+    /// Another internal node, by index
+    Node(usize),
+    /// A leaf, i.e. the end of a code
+    Leaf(char),
+}
+
+/// Where a byte takes one state
+#[derive(Clone, Copy)]
+struct Transition {
+    /// The state it ends up in
+    next: u32,
+    /// Where its symbols start in `emitted`
+    start: u32,
+    /// How many symbols it finishes
+    count: u8,
+}
+
+/// A byte-at-a-time decoder for one set of codes
+pub(crate) struct DecodeFsm {
+    /// The transitions for state `s` and byte `b`, at `s * 256 + b`
+    transitions: Vec<Transition>,
+    /// The symbols every transition finishes, one after another
+    emitted: Vec<char>,
+}
+
+impl DecodeFsm {
+    /// Compiles a set of codes into a state machine, or gives `None` if there's no code with any bits in it (i.e. at
+    /// most one symbol), since then there's nothing for the machine to do
+    ///
+    /// ## Arguments
+    ///
+    /// * `huffman_map`: the codes, as '0'/'1' strings
+    pub(crate) fn new(huffman_map: &HashMap<char, String>) -> Option<Self> {
+        if huffman_map.values().all(|code| code.is_empty()) {
+            return None;
+        }
+        // build the trie first, with node 0 as the root
+        let mut trie: Vec<[Child; 2]> = vec![[Child::Empty; 2]];
+        for (&ch, code) in huffman_map {
+            let mut node = 0;
+            for (i, bit) in code.bytes().enumerate() {
+                let bit = (bit == b'1') as usize;
+                if i == code.len() - 1 {
+                    trie[node][bit] = Child::Leaf(ch);
+                } else {
+                    node = match trie[node][bit] {
+                        Child::Node(next) => next,
+                        _ => {
+                            trie.push([Child::Empty; 2]);
+                            trie[node][bit] = Child::Node(trie.len() - 1);
+                            trie.len() - 1
+                        }
+                    };
+                }
+            }
+        }
+        // then work out where every byte takes every node
+        let mut fsm = DecodeFsm {
+            transitions: Vec::with_capacity(trie.len() * 256),
+            emitted: Vec::new(),
+        };
+        for state in 0..trie.len() {
+            for byte in 0..=255u8 {
+                let start = fsm.emitted.len();
+                let mut node = state;
+                for shift in (0..8).rev() {
+                    node = match trie[node][((byte >> shift) & 1) as usize] {
+                        Child::Node(next) => next,
+                        Child::Leaf(ch) => {
+                            fsm.emitted.push(ch);
+                            0
+                        }
+                        // a complete code set doesn't leave any gaps, so this can't happen
+                        Child::Empty => 0,
+                    };
+                }
+                fsm.transitions.push(Transition {
+                    next: node as u32,
+                    start: start as u32,
+                    count: (fsm.emitted.len() - start) as u8,
+                });
+            }
+        }
+        Some(fsm)
+    }
+
+    /// Roughly how many bytes of heap memory the machine takes up
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.transitions.capacity() * std::mem::size_of::<Transition>()
+            + self.emitted.capacity() * std::mem::size_of::<char>()
+    }
+
+    /// The state decoding starts in (and goes back to after every code)
+    pub(crate) fn start(&self) -> u32 {
+        0
+    }
+
+    /// Reads a byte in a state, giving the next state and the symbols that got finished
+    ///
+    /// ## Arguments
+    ///
+    /// * `state`: the current state
+    /// * `byte`: the next byte of input
+    pub(crate) fn step(&self, state: u32, byte: u8) -> (u32, &[char]) {
+        let t = self.transitions[state as usize * 256 + byte as usize];
+        let start = t.start as usize;
+        (t.next, &self.emitted[start..start + t.count as usize])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DecodeFsm;
+    use crate::btree::HuffTree;
+
+    fn fsm_decodes(input: &str) -> bool {
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree(&HuffTree::find_input_freqs(input));
+        let huffman_map = hufftree.generate_huffman_map();
+        let fsm = DecodeFsm::new(&huffman_map).unwrap();
+        let mut encoded_str = HuffTree::encode(input, &huffman_map).unwrap();
+        // pad out to whole bytes, like a real payload
+        while !encoded_str.len().is_multiple_of(8) {
+            encoded_str.push('0');
+        }
+        let mut state = fsm.start();
+        let mut decoded: String = String::new();
+        for chunk in encoded_str.as_bytes().chunks(8) {
+            let byte = chunk
+                .iter()
+                .fold(0, |acc, &bit| (acc << 1) | (bit == b'1') as u8);
+            let (next, symbols) = fsm.step(state, byte);
+            decoded.extend(symbols);
+            state = next;
+        }
+        // the padding might have finished off some extra symbols, but everything before it has to match
+        decoded.starts_with(input)
+    }
+
+    #[test]
+    fn fsm_test() {
+        assert!(fsm_decodes("ab"));
+        assert!(fsm_decodes("aaabbbbbccddd"));
+        assert!(fsm_decodes("dagoth ur was a hotep"));
+        assert!(fsm_decodes(
+            "whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune"
+        ));
+        assert!(DecodeFsm::new(
+            &HuffTree::find_input_freqs("aaaa")
+                .keys()
+                .map(|&ch| (ch, String::new()))
+                .collect()
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,488 @@
+//! Golomb and Rice codes, for streams of integers that are mostly small, like residuals and deltas
+//!
+//! When small values are common and each bigger one is a bit rarer than the one before (a geometric distribution,
+//! which is what prediction errors and gaps between events tend to look like), a Golomb code does about as well as a
+//! Huffman code, without any tree or table at all: just one parameter, `m`. A value `v` gets coded as `v / m` in
+//! unary (that many 1s, then a 0), then `v % m` in binary, in `log2(m)` bits, give or take one (a "truncated binary"
+//! code, for when `m` isn't a power of 2). A Rice code is a Golomb code with `m` a power of 2, `2^k`, which makes
+//! the remainder just the bottom `k` bits, so it's quicker and nearly as good.
+//!
+//! The parameter matters a lot (too small and the unary parts get long, too big and every remainder costs more bits
+//! than it needs), so `Code::rice_for()` and `Code::golomb_for()` work it out from the data. A value whose unary part
+//! would be 32 or more bits gets escaped instead (32 1s, then the value in 64 bits), so an outlier can't blow up.
+//!
+//! `Encoder` and `Decoder` work a chunk at a time, like the ones in `stream`; the stream ends by padding out the last
+//! byte with 1s, which can never finish a value. `compress()` and `decompress()` wrap a whole slice up with what it
+//! takes to decode it:
+//!
+//! ```text
+//! code        1 byte    0: Rice, 1: Golomb
+//! parameter   8 bytes   k for Rice, m for Golomb (little-endian, as is the count)
+//! count       8 bytes   how many values there are
+//! payload     the stream
+//! ```
+//!
+//! To go in a container, samples get Golomb coded by the pipeline stages `Stage::Golomb8`, `Stage::Golomb16` and
+//! `Stage::Golomb32` (see the `pipeline` module), which wrap their samples up with `compress()`.
+
+use crate::bits::{bit_at, BitWriter};
+use crate::error::{Error, Result};
+use std::convert::TryInto;
+
+/// How many 1s start an escaped value, which is also one more than the longest unary part a value can have
+const ESCAPE: u64 = 32;
+
+/// Which code to use, and its parameter
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Code {
+    /// A Rice code, with `m = 2^k` (a `k` over 63 counts as 63)
+    Rice(u32),
+    /// A Golomb code, with any `m` (a 0 counts as 1)
+    Golomb(u64),
+}
+
+impl Code {
+    /// Finds the Rice code that makes `values` the smallest
+    ///
+    /// ## Arguments
+    ///
+    /// * `values`: the values to be coded
+    pub fn rice_for(values: &[u64]) -> Code {
+        // the cost only goes down and then up again as k grows, so it's found once it stops going down
+        let mut best = (Code::Rice(0), Code::Rice(0).cost(values));
+        for k in 1..64 {
+            let cost = Code::Rice(k).cost(values);
+            if cost >= best.1 {
+                break;
+            }
+            best = (Code::Rice(k), cost);
+        }
+        best.0
+    }
+
+    /// Finds the Golomb code that suits `values`, going by their mean
+    ///
+    /// This is the best `m` for a geometric distribution with the same mean, which is what these codes are for,
+    /// though for other distributions a slightly different `m` can do better.
+    ///
+    /// ## Arguments
+    ///
+    /// * `values`: the values to be coded
+    pub fn golomb_for(values: &[u64]) -> Code {
+        if values.is_empty() {
+            return Code::Golomb(1);
+        }
+        let mean = values.iter().map(|&v| v as f64).sum::<f64>() / values.len() as f64;
+        // for a geometric distribution where each value is `p` times as likely as the one before, the best m is the
+        // smallest with p^m + p^(m + 1) <= 1
+        let p = mean / (mean + 1.0);
+        let m = ((1.0 + p).ln() / -p.ln()).ceil();
+        Code::Golomb(if m.is_finite() { (m as u64).max(1) } else { 1 })
+    }
+
+    /// Works out how many bits `values` take up in this code (without the padding at the end)
+    ///
+    /// ## Arguments
+    ///
+    /// * `values`: the values to be coded
+    pub fn cost(self, values: &[u64]) -> u64 {
+        let m = self.modulus();
+        values
+            .iter()
+            .map(|&value| match value / m {
+                q if q >= ESCAPE => ESCAPE + 64,
+                q => q + 1 + remainder_code(value % m, m).1 as u64,
+            })
+            .sum()
+    }
+
+    /// The `m` the code divides by
+    fn modulus(self) -> u64 {
+        match self {
+            Code::Rice(k) => 1 << k.min(63),
+            Code::Golomb(m) => m.max(1),
+        }
+    }
+}
+
+/// Works out the truncated binary code for a remainder, as its bits (right-aligned) and how many there are
+///
+/// With `b` bits being enough for every remainder, the first `2^b - m` remainders get `b - 1` bits, and the rest
+/// get `b` bits, shifted up past the short ones.
+///
+/// ## Arguments
+///
+/// * `r`: the remainder
+/// * `m`: what it's the remainder of dividing by
+fn remainder_code(r: u64, m: u64) -> (u64, u8) {
+    if m == 1 {
+        return (0, 0);
+    }
+    let b = 64 - (m - 1).leading_zeros();
+    let short = ((1u128 << b) - m as u128) as u64;
+    match r < short {
+        true => (r, (b - 1) as u8),
+        false => (r + short, b as u8),
+    }
+}
+
+/// The IO-free core of the encoder: values go in, coded bytes come out
+pub struct Encoder {
+    /// The `m` of the code
+    m: u64,
+    /// Holds bits that haven't made up a whole byte yet
+    bits: BitWriter,
+    /// How many bits have been pushed so far
+    written: u64,
+}
+
+impl Encoder {
+    /// Creates a new encoder at the start of a stream
+    ///
+    /// ## Arguments
+    ///
+    /// * `code`: the code to use
+    pub fn new(code: Code) -> Self {
+        Encoder {
+            m: code.modulus(),
+            bits: BitWriter::new(),
+            written: 0,
+        }
+    }
+
+    /// Encodes some more values, appending whatever output that produces
+    ///
+    /// Output goes out a 64-bit word at a time, so the last few bytes' worth of bits can be held back until there's a
+    /// whole word of them (or until `finish()`).
+    ///
+    /// ## Arguments
+    ///
+    /// * `values`: the values to encode
+    /// * `out`: where the coded bytes go
+    pub fn encode(&mut self, values: &[u64], out: &mut Vec<u8>) {
+        for &value in values {
+            match value / self.m {
+                q if q >= ESCAPE => {
+                    self.push_bits(u64::MAX, ESCAPE as u8, out);
+                    self.push_bits(value, 64, out);
+                }
+                q => {
+                    // q 1s and a 0
+                    self.push_bits(u64::MAX << 1, q as u8 + 1, out);
+                    let (bits, len) = remainder_code(value % self.m, self.m);
+                    self.push_bits(bits, len, out);
+                }
+            }
+        }
+    }
+
+    /// Ends the stream, padding out the last byte with 1s
+    ///
+    /// ## Arguments
+    ///
+    /// * `out`: where the coded bytes go
+    pub fn finish(&mut self, out: &mut Vec<u8>) {
+        let padding = (8 - self.written % 8) % 8;
+        self.push_bits(u64::MAX, padding as u8, out);
+        self.bits.align(out);
+    }
+
+    /// Pushes some bits, keeping count of them
+    fn push_bits(&mut self, bits: u64, len: u8, out: &mut Vec<u8>) {
+        self.bits.push_bits(bits, len, out);
+        self.written += len as u64;
+    }
+}
+
+/// The IO-free core of the decoder: coded bytes go in, the values come out
+pub struct Decoder {
+    /// The `m` of the code
+    m: u64,
+    /// Bits that have been taken from the input but not decoded yet, i.e. a value that got cut off at the end of the
+    /// last chunk of input
+    pending: Vec<bool>,
+}
+
+impl Decoder {
+    /// Creates a new decoder at the start of a stream
+    ///
+    /// ## Arguments
+    ///
+    /// * `code`: the code the stream was encoded with
+    pub fn new(code: Code) -> Self {
+        Decoder {
+            m: code.modulus(),
+            // no value takes more than an escape and 64 bits
+            pending: Vec::with_capacity(ESCAPE as usize + 64 + 8),
+        }
+    }
+
+    /// Decodes some more input, appending the decoded values
+    ///
+    /// ## Arguments
+    ///
+    /// * `input`: the coded bytes
+    /// * `out`: where the decoded values go
+    pub fn decode(&mut self, input: &[u8], out: &mut Vec<u64>) -> Result<()> {
+        for pos in 0..input.len() * 8 {
+            self.pending.push(bit_at(input, pos));
+            if let Some(value) = self.take_value()? {
+                out.push(value);
+                self.pending.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the stream ended where it should have, with nothing left over but the padding
+    pub fn finish(&self) -> Result<()> {
+        match self.pending.len() < 8 && self.pending.iter().all(|&bit| bit) {
+            true => Ok(()),
+            false => Err(Error::Corrupt("stream is truncated")),
+        }
+    }
+
+    /// Decodes the pending bits, if they make up a whole value
+    fn take_value(&self) -> Result<Option<u64>> {
+        let bits = &self.pending;
+        let q = bits.iter().take_while(|&&bit| bit).count() as u64;
+        if q >= ESCAPE {
+            return Ok(match bits.len() as u64 == ESCAPE + 64 {
+                true => Some(read_bits(&bits[ESCAPE as usize..])),
+                false => None,
+            });
+        }
+        if q as usize == bits.len() {
+            // the unary part hasn't ended yet
+            return Ok(None);
+        }
+        let rest = &bits[q as usize + 1..];
+        let r = match self.m {
+            1 => 0,
+            m => {
+                let b = 64 - (m - 1).leading_zeros() as usize;
+                let short = ((1u128 << b) - m as u128) as u64;
+                if rest.len() < b - 1 {
+                    return Ok(None);
+                }
+                match read_bits(&rest[..b - 1]) {
+                    r if r < short => r,
+                    _ if rest.len() < b => return Ok(None),
+                    _ => read_bits(&rest[..b]) - short,
+                }
+            }
+        };
+        if rest.len() > remainder_code(r, self.m).1 as usize {
+            return Err(Error::Corrupt("value has bits left over"));
+        }
+        q.checked_mul(self.m)
+            .and_then(|value| value.checked_add(r))
+            .map(Some)
+            .ok_or(Error::Corrupt("value is too big"))
+    }
+}
+
+/// Reads bits as a number, most significant first
+///
+/// ## Arguments
+///
+/// * `bits`: the bits (at most 64 of them)
+fn read_bits(bits: &[bool]) -> u64 {
+    bits.iter().fold(0, |value, &bit| value << 1 | bit as u64)
+}
+
+/// Compresses some values with whichever of `Code::rice_for()` and `Code::golomb_for()` makes them smaller
+///
+/// ## Arguments
+///
+/// * `values`: the values to compress
+pub fn compress(values: &[u64]) -> Vec<u8> {
+    let rice = Code::rice_for(values);
+    let golomb = Code::golomb_for(values);
+    match golomb.cost(values) < rice.cost(values) {
+        true => compress_with(values, golomb),
+        false => compress_with(values, rice),
+    }
+}
+
+/// Compresses some values with a given code
+///
+/// ## Arguments
+///
+/// * `values`: the values to compress
+/// * `code`: the code to use
+pub fn compress_with(values: &[u64], code: Code) -> Vec<u8> {
+    let mut out = Vec::with_capacity(17 + (code.cost(values) / 8) as usize + 1);
+    let (kind, parameter) = match code {
+        Code::Rice(k) => (0, k.min(63) as u64),
+        Code::Golomb(m) => (1, m.max(1)),
+    };
+    out.push(kind);
+    out.extend_from_slice(&parameter.to_le_bytes());
+    out.extend_from_slice(&(values.len() as u64).to_le_bytes());
+    let mut encoder = Encoder::new(code);
+    encoder.encode(values, &mut out);
+    encoder.finish(&mut out);
+    out
+}
+
+/// Decompresses what `compress()` or `compress_with()` made
+///
+/// ## Arguments
+///
+/// * `data`: the compressed bytes
+pub fn decompress(data: &[u8]) -> Result<Vec<u64>> {
+    if data.len() < 17 {
+        return Err(Error::BadHeader("header is truncated"));
+    }
+    let parameter = u64::from_le_bytes(data[1..9].try_into().unwrap());
+    let code = match (data[0], parameter) {
+        (0, k) if k < 64 => Code::Rice(k as u32),
+        (1, m) if m > 0 => Code::Golomb(m),
+        (0 | 1, _) => return Err(Error::BadHeader("code parameter out of range")),
+        _ => return Err(Error::BadHeader("unknown kind of code")),
+    };
+    let count = u64::from_le_bytes(data[9..17].try_into().unwrap());
+    // every value takes at least a bit
+    let mut values = Vec::with_capacity(count.min(data.len() as u64 * 8) as usize);
+    let mut decoder = Decoder::new(code);
+    decoder.decode(&data[17..], &mut values)?;
+    decoder.finish()?;
+    match values.len() as u64 == count {
+        true => Ok(values),
+        false => Err(Error::Corrupt("value count doesn't match the header")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Values from a geometric distribution with the given mean
+    fn geometric(n: usize, mean: f64) -> Vec<u64> {
+        let mut seed = 12345u64;
+        (0..n)
+            .map(|_| {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                let u = ((seed >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+                (u.ln() / (mean / (mean + 1.0)).ln()) as u64
+            })
+            .collect()
+    }
+
+    #[test]
+    fn code_test() {
+        let encode = |values: &[u64], code| {
+            let mut out = Vec::new();
+            let mut encoder = Encoder::new(code);
+            encoder.encode(values, &mut out);
+            encoder.finish(&mut out);
+            out
+        };
+        // 5 in Rice(2) is 1 in unary, then 01; 2 is 0 in unary, then 10; then a 1 to the end of the byte
+        assert_eq!(encode(&[5, 2], Code::Rice(2)), vec![0b1001_0101]);
+        // with m = 3, remainder 0 gets 1 bit and 1 and 2 get 2 bits: 4 is 10 + 10, 0 is 0 + 0, 2 is 0 + 11
+        assert_eq!(
+            encode(&[4, 0, 2], Code::Golomb(3)),
+            vec![0b1010_0001, 0b1111_1111]
+        );
+        // Rice(0) is plain unary
+        assert_eq!(encode(&[3], Code::Rice(0)), vec![0b1110_1111]);
+        // and anything too far out gets escaped
+        let escaped = encode(&[40], Code::Rice(0));
+        assert_eq!(escaped.len(), 12);
+        assert_eq!(&escaped[..4], &[0xff; 4]);
+        assert_eq!(Code::Rice(0).cost(&[40]), 96);
+        assert_eq!(Code::Golomb(3).cost(&[4, 0, 2]), 9);
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let values = [
+            geometric(2000, 0.5),
+            geometric(2000, 20.0),
+            vec![0, 1, u64::MAX, 7, 1 << 40, 0],
+            vec![],
+        ];
+        for values in &values {
+            for code in [
+                Code::Rice(0),
+                Code::Rice(3),
+                Code::Rice(63),
+                Code::Golomb(1),
+                Code::Golomb(3),
+                Code::Golomb(100),
+                Code::Golomb(u64::MAX),
+                Code::rice_for(values),
+                Code::golomb_for(values),
+            ] {
+                let compressed = compress_with(values, code);
+                assert_eq!(&decompress(&compressed).unwrap(), values);
+                assert_eq!(
+                    (compressed.len() - 17) as u64,
+                    code.cost(values).div_ceil(8)
+                );
+                // a byte at a time decodes the same
+                let mut decoder = Decoder::new(code);
+                let mut decoded = Vec::new();
+                for byte in &compressed[17..] {
+                    decoder.decode(&[*byte], &mut decoded).unwrap();
+                }
+                decoder.finish().unwrap();
+                assert_eq!(&decoded, values);
+            }
+        }
+    }
+
+    #[test]
+    fn estimate_test() {
+        for mean in [0.2, 3.0, 50.0, 1000.0] {
+            let values = geometric(5000, mean);
+            let rice = Code::rice_for(&values);
+            // nothing around it does better
+            let Code::Rice(k) = rice else { unreachable!() };
+            for k in k.saturating_sub(2)..k + 3 {
+                assert!(rice.cost(&values) <= Code::Rice(k).cost(&values));
+            }
+            // and the Golomb code is within a hair of the best there is
+            let golomb = Code::golomb_for(&values);
+            let best = (1..4000)
+                .map(|m| Code::Golomb(m).cost(&values))
+                .min()
+                .unwrap();
+            assert!(
+                golomb.cost(&values) as f64 <= best as f64 * 1.01,
+                "{}",
+                mean
+            );
+            assert!(golomb.cost(&values) <= rice.cost(&values));
+            assert!(compress(&values).len() <= compress_with(&values, rice).len());
+        }
+        assert_eq!(Code::rice_for(&[]), Code::Rice(0));
+        assert_eq!(Code::golomb_for(&[0, 0, 0]), Code::Golomb(1));
+    }
+
+    #[test]
+    fn corrupt_test() {
+        let values = geometric(100, 10.0);
+        let compressed = compress(&values);
+        assert!(matches!(
+            decompress(&compressed[..10]),
+            Err(Error::BadHeader(_))
+        ));
+        assert!(matches!(
+            decompress(&compressed[..compressed.len() - 1]),
+            Err(Error::Corrupt(_))
+        ));
+        let mut unknown = compressed.clone();
+        unknown[0] = 2;
+        assert!(matches!(decompress(&unknown), Err(Error::BadHeader(_))));
+        let mut zero = compressed.clone();
+        zero[0] = 1;
+        zero[1..9].copy_from_slice(&0u64.to_le_bytes());
+        assert!(matches!(decompress(&zero), Err(Error::BadHeader(_))));
+        let mut longer = compressed;
+        longer.push(0);
+        assert!(matches!(decompress(&longer), Err(Error::Corrupt(_))));
+    }
+}
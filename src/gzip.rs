@@ -0,0 +1,125 @@
+//! gzip (RFC 1952) output, so what this crate compresses can be opened by plain `gunzip` (and everything else that
+//! reads `.gz` files)
+//!
+//! A gzip member is a 10-byte header, a raw DEFLATE stream, and then the CRC-32 of the data and its length (mod
+//! 2^32). The DEFLATE stream gets written a block of up to 64 KiB at a time, each block either stored as it is, or
+//! coded with DEFLATE's fixed codes or with its own codes (see `deflate`), whichever comes out smallest. There's no
+//! LZ77, which is most of what gzip itself gets its size from, so the output's about as big as a `.huf`, not a
+//! `gzip`'d file.
+
+use crate::checksum::Crc32;
+use crate::deflate::{write_block, BitWriter, MAX_STORED_LEN};
+use std::io::{self, Write};
+
+/// What every gzip member starts with: the magic bytes, DEFLATE as the method, no flags, no modification time, no
+/// extra flags, and an unknown OS
+const GZIP_HEADER: [u8; 10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+
+/// Compresses everything written to it into a gzip member, writing it out to the wrapped writer
+///
+/// Bytes get held on to until there's a whole block's worth of them, so `flush()` only flushes the wrapped writer;
+/// the member isn't complete until `finish()` gets called.
+pub struct GzipWriter<W: Write> {
+    /// Where the member goes
+    inner: W,
+    /// Bytes waiting to go into the next block
+    buf: Vec<u8>,
+    /// The DEFLATE stream, which starts out holding the header, for the first block to take along with it
+    bits: BitWriter,
+    /// The checksum of everything written so far
+    crc: Crc32,
+    /// How many bytes have been written so far (mod 2^32)
+    len: u32,
+}
+
+impl<W: Write> GzipWriter<W> {
+    /// Creates a new gzip compressor
+    ///
+    /// ## Arguments
+    ///
+    /// * `inner`: the writer the member goes to
+    pub fn new(inner: W) -> Self {
+        let mut bits = BitWriter::default();
+        for byte in GZIP_HEADER {
+            bits.push(byte as u32, 8);
+        }
+        GzipWriter {
+            inner,
+            buf: Vec::with_capacity(MAX_STORED_LEN),
+            bits,
+            crc: Crc32::new(),
+            len: 0,
+        }
+    }
+
+    /// Gets a shared ref to the wrapped writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Ends the member, writing out the last block and the trailer, and hands back the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        write_block(&mut self.bits, &self.buf, true);
+        let mut out = self.bits.finish();
+        out.extend_from_slice(&self.crc.value().to_le_bytes());
+        out.extend_from_slice(&self.len.to_le_bytes());
+        self.inner.write_all(&out)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = data.len().min(MAX_STORED_LEN - self.buf.len());
+        self.buf.extend_from_slice(&data[..n]);
+        self.crc.update(&data[..n]);
+        self.len = self.len.wrapping_add(n as u32);
+        // the last block has to be marked as the last one, so a full block waits until there's more coming after it
+        if self.buf.len() == MAX_STORED_LEN && n < data.len() {
+            write_block(&mut self.bits, &self.buf, false);
+            self.buf.clear();
+            self.inner.write_all(&self.bits.take_bytes())?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::GzipWriter;
+    use flate2::read::GzDecoder;
+    use std::io::{Read, Write};
+
+    fn gunzip(member: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        GzDecoder::new(member).read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn gzip_test() {
+        let text = b"dagoth ur was a hotep".repeat(10_000);
+        let random: Vec<u8> = (0..100_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        for data in [&b""[..], b"a", &text, &random] {
+            let mut writer = GzipWriter::new(Vec::new());
+            for chunk in data.chunks(1000) {
+                writer.write_all(chunk).unwrap();
+            }
+            let member = writer.finish().unwrap();
+            assert_eq!(gunzip(&member), data);
+            // nothing ever gets much bigger than it was
+            assert!(member.len() <= data.len() + data.len() / 1000 + 30);
+        }
+        // text gets smaller
+        let mut writer = GzipWriter::new(Vec::new());
+        writer.write_all(&text).unwrap();
+        assert!(writer.finish().unwrap().len() < text.len() / 2);
+    }
+}
@@ -1 +1,53 @@
-pub mod btree;
\ No newline at end of file
+pub mod adaptive;
+pub mod alphabetic;
+#[cfg(feature = "futures")]
+pub mod async_futures;
+#[cfg(any(feature = "futures", feature = "tokio"))]
+mod async_state;
+#[cfg(feature = "tokio")]
+pub mod async_tokio;
+mod bits;
+pub mod block;
+pub mod btree;
+pub mod canonical;
+mod checksum;
+pub mod codebook;
+mod compat;
+pub mod container;
+pub mod context;
+mod dahuffman;
+pub mod deflate;
+pub mod dict;
+pub mod encoder;
+pub mod error;
+pub mod extensions;
+#[cfg(feature = "ffi")]
+mod ffi;
+mod file;
+pub mod freq;
+mod fsm;
+pub mod golomb;
+pub mod gzip;
+pub mod lz77;
+pub mod model;
+pub mod pipeline;
+#[cfg(feature = "python")]
+mod python;
+pub mod shannon_fano;
+pub mod stream;
+mod table;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "wasm")]
+mod wasm;
+pub mod wide;
+
+pub use codebook::Codebook;
+pub use compat::{bufread, read, write, Compression};
+pub use container::{BlockInfo, Checkpoint, DecodeOptions, EncodeOptions};
+pub use dict::Dictionary;
+pub use encoder::HuffmanEncoder;
+pub use error::{CodeTableError, Error, Result};
+#[cfg(feature = "mmap")]
+pub use file::compress_file_mmap;
+pub use file::{compress_file, decompress_file, decompress_file_parallel, decompress_file_with};
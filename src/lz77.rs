@@ -0,0 +1,571 @@
+//! LZ77 with Huffman-coded literals, lengths and distances, in a simple format of the crate's own (DEFLATE's idea,
+//! without its framing)
+//!
+//! The rest of the crate only ever codes bytes one at a time, which can't do anything about repetition: a paragraph
+//! that shows up twice costs twice as much. Here, anything that's been seen before (within the last `window` bytes)
+//! gets replaced by a match, a (length, distance) pair saying how many bytes to copy and how far back to copy them
+//! from, and whatever isn't a match stays a literal. Matches get found by hashing every 3 bytes, and chaining each
+//! position to the last one with the same hash, so finding the longest match means walking the chain (for at most
+//! `max_chain` steps, which is where most of the time goes).
+//!
+//! Literals and match lengths share one code, and distances have a code of their own. Lengths and distances can be
+//! big, so, like in DEFLATE, their codes are for buckets, whose sizes double every other bucket, with the exact
+//! value in some extra bits after the code. The codes are canonical (see the `canonical` module), so only their
+//! lengths go in the header:
+//!
+//! ```text
+//! length      8 bytes   original length in bytes (little-endian)
+//! literals    2 bytes   how many literal/length entries follow (at most 272: the 256 byte values, then 16
+//!                       length buckets)
+//! entries     1 byte each: 0 for a symbol that never comes up, otherwise 1 more than its code's length
+//! distances   1 byte    how many distance entries follow (at most 48)
+//! entries     1 byte each, just like the literals'
+//! -- then --
+//! payload     each literal's code, or each match's length code, length bits, distance code and distance bits,
+//!             most significant bit first, padded out to a whole byte
+//! ```
+//!
+//! A lone symbol gets a 1-bit code rather than an empty one (in either alphabet), so every literal or match takes at
+//! least a bit of payload, and a header can't say there's more to decode than the payload could possibly hold.
+//! There's no checksum; put the output in something that has one if it's going anywhere unreliable.
+
+use crate::bits::{BitWindow, BitWriter};
+use crate::canonical::{canonical_codes, code_lengths, limit_lengths};
+use crate::container::MAX_CODE_LEN;
+use crate::error::{Error, Result};
+use crate::table::DecodeTable;
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+/// The shortest match worth having
+const MIN_MATCH: usize = 3;
+/// The longest match there's a code for
+const MAX_MATCH: usize = 258;
+/// The furthest back a match can go
+pub const MAX_WINDOW: usize = 1 << 24;
+/// How many literal/length symbols there are: every byte, then the length buckets
+const LITERAL_CODES: usize = 256 + 16;
+/// How many distance symbols there are
+const DISTANCE_CODES: usize = 48;
+/// How many bits of hash the match finder uses
+const HASH_BITS: u32 = 15;
+
+/// How hard `compress_with()` looks for matches
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MatchOptions {
+    /// How far back matches can go, in bytes (32 KiB by default, like DEFLATE; anything over `MAX_WINDOW` gets cut
+    /// down to it, and 0 means no matches at all)
+    pub window: usize,
+    /// How many earlier positions to try for each match, at most (128 by default): more finds longer matches, but
+    /// takes longer
+    pub max_chain: usize,
+}
+
+impl Default for MatchOptions {
+    fn default() -> Self {
+        MatchOptions {
+            window: 32 * 1024,
+            max_chain: 128,
+        }
+    }
+}
+
+/// A piece of the input: a byte as it is, or a copy of earlier bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Token {
+    /// Just the byte
+    Literal(u8),
+    /// `len` bytes, copied from `dist` bytes back
+    Match { len: usize, dist: usize },
+}
+
+/// Compresses a whole byte slice with the default match options
+///
+/// ## Arguments
+///
+/// * `data`: the bytes to compress
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    compress_with(data, MatchOptions::default())
+}
+
+/// Compresses a whole byte slice, looking for matches as hard as `options` says
+///
+/// ## Arguments
+///
+/// * `data`: the bytes to compress
+/// * `options`: how far back, and how hard, to look for matches
+pub fn compress_with(data: &[u8], options: MatchOptions) -> Vec<u8> {
+    let tokens = find_matches(data, options);
+    let mut literal_counts = [0u64; LITERAL_CODES];
+    let mut distance_counts = [0u64; DISTANCE_CODES];
+    for &token in &tokens {
+        match token {
+            Token::Literal(byte) => literal_counts[byte as usize] += 1,
+            Token::Match { len, dist } => {
+                literal_counts[256 + bucket(len - MIN_MATCH + 1).0] += 1;
+                distance_counts[bucket(dist).0] += 1;
+            }
+        }
+    }
+    let literal_lengths = lengths_for(&literal_counts);
+    let distance_lengths = lengths_for(&distance_counts);
+    let mut out = Vec::with_capacity(data.len() / 2);
+    out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    out.extend_from_slice(&(literal_lengths.len() as u16).to_le_bytes());
+    out.extend(
+        literal_lengths
+            .iter()
+            .map(|len| len.map_or(0, |len| len + 1)),
+    );
+    out.push(distance_lengths.len() as u8);
+    out.extend(
+        distance_lengths
+            .iter()
+            .map(|len| len.map_or(0, |len| len + 1)),
+    );
+    let literal_codes = bits_for(&codes_for(&literal_lengths), LITERAL_CODES);
+    let distance_codes = bits_for(&codes_for(&distance_lengths), DISTANCE_CODES);
+    let mut bits = BitWriter::new();
+    for token in tokens {
+        match token {
+            Token::Literal(byte) => {
+                let (code, len) = literal_codes[byte as usize];
+                bits.push_bits(code, len, &mut out);
+            }
+            Token::Match { len, dist } => {
+                let (symbol, extra_len, extra) = bucket(len - MIN_MATCH + 1);
+                let (code, code_len) = literal_codes[256 + symbol];
+                bits.push_bits(code, code_len, &mut out);
+                bits.push_bits(extra as u64, extra_len, &mut out);
+                let (symbol, extra_len, extra) = bucket(dist);
+                let (code, code_len) = distance_codes[symbol];
+                bits.push_bits(code, code_len, &mut out);
+                bits.push_bits(extra as u64, extra_len, &mut out);
+            }
+        }
+    }
+    bits.align(&mut out);
+    out
+}
+
+/// Decompresses what `compress()` or `compress_with()` made
+///
+/// ## Arguments
+///
+/// * `data`: the compressed bytes
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut input = data;
+    let original_len = u64::from_le_bytes(take(&mut input)?);
+    let literals = u16::from_le_bytes(take(&mut input)?) as usize;
+    if literals > LITERAL_CODES {
+        return Err(Error::BadHeader("too many literal/length codes"));
+    }
+    let literal_lengths = read_lengths(&mut input, literals)?;
+    let distances = take::<1>(&mut input)?[0] as usize;
+    if distances > DISTANCE_CODES {
+        return Err(Error::BadHeader("too many distance codes"));
+    }
+    let distance_lengths = read_lengths(&mut input, distances)?;
+    let literal_table = DecodeTable::new(&codes_for(&literal_lengths));
+    let distance_table = match distance_lengths.iter().any(Option::is_some) {
+        true => Some(DecodeTable::new(&codes_for(&distance_lengths))),
+        false => None,
+    };
+    let mut out: Vec<u8> = Vec::with_capacity(original_len.min(data.len() as u64 * 8) as usize);
+    let mut bits = BitWindow::new(input, input.len() as u64);
+    while (out.len() as u64) < original_len {
+        let symbol = read_symbol(&mut bits, &literal_table)?;
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+        }
+        let len = unbucket(symbol - 256, &mut bits)? + MIN_MATCH - 1;
+        let distance_table = distance_table
+            .as_ref()
+            .ok_or(Error::Corrupt("match with no distance codes"))?;
+        let dist = unbucket(read_symbol(&mut bits, distance_table)?, &mut bits)?;
+        if dist > out.len() || (out.len() + len) as u64 > original_len {
+            return Err(Error::Corrupt("match reaches outside the data"));
+        }
+        // a match can overlap what it's copying (a run of one byte is a match 1 byte back), so this goes a byte at
+        // a time
+        let start = out.len() - dist;
+        for k in start..start + len {
+            out.push(out[k]);
+        }
+    }
+    // the rest of the last byte is padding
+    if !bits.is_finished() {
+        return Err(Error::Corrupt("payload length doesn't match the header"));
+    }
+    Ok(out)
+}
+
+/// Splits the input into literals and matches, taking the longest match at each position (if there's one at least
+/// `MIN_MATCH` long)
+///
+/// ## Arguments
+///
+/// * `data`: the bytes to split up
+/// * `options`: how far back, and how hard, to look for matches
+fn find_matches(data: &[u8], options: MatchOptions) -> Vec<Token> {
+    let window = options.window.min(MAX_WINDOW);
+    let mut tokens = Vec::with_capacity(data.len() / 2);
+    // the last position with each hash, and for each position in the window, the one before it with the same hash
+    // (usize::MAX for none)
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; window.min(data.len()).max(1)];
+    let hash = |pos: usize| {
+        let bytes = u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], 0]);
+        (bytes.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+    };
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut best = (0, 0);
+        if window > 0 && pos + MIN_MATCH <= data.len() {
+            let longest = (data.len() - pos).min(MAX_MATCH);
+            let mut candidate = head[hash(pos)];
+            let mut steps = 0;
+            while candidate != usize::MAX && pos - candidate <= window && steps < options.max_chain
+            {
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[pos..pos + longest])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best.0 {
+                    best = (len, pos - candidate);
+                    if len == longest {
+                        break;
+                    }
+                }
+                // a slot that's been reused since points forwards, not back
+                let next = prev[candidate % prev.len()];
+                if next >= candidate {
+                    break;
+                }
+                candidate = next;
+                steps += 1;
+            }
+        }
+        let step = match best {
+            (len, dist) if len >= MIN_MATCH => {
+                tokens.push(Token::Match { len, dist });
+                len
+            }
+            _ => {
+                tokens.push(Token::Literal(data[pos]));
+                1
+            }
+        };
+        for pos in (pos..pos + step).take_while(|pos| pos + MIN_MATCH <= data.len()) {
+            let slot = pos % prev.len();
+            prev[slot] = std::mem::replace(&mut head[hash(pos)], pos);
+        }
+        pos += step;
+    }
+    tokens
+}
+
+/// Finds the bucket a length or distance goes in, as its symbol, how many extra bits pin down the value within it,
+/// and what those bits are
+///
+/// The first four buckets hold 1 to 4, and after that, every two buckets hold twice as many values as the two before.
+///
+/// ## Arguments
+///
+/// * `value`: the length or distance (at least 1)
+fn bucket(value: usize) -> (usize, u8, u32) {
+    if value <= 4 {
+        return (value - 1, 0, 0);
+    }
+    let rest = (value - 1) as u32;
+    let top = 31 - rest.leading_zeros();
+    let symbol = 2 * top as usize + ((rest >> (top - 1)) & 1) as usize;
+    (symbol, (top - 1) as u8, rest & ((1 << (top - 1)) - 1))
+}
+
+/// Reads a length or distance back out, from its bucket's symbol and the extra bits after it
+///
+/// ## Arguments
+///
+/// * `symbol`: the bucket
+/// * `bits`: the payload, with the extra bits next
+fn unbucket(symbol: usize, bits: &mut BitWindow<&[u8]>) -> Result<usize> {
+    if symbol < 4 {
+        return Ok(symbol + 1);
+    }
+    let top = (symbol / 2) as u32;
+    let extra_len = top - 1;
+    bits.refill()?;
+    let extra = bits.peek().checked_shr(64 - extra_len).unwrap_or(0);
+    if !bits.consume(extra_len) {
+        return Err(Error::Corrupt("payload is truncated"));
+    }
+    let base = (2 | (symbol & 1) as u64) << extra_len;
+    Ok((base + extra + 1) as usize)
+}
+
+/// Reads the next symbol's code
+///
+/// ## Arguments
+///
+/// * `bits`: the payload, with a code next
+/// * `table`: the code's decoding table
+fn read_symbol(bits: &mut BitWindow<&[u8]>, table: &DecodeTable) -> Result<usize> {
+    bits.refill()?;
+    let (symbol, len) = table
+        .lookup(bits.peek())
+        .ok_or(Error::Corrupt("bits don't match any code"))?;
+    if !bits.consume(len) {
+        return Err(Error::Corrupt("payload is truncated"));
+    }
+    Ok(symbol as usize)
+}
+
+/// Works out the code lengths for an alphabet, with `None` for symbols that never come up (and none of those at the
+/// end)
+///
+/// ## Arguments
+///
+/// * `counts`: how often each symbol comes up
+fn lengths_for(counts: &[u64]) -> Vec<Option<u8>> {
+    let mut symbols: Vec<(usize, u64)> = counts
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|&(_, n)| n > 0)
+        .collect();
+    symbols.sort_unstable_by_key(|&(symbol, n)| (n, symbol));
+    let mut lengths: Vec<u64> = symbols.iter().map(|&(_, n)| n).collect();
+    code_lengths(&mut lengths);
+    limit_lengths(&mut lengths, MAX_CODE_LEN as u64);
+    let used = symbols
+        .iter()
+        .map(|&(symbol, _)| symbol + 1)
+        .max()
+        .unwrap_or(0);
+    let mut by_symbol = vec![None; used];
+    for (&(symbol, _), len) in symbols.iter().zip(lengths) {
+        // a lone symbol would get an empty code otherwise
+        by_symbol[symbol] = Some((len as u8).max(1));
+    }
+    by_symbol
+}
+
+/// Reads an alphabet's code lengths out of the header, making sure they make a code that can be decoded
+///
+/// ## Arguments
+///
+/// * `input`: what's left of the input
+/// * `count`: how many entries there are
+fn read_lengths(input: &mut &[u8], count: usize) -> Result<Vec<Option<u8>>> {
+    let lengths: Vec<Option<u8>> = (0..count)
+        .map(|_| take::<1>(input).map(|[entry]| entry.checked_sub(1)))
+        .collect::<Result<_>>()?;
+    let used: Vec<u8> = lengths.iter().flatten().copied().collect();
+    let complete = match used[..] {
+        [] => true,
+        [len] => len == 1,
+        _ => {
+            // each code of length `len` uses up 2^-len of the code space, which has to come to exactly 1
+            let mut total = 0u128;
+            for &len in &used {
+                if len == 0 || len > MAX_CODE_LEN {
+                    return Err(Error::BadHeader("code length out of range"));
+                }
+                total += 1 << (MAX_CODE_LEN - len);
+            }
+            total == 1 << MAX_CODE_LEN
+        }
+    };
+    match complete {
+        true => Ok(lengths),
+        false => Err(Error::BadHeader("code lengths don't make a complete code")),
+    }
+}
+
+/// Fills in the canonical codes for an alphabet's code lengths, with each symbol as the char of the same number
+///
+/// ## Arguments
+///
+/// * `lengths`: each symbol's code length, if it has one
+fn codes_for(lengths: &[Option<u8>]) -> HashMap<char, String> {
+    let lengths: Vec<(char, u64)> = lengths
+        .iter()
+        .enumerate()
+        .filter_map(|(symbol, len)| Some((char::from_u32(symbol as u32)?, (*len)? as u64)))
+        .collect();
+    canonical_codes(&lengths)
+}
+
+/// Turns codes into their bits, right-aligned, and lengths, indexed by symbol (with nothing for symbols that have
+/// no code)
+///
+/// ## Arguments
+///
+/// * `codes`: the codes, from `codes_for()`
+/// * `symbols`: how many symbols the alphabet has
+fn bits_for(codes: &HashMap<char, String>, symbols: usize) -> Vec<(u64, u8)> {
+    let mut bits = vec![(0, 0); symbols];
+    for (&symbol, code) in codes {
+        let value = code
+            .bytes()
+            .fold(0, |value, bit| value << 1 | (bit == b'1') as u64);
+        bits[symbol as usize] = (value, code.len() as u8);
+    }
+    bits
+}
+
+/// Takes the next `N` bytes off the front of the input
+///
+/// ## Arguments
+///
+/// * `input`: what's left of the input
+fn take<const N: usize>(input: &mut &[u8]) -> Result<[u8; N]> {
+    if input.len() < N {
+        return Err(Error::BadHeader("header is truncated"));
+    }
+    let (bytes, rest) = input.split_at(N);
+    *input = rest;
+    Ok(bytes.try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bucket_test() {
+        for value in 1..=MAX_WINDOW {
+            let (symbol, extra_len, extra) = bucket(value);
+            assert!(symbol < DISTANCE_CODES);
+            assert!(extra < 1 << extra_len);
+            if value <= MAX_MATCH - MIN_MATCH + 1 {
+                assert!(symbol < LITERAL_CODES - 256);
+            }
+            let mut bytes = Vec::new();
+            let mut bits = BitWriter::new();
+            bits.push_bits(extra as u64, extra_len, &mut bytes);
+            bits.align(&mut bytes);
+            let mut window = BitWindow::new(&bytes[..], bytes.len() as u64);
+            assert_eq!(unbucket(symbol, &mut window).unwrap(), value);
+            // the big ones are all alike, and take a while in a debug build
+            if value > 1 << 16 {
+                break;
+            }
+        }
+        assert_eq!(bucket(MAX_WINDOW).0, DISTANCE_CODES - 1);
+        assert_eq!(bucket(MAX_MATCH - MIN_MATCH + 1).0, LITERAL_CODES - 256 - 1);
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let text = "whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune, \
+                    or to take arms against a sea of troubles, and by opposing end them"
+            .repeat(50);
+        let random: Vec<u8> = (0..20_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let runs = [&[0u8; 1000][..], &[1; 10], &[0; 300]].concat();
+        for window in [0, 1, 4, 100, 32 * 1024, usize::MAX] {
+            let options = MatchOptions {
+                window,
+                ..MatchOptions::default()
+            };
+            for data in [
+                &b""[..],
+                b"a",
+                b"aaaa",
+                b"abcabcabcabc",
+                text.as_bytes(),
+                &random,
+                &runs,
+            ] {
+                let compressed = compress_with(data, options);
+                assert_eq!(decompress(&compressed).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn ratio_test() {
+        let text = "whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune, \
+                    or to take arms against a sea of troubles, and by opposing end them"
+            .repeat(50);
+        let plain = crate::container::compress_bytes(text.as_bytes(), &Default::default()).unwrap();
+        // the repeats all turn into matches, which byte-at-a-time codes can't do anything with
+        let compressed = compress(text.as_bytes());
+        assert!(compressed.len() < plain.len() / 8);
+        assert!(compressed.len() < crate::context::compress(text.as_bytes()).len() / 5);
+        // but not if they're further back than the window
+        let mut seed = 12345u64;
+        let random: Vec<u8> = (0..5000)
+            .map(|_| {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (seed >> 33) as u8
+            })
+            .collect();
+        let twice = random.repeat(2);
+        let near = compress_with(
+            &twice,
+            MatchOptions {
+                window: 5000,
+                ..MatchOptions::default()
+            },
+        );
+        let far = compress_with(
+            &twice,
+            MatchOptions {
+                window: 4999,
+                ..MatchOptions::default()
+            },
+        );
+        assert!(near.len() < random.len() * 6 / 5);
+        assert!(far.len() > random.len() * 9 / 5);
+    }
+
+    #[test]
+    fn corrupt_test() {
+        let data = b"dagoth ur was a hotep".repeat(10);
+        let compressed = compress(&data);
+        assert!(matches!(
+            decompress(&compressed[..5]),
+            Err(Error::BadHeader(_))
+        ));
+        assert!(matches!(
+            decompress(&compressed[..compressed.len() - 1]),
+            Err(Error::Corrupt(_))
+        ));
+        let mut longer = compressed.clone();
+        longer.push(0);
+        assert!(matches!(decompress(&longer), Err(Error::Corrupt(_))));
+        // a code length that leaves a gap in the code
+        let mut gap = compressed.clone();
+        let first = gap[10..].iter().position(|&entry| entry != 0).unwrap() + 10;
+        gap[first] += 1;
+        assert!(matches!(decompress(&gap), Err(Error::BadHeader(_))));
+        // and too many codes for there to be symbols
+        let mut too_many = compressed;
+        too_many[8..10].copy_from_slice(&1000u16.to_le_bytes());
+        assert!(matches!(decompress(&too_many), Err(Error::BadHeader(_))));
+    }
+    #[test]
+    fn lone_symbol_test() {
+        // a lone symbol still takes a bit each, so a header claiming far more of it than the payload holds just runs
+        // out of payload, rather than spinning on empty codes forever
+        let lone = compress(b"a");
+        assert_eq!(lone.len(), 8 + 2 + 98 + 1 + 1);
+        assert_eq!(decompress(&lone).unwrap(), b"a");
+        let mut bomb = lone.clone();
+        bomb[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(decompress(&bomb), Err(Error::Corrupt(_))));
+        // and an empty code is no code at all
+        let mut empty_code = lone;
+        empty_code[10 + usize::from(b'a')] = 1;
+        assert!(matches!(decompress(&empty_code), Err(Error::BadHeader(_))));
+        // a run of one byte only ever has the one distance, which gets its 1-bit code too
+        let runs = compress(&[0; 1000]);
+        assert_eq!(decompress(&runs).unwrap(), [0; 1000]);
+    }
+}
@@ -0,0 +1,184 @@
+//! A frequency model that only builds its tree and codes when they're needed
+//!
+//! Building a tree costs far more than counting a char, so something that keeps tweaking its frequencies (an editor
+//! showing the codes as someone types, say) shouldn't rebuild after every tweak. A `Model` holds on to the tree and
+//! codes it built last, and `update()` just counts and throws them away; they get built again the next time
+//! something asks for them, however many updates there were in between.
+
+use crate::btree::HuffTree;
+use crate::codebook::Codebook;
+use crate::error::Result;
+use crate::freq::FrequencyMap;
+
+/// Char frequencies, with the tree and codes built from them the last time they were asked for
+#[derive(Default)]
+pub struct Model {
+    /// The frequencies
+    freqs: FrequencyMap,
+    /// The tree and codes, if they've been built since the frequencies last changed
+    built: Option<(HuffTree, Codebook)>,
+    /// How many times the tree's been built
+    builds: u64,
+}
+
+impl Model {
+    /// Creates a new model with no frequencies
+    pub fn new() -> Self {
+        Model::default()
+    }
+
+    /// Counts one more occurrence of a char, which throws away the tree and codes
+    ///
+    /// ## Arguments
+    ///
+    /// * `ch`: the char to count
+    pub fn update(&mut self, ch: char) {
+        self.freqs.add(ch);
+        self.built = None;
+    }
+
+    /// Counts every char of a string, throwing away the tree and codes (once)
+    ///
+    /// ## Arguments
+    ///
+    /// * `input`: the string to count
+    pub fn update_str(&mut self, input: &str) {
+        self.freqs.add_str(input);
+        self.built = None;
+    }
+
+    /// Counts every byte of a slice (as chars 0 through 255), throwing away the tree and codes (once)
+    ///
+    /// ## Arguments
+    ///
+    /// * `input`: the bytes to count
+    pub fn update_bytes(&mut self, input: &[u8]) {
+        self.freqs.add_bytes(input);
+        self.built = None;
+    }
+
+    /// Gets the frequencies
+    pub fn freqs(&self) -> &FrequencyMap {
+        &self.freqs
+    }
+
+    /// Whether the tree and codes are built, i.e. nothing's been counted since they were last asked for
+    pub fn is_built(&self) -> bool {
+        self.built.is_some()
+    }
+
+    /// How many times the tree's been built, for seeing how much rebuilding the laziness saved
+    pub fn builds(&self) -> u64 {
+        self.builds
+    }
+
+    /// Gets the tree, building it first if the frequencies have changed
+    pub fn tree(&mut self) -> &HuffTree {
+        &self.build().0
+    }
+
+    /// Gets the codes, building them first if the frequencies have changed
+    pub fn codebook(&mut self) -> &Codebook {
+        &self.build().1
+    }
+
+    /// Encodes a string with the current codes, as a '0'/'1' string
+    ///
+    /// Fails with `Error::UnknownSymbol` if a char hasn't been counted.
+    ///
+    /// ## Arguments
+    ///
+    /// * `input`: the string to encode
+    pub fn encode(&mut self, input: &str) -> Result<String> {
+        self.codebook().encode(input)
+    }
+
+    /// Decodes a '0'/'1' string with the current tree
+    ///
+    /// This goes through `HuffTree::decode_into_slice()`, so bits that aren't '0' or '1', or that don't make up whole
+    /// codes, give `Error::Corrupt` rather than garbage. That includes any bits at all when only one char has been
+    /// counted, since its code is empty.
+    ///
+    /// ## Arguments
+    ///
+    /// * `encoded_str`: the string to decode
+    pub fn decode(&mut self, encoded_str: &str) -> Result<String> {
+        // every code's at least a bit long, and every char's at most 4 bytes of UTF-8
+        let mut out = vec![0; encoded_str.len() * 4];
+        let len = self.tree().decode_into_slice(encoded_str, &mut out)?;
+        out.truncate(len);
+        Ok(String::from_utf8(out).expect("decoded chars are always UTF-8"))
+    }
+
+    /// Builds the tree and codes, unless they're already built
+    fn build(&mut self) -> &(HuffTree, Codebook) {
+        if self.built.is_none() {
+            let mut tree = HuffTree::new();
+            tree.populate_tree(self.freqs.as_map());
+            let codebook = Codebook::from(tree.generate_huffman_map());
+            self.builds += 1;
+            self.built = Some((tree, codebook));
+        }
+        self.built.as_ref().unwrap()
+    }
+}
+
+impl From<FrequencyMap> for Model {
+    fn from(freqs: FrequencyMap) -> Self {
+        Model {
+            freqs,
+            ..Model::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn lazy_test() {
+        let mut model = Model::new();
+        for ch in "dagoth ur was a hotep".chars() {
+            model.update(ch);
+        }
+        // nothing's built until something asks
+        assert!(!model.is_built());
+        assert_eq!(model.builds(), 0);
+        let encoded = model.encode("a hotep").unwrap();
+        assert_eq!(model.decode(&encoded).unwrap(), "a hotep");
+        assert_eq!(
+            model.codebook(),
+            &Codebook::from_freqs(&HuffTree::find_input_freqs("dagoth ur was a hotep"))
+        );
+        assert!(model.is_built());
+        assert_eq!(model.builds(), 1);
+        // any number of updates only costs one rebuild
+        for _ in 0..100 {
+            model.update('z');
+        }
+        model.update_str("zzz");
+        assert!(!model.is_built());
+        assert!(model.codebook().get('z').unwrap().len() <= 2);
+        model.tree();
+        assert_eq!(model.builds(), 2);
+        assert_eq!(model.freqs().get('z'), 103);
+
+        let model = Model::from(FrequencyMap::from("aab"));
+        assert!(!model.is_built());
+        assert_eq!(model.freqs().total(), 3);
+    }
+
+    #[test]
+    fn decode_test() {
+        let mut model = Model::from(FrequencyMap::from("ab"));
+        assert!(matches!(model.decode("012"), Err(Error::Corrupt(_))));
+        assert_eq!(model.decode("").unwrap(), "");
+        // a lone char's code is empty, so any bits at all are corrupt (this used to hang)
+        let mut model = Model::new();
+        model.update('a');
+        assert!(matches!(model.decode("0"), Err(Error::Corrupt(_))));
+        assert_eq!(model.decode("").unwrap(), "");
+    }
+}
@@ -0,0 +1,1023 @@
+//! Transforms that can go in front of Huffman coding, to reshape the data into something it codes better
+//!
+//! Huffman coding only ever sees how often each byte comes up, so anything that makes a few bytes much more common
+//! than the rest helps it. A `Pipeline` is a list of `Stage`s, each one reversible, that get run over the data in
+//! order before it's coded, and then undone in reverse order after it's decoded.
+//!
+//! Move-to-front (`Stage::MoveToFront`) keeps a list of every byte value, most recently seen first, and swaps each
+//! byte for where it is in the list (and then moves it to the front). Bytes that keep coming back soon after they
+//! were last seen turn into small numbers, so the output's mostly 0s, 1s and 2s even where the input's spread out
+//! over the whole alphabet, as long as it's locally repetitive.
+//!
+//! The Burrows–Wheeler transform (`Stage::Bwt`) is what makes data locally repetitive: it sorts every rotation of a
+//! block, and keeps the last byte of each, in order, along with where the unrotated block ended up (a 4-byte index
+//! in front of each block). Bytes that come before the same context end up together, so text turns into long runs of
+//! a few bytes at a time, which move-to-front turns into long runs of 0s. Run-length coding (`Stage::RunLength`)
+//! then shortens those runs: any 4 of the same byte in a row are followed by how many more of it there are (up to
+//! 255). That's bzip2's pipeline (`Pipeline::bzip2()`), minus its fancier entropy coding.
+//!
+//! Delta coding (`Stage::Delta8`, `Stage::Delta16` and `Stage::Delta32`) is for streams of numbers instead:
+//! telemetry, PCM audio, sensor readings, anything where each sample is usually close to the one before. It reads the
+//! data as little-endian samples of 1, 2 or 4 bytes and swaps each one for how far it is from the one before (the
+//! first one from 0), zig-zagged so that small steps either way come out as small unsigned numbers (0, -1, 1, -2, ...
+//! turn into 0, 1, 2, 3, ...). A slowly-changing signal spread out over thousands of values turns into a few small
+//! residuals, which Huffman codes far better; for samples wider than a byte, the high bytes of the residuals are
+//! nearly all 0. Any bytes left over after the last whole sample are kept as they are, and blocks (see
+//! `EncodeOptions::block_size`) should be a whole number of samples, so every block starts on one.
+//!
+//! Golomb coding (`Stage::Golomb8`, `Stage::Golomb16` and `Stage::Golomb32`) reads the data as samples the same way,
+//! and codes them with a Golomb or Rice code (see the `golomb` module), whichever suits them better, which is for
+//! samples that are mostly small, like `Delta16`'s residuals. Any bytes left over after the last whole sample go in
+//! front, after a byte saying how many there are.
+//!
+//! An `EncodeOptions::pipeline` gets recorded in every header it's used for, as the `extensions::PIPELINE`
+//! extension, so decompressing knows what to undo (see the `container` module).
+
+use crate::error::{Error, Result};
+use crate::extensions::Value;
+use crate::golomb;
+use std::convert::TryInto;
+use std::fmt;
+use std::str::FromStr;
+
+/// How many bytes the Burrows–Wheeler transform sorts at a time
+pub const BWT_BLOCK_SIZE: usize = 1 << 20;
+
+/// One reversible transform
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// Swaps every byte for how many other bytes have been seen since it last was
+    MoveToFront,
+    /// Sorts every rotation of each block, keeping the last byte of each
+    Bwt,
+    /// Shortens every run of 4 or more of the same byte to 4 and a count
+    RunLength,
+    /// Swaps every byte for how far it is from the one before, zig-zagged
+    Delta8,
+    /// Swaps every 16-bit little-endian sample for how far it is from the one before, zig-zagged
+    Delta16,
+    /// Swaps every 32-bit little-endian sample for how far it is from the one before, zig-zagged
+    Delta32,
+    /// Golomb or Rice codes every byte
+    Golomb8,
+    /// Golomb or Rice codes every 16-bit little-endian sample
+    Golomb16,
+    /// Golomb or Rice codes every 32-bit little-endian sample
+    Golomb32,
+}
+
+impl Stage {
+    /// Every stage there is, in the order of their IDs
+    const ALL: [Stage; 9] = [
+        Stage::MoveToFront,
+        Stage::Bwt,
+        Stage::RunLength,
+        Stage::Delta8,
+        Stage::Delta16,
+        Stage::Delta32,
+        Stage::Golomb8,
+        Stage::Golomb16,
+        Stage::Golomb32,
+    ];
+
+    /// The number the header stores the stage as
+    fn id(self) -> u64 {
+        match self {
+            Stage::MoveToFront => 0,
+            Stage::Bwt => 1,
+            Stage::RunLength => 2,
+            Stage::Delta8 => 3,
+            Stage::Delta16 => 4,
+            Stage::Delta32 => 5,
+            Stage::Golomb8 => 6,
+            Stage::Golomb16 => 7,
+            Stage::Golomb32 => 8,
+        }
+    }
+
+    /// The stage's short name, which is how it gets written out and parsed
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::MoveToFront => "mtf",
+            Stage::Bwt => "bwt",
+            Stage::RunLength => "rle",
+            Stage::Delta8 => "delta8",
+            Stage::Delta16 => "delta16",
+            Stage::Delta32 => "delta32",
+            Stage::Golomb8 => "golomb8",
+            Stage::Golomb16 => "golomb16",
+            Stage::Golomb32 => "golomb32",
+        }
+    }
+
+    /// The most bytes `forward()` can turn a given number of bytes into
+    ///
+    /// ## Arguments
+    ///
+    /// * `len`: how many bytes go in
+    pub fn max_len(self, len: u64) -> u64 {
+        match self {
+            Stage::MoveToFront => len,
+            Stage::Bwt => len.saturating_add(len.div_ceil(BWT_BLOCK_SIZE as u64) * 4),
+            // at worst, every fifth byte is a count of 0
+            Stage::RunLength => len.saturating_add(len / 4),
+            Stage::Delta8 | Stage::Delta16 | Stage::Delta32 => len,
+            // no value takes more than 96 bits, and the leftover bytes come with a count and the code's header
+            Stage::Golomb8 => golomb_max_len(len, 1),
+            Stage::Golomb16 => golomb_max_len(len, 2),
+            Stage::Golomb32 => golomb_max_len(len, 4),
+        }
+    }
+
+    /// Runs the stage over some data
+    ///
+    /// ## Arguments
+    ///
+    /// * `data`: the bytes to transform
+    pub fn forward(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Stage::MoveToFront => move_to_front(data),
+            Stage::Bwt => bwt(data),
+            Stage::RunLength => run_length(data),
+            Stage::Delta8 => delta(data, 1),
+            Stage::Delta16 => delta(data, 2),
+            Stage::Delta32 => delta(data, 4),
+            Stage::Golomb8 => golomb(data, 1),
+            Stage::Golomb16 => golomb(data, 2),
+            Stage::Golomb32 => golomb(data, 4),
+        }
+    }
+
+    /// Undoes the stage, failing if the data couldn't have come out of `forward()`
+    ///
+    /// ## Arguments
+    ///
+    /// * `data`: the transformed bytes
+    pub fn inverse(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Stage::MoveToFront => Ok(move_to_front_inverse(data)),
+            Stage::Bwt => bwt_inverse(data),
+            Stage::RunLength => run_length_inverse(data),
+            Stage::Delta8 => Ok(delta_inverse(data, 1)),
+            Stage::Delta16 => Ok(delta_inverse(data, 2)),
+            Stage::Delta32 => Ok(delta_inverse(data, 4)),
+            Stage::Golomb8 => golomb_inverse(data, 1),
+            Stage::Golomb16 => golomb_inverse(data, 2),
+            Stage::Golomb32 => golomb_inverse(data, 4),
+        }
+    }
+}
+
+/// Its short name
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// From its short name
+impl FromStr for Stage {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Stage::ALL
+            .iter()
+            .copied()
+            .find(|stage| stage.name() == name)
+            .ok_or(Error::Unsupported("unknown pipeline stage"))
+    }
+}
+
+/// A list of stages, run in order before coding and undone in reverse order after decoding
+///
+/// An empty pipeline (the default) leaves the data alone.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Pipeline {
+    /// The stages, in the order they get run
+    stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    /// Creates a new empty pipeline
+    pub fn new() -> Self {
+        Pipeline::default()
+    }
+
+    /// Creates bzip2's pipeline: the Burrows–Wheeler transform, then move-to-front, then run-length coding
+    pub fn bzip2() -> Self {
+        Pipeline::new()
+            .then(Stage::Bwt)
+            .then(Stage::MoveToFront)
+            .then(Stage::RunLength)
+    }
+
+    /// Adds a stage to the end of the pipeline
+    ///
+    /// ## Arguments
+    ///
+    /// * `stage`: the stage to run after the ones already there
+    pub fn then(mut self, stage: Stage) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    /// Gets the stages, in the order they get run
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages
+    }
+
+    /// Whether there are no stages at all
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// The most bytes `forward()` can turn a given number of bytes into
+    ///
+    /// ## Arguments
+    ///
+    /// * `len`: how many bytes go in
+    pub fn max_len(&self, len: u64) -> u64 {
+        self.stages
+            .iter()
+            .fold(len, |len, stage| stage.max_len(len))
+    }
+
+    /// Runs every stage over some data, in order
+    ///
+    /// ## Arguments
+    ///
+    /// * `data`: the bytes to transform
+    pub fn forward(&self, data: &[u8]) -> Vec<u8> {
+        let mut data = data.to_vec();
+        for stage in &self.stages {
+            data = stage.forward(&data);
+        }
+        data
+    }
+
+    /// Undoes every stage, in reverse order
+    ///
+    /// ## Arguments
+    ///
+    /// * `data`: the transformed bytes
+    pub fn inverse(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut data = data.to_vec();
+        for stage in self.stages.iter().rev() {
+            data = stage.inverse(&data)?;
+        }
+        Ok(data)
+    }
+}
+
+impl From<Vec<Stage>> for Pipeline {
+    fn from(stages: Vec<Stage>) -> Self {
+        Pipeline { stages }
+    }
+}
+
+/// The stages' names, in order, separated by commas (e.g. `bwt,mtf,rle`)
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (k, stage) in self.stages.iter().enumerate() {
+            write!(f, "{}{}", if k == 0 { "" } else { "," }, stage)?;
+        }
+        Ok(())
+    }
+}
+
+/// From the stages' names, separated by commas (or `bzip2`, for `Pipeline::bzip2()`, or nothing at all, for no
+/// stages)
+impl FromStr for Pipeline {
+    type Err = Error;
+
+    fn from_str(names: &str) -> Result<Self> {
+        match names {
+            "" => Ok(Pipeline::new()),
+            "bzip2" => Ok(Pipeline::bzip2()),
+            names => names
+                .split(',')
+                .map(|name| name.trim().parse())
+                .collect::<Result<Vec<Stage>>>()
+                .map(Pipeline::from),
+        }
+    }
+}
+
+impl Pipeline {
+    /// The pipeline as the header's `PIPELINE` extension stores it: an array of the stages' IDs
+    pub(crate) fn to_value(&self) -> Value {
+        Value::Array(
+            self.stages
+                .iter()
+                .map(|stage| Value::Uint(stage.id()))
+                .collect(),
+        )
+    }
+
+    /// Reads a pipeline back out of the header's `PIPELINE` extension
+    ///
+    /// ## Arguments
+    ///
+    /// * `value`: the extension's value
+    pub(crate) fn from_value(value: &Value) -> Result<Self> {
+        let ids = match value {
+            Value::Array(ids) => ids,
+            _ => return Err(Error::BadHeader("pipeline extension isn't an array")),
+        };
+        ids.iter()
+            .map(|id| match id {
+                Value::Uint(id) => Stage::ALL
+                    .get(*id as usize)
+                    .copied()
+                    .ok_or(Error::Unsupported("unknown pipeline stage")),
+                _ => Err(Error::BadHeader("pipeline stage isn't an unsigned int")),
+            })
+            .collect::<Result<Vec<Stage>>>()
+            .map(Pipeline::from)
+    }
+}
+
+/// Swaps every byte for where it is in a list of byte values, most recently seen first
+///
+/// ## Arguments
+///
+/// * `data`: the bytes to transform
+pub fn move_to_front(data: &[u8]) -> Vec<u8> {
+    let mut order: [u8; 256] = std::array::from_fn(|i| i as u8);
+    data.iter()
+        .map(|&byte| {
+            // every byte value's somewhere in the list
+            let at = order.iter().position(|&b| b == byte).unwrap();
+            order.copy_within(..at, 1);
+            order[0] = byte;
+            at as u8
+        })
+        .collect()
+}
+
+/// Undoes `move_to_front()` (which any bytes at all could have come out of)
+///
+/// ## Arguments
+///
+/// * `data`: the transformed bytes
+pub fn move_to_front_inverse(data: &[u8]) -> Vec<u8> {
+    let mut order: [u8; 256] = std::array::from_fn(|i| i as u8);
+    data.iter()
+        .map(|&at| {
+            let byte = order[at as usize];
+            order.copy_within(..at as usize, 1);
+            order[0] = byte;
+            byte
+        })
+        .collect()
+}
+
+/// Runs the Burrows–Wheeler transform over each `BWT_BLOCK_SIZE` block, each one coming out as the index of the
+/// unrotated block among the sorted rotations (a `u32`), then the last byte of every rotation
+///
+/// ## Arguments
+///
+/// * `data`: the bytes to transform
+pub fn bwt(data: &[u8]) -> Vec<u8> {
+    bwt_blocks(data, BWT_BLOCK_SIZE)
+}
+
+/// Runs the Burrows–Wheeler transform over each block of a given size, for `bwt()`
+///
+/// ## Arguments
+///
+/// * `data`: the bytes to transform
+/// * `block_size`: how many bytes get sorted at a time
+fn bwt_blocks(data: &[u8], block_size: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len().div_ceil(block_size) * 4);
+    for block in data.chunks(block_size) {
+        let rotations = sort_rotations(block);
+        let primary = rotations.iter().position(|&start| start == 0).unwrap();
+        out.extend_from_slice(&(primary as u32).to_le_bytes());
+        let n = block.len();
+        out.extend(
+            rotations
+                .iter()
+                .map(|&start| block[(start as usize + n - 1) % n]),
+        );
+    }
+    out
+}
+
+/// Undoes `bwt()`, failing if a block's index is out of range
+///
+/// ## Arguments
+///
+/// * `data`: the transformed bytes
+pub fn bwt_inverse(data: &[u8]) -> Result<Vec<u8>> {
+    bwt_inverse_blocks(data, BWT_BLOCK_SIZE)
+}
+
+/// Undoes `bwt_blocks()`
+///
+/// ## Arguments
+///
+/// * `data`: the transformed bytes
+/// * `block_size`: how many bytes got sorted at a time
+fn bwt_inverse_blocks(data: &[u8], block_size: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    for chunk in data.chunks(block_size + 4) {
+        if chunk.len() <= 4 {
+            return Err(Error::Corrupt("BWT block is truncated"));
+        }
+        let (primary, last) = chunk.split_at(4);
+        let primary = u32::from_le_bytes(primary.try_into().unwrap()) as usize;
+        if primary >= last.len() {
+            return Err(Error::Corrupt("BWT block's index is out of range"));
+        }
+        // where each row's rotation, rotated right by one more, ends up: the first row starting with its last byte,
+        // counting up through the rows before it that end in the same byte
+        let mut starts = [0u32; 256];
+        for &byte in last {
+            starts[byte as usize] += 1;
+        }
+        let mut total = 0;
+        for start in starts.iter_mut() {
+            total += std::mem::replace(start, total);
+        }
+        let next: Vec<u32> = last
+            .iter()
+            .map(|&byte| {
+                let row = starts[byte as usize];
+                starts[byte as usize] += 1;
+                row
+            })
+            .collect();
+        // the unrotated block ends with the last byte of its row, and each row before that is the one it points to
+        let start = out.len();
+        out.resize(start + last.len(), 0);
+        let mut row = primary;
+        for byte in out[start..].iter_mut().rev() {
+            *byte = last[row];
+            row = next[row] as usize;
+        }
+    }
+    Ok(out)
+}
+
+/// Sorts every rotation of a block, giving back where each one starts
+///
+/// This doubles how many bytes of each rotation it's sorted by, every round, until they're all told apart (or the
+/// rounds cover the whole block, for a block that's made up of some shorter block repeated), with a counting sort
+/// each round, for O(n log n) time in all.
+///
+/// ## Arguments
+///
+/// * `block`: the block
+fn sort_rotations(block: &[u8]) -> Vec<u32> {
+    let n = block.len();
+    // the rotations in order so far, and each one's class (which rotations tie on)
+    let mut order: Vec<u32> = (0..n as u32).collect();
+    order.sort_by_key(|&start| block[start as usize]);
+    let mut class = vec![0u32; n];
+    for k in 1..n {
+        let same = block[order[k] as usize] == block[order[k - 1] as usize];
+        class[order[k] as usize] = class[order[k - 1] as usize] + !same as u32;
+    }
+    let mut by_second = vec![0u32; n];
+    let mut next_class = vec![0u32; n];
+    let mut counts = vec![0usize; n];
+    let mut len = 1;
+    while len < n && class[order[n - 1] as usize] as usize + 1 < n {
+        // sorted by the second half already, so a stable counting sort by the first half sorts by both
+        for (k, &start) in order.iter().enumerate() {
+            by_second[k] = ((start as usize + n - len) % n) as u32;
+        }
+        counts.iter_mut().for_each(|count| *count = 0);
+        for &start in &class {
+            counts[start as usize] += 1;
+        }
+        let mut total = 0;
+        for count in counts.iter_mut() {
+            total += std::mem::replace(count, total);
+        }
+        for &start in &by_second {
+            let slot = &mut counts[class[start as usize] as usize];
+            order[*slot] = start;
+            *slot += 1;
+        }
+        let key = |start: u32| {
+            let start = start as usize;
+            (class[start], class[(start + len) % n])
+        };
+        next_class[order[0] as usize] = 0;
+        for k in 1..n {
+            let same = key(order[k]) == key(order[k - 1]);
+            next_class[order[k] as usize] = next_class[order[k - 1] as usize] + !same as u32;
+        }
+        std::mem::swap(&mut class, &mut next_class);
+        len *= 2;
+    }
+    order
+}
+
+/// Shortens every run of 4 or more of the same byte to 4 of it and a count of how many more there were (up to 255,
+/// after which the run starts over)
+///
+/// ## Arguments
+///
+/// * `data`: the bytes to transform
+pub fn run_length(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut rest = data;
+    while let Some(&byte) = rest.first() {
+        let run = rest
+            .iter()
+            .take(4 + 255)
+            .take_while(|&&b| b == byte)
+            .count();
+        if run >= 4 {
+            out.extend_from_slice(&[byte; 4]);
+            out.push((run - 4) as u8);
+        } else {
+            out.extend(std::iter::repeat_n(byte, run));
+        }
+        rest = &rest[run..];
+    }
+    out
+}
+
+/// Undoes `run_length()`, failing if it ends right where a count should be
+///
+/// ## Arguments
+///
+/// * `data`: the transformed bytes
+pub fn run_length_inverse(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    // the byte the current run is of, and how long it is so far
+    let (mut last, mut run) = (None, 0);
+    for &byte in data {
+        if run == 4 {
+            out.extend(std::iter::repeat_n(last.unwrap(), byte as usize));
+            run = 0;
+            continue;
+        }
+        out.push(byte);
+        if last == Some(byte) {
+            run += 1;
+        } else {
+            (last, run) = (Some(byte), 1);
+        }
+    }
+    match run {
+        4 => Err(Error::Corrupt(
+            "run-length data ends where a count should be",
+        )),
+        _ => Ok(out),
+    }
+}
+
+/// Swaps every little-endian sample of a given width for the zig-zagged difference from the one before it (the first
+/// one from 0), leaving any bytes after the last whole sample as they are
+///
+/// Panics if `width` isn't 1 to 8.
+///
+/// ## Arguments
+///
+/// * `data`: the bytes to transform
+/// * `width`: how many bytes each sample is (1 to 8)
+pub fn delta(data: &[u8], width: usize) -> Vec<u8> {
+    check_width(width);
+    let bits = width as u32 * 8;
+    let mut out = Vec::with_capacity(data.len());
+    let mut prev = 0;
+    let samples = data.chunks_exact(width);
+    let rest = samples.remainder();
+    for sample in samples {
+        let sample = read_sample(sample);
+        out.extend_from_slice(&zigzag(sample.wrapping_sub(prev), bits).to_le_bytes()[..width]);
+        prev = sample;
+    }
+    out.extend_from_slice(rest);
+    out
+}
+
+/// Undoes `delta()` (which any bytes at all could have come out of)
+///
+/// Panics if `width` isn't 1 to 8.
+///
+/// ## Arguments
+///
+/// * `data`: the transformed bytes
+/// * `width`: how many bytes each sample is (1 to 8)
+pub fn delta_inverse(data: &[u8], width: usize) -> Vec<u8> {
+    check_width(width);
+    let mut out = Vec::with_capacity(data.len());
+    let mut prev = 0u64;
+    let residuals = data.chunks_exact(width);
+    let rest = residuals.remainder();
+    for residual in residuals {
+        prev = prev.wrapping_add(unzigzag(read_sample(residual)));
+        out.extend_from_slice(&prev.to_le_bytes()[..width]);
+    }
+    out.extend_from_slice(rest);
+    out
+}
+
+/// Maps a difference between two samples of some number of bits (wrapped around to that many bits) to an unsigned
+/// number of that many bits, small either way round coming out small: 0, -1, 1, -2, 2, ... to 0, 1, 2, 3, 4, ...
+///
+/// Panics if `bits` isn't 1 to 64.
+///
+/// ## Arguments
+///
+/// * `diff`: the difference, in the low `bits` bits
+/// * `bits`: how many bits a sample is
+pub fn zigzag(diff: u64, bits: u32) -> u64 {
+    assert!(
+        (1..=64).contains(&bits),
+        "samples can only be 1 to 64 bits, not {}",
+        bits
+    );
+    // sign-extend from the sample's top bit, then fold negatives in between the positives
+    let diff = ((diff << (64 - bits)) as i64) >> (64 - bits);
+    ((diff << 1) ^ (diff >> 63)) as u64 & (u64::MAX >> (64 - bits))
+}
+
+/// Undoes `zigzag()`, giving the difference back as a `u64` (which wraps around the same way, in the low bits)
+///
+/// ## Arguments
+///
+/// * `zigzagged`: the zig-zagged difference
+pub fn unzigzag(zigzagged: u64) -> u64 {
+    (zigzagged >> 1) ^ (zigzagged & 1).wrapping_neg()
+}
+
+/// Golomb or Rice codes every little-endian sample of a given width (see `golomb::compress()`), putting any bytes
+/// after the last whole sample in front, after a byte saying how many there are
+///
+/// Panics if `width` isn't 1 to 8.
+///
+/// ## Arguments
+///
+/// * `data`: the bytes to transform
+/// * `width`: how many bytes each sample is (1 to 8)
+pub fn golomb(data: &[u8], width: usize) -> Vec<u8> {
+    check_width(width);
+    let samples = data.chunks_exact(width);
+    let rest = samples.remainder();
+    let values: Vec<u64> = samples.map(read_sample).collect();
+    let mut out = vec![rest.len() as u8];
+    out.extend_from_slice(rest);
+    out.extend_from_slice(&golomb::compress(&values));
+    out
+}
+
+/// Undoes `golomb()`, failing if the data couldn't have come out of it
+///
+/// Panics if `width` isn't 1 to 8.
+///
+/// ## Arguments
+///
+/// * `data`: the transformed bytes
+/// * `width`: how many bytes each sample is (1 to 8)
+pub fn golomb_inverse(data: &[u8], width: usize) -> Result<Vec<u8>> {
+    check_width(width);
+    let (&rest_len, data) = data
+        .split_first()
+        .ok_or(Error::Corrupt("leftover byte count is missing"))?;
+    let rest_len = rest_len as usize;
+    if rest_len >= width || rest_len > data.len() {
+        return Err(Error::Corrupt("leftover byte count is out of range"));
+    }
+    let (rest, coded) = data.split_at(rest_len);
+    let values = golomb::decompress(coded)?;
+    let mut out = Vec::with_capacity(values.len() * width + rest_len);
+    for value in values {
+        if value.checked_shr(width as u32 * 8).unwrap_or(0) != 0 {
+            return Err(Error::Corrupt("sample is wider than the stage's samples"));
+        }
+        out.extend_from_slice(&value.to_le_bytes()[..width]);
+    }
+    out.extend_from_slice(rest);
+    Ok(out)
+}
+
+/// The most bytes `golomb()` can turn a given number of bytes into
+///
+/// ## Arguments
+///
+/// * `len`: how many bytes go in
+/// * `width`: how many bytes each sample is
+fn golomb_max_len(len: u64, width: u64) -> u64 {
+    (len / width)
+        .saturating_mul(12)
+        .saturating_add(1 + len % width + 17)
+}
+
+/// Makes sure a sample's width is one that fits in a `u64`, so the shifts and slicing in `delta()`, `golomb()` and
+/// their inverses panic with something more useful than an overflow or an out-of-bounds index
+///
+/// ## Arguments
+///
+/// * `width`: how many bytes each sample is
+fn check_width(width: usize) {
+    assert!(
+        (1..=8).contains(&width),
+        "samples can only be 1 to 8 bytes wide, not {}",
+        width
+    );
+}
+
+/// Reads a little-endian sample of up to 8 bytes
+///
+/// ## Arguments
+///
+/// * `bytes`: the sample's bytes
+fn read_sample(bytes: &[u8]) -> u64 {
+    let mut sample = [0; 8];
+    sample[..bytes.len()].copy_from_slice(bytes);
+    u64::from_le_bytes(sample)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::freq::FrequencyMap;
+
+    #[test]
+    fn move_to_front_test() {
+        assert_eq!(move_to_front(b"bbbaab"), vec![98, 0, 0, 98, 0, 1]);
+        assert_eq!(move_to_front_inverse(&[98, 0, 0, 98, 0, 1]), b"bbbaab");
+        let random: Vec<u8> = (0..10_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        assert_eq!(move_to_front_inverse(&move_to_front(&random)), random);
+        // runs of the same few bytes come out as mostly small numbers, so there's less entropy to code
+        let runs: Vec<u8> = (0..100u8).flat_map(|i| vec![i; 30]).collect();
+        let mtf = move_to_front(&runs);
+        assert!(mtf.iter().filter(|&&at| at == 0).count() > mtf.len() * 9 / 10);
+        let entropy = |data: &[u8]| {
+            let mut freqs = FrequencyMap::new();
+            freqs.add_bytes(data);
+            freqs.entropy()
+        };
+        assert!(entropy(&mtf) < entropy(&runs) / 4.0);
+    }
+
+    #[test]
+    fn bwt_test() {
+        let mut banana = 3u32.to_le_bytes().to_vec();
+        banana.extend_from_slice(b"nnbaaa");
+        assert_eq!(bwt(b"banana"), banana);
+        assert_eq!(bwt_inverse(&banana).unwrap(), b"banana");
+        assert!(bwt(b"").is_empty());
+        // repeats of a shorter block have rotations that tie, which still come back out
+        for data in [&b"a"[..], b"aaaa", b"abababab", b"abcabcabcab"] {
+            assert_eq!(bwt_inverse(&bwt(data)).unwrap(), data);
+        }
+        // more than one block, each with its own index
+        let random: Vec<u8> = (0..10_500u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let transformed = bwt_blocks(&random, 1000);
+        assert_eq!(transformed.len(), random.len() + 11 * 4);
+        assert_eq!(bwt_inverse_blocks(&transformed, 1000).unwrap(), random);
+        assert_eq!(bwt_inverse(&bwt(&random)).unwrap(), random);
+        assert!(matches!(
+            bwt_inverse(&[9, 0, 0, 0, b'a']),
+            Err(Error::Corrupt(_))
+        ));
+        assert!(matches!(bwt_inverse(&[0, 0, 0, 0]), Err(Error::Corrupt(_))));
+    }
+
+    #[test]
+    fn run_length_test() {
+        assert_eq!(run_length(b"abbbbbbc"), b"abbbb\x02c");
+        assert_eq!(run_length(b"aaaa"), b"aaaa\x00");
+        assert_eq!(run_length(b"aaab"), b"aaab");
+        let long = vec![7u8; 600];
+        assert_eq!(run_length(&long).len(), 5 + 5 + 5);
+        for data in [&b""[..], b"abbbbbbc", b"aaaa", b"aaab", &long] {
+            let transformed = run_length(data);
+            assert!(transformed.len() as u64 <= Stage::RunLength.max_len(data.len() as u64));
+            assert_eq!(run_length_inverse(&transformed).unwrap(), data);
+        }
+        assert!(matches!(
+            run_length_inverse(b"aaaa"),
+            Err(Error::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn bzip2_test() {
+        let text = "whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune, \
+                    or to take arms against a sea of troubles, and by opposing end them"
+            .repeat(50);
+        let pipeline = Pipeline::bzip2();
+        let transformed = pipeline.forward(text.as_bytes());
+        assert_eq!(pipeline.inverse(&transformed).unwrap(), text.as_bytes());
+        // BWT and MTF make it mostly 0s, which RLE then squeezes down
+        let entropy = |data: &[u8]| {
+            let mut freqs = FrequencyMap::new();
+            freqs.add_bytes(data);
+            freqs.entropy() * data.len() as f64
+        };
+        assert!(entropy(&transformed) < entropy(text.as_bytes()) / 10.0);
+    }
+
+    #[test]
+    fn delta_test() {
+        for (diff, zigzagged) in [(0, 0), (u64::MAX, 1), (1, 2), (u64::MAX - 1, 3), (2, 4)] {
+            assert_eq!(zigzag(diff, 64), zigzagged);
+            assert_eq!(unzigzag(zigzagged), diff);
+        }
+        // -1 in 8 bits, and the widest steps either way
+        assert_eq!(zigzag(0xff, 8), 1);
+        assert_eq!(zigzag(0x7f, 8), 0xfe);
+        assert_eq!(zigzag(0x80, 8), 0xff);
+        assert_eq!(delta(&[10, 12, 11, 11, 5], 1), vec![20, 4, 1, 0, 11]);
+        assert_eq!(
+            delta_inverse(&[20, 4, 1, 0, 11], 1),
+            vec![10, 12, 11, 11, 5]
+        );
+        // samples wrap around, and anything after the last whole sample stays put
+        let samples: Vec<u8> = [0i16, -3, 32767, -32768]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .chain([9])
+            .collect();
+        let transformed = delta(&samples, 2);
+        assert_eq!(transformed, vec![0, 0, 5, 0, 0xfb, 0xff, 2, 0, 9]);
+        assert_eq!(delta_inverse(&transformed, 2), samples);
+        let random: Vec<u8> = (0..10_001u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        for stage in [Stage::Delta8, Stage::Delta16, Stage::Delta32] {
+            let transformed = stage.forward(&random);
+            assert_eq!(transformed.len() as u64, stage.max_len(random.len() as u64));
+            assert_eq!(stage.inverse(&transformed).unwrap(), random);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "samples can only be 1 to 8 bytes wide, not 0")]
+    fn delta_zero_width_test() {
+        delta(&[1, 2, 3], 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "samples can only be 1 to 8 bytes wide, not 9")]
+    fn delta_inverse_wide_test() {
+        delta_inverse(&[0; 18], 9);
+    }
+
+    #[test]
+    #[should_panic(expected = "samples can only be 1 to 64 bits, not 0")]
+    fn zigzag_zero_bits_test() {
+        zigzag(1, 0);
+    }
+
+    #[test]
+    fn samples_test() {
+        // a slow sine wave of 16-bit PCM, plus a little noise, is spread out over thousands of sample values, but
+        // hardly ever steps by more than a few
+        let mut seed = 1u64;
+        let pcm: Vec<u8> = (0..20_000)
+            .flat_map(|i| {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                let noise = (seed >> 61) as f64;
+                let sample = (f64::sin(i as f64 / 2000.0) * 12_000.0 + noise) as i16;
+                sample.to_le_bytes()
+            })
+            .collect();
+        let pipeline = Pipeline::new().then(Stage::Delta16);
+        let transformed = pipeline.forward(&pcm);
+        assert_eq!(pipeline.inverse(&transformed).unwrap(), pcm);
+        let entropy = |data: &[u8]| {
+            let mut freqs = FrequencyMap::new();
+            freqs.add_bytes(data);
+            freqs.entropy() * data.len() as f64
+        };
+        assert!(entropy(&transformed) < entropy(&pcm) / 2.0);
+        // coding the residuals rather than the samples themselves
+        let options = crate::container::EncodeOptions {
+            pipeline,
+            ..Default::default()
+        };
+        let plain = crate::container::compress_bytes(&pcm, &Default::default()).unwrap();
+        let compressed = crate::container::compress_bytes(&pcm, &options).unwrap();
+        assert!(compressed.len() < plain.len() / 2);
+        assert_eq!(
+            crate::container::decompress_bytes(&compressed).unwrap(),
+            pcm
+        );
+    }
+
+    #[test]
+    fn golomb_test() {
+        // 3 samples of 0, 1 and 2, with a byte left over in front
+        let transformed = golomb(&[0, 0, 1, 0, 2, 0, 9], 2);
+        assert_eq!(transformed[..2], [1, 9]);
+        assert_eq!(golomb::decompress(&transformed[2..]).unwrap(), [0, 1, 2]);
+        assert_eq!(
+            golomb_inverse(&transformed, 2).unwrap(),
+            [0, 0, 1, 0, 2, 0, 9]
+        );
+        let random: Vec<u8> = (0..10_001u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        for stage in [Stage::Golomb8, Stage::Golomb16, Stage::Golomb32] {
+            for data in [&b""[..], b"a", &random] {
+                let transformed = stage.forward(data);
+                assert!(transformed.len() as u64 <= stage.max_len(data.len() as u64));
+                assert_eq!(stage.inverse(&transformed).unwrap(), data);
+            }
+        }
+        assert!(matches!(golomb_inverse(&[], 2), Err(Error::Corrupt(_))));
+        assert!(matches!(
+            golomb_inverse(&[2, 0, 0], 2),
+            Err(Error::Corrupt(_))
+        ));
+        // a value of 256 can't be a byte
+        let too_wide = [&[0][..], &golomb::compress(&[256])].concat();
+        assert!(matches!(
+            golomb_inverse(&too_wide, 1),
+            Err(Error::Corrupt(_))
+        ));
+        assert_eq!(golomb_inverse(&too_wide, 2).unwrap(), [0, 1]);
+    }
+
+    #[test]
+    fn golomb_container_test() {
+        // residuals of a slow ramp are all tiny, so the Rice code takes a couple of bits for each
+        let pcm: Vec<u8> = (0..20_000i32)
+            .flat_map(|i| ((i / 3) as i16).to_le_bytes())
+            .collect();
+        let options = crate::container::EncodeOptions {
+            pipeline: "delta16,golomb16".parse().unwrap(),
+            ..Default::default()
+        };
+        let compressed = crate::container::compress_bytes(&pcm, &options).unwrap();
+        assert!(compressed.len() < pcm.len() / 8);
+        let header = crate::container::Header::read_from(&mut compressed.as_slice()).unwrap();
+        assert_eq!(
+            header.extensions.pipeline().unwrap().stages(),
+            [Stage::Delta16, Stage::Golomb16]
+        );
+        let decompressed = crate::container::decompress(compressed.as_slice(), Vec::new()).unwrap();
+        assert_eq!(decompressed, pcm);
+    }
+
+    #[test]
+    fn names_test() {
+        assert_eq!(Pipeline::bzip2().to_string(), "bwt,mtf,rle");
+        assert_eq!(
+            "bwt,mtf,rle".parse::<Pipeline>().unwrap(),
+            Pipeline::bzip2()
+        );
+        assert_eq!("bzip2".parse::<Pipeline>().unwrap(), Pipeline::bzip2());
+        assert_eq!(
+            "delta16, rle".parse::<Pipeline>().unwrap().stages(),
+            [Stage::Delta16, Stage::RunLength]
+        );
+        assert_eq!(
+            "delta32,golomb32"
+                .parse::<Pipeline>()
+                .unwrap()
+                .to_value()
+                .to_string(),
+            "[5, 8]"
+        );
+        assert_eq!(
+            Pipeline::from(vec![Stage::Delta8, Stage::Delta32])
+                .to_value()
+                .to_string(),
+            "[3, 5]"
+        );
+        assert!("".parse::<Pipeline>().unwrap().is_empty());
+        assert!(matches!(
+            "bwt,lz77".parse::<Pipeline>(),
+            Err(Error::Unsupported(_))
+        ));
+        let value = Pipeline::bzip2().to_value();
+        assert_eq!(value.to_string(), "[1, 0, 2]");
+        assert_eq!(Pipeline::from_value(&value).unwrap(), Pipeline::bzip2());
+        assert!(matches!(
+            Pipeline::from_value(&Value::Array(vec![Value::Uint(9)])),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(matches!(
+            Pipeline::from_value(&Value::Uint(1)),
+            Err(Error::BadHeader(_))
+        ));
+    }
+
+    #[test]
+    fn pipeline_test() {
+        let data = b"dagoth ur was a hotep".repeat(10);
+        let pipeline = Pipeline::new();
+        assert!(pipeline.is_empty());
+        assert_eq!(pipeline.forward(&data), data);
+        let pipeline = pipeline.then(Stage::MoveToFront).then(Stage::MoveToFront);
+        assert_eq!(pipeline.stages().len(), 2);
+        let transformed = pipeline.forward(&data);
+        assert_eq!(transformed, move_to_front(&move_to_front(&data)));
+        assert_eq!(pipeline.inverse(&transformed).unwrap(), data);
+    }
+}
@@ -0,0 +1,214 @@
+//! Python bindings (with the `python` feature), so the crate can be used from Python without shelling out to `huff`
+//!
+//! ```text
+//! >>> import huffman
+//! >>> compressed = huffman.compress(b"dagoth ur was a hotep")
+//! >>> huffman.decompress(compressed)
+//! b'dagoth ur was a hotep'
+//! >>> dictionary = huffman.train_dictionary([b"some", b"samples"])
+//! >>> huffman.decompress(huffman.compress(b"same", dictionary=dictionary), dictionary=dictionary)
+//! b'same'
+//! >>> huffman.Codebook.from_text("aabc").codes()
+//! {'a': '0', 'b': '10', 'c': '11'}
+//! ```
+//!
+//! Compressing and decompressing let go of the GIL while they work, so other Python threads can carry on. Errors come
+//! out as `OSError` if reading or writing failed, and `ValueError` for anything else (e.g. corrupt data).
+
+use crate::codebook::Codebook;
+use crate::container::{compress_slice, decompress_with, DecodeOptions, EncodeOptions};
+use crate::dict::Dictionary;
+use crate::error::Error;
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+impl From<Error> for PyErr {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => PyIOError::new_err(e.to_string()),
+            e => PyValueError::new_err(e.to_string()),
+        }
+    }
+}
+
+/// Reads a dictionary saved by `train_dictionary()`
+///
+/// ## Arguments
+///
+/// * `saved`: the saved dictionary
+fn load_dictionary(saved: &[u8]) -> Result<Arc<Dictionary>, Error> {
+    Dictionary::read_from(&mut &saved[..]).map(Arc::new)
+}
+
+/// `compress(data, dictionary=None, canonical=False)`: compresses some bytes into a container
+///
+/// ## Arguments
+///
+/// * `data`: the bytes to compress
+/// * `dictionary`: a dictionary from `train_dictionary()` to compress with, if any
+/// * `canonical`: whether to use canonical codes (see `EncodeOptions::canonical`)
+#[pyfunction]
+#[pyo3(signature = (data, dictionary = None, canonical = false))]
+fn compress<'py>(
+    py: Python<'py>,
+    data: &[u8],
+    dictionary: Option<&[u8]>,
+    canonical: bool,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let options = EncodeOptions {
+        dictionary: dictionary.map(load_dictionary).transpose()?,
+        canonical,
+        ..EncodeOptions::default()
+    };
+    let compressed = py.allow_threads(|| compress_slice(data, Vec::new(), &options))?;
+    Ok(PyBytes::new(py, &compressed))
+}
+
+/// `decompress(data, dictionary=None)`: decompresses a container
+///
+/// ## Arguments
+///
+/// * `data`: the container
+/// * `dictionary`: the dictionary it was compressed with, if it was
+#[pyfunction]
+#[pyo3(signature = (data, dictionary = None))]
+fn decompress<'py>(
+    py: Python<'py>,
+    data: &[u8],
+    dictionary: Option<&[u8]>,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let options = DecodeOptions {
+        dictionary: dictionary.map(load_dictionary).transpose()?,
+        ..DecodeOptions::default()
+    };
+    let decompressed = py.allow_threads(|| decompress_with(data, Vec::new(), &options))?;
+    Ok(PyBytes::new(py, &decompressed))
+}
+
+/// `train_dictionary(samples)`: trains a dictionary on a list of samples, giving it back saved as bytes
+///
+/// ## Arguments
+///
+/// * `samples`: the samples
+#[pyfunction]
+fn train_dictionary<'py>(py: Python<'py>, samples: Vec<Vec<u8>>) -> PyResult<Bound<'py, PyBytes>> {
+    let dict = Dictionary::train(samples.iter().map(Vec::as_slice)).map_err(Error::from)?;
+    let mut saved = Vec::new();
+    dict.write_to(&mut saved).map_err(Error::from)?;
+    Ok(PyBytes::new(py, &saved))
+}
+
+/// `Codebook(freqs)`: every symbol's code, built from a dict of chars to how often they show up
+#[pyclass(name = "Codebook", module = "huffman")]
+struct PyCodebook {
+    /// The codes
+    inner: Codebook,
+}
+
+#[pymethods]
+impl PyCodebook {
+    #[new]
+    fn new(freqs: HashMap<char, i32>) -> Self {
+        PyCodebook {
+            inner: Codebook::from_freqs(&freqs),
+        }
+    }
+
+    /// `Codebook.from_text(text)`: the codes for a string's chars
+    #[staticmethod]
+    fn from_text(text: &str) -> Self {
+        PyCodebook::new(crate::btree::HuffTree::find_input_freqs(text))
+    }
+
+    /// `Codebook.from_pairs(codes, complete=True)`: takes a dict of chars to codes from somewhere else, checking it
+    /// (see `Codebook::from_pairs()`)
+    #[staticmethod]
+    #[pyo3(signature = (codes, complete = true))]
+    fn from_pairs(codes: HashMap<char, String>, complete: bool) -> PyResult<Self> {
+        Ok(PyCodebook {
+            inner: Codebook::from_pairs(codes, complete)?,
+        })
+    }
+
+    /// `codebook.get(ch)`: a char's code, or `None` if it doesn't have one
+    fn get(&self, ch: char) -> Option<&str> {
+        self.inner.get(ch)
+    }
+
+    /// `codebook.codes()`: every char's code, as a dict
+    fn codes(&self) -> HashMap<char, String> {
+        self.inner.as_map().clone()
+    }
+
+    /// `codebook.encode(text)`: encodes a string, as a string of '0's and '1's
+    fn encode(&self, text: &str) -> PyResult<String> {
+        Ok(self.inner.encode(text)?)
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Codebook(<{} codes>)", self.inner.len())
+    }
+}
+
+/// The `huffman` Python module
+#[pymodule]
+fn huffman(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(compress, m)?)?;
+    m.add_function(wrap_pyfunction!(decompress, m)?)?;
+    m.add_function(wrap_pyfunction!(train_dictionary, m)?)?;
+    m.add_class::<PyCodebook>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+    use std::ffi::CString;
+
+    #[test]
+    fn python_test() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals
+                .set_item("huffman", pyo3::wrap_pymodule!(super::huffman)(py))
+                .unwrap();
+            let script = CString::new(
+                r#"
+data = b"dagoth ur was a hotep" * 10
+assert huffman.decompress(huffman.compress(data)) == data
+assert huffman.decompress(huffman.compress(data, canonical=True)) == data
+dictionary = huffman.train_dictionary([b"dagoth ur", b"was a hotep"])
+compressed = huffman.compress(data, dictionary=dictionary)
+assert huffman.decompress(compressed, dictionary=dictionary) == data
+try:
+    huffman.decompress(compressed)
+    assert False, "decompressed without the dictionary"
+except ValueError:
+    pass
+codebook = huffman.Codebook.from_text("aabc")
+assert codebook.codes() == {"a": "0", "b": "10", "c": "11"}
+assert len(codebook) == 3 and codebook.get("z") is None
+assert codebook.encode("cab") == "11010"
+assert huffman.Codebook({"a": 2, "b": 1, "c": 1}).codes() == codebook.codes()
+assert huffman.Codebook.from_pairs({"a": "0", "b": "10"}, complete=False).get("b") == "10"
+try:
+    huffman.Codebook.from_pairs({"a": "0", "b": "10"})
+    assert False, "took an incomplete code"
+except ValueError:
+    pass
+"#,
+            )
+            .unwrap();
+            py.run(&script, Some(&globals), None).unwrap();
+        });
+    }
+}
@@ -0,0 +1,112 @@
+//! Shannon–Fano codes, the top-down construction that came just before Huffman's, for comparing against it
+//!
+//! The symbols get sorted from most to least common, then split in two where the halves' totals come out closest,
+//! one half getting a 0 and the other a 1, and then each half gets split the same way, until every symbol is on its
+//! own. That's usually about as good as Huffman's, but not always: a split can't look ahead, so now and then it puts
+//! a symbol a bit deeper than it should go, and the codes come out a few percent longer. They're never shorter, since
+//! Huffman's are optimal.
+
+use std::collections::HashMap;
+
+/// Makes Shannon–Fano codes straight from char frequencies
+///
+/// Ties between equal frequencies go in char order, so the same frequencies always get the same codes. A single char
+/// gets an empty code, like it would from a tree.
+///
+/// ## Arguments
+///
+/// * `char_map`: how often each char shows up
+pub fn generate_huffman_map(char_map: &HashMap<char, i32>) -> HashMap<char, String> {
+    let mut symbols: Vec<(char, u64)> = char_map
+        .iter()
+        .map(|(&ch, &freq)| (ch, freq.max(0) as u64))
+        .collect();
+    symbols.sort_unstable_by_key(|&(ch, freq)| (std::cmp::Reverse(freq), ch));
+    let mut huffman_map = HashMap::with_capacity(symbols.len());
+    // the ranges of symbols still to be split, and the code they've got so far (kept on a stack rather than
+    // recursing, since skewed enough frequencies make the splits as deep as there are symbols)
+    let mut ranges = vec![(0, symbols.len(), String::new())];
+    while let Some((start, end, code)) = ranges.pop() {
+        match end - start {
+            0 => {}
+            1 => {
+                huffman_map.insert(symbols[start].0, code);
+            }
+            _ => {
+                let split = split_point(&symbols[start..end]) + start;
+                let (mut left, mut right) = (code.clone(), code);
+                left.push('0');
+                right.push('1');
+                ranges.push((start, split, left));
+                ranges.push((split, end, right));
+            }
+        }
+    }
+    huffman_map
+}
+
+/// Finds where to split a run of (at least two) symbols so the two halves' totals are as close as they can be, with
+/// at least one symbol on each side
+///
+/// ## Arguments
+///
+/// * `symbols`: the symbols, from most to least common
+fn split_point(symbols: &[(char, u64)]) -> usize {
+    let total: u64 = symbols.iter().map(|&(_, freq)| freq).sum();
+    let mut left = 0;
+    let mut best = (u64::MAX, 1);
+    for (i, &(_, freq)) in symbols[..symbols.len() - 1].iter().enumerate() {
+        left += freq;
+        let diff = left.abs_diff(total - left);
+        if diff < best.0 {
+            best = (diff, i + 1);
+        }
+    }
+    best.1
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::btree::HuffTree;
+
+    #[test]
+    fn shannon_fano_test() {
+        assert!(generate_huffman_map(&HashMap::new()).is_empty());
+        assert_eq!(
+            generate_huffman_map(&HuffTree::find_input_freqs("aaa"))[&'a'],
+            ""
+        );
+        // the textbook example: the first split is a b | c d e, so a gets 2 bits, where Huffman gives it just 1
+        let char_map: HashMap<char, i32> = [('a', 15), ('b', 7), ('c', 6), ('d', 6), ('e', 5)]
+            .iter()
+            .copied()
+            .collect();
+        let huffman_map = generate_huffman_map(&char_map);
+        assert_eq!(huffman_map[&'a'], "00");
+        assert_eq!(huffman_map[&'b'], "01");
+        assert_eq!(huffman_map[&'c'], "10");
+        assert_eq!(huffman_map[&'d'], "110");
+        assert_eq!(huffman_map[&'e'], "111");
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree(&char_map);
+        assert_eq!(HuffTree::encoded_len(&char_map, &huffman_map), 89);
+        assert_eq!(
+            HuffTree::encoded_len(&char_map, &hufftree.generate_huffman_map()),
+            87
+        );
+
+        // never better than Huffman, and still a complete prefix code
+        let input =
+            "whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune";
+        let char_map = HuffTree::find_input_freqs(input);
+        let huffman_map = generate_huffman_map(&char_map);
+        hufftree.populate_tree(&char_map);
+        assert!(
+            HuffTree::encoded_len(&char_map, &hufftree.generate_huffman_map())
+                <= HuffTree::encoded_len(&char_map, &huffman_map)
+        );
+        let codebook = crate::codebook::Codebook::from_pairs(huffman_map, true).unwrap();
+        assert_eq!(codebook.len(), char_map.len());
+    }
+}
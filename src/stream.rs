@@ -0,0 +1,977 @@
+//! One-pass streaming compression, for when the input can't be read twice (e.g. when it's coming from a pipe)
+//!
+//! Instead of counting frequencies up front, both ends start out with a flat model over every byte value (plus
+//! flush and end-of-stream markers), and rebuild the tree from the counts seen so far every so often. The decoder
+//! sees exactly the same symbols in exactly the same order as the encoder did, so it rebuilds exactly the same trees
+//! at exactly the same points, and nothing about the model ever needs to be written out.
+//!
+//! A flush marker is followed by padding out to the next byte boundary, so everything before it can be decoded
+//! without waiting for more bytes. The end-of-stream marker is padded the same way, and then followed by a 4-byte
+//! (little-endian) CRC-32 footer of everything that went in.
+
+use crate::bits::{bit_at, BitWriter, Sink, SliceSink};
+use crate::checksum::Crc32;
+use crate::error::{Error, Result};
+use std::cmp::Reverse;
+use std::io::{self, BufRead, Read, Write};
+
+/// The symbol marking the end of the stream; bytes go in as symbols 0 through 255, so this comes right after them
+const END_OF_STREAM: u16 = 256;
+/// The symbol marking a flush, after which the rest of the byte is padding
+const FLUSH: u16 = 257;
+/// How many symbols the model has: every byte value, plus the two markers
+const SYMBOL_COUNT: usize = 258;
+/// How many nodes the model's tree has: a leaf for every symbol, plus one for every merge
+const NODE_COUNT: usize = 2 * SYMBOL_COUNT - 1;
+/// Where the head of the model's tree is (every symbol's always in the tree, so the last merge is always the head)
+const HEAD: usize = NODE_COUNT - 1;
+/// How many symbols to wait before the first rebuild (this doubles after every rebuild, up to the max)
+const MIN_REBUILD_INTERVAL: usize = 32;
+/// The most symbols we'll go between rebuilds, once the model has settled down
+const MAX_REBUILD_INTERVAL: usize = 4096;
+/// Once the counts add up to more than this, they all get halved, which keeps them from overflowing and lets the
+/// model forget about old data as the input drifts (it also keeps the codes short: a code can only be `n` bits long
+/// if the counts add up to at least the `n`th Fibonacci number or so, so none of them come anywhere near 64 bits)
+const MAX_TOTAL_FREQ: i32 = 1 << 24;
+
+/// A node in the model's tree
+#[derive(Clone, Copy)]
+enum Node {
+    /// A symbol
+    Leaf(u16),
+    /// The indices of the children a 0 and a 1 lead to
+    Branch(u16, u16),
+}
+
+/// The adaptive model shared (in lockstep) by the encoder and decoder
+///
+/// Everything's kept in fixed-size arrays, which every rebuild just writes over, so nothing ever gets allocated once
+/// the model's been made.
+struct Model {
+    /// How many times each symbol has been seen (starting from 1, so that everything always has a code)
+    counts: [i32; SYMBOL_COUNT],
+    /// The sum of everything in `counts`
+    total: i32,
+    /// The tree built from `counts` at the last rebuild: the leaves first (from smallest count to largest), then the
+    /// merged nodes in the order they were merged, so the head's last
+    nodes: [Node; NODE_COUNT],
+    /// The count under each node in `nodes`
+    freqs: [i32; NODE_COUNT],
+    /// Each symbol's code from `nodes`, right-aligned, along with how many bits long it is
+    codes: [(u64, u8); SYMBOL_COUNT],
+    /// How many symbols have gone by since the last rebuild
+    since_rebuild: usize,
+    /// How many symbols to wait before the next rebuild
+    rebuild_interval: usize,
+}
+
+impl Model {
+    /// Creates the flat starting model
+    fn new() -> Self {
+        let mut model = Model {
+            counts: [1; SYMBOL_COUNT],
+            total: SYMBOL_COUNT as i32,
+            nodes: [Node::Leaf(0); NODE_COUNT],
+            freqs: [0; NODE_COUNT],
+            codes: [(0, 0); SYMBOL_COUNT],
+            since_rebuild: 0,
+            rebuild_interval: MIN_REBUILD_INTERVAL,
+        };
+        model.rebuild();
+        model
+    }
+
+    /// Rebuilds the tree and codes from the current counts, over the top of the old ones
+    ///
+    /// This builds exactly the tree `HuffTree::populate_tree()` would from the same counts (the format depends on
+    /// it), with the same two-queue construction and the same tie-breaking, just without any of its allocations.
+    fn rebuild(&mut self) {
+        // sort from smallest to largest count, with ties broken by symbol the same way `HuffTree` does
+        let mut order = [0u16; SYMBOL_COUNT];
+        for (i, sym) in order.iter_mut().enumerate() {
+            *sym = i as u16;
+        }
+        let counts = &self.counts;
+        order.sort_unstable_by_key(|&sym| (counts[sym as usize], Reverse(sym)));
+        for (i, &sym) in order.iter().enumerate() {
+            self.nodes[i] = Node::Leaf(sym);
+            self.freqs[i] = self.counts[sym as usize];
+        }
+        // then merge the smallest two nodes, over and over, until all that's left is the head
+        let mut next_leaf = 0;
+        let mut merged = Merged::new();
+        for parent in SYMBOL_COUNT..NODE_COUNT {
+            let right = self.take_smallest(&mut next_leaf, &mut merged);
+            let left = self.take_smallest(&mut next_leaf, &mut merged);
+            self.nodes[parent] = Node::Branch(left, right);
+            self.freqs[parent] = self.freqs[left as usize] + self.freqs[right as usize];
+            merged.push(parent as u16, &self.freqs);
+        }
+        // every node comes after its children, so going backwards gets to each parent's code before its children's
+        let mut node_codes = [(0u64, 0u8); NODE_COUNT];
+        for i in (SYMBOL_COUNT..NODE_COUNT).rev() {
+            if let Node::Branch(left, right) = self.nodes[i] {
+                let (code, len) = node_codes[i];
+                node_codes[left as usize] = (code << 1, len + 1);
+                node_codes[right as usize] = ((code << 1) | 1, len + 1);
+            }
+        }
+        for (i, &sym) in order.iter().enumerate() {
+            self.codes[sym as usize] = node_codes[i];
+        }
+        self.since_rebuild = 0;
+    }
+
+    /// Takes whichever's smaller out of the next leaf and the front of the merged nodes, preferring the merged node
+    /// on a tie (like `TieBreak::Newest`)
+    ///
+    /// ## Arguments
+    ///
+    /// * `next_leaf`: the index of the next leaf that hasn't been taken yet
+    /// * `merged`: the merged nodes waiting to be taken
+    fn take_smallest(&self, next_leaf: &mut usize, merged: &mut Merged) -> u16 {
+        match merged.front_freq(&self.freqs) {
+            Some(freq) if *next_leaf == SYMBOL_COUNT || freq <= self.freqs[*next_leaf] => {
+                merged.pop()
+            }
+            _ => {
+                *next_leaf += 1;
+                (*next_leaf - 1) as u16
+            }
+        }
+    }
+
+    /// Records one more occurrence of a symbol, rebuilding if it's time to
+    ///
+    /// ## Arguments
+    ///
+    /// * `sym`: the symbol just coded
+    fn update(&mut self, sym: u16) {
+        self.counts[sym as usize] += 1;
+        self.total += 1;
+        if self.total > MAX_TOTAL_FREQ {
+            // halve everything, rounding up so nothing ever drops to 0
+            self.total = 0;
+            for cnt in self.counts.iter_mut() {
+                *cnt = (*cnt + 1) / 2;
+                self.total += *cnt;
+            }
+        }
+        self.since_rebuild += 1;
+        if self.since_rebuild >= self.rebuild_interval {
+            self.rebuild();
+            self.rebuild_interval = (self.rebuild_interval * 2).min(MAX_REBUILD_INTERVAL);
+        }
+    }
+}
+
+/// The merged nodes waiting to be merged again while the model's tree is built, grouped into runs of equal frequency
+/// so the newest of the smallest can come out first
+struct Merged {
+    /// The queued node indices, each run taking up a range of them
+    nodes: [u16; SYMBOL_COUNT],
+    /// The range of `nodes` each run takes up
+    runs: [(usize, usize); SYMBOL_COUNT],
+    /// The first run that still has anything in it
+    front: usize,
+    /// How many runs there have been
+    len: usize,
+}
+
+impl Merged {
+    /// Creates an empty queue
+    fn new() -> Self {
+        Merged {
+            nodes: [0; SYMBOL_COUNT],
+            runs: [(0, 0); SYMBOL_COUNT],
+            front: 0,
+            len: 0,
+        }
+    }
+
+    /// The frequency of the front run, or `None` if nothing's queued
+    ///
+    /// ## Arguments
+    ///
+    /// * `freqs`: the count under each node
+    fn front_freq(&self, freqs: &[i32]) -> Option<i32> {
+        if self.front == self.len {
+            return None;
+        }
+        Some(freqs[self.nodes[self.runs[self.front].0] as usize])
+    }
+
+    /// Takes the newest node out of the front run (which mustn't be empty)
+    fn pop(&mut self) -> u16 {
+        let run = &mut self.runs[self.front];
+        run.1 -= 1;
+        let node = self.nodes[run.1];
+        if run.0 == run.1 {
+            self.front += 1;
+        }
+        node
+    }
+
+    /// Queues up a newly merged node, which is never smaller than anything already queued
+    ///
+    /// Only the back run ever grows, and it always ends at the highest index in use (it's only ever cut short if it's
+    /// also the front run), so there's always room for however many merges there are.
+    ///
+    /// ## Arguments
+    ///
+    /// * `node`: the new node's index
+    /// * `freqs`: the count under each node
+    fn push(&mut self, node: u16, freqs: &[i32]) {
+        let end = if self.front == self.len {
+            0
+        } else {
+            let (start, end) = self.runs[self.len - 1];
+            if freqs[self.nodes[start] as usize] == freqs[node as usize] {
+                self.nodes[end] = node;
+                self.runs[self.len - 1].1 += 1;
+                return;
+            }
+            end
+        };
+        self.nodes[end] = node;
+        self.runs[self.len] = (end, end + 1);
+        self.len += 1;
+    }
+}
+
+/// The IO-free core of the streaming encoder: bytes go in, compressed bytes come out
+pub struct Encoder {
+    /// The adaptive model
+    model: Model,
+    /// Holds bits that haven't made up a whole byte yet
+    bits: BitWriter,
+    /// The checksum of everything encoded so far
+    crc: Crc32,
+    /// Whether anything's been encoded since the last flush
+    unflushed: bool,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Encoder::new()
+    }
+}
+
+impl Encoder {
+    /// Creates a new encoder at the start of a stream
+    pub fn new() -> Self {
+        Encoder {
+            model: Model::new(),
+            bits: BitWriter::new(),
+            crc: Crc32::new(),
+            unflushed: false,
+        }
+    }
+
+    /// Encodes some more input, appending whatever output that produces
+    ///
+    /// Output goes out a 64-bit word at a time, so the last few bytes' worth of bits can be held back until there's a
+    /// whole word of them (or until `flush()` or `finish()`).
+    ///
+    /// ## Arguments
+    ///
+    /// * `input`: the bytes to encode
+    /// * `out`: where the compressed bytes go
+    pub fn encode(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        for &byte in input {
+            self.encode_byte(byte, out);
+        }
+    }
+
+    /// Makes everything encoded so far decodable on its own, by appending a flush marker and padding out the byte
+    ///
+    /// This costs a marker and up to 7 bits of padding, so it's a no-op if nothing's been encoded since the last
+    /// flush.
+    ///
+    /// ## Arguments
+    ///
+    /// * `out`: where the compressed bytes go
+    pub fn flush(&mut self, out: &mut Vec<u8>) {
+        self.flush_into(out);
+    }
+
+    /// Ends the stream, appending the end-of-stream marker, the padding for the last byte, and the checksum footer
+    ///
+    /// ## Arguments
+    ///
+    /// * `out`: where the compressed bytes go
+    pub fn finish(&mut self, out: &mut Vec<u8>) {
+        self.finish_into(out);
+    }
+
+    /// Encodes a single byte of input into any sink
+    fn encode_byte<S: Sink>(&mut self, byte: u8, out: &mut S) {
+        self.push_symbol(u16::from(byte), out);
+        self.crc.update(&[byte]);
+        self.unflushed = true;
+    }
+
+    /// `flush()`, but into any sink
+    fn flush_into<S: Sink>(&mut self, out: &mut S) {
+        if self.unflushed {
+            self.push_symbol(FLUSH, out);
+            self.bits.align(out);
+            self.unflushed = false;
+        }
+    }
+
+    /// `finish()`, but into any sink
+    fn finish_into<S: Sink>(&mut self, out: &mut S) {
+        self.push_symbol(END_OF_STREAM, out);
+        self.bits.align(out);
+        for byte in self.crc.value().to_le_bytes() {
+            out.push(byte);
+        }
+    }
+
+    /// Encodes one symbol and updates the model with it
+    fn push_symbol<S: Sink>(&mut self, sym: u16, out: &mut S) {
+        let (bits, len) = self.model.codes[sym as usize];
+        self.bits.push_bits(bits, len, out);
+        self.model.update(sym);
+    }
+}
+
+/// The IO-free core of the streaming decoder: compressed bytes go in, the original bytes come out
+pub struct Decoder {
+    /// The adaptive model, kept in lockstep with the encoder's
+    model: Model,
+    /// Bits that have been taken from the input but not decoded yet: either a code that got cut off at the end of
+    /// the last chunk of input, or the rest of a byte when the output filled up partway through it
+    pending: Vec<bool>,
+    /// The checksum of everything decoded so far
+    crc: Crc32,
+    /// Whether we've hit the end-of-stream marker
+    ended: bool,
+    /// The checksum footer, as far as it's been read
+    footer: [u8; 4],
+    /// How much of the footer has been read
+    footer_len: usize,
+    /// Whether the footer's been read and checked
+    done: bool,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder::new()
+    }
+}
+
+impl Decoder {
+    /// Creates a new decoder at the start of a stream
+    pub fn new() -> Self {
+        Decoder {
+            model: Model::new(),
+            // no code can be longer than there are symbols, so this never has to grow
+            pending: Vec::with_capacity(SYMBOL_COUNT + 8),
+            crc: Crc32::new(),
+            ended: false,
+            footer: [0; 4],
+            footer_len: 0,
+            done: false,
+        }
+    }
+
+    /// Whether the end of the stream has been reached (and its checksum checked out)
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Decodes some more compressed input, appending the decoded bytes and returning how many input bytes were used
+    ///
+    /// All of the input gets used unless the end of the stream turns up partway through it, in which case whatever
+    /// follows the stream's footer is left alone. Once the footer's been read, a checksum mismatch gives an error.
+    ///
+    /// ## Arguments
+    ///
+    /// * `input`: the compressed bytes
+    /// * `out`: where the decoded bytes go
+    pub fn decode(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<usize> {
+        self.decode_into(input, out)
+    }
+
+    /// `decode()`, but into any sink, stopping early (at a symbol boundary) if the sink fills up
+    fn decode_into<S: Sink>(&mut self, input: &[u8], out: &mut S) -> Result<usize> {
+        let total_bits = input.len() * 8;
+        let mut pos = 0;
+        while !self.ended {
+            if out.is_full() {
+                // stash the rest of the current byte, so the caller can carry on from a byte boundary
+                while pos % 8 != 0 {
+                    self.pending.push(bit_at(input, pos));
+                    pos += 1;
+                }
+                return Ok(pos / 8);
+            }
+            // walk down the tree as far as the input lets us, starting with whatever's pending
+            let mut node = HEAD;
+            let mut used = 0;
+            let sym = loop {
+                let (left, right) = match self.model.nodes[node] {
+                    Node::Leaf(sym) => break Some(sym),
+                    Node::Branch(left, right) => (left, right),
+                };
+                let bit = if used < self.pending.len() {
+                    self.pending[used]
+                } else if pos < total_bits {
+                    let bit = bit_at(input, pos);
+                    self.pending.push(bit);
+                    pos += 1;
+                    bit
+                } else {
+                    break None;
+                };
+                node = if bit { right } else { left } as usize;
+                used += 1;
+            };
+            match sym {
+                Some(sym) => {
+                    if sym == END_OF_STREAM || sym == FLUSH {
+                        // whatever's left of the byte is padding (pending bits always run up to a byte boundary)
+                        self.pending.clear();
+                        pos = pos.div_ceil(8) * 8;
+                        self.ended = sym == END_OF_STREAM;
+                    } else {
+                        self.pending.drain(..used);
+                        self.crc.update(&[sym as u8]);
+                        out.push(sym as u8);
+                    }
+                    self.model.update(sym);
+                }
+                // ran out of input partway through a code, so the bits stay pending until the next call
+                None => return Ok(input.len()),
+            }
+        }
+        // the footer might not all be here yet, either
+        let mut used = pos / 8;
+        while self.footer_len < 4 && used < input.len() {
+            self.footer[self.footer_len] = input[used];
+            self.footer_len += 1;
+            used += 1;
+        }
+        if self.footer_len == 4 && !self.done {
+            self.done = true;
+            let expected = u32::from_le_bytes(self.footer);
+            if expected != self.crc.value() {
+                return Err(Error::ChecksumMismatch {
+                    expected,
+                    found: self.crc.value(),
+                });
+            }
+        }
+        Ok(used)
+    }
+}
+
+/// A push-style compressor that only ever writes into caller-provided buffers
+///
+/// Output that doesn't fit in the caller's buffer waits in a small fixed-size spill buffer inside the compressor
+/// until the next call, so any size of output buffer works. None of these calls allocate anything: even the adaptive
+/// model rebuilds its tree into fixed-size tables it already has.
+pub struct Compressor {
+    /// The encoder doing the actual work
+    encoder: Encoder,
+    /// Output that didn't fit in the caller's buffer last time
+    spill: Spill,
+    /// Whether the end of the stream has been encoded
+    finished: bool,
+}
+
+impl Default for Compressor {
+    fn default() -> Self {
+        Compressor::new()
+    }
+}
+
+impl Compressor {
+    /// Creates a new compressor at the start of a stream
+    pub fn new() -> Self {
+        Compressor {
+            encoder: Encoder::new(),
+            spill: Spill::new(),
+            finished: false,
+        }
+    }
+
+    /// Compresses as much of `input` as will fit into `out`, returning (bytes consumed, bytes produced)
+    ///
+    /// ## Arguments
+    ///
+    /// * `input`: the bytes to compress
+    /// * `out`: where the compressed bytes go
+    pub fn feed(&mut self, input: &[u8], out: &mut [u8]) -> (usize, usize) {
+        let mut produced = self.spill.drain_into(out);
+        let mut consumed = 0;
+        if self.finished {
+            return (0, produced);
+        }
+        while self.spill.is_empty() && consumed < input.len() && produced < out.len() {
+            self.encoder.encode_byte(input[consumed], &mut self.spill);
+            consumed += 1;
+            produced += self.spill.drain_into(&mut out[produced..]);
+        }
+        (consumed, produced)
+    }
+
+    /// Makes everything fed so far decodable on its own (see `Encoder::flush()`), returning (bytes produced, whether
+    /// the flush is completely out); keep calling it with fresh space until it says it's done
+    ///
+    /// ## Arguments
+    ///
+    /// * `out`: where the compressed bytes go
+    pub fn flush(&mut self, out: &mut [u8]) -> (usize, bool) {
+        let mut produced = self.spill.drain_into(out);
+        if self.spill.is_empty() && !self.finished {
+            self.encoder.flush_into(&mut self.spill);
+            produced += self.spill.drain_into(&mut out[produced..]);
+        }
+        (produced, self.spill.is_empty())
+    }
+
+    /// Ends the stream, returning (bytes produced, whether the end of the stream is completely out); keep calling it
+    /// with fresh space until it says it's done
+    ///
+    /// ## Arguments
+    ///
+    /// * `out`: where the compressed bytes go
+    pub fn finish(&mut self, out: &mut [u8]) -> (usize, bool) {
+        let mut produced = self.spill.drain_into(out);
+        if self.spill.is_empty() && !self.finished {
+            self.encoder.finish_into(&mut self.spill);
+            self.finished = true;
+            produced += self.spill.drain_into(&mut out[produced..]);
+        }
+        (produced, self.finished && self.spill.is_empty())
+    }
+}
+
+/// A push-style decompressor that only ever writes into caller-provided buffers
+///
+/// Like `Compressor`, none of its calls allocate anything (the bits of a code cut off at the end of the input wait
+/// in a buffer made up front that's big enough for the longest code).
+pub struct Decompressor {
+    /// The decoder doing the actual work
+    decoder: Decoder,
+}
+
+impl Default for Decompressor {
+    fn default() -> Self {
+        Decompressor::new()
+    }
+}
+
+impl Decompressor {
+    /// Creates a new decompressor at the start of a stream
+    pub fn new() -> Self {
+        Decompressor {
+            decoder: Decoder::new(),
+        }
+    }
+
+    /// Decompresses as much of `input` as will fit into `out`, returning (bytes consumed, bytes produced)
+    ///
+    /// Input that's been consumed doesn't need to be passed in again, even if it ended partway through a code.
+    ///
+    /// ## Arguments
+    ///
+    /// * `input`: the compressed bytes
+    /// * `out`: where the decompressed bytes go
+    pub fn feed(&mut self, input: &[u8], out: &mut [u8]) -> Result<(usize, usize)> {
+        let mut sink = SliceSink::new(out);
+        let consumed = self.decoder.decode_into(input, &mut sink)?;
+        Ok((consumed, sink.len()))
+    }
+
+    /// Whether the end of the stream has been reached (and its checksum checked out)
+    pub fn is_done(&self) -> bool {
+        self.decoder.is_done()
+    }
+}
+
+/// The fixed-size buffer a `Compressor` keeps its leftover output in
+struct Spill {
+    /// The leftover bytes (big enough for the longest possible code plus the end of the stream)
+    buf: [u8; 2 * SYMBOL_COUNT / 8 + 16],
+    /// How far `buf` has been filled
+    len: usize,
+    /// How much of `buf` has been handed out already
+    pos: usize,
+}
+
+impl Spill {
+    /// Creates a new empty spill buffer
+    fn new() -> Self {
+        Spill {
+            buf: [0; 2 * SYMBOL_COUNT / 8 + 16],
+            len: 0,
+            pos: 0,
+        }
+    }
+
+    /// Whether everything in here has been handed out
+    fn is_empty(&self) -> bool {
+        self.pos == self.len
+    }
+
+    /// Copies as much as will fit into `out`, returning how much that was
+    fn drain_into(&mut self, out: &mut [u8]) -> usize {
+        let n = out.len().min(self.len - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        if self.is_empty() {
+            self.len = 0;
+            self.pos = 0;
+        }
+        n
+    }
+}
+
+impl Sink for Spill {
+    fn push(&mut self, byte: u8) {
+        self.buf[self.len] = byte;
+        self.len += 1;
+    }
+}
+
+/// Compresses everything written to it in a single pass, writing the compressed stream to the wrapped writer
+///
+/// `flush()` makes everything written so far decodable by whoever's on the other end (at the cost of a marker and
+/// some padding), but the stream isn't complete until `finish()` gets called.
+pub struct HuffmanWriter<W: Write> {
+    /// Where the compressed bytes go
+    inner: W,
+    /// The encoder doing the actual work
+    encoder: Encoder,
+    /// Scratch space for the encoder's output
+    buf: Vec<u8>,
+}
+
+impl<W: Write> HuffmanWriter<W> {
+    /// Creates a new streaming compressor
+    ///
+    /// ## Arguments
+    ///
+    /// * `inner`: the writer the compressed stream goes to
+    pub fn new(inner: W) -> Self {
+        HuffmanWriter {
+            inner,
+            encoder: Encoder::new(),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Gets a shared ref to the wrapped writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Ends the stream, writing out the end-of-stream marker, the last partial byte, and the checksum footer, and
+    /// hands back the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.encoder.finish(&mut self.buf);
+        self.inner.write_all(&self.buf)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for HuffmanWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.encoder.encode(data, &mut self.buf);
+        self.inner.write_all(&self.buf)?;
+        self.buf.clear();
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder.flush(&mut self.buf);
+        self.inner.write_all(&self.buf)?;
+        self.buf.clear();
+        self.inner.flush()
+    }
+}
+
+/// Decompresses a stream made by `HuffmanWriter` as it's read
+///
+/// Compressed bytes get decoded straight out of the wrapped reader's own buffer (via `fill_buf()`), and only as many
+/// as the stream actually uses get consumed, so anything following the end of the stream is left in the reader for
+/// whoever's next (see `into_inner()`). Plain `Read`ers can be wrapped in a `BufReader` first.
+pub struct HuffmanReader<R: BufRead> {
+    /// Where the compressed bytes come from
+    inner: R,
+    /// The decoder doing the actual work
+    decoder: Decoder,
+    /// Decoded bytes that haven't been read out yet
+    out_buf: Vec<u8>,
+    /// How much of `out_buf` has been read out
+    out_pos: usize,
+}
+
+impl<R: BufRead> HuffmanReader<R> {
+    /// Creates a new streaming decompressor
+    ///
+    /// ## Arguments
+    ///
+    /// * `inner`: the reader the compressed stream comes from
+    pub fn new(inner: R) -> Self {
+        HuffmanReader {
+            inner,
+            decoder: Decoder::new(),
+            out_buf: Vec::new(),
+            out_pos: 0,
+        }
+    }
+
+    /// Gets a shared ref to the wrapped reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Hands back the wrapped reader, positioned just past the end of the stream if it's been read to the end
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: BufRead> Read for HuffmanReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // keep decoding whatever's buffered until we have something to hand back, or the stream's over
+        while self.out_pos == self.out_buf.len() && !self.decoder.is_done() {
+            self.out_buf.clear();
+            self.out_pos = 0;
+            let in_buf = self.inner.fill_buf()?;
+            if in_buf.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "compressed stream ended without an end-of-stream marker",
+                ));
+            }
+            let used = self.decoder.decode(in_buf, &mut self.out_buf)?;
+            self.inner.consume(used);
+        }
+        let n = buf.len().min(self.out_buf.len() - self.out_pos);
+        buf[..n].copy_from_slice(&self.out_buf[self.out_pos..self.out_pos + n]);
+        self.out_pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Compressor, Decoder, Decompressor, Encoder, HuffmanReader, HuffmanWriter, Model,
+        SYMBOL_COUNT,
+    };
+    use crate::btree::HuffTree;
+    use std::collections::HashMap;
+    use std::io::{BufReader, Read, Write};
+
+    fn round_trip(input: &[u8]) -> bool {
+        let mut writer = HuffmanWriter::new(Vec::new());
+        writer.write_all(input).unwrap();
+        let compressed = writer.finish().unwrap();
+        let mut decompressed = Vec::new();
+        HuffmanReader::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        decompressed == input
+    }
+
+    fn chunked_round_trip(input: &[u8], chunk_size: usize) -> bool {
+        let mut encoder = Encoder::new();
+        let mut compressed = Vec::new();
+        for chunk in input.chunks(chunk_size) {
+            encoder.encode(chunk, &mut compressed);
+        }
+        encoder.finish(&mut compressed);
+        // trailing junk after the end of the stream should be left alone
+        let stream_len = compressed.len();
+        compressed.extend_from_slice(b"junk");
+        let mut decoder = Decoder::new();
+        let mut decompressed = Vec::new();
+        let mut used = 0;
+        for chunk in compressed.chunks(chunk_size) {
+            used += decoder.decode(chunk, &mut decompressed).unwrap();
+            if decoder.is_done() {
+                break;
+            }
+        }
+        decoder.is_done() && used == stream_len && decompressed == input
+    }
+
+    #[test]
+    fn stream_round_trip_test() {
+        assert!(round_trip(b""));
+        assert!(round_trip(b"aaabbbbbccddd"));
+        assert!(round_trip(b"dagoth ur was a hotep"));
+        assert!(round_trip(
+            &(0..=255u8).cycle().take(20000).collect::<Vec<u8>>()
+        ));
+        assert!(round_trip(
+            &"whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune"
+                .repeat(200)
+                .into_bytes()
+        ));
+    }
+
+    #[test]
+    fn chunked_stream_test() {
+        let input =
+            "whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune"
+                .repeat(100)
+                .into_bytes();
+        assert!(chunked_round_trip(&input, 1));
+        assert!(chunked_round_trip(&input, 7));
+        assert!(chunked_round_trip(&input, 4096));
+    }
+
+    #[test]
+    fn reader_leaves_trailing_data_test() {
+        let mut writer = HuffmanWriter::new(Vec::new());
+        writer.write_all(b"dagoth ur was a hotep").unwrap();
+        let mut compressed = writer.finish().unwrap();
+        compressed.extend_from_slice(b"trailer");
+        // a tiny buffer, so the stream gets fed through in lots of little pieces
+        let mut reader = HuffmanReader::new(BufReader::with_capacity(3, compressed.as_slice()));
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed).unwrap();
+        let mut rest = Vec::new();
+        reader.into_inner().read_to_end(&mut rest).unwrap();
+        assert_eq!(decompressed, b"dagoth ur was a hotep");
+        assert_eq!(rest, b"trailer");
+    }
+
+    #[test]
+    fn flush_test() {
+        let mut writer = HuffmanWriter::new(Vec::new());
+        writer.write_all(b"dagoth ur ").unwrap();
+        writer.flush().unwrap();
+        // flushing twice in a row shouldn't add anything
+        let flushed_len = writer.get_ref().len();
+        writer.flush().unwrap();
+        assert_eq!(writer.get_ref().len(), flushed_len);
+        let mut decoder = Decoder::new();
+        let mut decompressed = Vec::new();
+        decoder.decode(writer.get_ref(), &mut decompressed).unwrap();
+        assert_eq!(decompressed, b"dagoth ur ");
+        assert!(!decoder.is_done());
+        writer.write_all(b"was a hotep").unwrap();
+        let compressed = writer.finish().unwrap();
+        decoder
+            .decode(&compressed[flushed_len..], &mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, b"dagoth ur was a hotep");
+        assert!(decoder.is_done());
+    }
+
+    #[test]
+    fn bad_footer_test() {
+        let mut writer = HuffmanWriter::new(Vec::new());
+        writer.write_all(b"dagoth ur was a hotep").unwrap();
+        let mut compressed = writer.finish().unwrap();
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        let mut decompressed = Vec::new();
+        assert!(HuffmanReader::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .is_err());
+    }
+
+    fn fixed_buffer_round_trip(input: &[u8], in_size: usize, out_size: usize) -> bool {
+        let mut compressor = Compressor::new();
+        let mut out = vec![0; out_size];
+        let mut compressed = Vec::new();
+        for mut chunk in input.chunks(in_size) {
+            while !chunk.is_empty() {
+                let (consumed, produced) = compressor.feed(chunk, &mut out);
+                chunk = &chunk[consumed..];
+                compressed.extend_from_slice(&out[..produced]);
+            }
+        }
+        loop {
+            let (produced, done) = compressor.finish(&mut out);
+            compressed.extend_from_slice(&out[..produced]);
+            if done {
+                break;
+            }
+        }
+        // should be exactly what the growable version makes
+        let mut encoder = Encoder::new();
+        let mut expected = Vec::new();
+        encoder.encode(input, &mut expected);
+        encoder.finish(&mut expected);
+        let mut decompressor = Decompressor::new();
+        let mut decompressed = Vec::new();
+        let mut rest = compressed.as_slice();
+        while !decompressor.is_done() {
+            let (consumed, produced) = decompressor
+                .feed(&rest[..rest.len().min(in_size)], &mut out)
+                .unwrap();
+            rest = &rest[consumed..];
+            decompressed.extend_from_slice(&out[..produced]);
+        }
+        compressed == expected && decompressed == input && rest.is_empty()
+    }
+
+    #[test]
+    fn fixed_buffer_test() {
+        let input =
+            "whether 'tis nobler in the end to suffer th' slings and arrows of outrageous fortune"
+                .repeat(50)
+                .into_bytes();
+        assert!(fixed_buffer_round_trip(b"", 1, 1));
+        assert!(fixed_buffer_round_trip(&input, 1, 1));
+        assert!(fixed_buffer_round_trip(&input, 7, 3));
+        assert!(fixed_buffer_round_trip(&input, 3, 7));
+        assert!(fixed_buffer_round_trip(&input, 4096, 4096));
+    }
+
+    #[test]
+    fn stream_compresses_test() {
+        let input = "aaaaaaaabbbbccd".repeat(1000).into_bytes();
+        let mut writer = HuffmanWriter::new(Vec::new());
+        writer.write_all(&input).unwrap();
+        assert!(writer.finish().unwrap().len() < input.len() / 3);
+    }
+
+    fn model_matches_tree(counts: [i32; SYMBOL_COUNT]) -> bool {
+        let mut model = Model::new();
+        model.counts = counts;
+        model.rebuild();
+        let char_map: HashMap<char, i32> = (0..SYMBOL_COUNT as u32)
+            .map(|sym| (char::from_u32(sym).unwrap(), counts[sym as usize]))
+            .collect();
+        let mut tree = HuffTree::new();
+        tree.populate_tree(&char_map);
+        tree.generate_huffman_map().iter().all(|(&ch, code)| {
+            let (bits, len) = model.codes[ch as usize];
+            format!("{:0width$b}", bits, width = len as usize) == *code
+        })
+    }
+
+    #[test]
+    fn model_matches_tree_test() {
+        // the stream's codes have to be exactly the ones `HuffTree` would make, ties and all
+        assert!(model_matches_tree([1; SYMBOL_COUNT]));
+        let mut seed = 12345u64;
+        for spread in [2, 5, 100, 1 << 16] {
+            let mut counts = [0; SYMBOL_COUNT];
+            for cnt in counts.iter_mut() {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                *cnt = (seed >> 33) as i32 % spread + 1;
+            }
+            assert!(model_matches_tree(counts));
+        }
+        // lopsided enough to make codes as long as they can get
+        let mut fib = [1; SYMBOL_COUNT];
+        for i in 2..36 {
+            fib[i] = fib[i - 1] + fib[i - 2];
+        }
+        assert!(model_matches_tree(fib));
+    }
+}
@@ -0,0 +1,204 @@
+//! Table-driven decoding, which looks up several bits at once instead of walking the tree one bit at a time
+//!
+//! This is the same trick zlib uses: a primary table indexed by the next few bits of input says which symbol they
+//! start with (and how long its code is), and codes too long for the primary table send the lookup on to a smaller
+//! secondary table for the bits after that (and so on, for really long codes).
+
+use std::collections::HashMap;
+
+/// The most bits the primary table gets indexed by
+const PRIMARY_BITS: u32 = 10;
+/// The most bits a secondary table gets indexed by
+const SECONDARY_BITS: u32 = 6;
+
+/// One slot in a table
+#[derive(Clone, Copy, Debug)]
+enum Entry {
+    /// The bits so far are a whole code
+    Symbol {
+        /// The symbol they code for
+        ch: char,
+        /// How many of this table's bits the code actually uses
+        len: u32,
+    },
+    /// The bits so far are the start of a longer code, which is finished off in another table
+    Next {
+        /// Where that table starts in `entries`
+        start: usize,
+        /// How many bits it gets indexed by
+        bits: u32,
+    },
+    /// The bits so far aren't the start of any code (only ever the case for an empty tree)
+    Invalid,
+}
+
+/// A multi-level lookup table for decoding with one set of codes
+pub(crate) struct DecodeTable {
+    /// Every table, one after another, starting with the primary one
+    entries: Vec<Entry>,
+    /// How many bits the primary table gets indexed by
+    primary_bits: u32,
+}
+
+impl DecodeTable {
+    /// Builds the tables for a set of codes
+    ///
+    /// ## Arguments
+    ///
+    /// * `huffman_map`: the codes, as '0'/'1' strings
+    pub(crate) fn new(huffman_map: &HashMap<char, String>) -> Self {
+        let mut codes: Vec<(char, &str)> = huffman_map
+            .iter()
+            .map(|(&ch, code)| (ch, code.as_str()))
+            .collect();
+        // sorted, so that codes sharing a prefix end up next to each other
+        codes.sort_unstable_by_key(|&(_, code)| code);
+        let mut table = DecodeTable {
+            entries: Vec::new(),
+            primary_bits: 0,
+        };
+        table.primary_bits = table.build(&codes, PRIMARY_BITS);
+        table
+    }
+
+    /// Adds a table for a group of codes (with whatever prefix got them here already stripped off), returning how
+    /// many bits it gets indexed by
+    ///
+    /// ## Arguments
+    ///
+    /// * `codes`: the codes, sorted
+    /// * `max_bits`: the most bits the table can be indexed by
+    fn build(&mut self, codes: &[(char, &str)], max_bits: u32) -> u32 {
+        let longest = codes.iter().map(|&(_, code)| code.len()).max();
+        let bits = match longest {
+            Some(longest) => (longest as u32).min(max_bits),
+            None => {
+                self.entries.push(Entry::Invalid);
+                return 0;
+            }
+        };
+        let start = self.entries.len();
+        self.entries.resize(start + (1 << bits), Entry::Invalid);
+        let mut i = 0;
+        while i < codes.len() {
+            let (ch, code) = codes[i];
+            if code.len() as u32 <= bits {
+                // a short code fills every slot that starts with it
+                let len = code.len() as u32;
+                let first = start + (parse_bits(code) << (bits - len)) as usize;
+                for entry in &mut self.entries[first..first + (1 << (bits - len))] {
+                    *entry = Entry::Symbol { ch, len };
+                }
+                i += 1;
+            } else {
+                // a long code gets a table of its own, shared with everything else that starts the same way
+                let prefix = &code[..bits as usize];
+                let group = codes[i..]
+                    .iter()
+                    .take_while(|&&(_, code)| code.starts_with(prefix))
+                    .count();
+                let rest: Vec<(char, &str)> = codes[i..i + group]
+                    .iter()
+                    .map(|&(ch, code)| (ch, &code[bits as usize..]))
+                    .collect();
+                let next_start = self.entries.len();
+                let next_bits = self.build(&rest, SECONDARY_BITS);
+                self.entries[start + parse_bits(prefix) as usize] = Entry::Next {
+                    start: next_start,
+                    bits: next_bits,
+                };
+                i += group;
+            }
+        }
+        bits
+    }
+
+    /// Roughly how many bytes of heap memory the tables take up
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.entries.capacity() * std::mem::size_of::<Entry>()
+    }
+
+    /// Decodes the symbol at the front of `window`, returning it along with how many bits its code is, or `None` if
+    /// the bits don't make up any code
+    ///
+    /// The window holds the next bits of input most significant bit first; whatever's past the end of the input
+    /// should be zeroes, and it's up to the caller to check the code didn't run into them.
+    ///
+    /// ## Arguments
+    ///
+    /// * `window`: the next 64 bits of input
+    pub(crate) fn lookup(&self, window: u64) -> Option<(char, u32)> {
+        let mut start = 0;
+        let mut bits = self.primary_bits;
+        let mut used = 0;
+        loop {
+            let index = match bits {
+                0 => 0,
+                bits => ((window << used) >> (64 - bits)) as usize,
+            };
+            match self.entries[start + index] {
+                Entry::Symbol { ch, len } => return Some((ch, used + len)),
+                Entry::Next {
+                    start: next_start,
+                    bits: next_bits,
+                } => {
+                    used += bits;
+                    start = next_start;
+                    bits = next_bits;
+                }
+                Entry::Invalid => return None,
+            }
+        }
+    }
+}
+
+/// Turns a '0'/'1' string into the number it spells out in binary
+fn parse_bits(code: &str) -> u64 {
+    code.bytes()
+        .fold(0, |acc, bit| (acc << 1) | (bit == b'1') as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::DecodeTable;
+    use crate::btree::HuffTree;
+
+    /// Left-aligns a '0'/'1' string in a 64-bit window
+    fn window(bits: &str) -> u64 {
+        match bits.len() {
+            0 => 0,
+            len => super::parse_bits(bits) << (64 - len),
+        }
+    }
+
+    fn table_matches_tree(input: &str) -> bool {
+        let mut hufftree = HuffTree::new();
+        hufftree.populate_tree(&HuffTree::find_input_freqs(input));
+        let huffman_map = hufftree.generate_huffman_map();
+        let table = DecodeTable::new(&huffman_map);
+        huffman_map.iter().all(|(&ch, code)| {
+            // whatever comes after the code shouldn't matter
+            table.lookup(window(code)) == Some((ch, code.len() as u32))
+                && table.lookup(window(code) | (u64::MAX >> code.len()))
+                    == Some((ch, code.len() as u32))
+        })
+    }
+
+    #[test]
+    fn lookup_test() {
+        assert!(table_matches_tree("a"));
+        assert!(table_matches_tree("aaabbbbbccddd"));
+        assert!(table_matches_tree("dagoth ur was a hotep"));
+        // frequencies going up like powers of two make codes as long as there are symbols, which needs several levels
+        let skewed: String = (0..30u32)
+            .map(|i| {
+                char::from_u32('a' as u32 + i)
+                    .unwrap()
+                    .to_string()
+                    .repeat(1 << (i / 2))
+            })
+            .collect();
+        assert!(table_matches_tree(&skewed));
+        assert_eq!(DecodeTable::new(&Default::default()).lookup(0), None);
+    }
+}
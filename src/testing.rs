@@ -0,0 +1,105 @@
+//! proptest strategies for this crate's types, so crates built on it can property-test their own round trips without
+//! writing their own generators (with the `testing` feature)
+//!
+//! ```text
+//! proptest! {
+//!     #[test]
+//!     fn my_round_trip((input, compressed) in huffman::testing::compressed()) {
+//!         prop_assert_eq!(my_decompress(&compressed)?, input);
+//!     }
+//! }
+//! ```
+
+use crate::btree::HuffTree;
+use crate::codebook::Codebook;
+use crate::container::{compress_bytes, EncodeOptions};
+use crate::freq::FrequencyMap;
+use proptest::collection::{hash_map, vec};
+use proptest::option;
+use proptest::prelude::*;
+
+/// The most symbols `frequencies()` makes up
+const MAX_SYMBOLS: usize = 300;
+/// The longest input `inputs()` makes up
+const MAX_INPUT_LEN: usize = 8192;
+
+/// Frequencies for up to a few hundred chars (more than there are byte values, so anything built on the `char` APIs
+/// gets some that don't fit in a byte), every one of them at least 1
+pub fn frequencies() -> impl Strategy<Value = FrequencyMap> {
+    hash_map(any::<char>(), 1..=u16::MAX as i32, 0..=MAX_SYMBOLS).prop_map(FrequencyMap::from)
+}
+
+/// Trees built from `frequencies()`, including empty ones and ones with a single leaf
+pub fn trees() -> impl Strategy<Value = HuffTree> {
+    frequencies().prop_map(|freqs| {
+        let mut tree = HuffTree::new();
+        tree.populate_tree(freqs.as_map());
+        tree
+    })
+}
+
+/// The codes for `frequencies()`, which are always prefix-free and complete
+pub fn codebooks() -> impl Strategy<Value = Codebook> {
+    frequencies().prop_map(|freqs| Codebook::from_freqs(freqs.as_map()))
+}
+
+/// Inputs to compress, up to a few kilobytes: some of them random bytes, and some of them made out of just a few byte
+/// values, which is more like what actually gets compressed
+pub fn inputs() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        vec(any::<u8>(), 0..=MAX_INPUT_LEN),
+        vec(prop::sample::select(&b"acgt \n"[..]), 0..=MAX_INPUT_LEN),
+    ]
+}
+
+/// Every mix of the options that change what a compressed file looks like: checksums or not, one pass or two,
+/// canonical codes or not, and blocks of any size or none
+pub fn encode_options() -> impl Strategy<Value = EncodeOptions> {
+    (
+        any::<bool>(),
+        any::<bool>(),
+        any::<bool>(),
+        option::of(1..=MAX_INPUT_LEN),
+    )
+        .prop_map(
+            |(checksum, streaming, canonical, block_size)| EncodeOptions {
+                checksum,
+                streaming,
+                canonical,
+                block_size,
+                ..EncodeOptions::default()
+            },
+        )
+}
+
+/// `inputs()` along with what they compress to, with any of `encode_options()`
+pub fn compressed() -> impl Strategy<Value = (Vec<u8>, Vec<u8>)> {
+    (inputs(), encode_options()).prop_map(|(input, options)| {
+        let compressed =
+            compress_bytes(&input, &options).expect("compressing into memory can't fail");
+        (input, compressed)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{codebooks, compressed, trees};
+    use crate::codebook::Codebook;
+    use crate::container::decompress_bytes;
+    use proptest::prelude::*;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn compressed_test((input, compressed) in compressed()) {
+            prop_assert_eq!(decompress_bytes(&compressed).unwrap(), input);
+        }
+
+        #[test]
+        fn codebooks_test(codebook in codebooks(), mut tree in trees()) {
+            prop_assert!(Codebook::from_pairs(codebook.into_map(), true).is_ok());
+            prop_assert!(Codebook::from_pairs(tree.generate_huffman_map(), true).is_ok());
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! JavaScript bindings, through wasm-bindgen (with the `wasm` feature), so browsers can read and write the same
+//! containers as everything else
+//!
+//! ```text
+//! import { compress, decompress, codebook } from "huffman";
+//! const compressed = compress(new TextEncoder().encode("dagoth ur was a hotep"));
+//! decompress(compressed);            // Uint8Array [100, 97, 103, ...]
+//! codebook(new Uint8Array([97, 97, 98, 99]));  // Map { 97 => "0", 98 => "10", 99 => "11" }
+//! ```
+//!
+//! Errors (e.g. from corrupt data) get thrown as JavaScript `Error`s.
+
+use crate::container::{compress_slice, decompress, EncodeOptions};
+use crate::freq::FrequencyMap;
+use wasm_bindgen::prelude::*;
+
+/// `compress(data, canonical = false)`: compresses a `Uint8Array` into a container
+///
+/// ## Arguments
+///
+/// * `data`: the bytes to compress
+/// * `canonical`: whether to use canonical codes (see `EncodeOptions::canonical`)
+#[wasm_bindgen(js_name = compress)]
+pub fn js_compress(data: &[u8], canonical: Option<bool>) -> Result<Vec<u8>, JsError> {
+    let options = EncodeOptions {
+        canonical: canonical.unwrap_or(false),
+        ..EncodeOptions::default()
+    };
+    Ok(compress_slice(data, Vec::new(), &options)?)
+}
+
+/// `decompress(data)`: decompresses a container held in a `Uint8Array`
+///
+/// ## Arguments
+///
+/// * `data`: the container
+#[wasm_bindgen(js_name = decompress)]
+pub fn js_decompress(data: &[u8]) -> Result<Vec<u8>, JsError> {
+    Ok(decompress(data, Vec::new())?)
+}
+
+/// `codebook(data)`: the codes a `Uint8Array` would get compressed with, as a `Map` of byte values to strings of
+/// '0's and '1's
+///
+/// ## Arguments
+///
+/// * `data`: the bytes
+#[wasm_bindgen(js_name = codebook)]
+pub fn js_codebook(data: &[u8]) -> js_sys::Map {
+    let mut freqs = FrequencyMap::new();
+    freqs.add_bytes(data);
+    let codes = js_sys::Map::new();
+    let mut sorted: Vec<(char, String)> = crate::codebook::Codebook::from_freqs(freqs.as_map())
+        .into_map()
+        .into_iter()
+        .collect();
+    sorted.sort_unstable();
+    for (ch, code) in sorted {
+        codes.set(&JsValue::from(ch as u32), &JsValue::from(code));
+    }
+    codes
+}
+
+#[cfg(test)]
+mod test {
+    use super::{js_compress, js_decompress};
+
+    // (anything that makes a JavaScript value, like `codebook()` or an error, only works on wasm itself)
+    #[test]
+    fn wasm_test() {
+        let data = b"dagoth ur was a hotep".repeat(10);
+        for canonical in [None, Some(true)] {
+            let compressed = js_compress(&data, canonical).unwrap();
+            assert_eq!(js_decompress(&compressed).unwrap(), data);
+        }
+    }
+}
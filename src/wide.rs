@@ -0,0 +1,345 @@
+//! Huffman coding for 16-bit symbols, like UTF-16 text or 16-bit samples
+//!
+//! Everything else in the crate codes bytes, or chars, and a 16-bit value can't just be cast to a char: the
+//! surrogates (0xD800 to 0xDFFF) aren't chars at all, and splitting each value into two bytes throws away most of
+//! what the codes could learn about it. Here, the symbols are `u16`s all the way through: counted in one flat array
+//! of 65536 counts, with canonical codes (see the `canonical` module) whose lengths are all the header stores, none
+//! longer than `MAX_CODE_LEN` bits.
+//!
+//! ```text
+//! length      8 bytes   how many symbols there are (little-endian, as is everything else)
+//! entries     4 bytes   how many (symbol, length) entries follow
+//! entries     3 bytes each, in symbol order: the symbol (2 bytes), then the length of its code
+//! payload     the packed codes, most significant bit first, padded out to a whole byte
+//! ```
+//!
+//! A lone symbol gets a 1-bit code rather than an empty one, so every symbol takes at least a bit of payload, and a
+//! header can't say there's more to decode than 8 symbols per payload byte. There's no checksum; put the output in
+//! something that has one if it's going anywhere unreliable.
+
+use crate::bits::{BitWindow, BitWriter};
+use crate::canonical::{code_lengths, limit_lengths};
+use crate::error::{Error, Result};
+use std::convert::TryInto;
+
+/// The longest a code can be (which is plenty for 65536 symbols, and keeps the decoder's search through the lengths
+/// short)
+pub const MAX_CODE_LEN: u8 = 24;
+
+/// Counts how many times each 16-bit value comes up
+///
+/// ## Arguments
+///
+/// * `symbols`: the symbols to count
+pub fn count(symbols: &[u16]) -> Vec<u64> {
+    let mut counts = vec![0u64; 1 << 16];
+    for &symbol in symbols {
+        counts[symbol as usize] += 1;
+    }
+    counts
+}
+
+/// Works out every symbol's code length, leaving out symbols that never come up
+///
+/// The lengths come back in symbol order. A single symbol gets a length of 1 (not the 0 `HuffTree` would give it),
+/// so that every symbol costs at least a bit.
+///
+/// ## Arguments
+///
+/// * `counts`: how many times each symbol comes up, as from `count()`
+pub fn lengths(counts: &[u64]) -> Vec<(u16, u8)> {
+    let mut symbols: Vec<(u16, u64)> = (0..=u16::MAX)
+        .zip(counts.iter().copied())
+        .filter(|&(_, n)| n > 0)
+        .collect();
+    symbols.sort_unstable_by_key(|&(symbol, n)| (n, symbol));
+    let mut lengths: Vec<u64> = symbols.iter().map(|&(_, n)| n).collect();
+    code_lengths(&mut lengths);
+    limit_lengths(&mut lengths, MAX_CODE_LEN as u64);
+    let mut lengths: Vec<(u16, u8)> = symbols
+        .iter()
+        .zip(lengths)
+        .map(|(&(symbol, _), len)| (symbol, (len as u8).max(1)))
+        .collect();
+    lengths.sort_unstable();
+    lengths
+}
+
+/// Compresses some 16-bit symbols
+///
+/// ## Arguments
+///
+/// * `symbols`: the symbols to compress
+pub fn compress(symbols: &[u16]) -> Vec<u8> {
+    let lengths = lengths(&count(symbols));
+    let mut out = Vec::with_capacity(12 + lengths.len() * 3 + symbols.len());
+    out.extend_from_slice(&(symbols.len() as u64).to_le_bytes());
+    out.extend_from_slice(&(lengths.len() as u32).to_le_bytes());
+    for &(symbol, len) in &lengths {
+        out.extend_from_slice(&symbol.to_le_bytes());
+        out.push(len);
+    }
+    let mut codes = vec![(0u64, 0u8); 1 << 16];
+    let canonical = Canonical::new(&lengths);
+    for (k, &symbol) in canonical.symbols.iter().enumerate() {
+        codes[symbol as usize] = canonical.code(k);
+    }
+    let mut bits = BitWriter::new();
+    for &symbol in symbols {
+        let (code, len) = codes[symbol as usize];
+        bits.push_bits(code, len, &mut out);
+    }
+    bits.align(&mut out);
+    out
+}
+
+/// Decompresses what `compress()` made
+///
+/// ## Arguments
+///
+/// * `data`: the compressed bytes
+pub fn decompress(data: &[u8]) -> Result<Vec<u16>> {
+    if data.len() < 12 {
+        return Err(Error::BadHeader("header is truncated"));
+    }
+    let original_len = u64::from_le_bytes(data[..8].try_into().unwrap());
+    let entries = u32::from_le_bytes(data[8..12].try_into().unwrap()) as usize;
+    if entries > 1 << 16 {
+        return Err(Error::BadHeader("symbol count out of range"));
+    }
+    let table = data
+        .get(12..12 + entries * 3)
+        .ok_or(Error::BadHeader("header is truncated"))?;
+    let lengths: Vec<(u16, u8)> = table
+        .chunks(3)
+        .map(|entry| (u16::from_le_bytes([entry[0], entry[1]]), entry[2]))
+        .collect();
+    check_lengths(&lengths)?;
+    let payload = &data[12 + entries * 3..];
+    let canonical = Canonical::new(&lengths);
+    let mut out = Vec::with_capacity(original_len.min(payload.len() as u64 * 8) as usize);
+    let mut bits = BitWindow::new(payload, payload.len() as u64);
+    for _ in 0..original_len {
+        bits.refill()?;
+        let (symbol, len) = canonical
+            .decode(bits.peek())
+            .ok_or(Error::Corrupt("bits don't match any code"))?;
+        if !bits.consume(len as u32) {
+            return Err(Error::Corrupt("payload is truncated"));
+        }
+        out.push(symbol);
+    }
+    // the rest of the last byte is padding
+    if !bits.is_finished() {
+        return Err(Error::Corrupt("payload length doesn't match the header"));
+    }
+    Ok(out)
+}
+
+/// Compresses a string as its UTF-16 code units
+///
+/// ## Arguments
+///
+/// * `text`: the string to compress
+pub fn compress_utf16(text: &str) -> Vec<u8> {
+    compress(&text.encode_utf16().collect::<Vec<u16>>())
+}
+
+/// Decompresses what `compress_utf16()` made back into a string
+///
+/// ## Arguments
+///
+/// * `data`: the compressed bytes
+pub fn decompress_utf16(data: &[u8]) -> Result<String> {
+    String::from_utf16(&decompress(data)?).map_err(|_| Error::Corrupt("not valid UTF-16"))
+}
+
+/// Makes sure code lengths make a code that can be decoded: symbols in order, each listed once, and (unless there's
+/// just the one, whose code is 1 bit) lengths that use up every code without running out of them
+///
+/// ## Arguments
+///
+/// * `lengths`: the symbols and their code lengths
+fn check_lengths(lengths: &[(u16, u8)]) -> Result<()> {
+    if lengths.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+        return Err(Error::BadHeader("symbols out of order"));
+    }
+    let complete = match lengths {
+        [] => true,
+        [(_, len)] => *len == 1,
+        lengths => {
+            // each code of length `len` uses up 2^-len of the code space, which has to come to exactly 1
+            let mut used = 0u64;
+            for &(_, len) in lengths {
+                if len == 0 || len > MAX_CODE_LEN {
+                    return Err(Error::BadHeader("code length out of range"));
+                }
+                used += 1 << (MAX_CODE_LEN - len);
+            }
+            used == 1 << MAX_CODE_LEN
+        }
+    };
+    match complete {
+        true => Ok(()),
+        false => Err(Error::BadHeader("code lengths don't make a complete code")),
+    }
+}
+
+/// Canonical codes, kept as just what it takes to work them out: the symbols sorted by code length, and where each
+/// length's codes start
+struct Canonical {
+    /// The symbols, shortest code first (and in symbol order within a length)
+    symbols: Vec<u16>,
+    /// For each length, the first code of that length, and how many symbols come before it in `symbols`
+    firsts: [(u64, usize); MAX_CODE_LEN as usize + 1],
+    /// How many codes there are of each length
+    counts: [usize; MAX_CODE_LEN as usize + 1],
+}
+
+impl Canonical {
+    /// Lays out the codes for some code lengths
+    ///
+    /// ## Arguments
+    ///
+    /// * `lengths`: the symbols and their code lengths, which have to make a complete code
+    fn new(lengths: &[(u16, u8)]) -> Self {
+        let mut sorted = lengths.to_vec();
+        sorted.sort_unstable_by_key(|&(symbol, len)| (len, symbol));
+        let mut counts = [0; MAX_CODE_LEN as usize + 1];
+        for &(_, len) in &sorted {
+            counts[len as usize] += 1;
+        }
+        // each length's codes start just after the last of the length before's, with a 0 on the end
+        let mut firsts = [(0, 0); MAX_CODE_LEN as usize + 1];
+        let (mut code, mut index) = (0u64, counts[0]);
+        for len in 1..=MAX_CODE_LEN as usize {
+            firsts[len] = (code, index);
+            code = (code + counts[len] as u64) << 1;
+            index += counts[len];
+        }
+        Canonical {
+            symbols: sorted.iter().map(|&(symbol, _)| symbol).collect(),
+            firsts,
+            counts,
+        }
+    }
+
+    /// Gets the code of the `k`th symbol in `symbols`, right-aligned, and its length
+    ///
+    /// ## Arguments
+    ///
+    /// * `k`: where the symbol is in `symbols`
+    fn code(&self, k: usize) -> (u64, u8) {
+        let len = (1..=MAX_CODE_LEN as usize)
+            .find(|&len| k < self.firsts[len].1 + self.counts[len])
+            .unwrap();
+        let (first, index) = self.firsts[len];
+        (first + (k - index) as u64, len as u8)
+    }
+
+    /// Decodes the code at the start of a bit window, giving its symbol and length
+    ///
+    /// ## Arguments
+    ///
+    /// * `window`: the next bits, most significant first
+    fn decode(&self, window: u64) -> Option<(u16, u8)> {
+        // a code of each length is either one of that length's, or something that starts a longer one
+        (1..=MAX_CODE_LEN as usize).find_map(|len| {
+            let (first, index) = self.firsts[len];
+            let offset = (window >> (64 - len)).checked_sub(first)?;
+            match offset < self.counts[len] as u64 {
+                true => Some((self.symbols[index + offset as usize], len as u8)),
+                false => None,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip_test() {
+        let mut seed = 12345u64;
+        let samples: Vec<u16> = (0..20_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                // roughly bell-shaped around the middle, like a quiet signal
+                let a = (seed >> 40) as u16 % 200;
+                let b = (seed >> 20) as u16 % 200;
+                32_768 - 200 + a + b
+            })
+            .collect();
+        let all: Vec<u16> = (0..=u16::MAX).collect();
+        for symbols in [&[][..], &[7], &[0xd800, 0xd800], &samples, &all] {
+            let compressed = compress(symbols);
+            assert_eq!(decompress(&compressed).unwrap(), symbols);
+        }
+        // every symbol being equally likely takes all 16 bits
+        assert_eq!(compress(&all).len(), 12 + 3 * 65536 + 2 * 65536);
+        // and a narrow spread takes a lot fewer
+        assert!(compress(&samples).len() < samples.len() * 2 * 2 / 3);
+    }
+
+    #[test]
+    fn utf16_test() {
+        // surrogate pairs and all, which a char can't hold half of
+        let text = "Grüße, 世界! 🦀🦀 ".repeat(100);
+        let compressed = compress_utf16(&text);
+        assert_eq!(decompress_utf16(&compressed).unwrap(), text);
+        assert!(compressed.len() < text.encode_utf16().count());
+        // half a surrogate pair isn't text
+        let lone = compress(&[0xd83e]);
+        assert!(matches!(decompress_utf16(&lone), Err(Error::Corrupt(_))));
+    }
+
+    #[test]
+    fn lengths_test() {
+        // Fibonacci counts make the deepest tree there is, which has to get cut down
+        let mut counts = vec![0u64; 1 << 16];
+        let (mut a, mut b) = (1u64, 1u64);
+        for count in counts.iter_mut().take(40) {
+            *count = a;
+            (a, b) = (b, a + b);
+        }
+        let lengths = lengths(&counts);
+        assert_eq!(lengths.len(), 40);
+        assert!(lengths.iter().all(|&(_, len)| len <= MAX_CODE_LEN));
+        check_lengths(&lengths).unwrap();
+        let symbols: Vec<u16> = (0..40u16).chain([0, 0, 39]).collect();
+        assert_eq!(decompress(&compress(&symbols)).unwrap(), symbols);
+    }
+
+    #[test]
+    fn corrupt_test() {
+        let compressed = compress(&[1, 2, 3, 3, 3, 0xffff]);
+        assert!(matches!(
+            decompress(&compressed[..14]),
+            Err(Error::BadHeader(_))
+        ));
+        assert!(matches!(
+            decompress(&compressed[..compressed.len() - 1]),
+            Err(Error::Corrupt(_))
+        ));
+        let mut swapped = compressed.clone();
+        swapped[12..15].copy_from_slice(&[0xff, 0xff, 1]);
+        assert!(matches!(decompress(&swapped), Err(Error::BadHeader(_))));
+        let mut longer = compressed.clone();
+        longer.push(0);
+        assert!(matches!(decompress(&longer), Err(Error::Corrupt(_))));
+        let mut gap = compressed;
+        gap[14] += 1;
+        assert!(matches!(decompress(&gap), Err(Error::BadHeader(_))));
+        // a lone symbol still takes a bit each, so a header claiming far more of it than the payload holds just runs
+        // out of payload
+        let lone = compress(&[7; 100]);
+        assert_eq!(lone.len(), 12 + 3 + 13);
+        let mut bomb = lone.clone();
+        bomb[..8].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        assert!(matches!(decompress(&bomb), Err(Error::Corrupt(_))));
+        let mut empty_code = lone;
+        empty_code[14] = 0;
+        assert!(matches!(decompress(&empty_code), Err(Error::BadHeader(_))));
+    }
+}